@@ -0,0 +1,121 @@
+//! move_resolve の探索で多く呼ばれる処理の所要時間を測る.
+//!
+//! `cargo bench --bench move_resolve` で実行する. 結果は処理ごとの時間を標準出力に書く.
+
+use std::time::{Duration, Instant};
+
+use procon2021_comp::{
+    basis::Movement,
+    grid::{board::BoardFinder, Grid, Pos},
+    move_resolve::{resolve_with_config, BeamConfig, ResolveParam},
+};
+
+const MOVEMENTS: [Movement; 4] = [
+    Movement::Up,
+    Movement::Right,
+    Movement::Down,
+    Movement::Left,
+];
+
+fn measure(name: &str, iterations: u32, mut f: impl FnMut() -> u64) {
+    // 最適化で処理が消えないよう, 結果を集めて出力する
+    let mut checksum = 0u64;
+    let start = Instant::now();
+    for _ in 0..iterations {
+        checksum = checksum.wrapping_add(f());
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<32} {:>12?}/iter (checksum {})",
+        name,
+        elapsed / iterations,
+        checksum
+    );
+}
+
+/// 選択中の断片を盤面全体で動かし続ける. 交換 1 回ごとに移動先を求める処理と同じ.
+fn walk(grid: Grid, mut next: impl FnMut(Pos, Movement) -> Pos) -> u64 {
+    let mut pos = grid.pos(0, 0);
+    let mut sum = 0u64;
+    for i in 0..4096usize {
+        pos = next(pos, MOVEMENTS[(i * 7 + i / 5) % 4]);
+        sum += pos.x() as u64 + pos.y() as u64;
+    }
+    sum
+}
+
+/// 16x16 の盤面で, 決まった順に選んだマスを 1 つの巡回で動かした問題.
+fn large_cycle(grid: Grid) -> Vec<(Pos, Pos)> {
+    let cycle: Vec<_> = grid.all_pos().step_by(7).take(40).collect();
+    (0..cycle.len())
+        .map(|i| (cycle[i], cycle[(i + 1) % cycle.len()]))
+        .collect()
+}
+
+fn main() {
+    let grid = Grid::new(16, 16);
+
+    measure("BoardFinder::new per move", 2000, || {
+        walk(grid, |pos, mov| {
+            BoardFinder::new(grid).move_pos_to(pos, mov)
+        })
+    });
+    measure("Grid::looping_move", 2000, || {
+        walk(grid, |pos, mov| grid.looping_move(pos, mov))
+    });
+
+    let movements = large_cycle(grid);
+    let param = ResolveParam {
+        select_limit: 8,
+        swap_cost: 1,
+        select_cost: 8,
+    };
+    let beam = BeamConfig {
+        phase1: 20,
+        phase3: 10,
+        ..BeamConfig::default()
+    };
+    let start = Instant::now();
+    let mut answers = 0u64;
+    for _ in resolve_with_config(grid, &movements, param, beam)
+        .unwrap()
+        .take(3)
+    {
+        answers += 1;
+        if Duration::from_secs(60) < start.elapsed() {
+            break;
+        }
+    }
+    println!(
+        "{:<32} {:>12?} ({} answers)",
+        "resolve 16x16 cycle of 40",
+        start.elapsed(),
+        answers
+    );
+
+    // ビームサーチの展開がスレッド数に応じて速くなるかを見る. 8 コア以上の環境で実行する
+    let beam = BeamConfig::default();
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut threads = vec![1, 2, 4, 8];
+    threads.retain(|&n| n < cores);
+    threads.push(cores);
+    for n in threads {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(n)
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        let found = pool.install(|| {
+            resolve_with_config(grid, &movements, param, beam)
+                .unwrap()
+                .next()
+                .is_some()
+        });
+        println!(
+            "{:<32} {:>12?} (found: {})",
+            format!("first answer with {} threads", n),
+            start.elapsed(),
+            found
+        );
+    }
+}
@@ -0,0 +1,46 @@
+//! ヒントの数に応じて pixel_match の復元にかかる時間を測る.
+//!
+//! `cargo bench --bench pixel_match` で実行する. 結果はヒントの数ごとの時間を標準出力に書く.
+
+use std::time::Instant;
+
+use procon2021_comp::{
+    basis::Dir,
+    fragment::Fragment,
+    grid::Grid,
+    pixel_match::{EdgePos, ResolveHints, Resolver},
+    scramble::smooth_problem,
+};
+
+/// 隣り合わない断片の組を `count` 個ブロックリストに入れる. 正しい復元には影響しない.
+fn distant_blocklist(grid: Grid, count: usize) -> ResolveHints {
+    const DIRS: [Dir; 4] = [Dir::North, Dir::East, Dir::South, Dir::West];
+    grid.all_pos()
+        .take(count)
+        .enumerate()
+        .fold(ResolveHints::builder(grid), |builder, (i, pos)| {
+            let against = grid.pos((pos.x() + 5) % 16, (pos.y() + 7) % 16);
+            builder.block(pos, EdgePos::new(against, DIRS[i % 4]))
+        })
+        .build()
+        .unwrap()
+}
+
+fn main() {
+    let problem = smooth_problem(16, 16);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+
+    for count in [0, 16, 48, 96] {
+        let mut resolver = Resolver::new(fragments.clone(), grid);
+        resolver.set_hints(distant_blocklist(grid, count));
+        let start = Instant::now();
+        let arrangement = resolver.resolve_without_gui().unwrap();
+        println!(
+            "{:<32} {:>12?} (mean seam: {:.1})",
+            format!("resolve 16x16 with {} blocks", count),
+            start.elapsed(),
+            resolver.check_seams(&arrangement).mean()
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{
+    basis::{Operation, Problem},
+    grid::Pos,
+    simulator,
+};
+
+/// `AnswerDiff` は 2 つの回答の操作列の違いを表す.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnswerDiff {
+    /// `right` のコストから `left` のコストを引いたもの.
+    pub cost_delta: i64,
+    /// 先頭から一致している操作の数.
+    pub common_prefix: usize,
+    /// 選択する位置が異なる操作の一覧.
+    pub differing_selections: Vec<SelectionDiff>,
+}
+
+/// `SelectionDiff` は `index` 番目の操作で選択する位置の違いを表す. 操作が存在しない側は `None` になる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectionDiff {
+    pub index: usize,
+    pub left: Option<Pos>,
+    pub right: Option<Pos>,
+}
+
+impl AnswerDiff {
+    pub fn is_same(&self) -> bool {
+        self.cost_delta == 0 && self.differing_selections.is_empty()
+    }
+}
+
+/// 回答 `left` と `right` を `problem` のコストで比較する.
+pub fn diff(problem: &Problem, left: &[Operation], right: &[Operation]) -> AnswerDiff {
+    let cost_of = |ops| simulator::cost(problem.select_cost, problem.swap_cost, ops) as i64;
+    let cost_delta = cost_of(right) - cost_of(left);
+
+    let common_prefix = left
+        .iter()
+        .zip(right.iter())
+        .take_while(|(l, r)| l == r)
+        .count();
+
+    let differing_selections = (0..left.len().max(right.len()))
+        .map(|index| SelectionDiff {
+            index,
+            left: left.get(index).map(|op| op.select),
+            right: right.get(index).map(|op| op.select),
+        })
+        .filter(|diff| diff.left != diff.right)
+        .collect();
+
+    AnswerDiff {
+        cost_delta,
+        common_prefix,
+        differing_selections,
+    }
+}
+
+#[test]
+fn test_diff() {
+    use crate::{
+        basis::{Image, Movement::*},
+        grid::{Dims, Grid},
+    };
+
+    let problem = Problem {
+        select_limit: 2,
+        select_cost: 10,
+        swap_cost: 1,
+        dims: Dims {
+            width: 3,
+            height: 3,
+        },
+        image: Image {
+            width: 0,
+            height: 0,
+            pixels: vec![],
+        },
+    };
+    let grid = Grid::new(3, 3);
+    let left = vec![
+        Operation {
+            select: grid.pos(0, 0),
+            movements: vec![Right, Down],
+        },
+        Operation {
+            select: grid.pos(2, 2),
+            movements: vec![Up],
+        },
+    ];
+    let right = vec![
+        Operation {
+            select: grid.pos(0, 0),
+            movements: vec![Right, Down],
+        },
+        Operation {
+            select: grid.pos(1, 2),
+            movements: vec![Up, Left],
+        },
+    ];
+
+    let result = diff(&problem, &left, &right);
+    assert_eq!(1, result.cost_delta);
+    assert_eq!(1, result.common_prefix);
+    assert_eq!(
+        vec![SelectionDiff {
+            index: 1,
+            left: Some(grid.pos(2, 2)),
+            right: Some(grid.pos(1, 2)),
+        }],
+        result.differing_selections
+    );
+
+    let result = diff(&problem, &left, &left[..1]);
+    assert_eq!(-11, result.cost_delta);
+    assert_eq!(1, result.common_prefix);
+    assert_eq!(None, result.differing_selections[0].right);
+
+    assert!(diff(&problem, &left, &left).is_same());
+}
@@ -0,0 +1,118 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::{
+    basis::{Operation, Rot},
+    kaitou,
+    move_resolve::ResolveParam,
+    simulator,
+};
+
+/// `AnswerSink` は回答の候補を受け取り, それまでより安いものが来るたびにすぐファイルへ書き出す.
+///
+/// 書き出しは一時ファイルを経由してから名前を変えるので, 途中で強制終了されても壊れたファイルは残らない.
+#[derive(Debug)]
+pub struct AnswerSink {
+    dir: PathBuf,
+    prefix: String,
+    param: ResolveParam,
+    best_cost: Option<u64>,
+}
+
+impl AnswerSink {
+    /// `dir` に `{prefix}-{cost}.txt` という名前で回答を書き出す. `dir` が無ければ作る.
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        prefix: impl Into<String>,
+        param: ResolveParam,
+    ) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create directory {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            prefix: prefix.into(),
+            param,
+            best_cost: None,
+        })
+    }
+
+    /// これまでに書き出した回答のうち最も安いもののコストを返す.
+    pub fn best_cost(&self) -> Option<u64> {
+        self.best_cost
+    }
+
+    /// 回答の候補を渡す. それまでより安ければ書き出して, そのパスを返す.
+    pub fn offer(&mut self, ops: &[Operation], rots: &[Rot]) -> Result<Option<PathBuf>> {
+        let cost = simulator::cost(self.param.select_cost, self.param.swap_cost, ops);
+        if matches!(self.best_cost, Some(best) if best <= cost) {
+            return Ok(None);
+        }
+        let path = self.dir.join(format!("{}-{}.txt", self.prefix, cost));
+        write_atomically(&path, kaitou::ans(ops, rots).as_bytes())?;
+        self.best_cost = Some(cost);
+        Ok(Some(path))
+    }
+}
+
+fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)
+        .with_context(|| format!("failed to create {}", tmp_path.display()))?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("failed to rename to {}", path.display()))?;
+    Ok(())
+}
+
+#[test]
+fn test_answer_sink() {
+    use crate::{basis::Movement, grid::Grid};
+
+    let dir = std::env::temp_dir().join(format!("answer_sink_test_{}", std::process::id()));
+    let mut sink = AnswerSink::new(
+        &dir,
+        "answer",
+        ResolveParam {
+            select_limit: 2,
+            swap_cost: 1,
+            select_cost: 3,
+        },
+    )
+    .unwrap();
+
+    let grid = Grid::new(2, 2);
+    let rots = vec![Rot::R0; 4];
+    let op = |movements: Vec<Movement>| Operation {
+        select: grid.pos(1, 0),
+        movements,
+    };
+
+    let path = sink
+        .offer(&[op(vec![Movement::Left, Movement::Right])], &rots)
+        .unwrap()
+        .unwrap();
+    assert_eq!(dir.join("answer-5.txt"), path);
+    assert!(sink
+        .offer(&[op(vec![Movement::Left, Movement::Right])], &rots)
+        .unwrap()
+        .is_none());
+    let path = sink
+        .offer(&[op(vec![Movement::Down])], &rots)
+        .unwrap()
+        .unwrap();
+    assert_eq!(Some(4), sink.best_cost());
+    assert_eq!(
+        kaitou::ans(&[op(vec![Movement::Down])], &rots),
+        fs::read_to_string(path).unwrap()
+    );
+    assert!(!dir.join("answer-4.tmp").exists());
+
+    fs::remove_dir_all(dir).unwrap();
+}
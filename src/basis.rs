@@ -1,8 +1,11 @@
 #![allow(dead_code)]
 
-use std::ops::{Add, AddAssign};
+use std::ops::{Add, AddAssign, Sub};
 
-use crate::grid::Pos;
+use crate::{
+    error::{Error, Result},
+    grid::{Dims, Grid, Overflow, Pos},
+};
 
 /// `Color` は 24 ビットの RGB カラーを表す.
 #[derive(Clone, Copy, PartialEq)]
@@ -21,6 +24,15 @@ impl Color {
         let b = (self.b as i16 - c.b as i16) as f64;
         f64::sqrt(r * r + g * g + b * b)
     }
+
+    /// RGB色空間での色同士のマンハッタン距離を求める
+    #[inline]
+    pub fn manhattan_distance(&self, c: Color) -> f64 {
+        let r = (self.r as i16 - c.r as i16).abs();
+        let g = (self.g as i16 - c.g as i16).abs();
+        let b = (self.b as i16 - c.b as i16).abs();
+        (r + g + b) as f64
+    }
 }
 
 impl std::fmt::Debug for Color {
@@ -36,6 +48,7 @@ impl std::fmt::Debug for Color {
 
 /// `Movement` はある断片画像を動かして入れ替える向きを表す.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Movement {
     Up,
     Right,
@@ -44,30 +57,37 @@ pub enum Movement {
 }
 
 impl Movement {
-    /// `from` から `to` へ移動させるときの向きを求める.
-    /// 要件: (from.x() == to.x()) ^ (from.y() == to.y())`
-    pub fn between_pos(from: Pos, to: Pos) -> Self {
-        use Movement::*;
-        let from_x = from.x() as i32;
-        let from_y = from.y() as i32;
-        let to_x = to.x() as i32;
-        let to_y = to.y() as i32;
-        if from_x == to_x {
-            match (1 < (from_y - to_y).abs(), from_y < to_y) {
-                (true, true) => Up,
-                (true, false) => Down,
-                (false, true) => Down,
-                (false, false) => Up,
-            }
-        } else if from_y == to_y {
-            match (1 < (from_x - to_x).abs(), from_x < to_x) {
-                (true, true) => Left,
-                (true, false) => Right,
-                (false, true) => Right,
-                (false, false) => Left,
-            }
-        } else {
-            unreachable!()
+    pub const ALL: [Movement; 4] = [
+        Movement::Up,
+        Movement::Right,
+        Movement::Down,
+        Movement::Left,
+    ];
+
+    /// 盤面 `grid` で `from` から隣の `to` へ移動させるときの向きを求める. 盤面の端では反対側に回り込んだ隣も含める.
+    ///
+    /// 回り込みを考えても隣り合っていなければ [`Error::NotAdjacent`] を返す.
+    /// 幅 (高さ) が 2 の盤面では左右 (上下) のどちらでも同じ隣に移るので, 回り込まない方を返す.
+    pub fn between_adjacent(grid: Grid, from: Pos, to: Pos) -> Result<Self> {
+        let find = |overflow| {
+            Self::ALL.into_iter().find(|movement| {
+                let (dx, dy) = movement.delta();
+                grid.offset(from, dx, dy, overflow) == Some(to)
+            })
+        };
+        find(Overflow::Checked)
+            .or_else(|| find(Overflow::Wrap))
+            .filter(|_| from != to)
+            .ok_or(Error::NotAdjacent { from, to })
+    }
+
+    /// この向きに 1 つ動かしたときの `(x, y)` の変化.
+    pub fn delta(self) -> (i32, i32) {
+        match self {
+            Movement::Up => (0, -1),
+            Movement::Right => (1, 0),
+            Movement::Down => (0, 1),
+            Movement::Left => (-1, 0),
         }
     }
 
@@ -97,17 +117,39 @@ impl Movement {
             Movement::Left => Movement::Right,
         }
     }
+
+    /// この向きに動かしたときに, 動かした断片から見て移動先がある辺の向き.
+    pub fn to_dir(self) -> Dir {
+        match self {
+            Movement::Up => Dir::North,
+            Movement::Right => Dir::East,
+            Movement::Down => Dir::South,
+            Movement::Left => Dir::West,
+        }
+    }
+
+    /// 回答で使う 1 文字 `U`, `R`, `D`, `L` で表す.
+    pub fn as_char(self) -> char {
+        match self {
+            Movement::Up => 'U',
+            Movement::Right => 'R',
+            Movement::Down => 'D',
+            Movement::Left => 'L',
+        }
+    }
 }
 
 /// `Operation` は座標 `select` の断片画像を選択してから `movements` の入れ替えを行う操作を表す.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Operation {
     pub select: Pos,
     pub movements: Vec<Movement>,
 }
 
 /// `Rot` はある断片画像を原画像の状態から時計回りに回転させた角度を表す.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Rot {
     R0,
     R90,
@@ -138,6 +180,11 @@ impl Rot {
         }
     }
 
+    /// この回転を打ち消す回転. `rot + rot.inverse()` は `Rot::R0` になる.
+    pub fn inverse(self) -> Self {
+        Self::from_num((4 - self.as_num()) % 4)
+    }
+
     pub fn as_degrees(self) -> f64 {
         match self {
             Rot::R0 => 0.0,
@@ -173,6 +220,27 @@ pub enum Dir {
 }
 
 impl Dir {
+    /// 北から時計回りに 0, 1, 2, 3 で表す.
+    #[inline]
+    pub fn as_num(self) -> u8 {
+        match self {
+            Dir::North => 0,
+            Dir::East => 1,
+            Dir::South => 2,
+            Dir::West => 3,
+        }
+    }
+
+    /// この辺の向きに断片を 1 つ動かす移動.
+    pub fn to_movement(self) -> Movement {
+        match self {
+            Dir::North => Movement::Up,
+            Dir::East => Movement::Right,
+            Dir::South => Movement::Down,
+            Dir::West => Movement::Left,
+        }
+    }
+
     fn r90(self) -> Dir {
         match self {
             Dir::North => Dir::East,
@@ -204,18 +272,19 @@ impl Dir {
     }
 
     /// この辺 `self` に別の断片画像を回転させてその辺 `other` をつなげるとき, 別の断片画像を回転させる角度を計算する.
-    pub fn calc_rot(mut self, mut other: Self) -> Rot {
-        while !matches!(self, Dir::North) {
-            self = self.r90();
-            other = other.r90();
-        }
-        use Rot::*;
-        match other {
-            Dir::North => R180,
-            Dir::East => R90,
-            Dir::South => R0,
-            Dir::West => R270,
-        }
+    ///
+    /// `other` は回転させると `self` の対辺の向きになる.
+    pub fn calc_rot(self, other: Self) -> Rot {
+        self.opposite() - other
+    }
+}
+
+/// `a - b` は, 向き `b` を `a` に重ねるために時計回りに回転させる角度を表す. `b.rotate(a - b)` は `a` になる.
+impl Sub for Dir {
+    type Output = Rot;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Rot::from_num((self.as_num() + 4 - rhs.as_num()) % 4)
     }
 }
 
@@ -225,11 +294,18 @@ pub struct Problem {
     pub select_limit: u8,
     pub select_cost: u16,
     pub swap_cost: u16,
-    pub rows: u8,
-    pub cols: u8,
+    /// 横と縦に並ぶ断片の数
+    pub dims: Dims,
     pub image: Image,
 }
 
+impl Problem {
+    /// 断片を並べる盤面.
+    pub fn grid(&self) -> Grid {
+        self.dims.into()
+    }
+}
+
 pub struct Image {
     pub width: u16,
     pub height: u16,
@@ -244,3 +320,101 @@ impl std::fmt::Debug for Image {
             .finish_non_exhaustive()
     }
 }
+
+#[test]
+fn test_between_adjacent() {
+    for (width, height) in [(1, 3), (2, 2), (3, 5), (16, 2), (16, 16)] {
+        let grid = Grid::new(width, height);
+        for from in grid.all_pos() {
+            for movement in Movement::ALL {
+                let to = grid.looping_move(from, movement);
+                if from == to {
+                    continue;
+                }
+                let found = Movement::between_adjacent(grid, from, to).unwrap();
+                assert_eq!(to, grid.looping_move(from, found), "{:?} -> {:?}", from, to);
+                let ambiguous = match movement {
+                    Movement::Up | Movement::Down => height == 2,
+                    Movement::Right | Movement::Left => width == 2,
+                };
+                if ambiguous {
+                    // 回り込まない方を選ぶ
+                    let (dx, dy) = found.delta();
+                    assert_eq!(Some(to), grid.offset(from, dx, dy, Overflow::Checked));
+                } else {
+                    assert_eq!(movement, found, "{:?} -> {:?}", from, to);
+                }
+            }
+        }
+    }
+
+    // 盤面の継ぎ目をまたぐ移動
+    let grid = Grid::new(5, 4);
+    let cases = [
+        (grid.pos(0, 2), grid.pos(4, 2), Movement::Left),
+        (grid.pos(4, 2), grid.pos(0, 2), Movement::Right),
+        (grid.pos(3, 0), grid.pos(3, 3), Movement::Up),
+        (grid.pos(3, 3), grid.pos(3, 0), Movement::Down),
+    ];
+    for (from, to, expected) in cases {
+        assert_eq!(
+            expected,
+            Movement::between_adjacent(grid, from, to).unwrap()
+        );
+    }
+
+    for (from, to) in [
+        (grid.pos(0, 0), grid.pos(2, 0)),
+        (grid.pos(0, 0), grid.pos(1, 1)),
+        (grid.pos(0, 0), grid.pos(4, 3)),
+        (grid.pos(1, 1), grid.pos(1, 1)),
+    ] {
+        assert!(matches!(
+            Movement::between_adjacent(grid, from, to),
+            Err(Error::NotAdjacent { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_rot_dir_algebra() {
+    let rots = [Rot::R0, Rot::R90, Rot::R180, Rot::R270];
+    let dirs = [Dir::North, Dir::East, Dir::South, Dir::West];
+
+    assert_eq!(Rot::R270, Rot::R90.inverse());
+    assert_eq!(Rot::R180, Rot::R180.inverse());
+    for rot in rots {
+        assert_eq!(Rot::R0, rot + rot.inverse());
+        assert_eq!(rot, rot.inverse().inverse());
+    }
+
+    assert_eq!(Rot::R90, Dir::East - Dir::North);
+    assert_eq!(Rot::R270, Dir::North - Dir::East);
+    for a in dirs {
+        assert_eq!(Rot::R0, a - a);
+        assert_eq!(Rot::R180, a.opposite() - a);
+        for b in dirs {
+            assert_eq!(a, b.rotate(a - b));
+            assert_eq!((a - b).inverse(), b - a);
+        }
+        for rot in rots {
+            assert_eq!(rot, a.rotate(rot) - a);
+        }
+    }
+
+    for movement in Movement::ALL {
+        assert_eq!(movement, movement.to_dir().to_movement());
+        assert_eq!(movement.opposite().to_dir(), movement.to_dir().opposite());
+        assert_eq!(
+            movement.turn_right().to_dir(),
+            movement.to_dir().rotate(Rot::R90)
+        );
+    }
+
+    // 北の辺に別の断片をつなげるときの回転
+    let expected = [Rot::R180, Rot::R90, Rot::R0, Rot::R270];
+    for (other, rot) in dirs.into_iter().zip(expected) {
+        assert_eq!(rot, Dir::North.calc_rot(other));
+        assert_eq!(Dir::South, other.rotate(rot));
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _, Result};
+
+/// `Standing` は提出した回答の評価を表す. 不一致数が少ないほど良く, 同じならコストが小さいほど良い.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Standing {
+    pub mismatch_count: usize,
+    pub cost: u64,
+}
+
+/// `BestSubmission` はこれまでに受理された最も良い回答の評価を記録し, ファイルに保存する.
+#[derive(Debug, Clone)]
+pub struct BestSubmission {
+    path: PathBuf,
+    best: Option<Standing>,
+}
+
+impl BestSubmission {
+    /// `path` から記録を読み込む. ファイルが無ければ記録なしとして始める.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let best = match fs::read_to_string(&path) {
+            Ok(content) => Some(
+                parse_standing(&content)
+                    .with_context(|| format!("failed to parse {}", path.display()))?,
+            ),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        Ok(Self { path, best })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn best(&self) -> Option<Standing> {
+        self.best
+    }
+
+    /// `candidate` の評価の回答を提出すると記録が良くなりうるかどうかを返す.
+    pub fn could_improve(&self, candidate: Standing) -> bool {
+        match self.best {
+            Some(best) => candidate < best,
+            None => true,
+        }
+    }
+
+    /// 受理された回答の評価を記録する. 記録が良くなったときはファイルに保存して `true` を返す.
+    pub fn record(&mut self, standing: Standing) -> Result<bool> {
+        if !self.could_improve(standing) {
+            return Ok(false);
+        }
+        fs::write(
+            &self.path,
+            format!("{} {}\n", standing.mismatch_count, standing.cost),
+        )
+        .with_context(|| format!("failed to write {}", self.path.display()))?;
+        self.best = Some(standing);
+        Ok(true)
+    }
+}
+
+fn parse_standing(content: &str) -> Result<Standing> {
+    let mut tokens = content.split_ascii_whitespace();
+    let mut next = |name: &str| -> Result<u64> {
+        tokens
+            .next()
+            .with_context(|| format!("expected {}", name))?
+            .parse()
+            .with_context(|| format!("failed to parse {}", name))
+    };
+    let mismatch_count = next("mismatch count")? as usize;
+    let cost = next("cost")?;
+    if tokens.next().is_some() {
+        bail!("unexpected trailing tokens");
+    }
+    Ok(Standing {
+        mismatch_count,
+        cost,
+    })
+}
+
+#[test]
+fn test_best_submission() {
+    let path =
+        std::env::temp_dir().join(format!("best_submission_test_{}.txt", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let mut best = BestSubmission::load(&path).unwrap();
+    assert_eq!(None, best.best());
+    let standing = |mismatch_count, cost| Standing {
+        mismatch_count,
+        cost,
+    };
+    assert!(best.could_improve(standing(10, 100)));
+
+    assert!(best.record(standing(2, 50)).unwrap());
+    assert!(!best.record(standing(2, 60)).unwrap());
+    assert!(best.could_improve(standing(0, 200)));
+    assert!(best.record(standing(0, 200)).unwrap());
+    assert!(!best.could_improve(standing(0, 200)));
+
+    let reloaded = BestSubmission::load(&path).unwrap();
+    assert_eq!(Some(standing(0, 200)), reloaded.best());
+
+    fs::write(&path, "hoge").unwrap();
+    assert!(BestSubmission::load(&path).is_err());
+    fs::remove_file(path).unwrap();
+}
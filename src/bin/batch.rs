@@ -0,0 +1,14 @@
+use clap::Parser;
+use procon2021_comp::cli::batch::{run, BatchArgs};
+
+#[derive(Debug, Parser)]
+#[clap(about = "Solve every problem in a directory and report the results")]
+struct Cli {
+    #[clap(flatten)]
+    args: BatchArgs,
+}
+
+fn main() -> anyhow::Result<()> {
+    procon2021_comp::cli::init_tracing();
+    run(Cli::parse().args)
+}
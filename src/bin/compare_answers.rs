@@ -0,0 +1,35 @@
+use std::{fs::File, io::BufReader};
+
+use procon2021_comp::{answer_diff, image, kaitou};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let problem = {
+        let path = args.next().expect("the problem file must be given");
+        let file = File::open(path).expect("failed to open problem file");
+        image::read_problem(BufReader::new(file)).unwrap()
+    };
+    let grid = problem.grid();
+
+    let mut read_answer = || {
+        let path = args.next().expect("two answer files must be given");
+        let answer = std::fs::read_to_string(&path).expect("failed to open answer file");
+        let (_, ops) = kaitou::parse(grid, &answer)
+            .unwrap_or_else(|e| panic!("failed to parse {}: {:?}", path, e));
+        ops
+    };
+    let left = read_answer();
+    let right = read_answer();
+
+    let diff = answer_diff::diff(&problem, &left, &right);
+    println!("cost delta (right - left): {:+}", diff.cost_delta);
+    println!(
+        "common prefix: {} of {} / {} operations",
+        diff.common_prefix,
+        left.len(),
+        right.len()
+    );
+    for answer_diff::SelectionDiff { index, left, right } in diff.differing_selections {
+        println!("selection #{}: {:?} -> {:?}", index, left, right);
+    }
+}
@@ -0,0 +1,186 @@
+//! 競技サーバの代わりに問題画像を配信し, 提出された回答を simulator で採点するサーバ.
+//!
+//! usage: mock_server <solved problem ppm> [port] [seed]
+//!
+//! 環境変数 `TOKEN` を設定すると, `procon-token` ヘッダが一致しない提出を拒否する.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use anyhow::{bail, Context as _, Result};
+use procon2021_comp::{
+    basis::{Problem, Rot},
+    grid::{Pos, VecOnGrid},
+    image, scramble, simulator,
+};
+
+struct Server {
+    problem: Problem,
+    problem_ppm: Vec<u8>,
+    etag: String,
+    truth: VecOnGrid<(Pos, Rot)>,
+    token: Option<String>,
+    request_count: usize,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    token: Option<String>,
+    if_none_match: Option<String>,
+    body: String,
+}
+
+fn main() {
+    procon2021_comp::cli::init_tracing();
+    let mut args = std::env::args().skip(1);
+    let original = {
+        let path = args.next().expect("the solved problem file must be given");
+        let file = File::open(path).expect("failed to open problem file");
+        image::read_problem(BufReader::new(file)).unwrap()
+    };
+    let port: u16 = args
+        .next()
+        .map_or(8080, |x| x.parse().expect("the port must be an integer"));
+    let seed: u64 = args
+        .next()
+        .map_or(0, |x| x.parse().expect("the seed must be an integer"));
+
+    let scramble::Scrambled { problem, truth } = scramble::scramble(&original, seed);
+    let mut problem_ppm = vec![];
+    image::write_problem(&problem, &mut problem_ppm).unwrap();
+
+    let mut server = Server {
+        etag: format!("\"{:016x}\"", fxhash::hash64(&problem_ppm)),
+        problem,
+        problem_ppm,
+        truth,
+        token: std::env::var("TOKEN").ok(),
+        request_count: 0,
+    };
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).expect("failed to bind the port");
+    tracing::info!("listening on http://127.0.0.1:{}", port);
+    for stream in listener.incoming() {
+        let result = stream
+            .context("failed to accept")
+            .and_then(|stream| server.handle(stream));
+        if let Err(e) = result {
+            tracing::warn!(?e, "failed to handle a request");
+        }
+    }
+}
+
+impl Server {
+    fn handle(&mut self, mut stream: TcpStream) -> Result<()> {
+        let request = read_request(&mut stream)?;
+        tracing::info!(method = %request.method, path = %request.path);
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/problem.ppm") => {
+                if request.if_none_match.as_deref() == Some(self.etag.as_str()) {
+                    return respond(&mut stream, "304 Not Modified", &[], b"");
+                }
+                let etag = ("ETag", self.etag.as_str());
+                respond(&mut stream, "200 OK", &[etag], &self.problem_ppm)
+            }
+            // 提出前にトークンを確かめるための要求
+            ("HEAD", "/") => {
+                if self.token.is_some() && self.token != request.token {
+                    return respond(&mut stream, "401 Unauthorized", &[], b"");
+                }
+                respond(&mut stream, "200 OK", &[], b"")
+            }
+            ("POST", "/") => {
+                if self.token.is_some() && self.token != request.token {
+                    return respond(&mut stream, "401 Unauthorized", &[], b"invalid token");
+                }
+                match simulator::simulate(&self.problem, &self.truth, &request.body) {
+                    Ok(score) => {
+                        self.request_count += 1;
+                        tracing::info!(?score, "graded");
+                        let request_id = self.request_count.to_string();
+                        let body = format!(
+                            "ACCEPTED {} {}",
+                            score.pos_mismatch_count, score.rot_mismatch_count
+                        );
+                        respond(
+                            &mut stream,
+                            "200 OK",
+                            &[("procon-request-id", &request_id)],
+                            body.as_bytes(),
+                        )
+                    }
+                    Err(e) => {
+                        let body = format!("{:?}", e);
+                        respond(&mut stream, "400 Bad Request", &[], body.as_bytes())
+                    }
+                }
+            }
+            _ => respond(&mut stream, "404 Not Found", &[], b"not found"),
+        }
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut tokens = line.split_ascii_whitespace();
+    let (method, path) = match (tokens.next(), tokens.next()) {
+        (Some(method), Some(path)) => (method.to_owned(), path.to_owned()),
+        _ => bail!("invalid request line: {}", line),
+    };
+
+    let mut content_length = 0;
+    let mut token = None;
+    let mut if_none_match = None;
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').context("invalid header")?;
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.parse().context("invalid length")?,
+            "procon-token" => token = Some(value.to_owned()),
+            "if-none-match" => if_none_match = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Request {
+        method,
+        path,
+        token,
+        if_none_match,
+        body: String::from_utf8(body).context("the body must be utf-8")?,
+    })
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\n", status)?;
+    for (name, value) in headers {
+        write!(stream, "{}: {}\r\n", name, value)?;
+    }
+    write!(
+        stream,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
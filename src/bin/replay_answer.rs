@@ -0,0 +1,26 @@
+//! 回答ファイルを問題画像に適用し, 断片を並べ直した画像を PNG で書き出す.
+//!
+//! `replay_answer <問題ファイル> <回答ファイル> <出力する PNG>`
+
+use std::{fs::File, io::BufReader};
+
+use procon2021_comp::{image, render, simulator};
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let problem = {
+        let path = args.next().expect("the problem file must be given");
+        let file = File::open(path).expect("failed to open problem file");
+        image::read_problem(BufReader::new(file))?
+    };
+    let answer = {
+        let path = args.next().expect("the answer file must be given");
+        std::fs::read_to_string(path).expect("failed to open answer file")
+    };
+    let output = args.next().expect("the output file must be given");
+
+    let replayed = simulator::render_answer(&problem, &answer)?;
+    render::save_png(&replayed, &output)?;
+    println!("wrote {}", output);
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! 別のスレッドや期限から, 時間のかかる復元や探索をやめさせる.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// `CancellationToken` は `cancel` が呼ばれるか期限を過ぎたら, それを持つ処理にやめるよう伝える.
+///
+/// 複製したものはすべて同じ状態を共有するので, 複製を処理に渡し, 元のものから `cancel` を呼ぶ.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `deadline` を過ぎたらやめさせる.
+    pub fn with_deadline(self, deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    /// 今から `timeout` が経ったらやめさせる.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// この token とその複製を持つすべての処理にやめるよう伝える.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// やめるよう求められたか, 期限を過ぎたか.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || matches!(self.deadline, Some(deadline) if deadline <= Instant::now())
+    }
+}
+
+#[test]
+fn test_cancellation_token() {
+    let token = CancellationToken::new();
+    let cloned = token.clone();
+    assert!(!cloned.is_cancelled());
+    token.cancel();
+    assert!(cloned.is_cancelled());
+
+    assert!(CancellationToken::new()
+        .with_timeout(Duration::ZERO)
+        .is_cancelled());
+    assert!(!CancellationToken::new()
+        .with_timeout(Duration::from_secs(60))
+        .is_cancelled());
+}
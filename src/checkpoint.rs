@@ -0,0 +1,148 @@
+//! pixel_match で復元した断片の配置を保存し, 画像の復元をやり直さずに move_resolve から再開できるようにする.
+//!
+//! 1 行目に幅と高さを書き, 続く各行に復元画像の 1 行分の断片を `<問題画像での座標>:<回転 0-3>` で並べる.
+//! 座標は回答と同じく 2 桁の 16 進数で表す.
+//! `serde` フィーチャーが有効なら, `RecoveredArrangement::to_json` で書き出した `.json` のファイルも読み込める.
+//!
+//! ```text
+//! 3 2
+//! 00:0 21:1 10:3
+//! 11:2 01:0 20:1
+//! ```
+
+use std::{fmt::Write as _, path::Path};
+
+use anyhow::{bail, ensure, Context as _, Result};
+
+#[cfg(feature = "serde")]
+use crate::fragment::arrangement::RecoveredArrangement;
+use crate::{
+    basis::Rot,
+    fragment::Fragment,
+    grid::{Grid, Pos, VecOnGrid},
+};
+
+/// 復元した配置を `path` に保存する.
+pub fn save(path: impl AsRef<Path>, recovered: &VecOnGrid<Fragment>) -> Result<()> {
+    let path = path.as_ref();
+    std::fs::write(path, write_arrangement(recovered))
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// `path` に保存された配置を読み込み, `fragments` を並べ直して復元画像を作る.
+pub fn load(
+    path: impl AsRef<Path>,
+    grid: Grid,
+    fragments: &[Fragment],
+) -> Result<VecOnGrid<Fragment>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    #[cfg(feature = "serde")]
+    if path.extension().is_some_and(|ext| ext == "json") {
+        return RecoveredArrangement::from_json(&text, grid, fragments)
+            .map(RecoveredArrangement::into_recovered)
+            .with_context(|| format!("failed to parse {}", path.display()));
+    }
+    read_arrangement(grid, fragments, &text)
+        .with_context(|| format!("failed to parse {}", path.display()))
+}
+
+fn write_arrangement(recovered: &VecOnGrid<Fragment>) -> String {
+    let grid = recovered.grid;
+    let mut result = format!("{} {}\n", grid.width(), grid.height());
+    for y in 0..grid.height() {
+        let line: Vec<_> = (0..grid.width())
+            .map(|x| {
+                let fragment = &recovered[grid.pos(x, y)];
+                format!(
+                    "{:X}{:X}:{}",
+                    fragment.pos.x(),
+                    fragment.pos.y(),
+                    fragment.rot.as_num()
+                )
+            })
+            .collect();
+        let _ = writeln!(result, "{}", line.join(" "));
+    }
+    result
+}
+
+fn read_arrangement(grid: Grid, fragments: &[Fragment], text: &str) -> Result<VecOnGrid<Fragment>> {
+    let mut lines = text.lines();
+    let size = lines.next().context("the file is empty")?;
+    let expected = format!("{} {}", grid.width(), grid.height());
+    ensure!(
+        size.trim() == expected,
+        "the arrangement is {}, but the problem is {}",
+        size.trim(),
+        expected
+    );
+
+    let mut recovered = Vec::with_capacity(fragments.len());
+    let mut used = VecOnGrid::with_init(grid, false);
+    for (y, line) in lines.enumerate() {
+        let cells: Vec<_> = line.split_ascii_whitespace().collect();
+        ensure!(
+            cells.len() == grid.width() as usize,
+            "line {} must have {} fragments",
+            y + 2,
+            grid.width()
+        );
+        for cell in cells {
+            let (pos, rot) = parse_cell(grid, cell)
+                .with_context(|| format!("failed to parse line {}: '{}'", y + 2, line))?;
+            ensure!(!used[pos], "{} appears more than once", &cell[..2]);
+            used[pos] = true;
+
+            let mut fragment = fragments
+                .iter()
+                .find(|f| f.pos == pos)
+                .context("the fragment is missing from the problem")?
+                .clone();
+            fragment.rotate(rot);
+            recovered.push(fragment);
+        }
+    }
+    ensure!(
+        recovered.len() == fragments.len(),
+        "expected {} rows, but found {}",
+        grid.height(),
+        recovered.len() / grid.width() as usize
+    );
+    Ok(VecOnGrid::from_vec(grid, recovered).unwrap())
+}
+
+fn parse_cell(grid: Grid, cell: &str) -> Result<(Pos, Rot)> {
+    let digits: Vec<_> = cell.chars().map(|c| c.to_digit(16)).collect();
+    let (x, y, rot) = match digits.as_slice() {
+        &[Some(x), Some(y), _, Some(rot @ 0..=3)] if cell.as_bytes()[2] == b':' => (x, y, rot),
+        _ => bail!("expected XY:R, but found {}", cell),
+    };
+    let (x, y) = (x as u8, y as u8);
+    ensure!(
+        x < grid.width() && y < grid.height(),
+        "{} is out of the grid",
+        cell
+    );
+    Ok((grid.pos(x, y), Rot::from_num(rot as u8)))
+}
+
+#[test]
+fn test_checkpoint() {
+    let problem = include_bytes!("../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+
+    let text = "2 2\n11:0 01:3\n10:1 00:2\n";
+    let recovered = read_arrangement(grid, &fragments, text).unwrap();
+    assert_eq!(grid.pos(0, 1), recovered[grid.pos(1, 0)].pos);
+    assert_eq!(Rot::R270, recovered[grid.pos(1, 0)].rot);
+    assert_eq!(text, write_arrangement(&recovered));
+
+    assert!(read_arrangement(grid, &fragments, "3 2\n11:0 01:3\n10:1 00:2\n").is_err());
+    assert!(read_arrangement(grid, &fragments, "2 2\n11:0 11:3\n10:1 00:2\n").is_err());
+    assert!(read_arrangement(grid, &fragments, "2 2\n11:0 01:4\n10:1 00:2\n").is_err());
+    assert!(read_arrangement(grid, &fragments, "2 2\n11:0 01:3\n").is_err());
+}
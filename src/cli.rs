@@ -0,0 +1,668 @@
+//! 各バイナリのコマンドライン引数と, それぞれの処理の本体.
+
+pub mod batch;
+#[cfg(feature = "net")]
+pub mod confirm;
+pub mod edge_scores;
+pub mod make_problem;
+pub mod offline;
+#[cfg(feature = "net")]
+pub mod online;
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Context as _, Result};
+
+#[cfg(feature = "ga")]
+use crate::pixel_match::GeneticParam;
+use crate::{
+    basis::{Problem, Rot},
+    checkpoint,
+    config::Config,
+    fragment::{arrangement::RecoveredArrangement, Fragment},
+    grid::{Dims, Grid, VecOnGrid},
+    image, memory,
+    move_resolve::{cost_model::CostWeighting, BeamConfig, PhaseBudgets},
+    pixel_match::{
+        DoubleSideScoring, GuiOptions, Metric, Resolver, Strategy, WindowSize, DEFAULT_SOFT_PENALTY,
+    },
+    render,
+};
+
+/// 環境変数 `RUST_LOG` で出力を絞り込める tracing の subscriber を設定する. 指定がなければ info 以上を出力する.
+///
+/// 標準出力は問題や回答の出力に使うことがあるので, ログは標準エラー出力に書く.
+pub fn init_tracing() {
+    use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// ビームサーチや辺の照合で使う rayon のスレッドを `threads` 個にする. 最初の 1 回だけ呼べる.
+///
+/// 省略すると, GUI のスレッドや OS が固まらないよう CPU のコアを 1 つ残して使う.
+pub fn init_thread_pool(threads: Option<usize>) -> Result<()> {
+    let threads = threads.unwrap_or_else(default_threads);
+    ensure!(0 < threads, "the number of threads must be positive");
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("worker-{}", i))
+        .build_global()
+        .context("failed to build the thread pool")?;
+    tracing::debug!(threads, "built the thread pool");
+    Ok(())
+}
+
+fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get().saturating_sub(1).max(1))
+}
+
+/// 問題を解いている間に RSS を測る間隔
+const RSS_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 解いている間に測ったメモリの使い方を知らせる. 最大値はプロセス全体のもの.
+fn log_memory_usage(usage: memory::RssUsage) {
+    match usage.peak {
+        Some(peak) => tracing::info!(
+            process_peak_rss_mib = memory::to_mib(peak),
+            rss_growth_mib = usage.growth.map(memory::to_mib),
+            "memory usage"
+        ),
+        None => tracing::debug!("could not measure the memory usage"),
+    }
+}
+
+/// 問題を解くコマンドに共通する引数.
+///
+/// 省略した値は `--config` の設定ファイルから補う.
+#[derive(Debug, Clone, clap::Args)]
+pub struct SolveArgs {
+    #[clap(long, env = "PROCON_CONFIG", help = "Load settings from the TOML file")]
+    pub config: Option<PathBuf>,
+
+    #[clap(long, help = "Load pixel_match hints from the file")]
+    pub hints: Option<PathBuf>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Skip pixel_match and use the arrangement saved in the file (.json needs the serde feature)"
+    )]
+    pub resume_from: Option<PathBuf>,
+
+    #[clap(
+        long,
+        env = "RECOVERED_IMAGE",
+        value_name = "FILE",
+        help = "Save the recovered image as PNG to the file [default: recovered-<epoch>.png]"
+    )]
+    pub recovered_image: Option<PathBuf>,
+
+    #[clap(
+        long,
+        env = "TIME_BUDGET",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        help = "Stop taking answer candidates after this many seconds of searching"
+    )]
+    pub time_budget: Option<f64>,
+
+    #[clap(
+        long,
+        env = "MATCH_BUDGET",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        help = "Stop recovering the image after this many seconds, cutting annealing and the genetic strategy short. The time left over goes to move_resolve phase1 and phase2"
+    )]
+    pub match_budget: Option<f64>,
+
+    #[clap(
+        long,
+        env = "SEARCH_BUDGET",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        help = "Stop move_resolve phase1 and phase2 after this many seconds once an answer is found. The time left over goes to phase3"
+    )]
+    pub search_budget: Option<f64>,
+
+    #[clap(
+        long,
+        env = "COMPLETION_BUDGET",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        help = "Stop move_resolve phase3 after this many seconds past the search budget once an answer is found"
+    )]
+    pub completion_budget: Option<f64>,
+
+    #[clap(
+        long,
+        env = "SUBMIT_MARGIN",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        help = "Stop taking answer candidates this many seconds before the time budget runs out, to leave time to submit [default: 0]"
+    )]
+    pub submit_margin: Option<f64>,
+
+    #[clap(
+        long,
+        env = "PHASE1_BEAM_WIDTH",
+        help = "Beam width of move_resolve phase1 [default: 200]"
+    )]
+    pub phase1_beam_width: Option<usize>,
+
+    #[clap(
+        long,
+        env = "PHASE3_BEAM_WIDTH",
+        help = "Beam width of move_resolve phase3 [default: 50]"
+    )]
+    pub phase3_beam_width: Option<usize>,
+
+    #[clap(
+        long,
+        env = "DIVERSITY_ROWS",
+        value_name = "ROWS",
+        help = "Also bucket beam states by the top ROWS unsolved rows to keep them diverse [default: 0, disabled]"
+    )]
+    pub diversity_rows: Option<u8>,
+
+    #[clap(
+        long,
+        env = "COST_WEIGHTING",
+        help = "How to weigh select and swap costs in move_resolve: as-is, or fewer-selects to count each select twice [default: as-is]"
+    )]
+    pub cost_weighting: Option<CostWeighting>,
+
+    #[clap(
+        long,
+        env = "THREADS",
+        value_name = "N",
+        help = "Number of worker threads for beam search and edge matching [default: all but one CPU core, to keep the GUI and the OS responsive]"
+    )]
+    pub threads: Option<usize>,
+
+    #[clap(
+        long,
+        env = "METRIC",
+        help = "Color distance to match edges with: euclidean or manhattan [default: euclidean]"
+    )]
+    pub metric: Option<Metric>,
+
+    #[clap(
+        long,
+        env = "STRATEGY",
+        help = "How to assemble fragments: shaker, borders-first or genetic (needs the ga feature) [default: shaker]"
+    )]
+    pub strategy: Option<Strategy>,
+
+    #[clap(
+        long,
+        env = "DOUBLE_SIDE",
+        help = "How to combine scores of two edges when filling a cell from two neighbors: max, or a weight of the worse edge from 0 to 1 [default: max]"
+    )]
+    pub double_side: Option<DoubleSideScoring>,
+
+    #[clap(
+        long,
+        env = "ANNEAL_BUDGET",
+        value_name = "SECONDS",
+        value_parser = parse_seconds,
+        help = "Refine the assembled image by simulated annealing for this many seconds"
+    )]
+    pub anneal_budget: Option<f64>,
+
+    #[cfg(feature = "ga")]
+    #[clap(
+        long,
+        env = "GA_POPULATION",
+        help = "Population of the genetic strategy [default: 50]"
+    )]
+    pub ga_population: Option<usize>,
+
+    #[cfg(feature = "ga")]
+    #[clap(
+        long,
+        env = "GA_GENERATIONS",
+        help = "Number of generations of the genetic strategy [default: 200]"
+    )]
+    pub ga_generations: Option<usize>,
+
+    #[clap(
+        long,
+        env = "SOFT_PENALTY",
+        help = "Multiply scores of pairs soft-blocked in the GUI (Alt+Space) by this [default: 1.5]"
+    )]
+    pub soft_penalty: Option<f64>,
+
+    #[clap(
+        long,
+        help = "Assemble fragments without rotating them, for problems whose fragments are not rotated"
+    )]
+    pub fixed_rotation: bool,
+
+    #[clap(
+        long,
+        env = "REVIEW_THRESHOLD",
+        value_name = "K",
+        help = "Open the GUI only when a seam is worse than the mean by more than K standard deviations. without the GUI, just log such seams"
+    )]
+    pub review_threshold: Option<f64>,
+
+    #[clap(
+        long,
+        env = "WINDOW_SIZE",
+        value_name = "WxH",
+        help = "Initial size of the GUI window [default: the size when the GUI was last closed, or 800x800]"
+    )]
+    pub window_size: Option<WindowSize>,
+
+    #[clap(
+        long,
+        env = "GUI_SCALE",
+        help = "Scale texts in the GUI by this, on top of the HiDPI scale factor [default: 1.0]"
+    )]
+    pub gui_scale: Option<f64>,
+
+    #[clap(
+        long,
+        help = "Use the automatically recovered image without opening the GUI"
+    )]
+    pub no_gui: bool,
+
+    #[clap(flatten)]
+    pub overrides: ProblemOverrides,
+}
+
+/// 問題のヘッダの値を置き換える引数.
+///
+/// ヘッダが間違っているときや, 選択回数などを変えて試したいときに使う.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct ProblemOverrides {
+    #[clap(
+        long,
+        help = "Override the number of fragments in a row (the width) given in the problem header"
+    )]
+    pub width: Option<u8>,
+
+    #[clap(
+        long,
+        help = "Override the number of fragments in a column (the height) given in the problem header"
+    )]
+    pub height: Option<u8>,
+
+    #[clap(
+        long,
+        help = "Override the maximum number of selections given in the problem header"
+    )]
+    pub select_limit: Option<u8>,
+
+    #[clap(
+        long,
+        help = "Override the cost rate of a selection given in the problem header"
+    )]
+    pub select_cost: Option<u16>,
+
+    #[clap(
+        long,
+        help = "Override the cost rate of a swap given in the problem header"
+    )]
+    pub swap_cost: Option<u16>,
+}
+
+impl ProblemOverrides {
+    /// 指定された値で `problem` のヘッダの値を置き換える. 画像を置き換えた数の正方形の断片に分割できなければエラーを返す.
+    pub fn apply(&self, problem: &mut Problem) -> Result<()> {
+        let dims = Dims {
+            width: self.width.unwrap_or(problem.dims.width),
+            height: self.height.unwrap_or(problem.dims.height),
+        };
+        image::validate_split((problem.image.width, problem.image.height), dims)
+            .context("cannot override the split count")?;
+        let select_limit = self.select_limit.unwrap_or(problem.select_limit);
+        ensure!(0 < select_limit, "the select limit must be positive");
+
+        let header = |p: &Problem| (p.dims, p.select_limit, p.select_cost, p.swap_cost);
+        let before = header(problem);
+        problem.dims = dims;
+        problem.select_limit = select_limit;
+        problem.select_cost = self.select_cost.unwrap_or(problem.select_cost);
+        problem.swap_cost = self.swap_cost.unwrap_or(problem.swap_cost);
+        if before != header(problem) {
+            tracing::warn!(?before, after = ?header(problem), "overrode the problem header");
+        }
+        Ok(())
+    }
+}
+
+/// 秒数の引数を読む. `Duration` にできない負の数や NaN, 大きすぎる数は受け付けない.
+fn parse_seconds(s: &str) -> Result<f64, String> {
+    let secs: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    Duration::try_from_secs_f64(secs)
+        .map(|_| secs)
+        .map_err(|_| format!("{} is not a non-negative number of seconds", s))
+}
+
+/// GUI を閉じたときのウィンドウの大きさを保存しておくファイル.
+const WINDOW_GEOMETRY_PATH: &str = "gui-window.txt";
+
+impl SolveArgs {
+    /// 設定ファイルを読み込み, 引数で指定されなかった値をそこから補う.
+    pub fn load_config(&mut self) -> Result<Config> {
+        let config = Config::load(self.config.as_deref())?;
+        self.merge(&config);
+        Ok(config)
+    }
+
+    fn merge(&mut self, config: &Config) {
+        self.hints = self.hints.take().or_else(|| config.gui.hints.clone());
+        self.recovered_image = self
+            .recovered_image
+            .take()
+            .or_else(|| config.gui.recovered_image.clone());
+        self.time_budget = self.time_budget.or(config.search.time_budget);
+        self.match_budget = self.match_budget.or(config.budget.matching);
+        self.search_budget = self.search_budget.or(config.budget.search);
+        self.completion_budget = self.completion_budget.or(config.budget.completion);
+        self.submit_margin = self.submit_margin.or(config.budget.submit_margin);
+        self.phase1_beam_width = self.phase1_beam_width.or(config.search.phase1_beam_width);
+        self.phase3_beam_width = self.phase3_beam_width.or(config.search.phase3_beam_width);
+        self.diversity_rows = self.diversity_rows.or(config.search.diversity_rows);
+        self.cost_weighting = self.cost_weighting.or(config.search.cost_weighting);
+        self.threads = self.threads.or(config.search.threads);
+        self.metric = self.metric.or(config.matching.metric);
+        self.strategy = self.strategy.or(config.matching.strategy);
+        self.double_side = self.double_side.or(config.matching.double_side);
+        self.anneal_budget = self.anneal_budget.or(config.matching.anneal_budget);
+        #[cfg(feature = "ga")]
+        {
+            self.ga_population = self.ga_population.or(config.matching.ga_population);
+            self.ga_generations = self.ga_generations.or(config.matching.ga_generations);
+        }
+        self.soft_penalty = self.soft_penalty.or(config.matching.soft_penalty);
+        self.fixed_rotation |= config.matching.fixed_rotation == Some(true);
+        self.review_threshold = self.review_threshold.or(config.gui.review_threshold);
+        self.window_size = self.window_size.or(config.gui.window_size);
+        self.gui_scale = self.gui_scale.or(config.gui.scale);
+        self.no_gui |= config.gui.enabled == Some(false);
+    }
+
+    pub fn beam_config(&self) -> BeamConfig {
+        let default = BeamConfig::default();
+        BeamConfig {
+            phase1: self.phase1_beam_width.unwrap_or(default.phase1),
+            phase3: self.phase3_beam_width.unwrap_or(default.phase3),
+            diversity_rows: self.diversity_rows.unwrap_or(default.diversity_rows),
+            weighting: self.cost_weighting.unwrap_or(default.weighting),
+        }
+    }
+
+    /// ヒントのファイルが指定されていれば読み込んだ `Resolver` を作る.
+    pub fn resolver(&self, fragments: Vec<Fragment>, grid: Grid) -> Result<Resolver> {
+        let mut resolver = Resolver::new(fragments, grid);
+        resolver.set_metric(self.metric.unwrap_or_default());
+        resolver.set_strategy(self.strategy.unwrap_or_default());
+        resolver.set_double_side_scoring(self.double_side.unwrap_or_default());
+        resolver.set_anneal_budget(self.anneal_budget.map(Duration::from_secs_f64));
+        resolver.set_match_budget(self.match_budget.map(Duration::from_secs_f64));
+        #[cfg(feature = "ga")]
+        {
+            let default = GeneticParam::default();
+            resolver.set_genetic_param(GeneticParam {
+                population: self.ga_population.unwrap_or(default.population),
+                generations: self.ga_generations.unwrap_or(default.generations),
+            });
+        }
+        resolver.set_soft_penalty(self.soft_penalty.unwrap_or(DEFAULT_SOFT_PENALTY));
+        resolver.set_fixed_rotation(self.fixed_rotation);
+        resolver.set_gui_options(self.gui_options());
+        if let Some(path) = &self.hints {
+            resolver.load_hints(path)?;
+        }
+        Ok(resolver)
+    }
+
+    /// 指定が無ければ, 前回 GUI を閉じたときのウィンドウの大きさで開く.
+    fn gui_options(&self) -> GuiOptions {
+        let default = GuiOptions::default();
+        let last_size = || {
+            let text = std::fs::read_to_string(WINDOW_GEOMETRY_PATH).ok()?;
+            match text.trim().parse() {
+                Ok(size) => Some(size),
+                Err(e) => {
+                    tracing::warn!(?e, path = WINDOW_GEOMETRY_PATH, "ignored the window size");
+                    None
+                }
+            }
+        };
+        GuiOptions {
+            window_size: self
+                .window_size
+                .or_else(last_size)
+                .unwrap_or(default.window_size),
+            scale: self.gui_scale.unwrap_or(default.scale),
+        }
+    }
+
+    /// GUI を開かないなら, 何度復元しても同じ配置になる.
+    pub fn is_interactive(&self) -> bool {
+        !self.no_gui && self.resume_from.is_none()
+    }
+
+    /// 画像を復元し, その配置を `arrangement-{epoch}.txt` に保存する. `resume_from` があれば復元せずにそれを読み込む.
+    ///
+    /// GUI を開いたときは, そこで与えたヒントを `hints-{epoch}.txt` に保存する.
+    pub fn recover(&self, resolver: &mut Resolver, epoch: u64) -> Result<RecoveredArrangement> {
+        if let Some(path) = &self.resume_from {
+            let recovered = checkpoint::load(path, resolver.grid(), resolver.fragments())?;
+            let arrangement = RecoveredArrangement::new(recovered);
+            // 回転させない問題なら回答の回転情報はすべて 0 になるので, 回転させた配置は取り違えている
+            ensure!(
+                !self.fixed_rotation || arrangement.rotations().iter().all(|&rot| rot == Rot::R0),
+                "{} rotates some fragments, but the rotation is fixed",
+                path.display()
+            );
+            tracing::info!(path = %path.display(), "resumed from the arrangement");
+            return Ok(arrangement);
+        }
+
+        let recovered = if self.no_gui {
+            let recovered = resolver.resolve_without_gui()?;
+            if let Some(k) = self.review_threshold {
+                resolver.check_seams(&recovered).warn_suspicious(k);
+            }
+            recovered
+        } else {
+            let recovered = match self.review_threshold {
+                Some(k) => resolver.resolve_if_suspicious(k)?,
+                None => resolver.resolve()?,
+            };
+            let path = format!("hints-{}.txt", epoch);
+            match resolver.save_hints(&path) {
+                Ok(()) => tracing::info!(%path, "saved hints"),
+                Err(e) => tracing::warn!(?e, "failed to save hints"),
+            }
+            let size = resolver.gui_options().window_size;
+            if let Err(e) = std::fs::write(WINDOW_GEOMETRY_PATH, format!("{}\n", size)) {
+                tracing::warn!(?e, "failed to save the window size");
+            }
+            recovered
+        };
+
+        let path = format!("arrangement-{}.txt", epoch);
+        match checkpoint::save(&path, recovered.recovered()) {
+            Ok(()) => tracing::info!(%path, "saved the arrangement"),
+            Err(e) => tracing::warn!(?e, "failed to save the arrangement"),
+        }
+        // 手で直して --resume-from で読み込めるように, JSON でも残す
+        #[cfg(feature = "serde")]
+        {
+            let path = format!("arrangement-{}.json", epoch);
+            match std::fs::write(&path, recovered.to_json()) {
+                Ok(()) => tracing::info!(%path, "saved the arrangement as JSON"),
+                Err(e) => tracing::warn!(?e, "failed to save the arrangement as JSON"),
+            }
+        }
+        // GUI を開かなかったときも, 復元した画像を後から目で確かめられるように残す
+        let path = match &self.recovered_image {
+            Some(path) => path.display().to_string(),
+            None => format!("recovered-{}.png", epoch),
+        };
+        let fragments = recovered.recovered().iter().cloned().map(Some).collect();
+        let fragments = VecOnGrid::from_vec(recovered.grid(), fragments).unwrap();
+        match render::save_png(&render::compose(&fragments), &path) {
+            Ok(()) => tracing::info!(%path, "saved the recovered image"),
+            Err(e) => tracing::warn!(?e, "failed to save the recovered image"),
+        }
+        Ok(recovered)
+    }
+
+    /// `move_resolve` の段階ごとの時間. 画像の復元に `matching` かかったので, `match_budget` の残りを `phase1` と `phase2` に回す.
+    pub fn phase_budgets(&self, matching: Duration) -> PhaseBudgets {
+        let left_over = self
+            .match_budget
+            .map(|secs| Duration::from_secs_f64(secs).saturating_sub(matching))
+            .unwrap_or_default();
+        PhaseBudgets {
+            search: self
+                .search_budget
+                .map(|secs| Duration::from_secs_f64(secs) + left_over),
+            completion: self.completion_budget.map(Duration::from_secs_f64),
+        }
+    }
+
+    /// `time_budget` から `submit_margin` を除いた時間が過ぎたら, その時点で探索中の候補を最後に打ち切る.
+    pub fn limit_time<I: Iterator>(&self, candidates: I) -> impl Iterator<Item = I::Item> {
+        let margin = Duration::from_secs_f64(self.submit_margin.unwrap_or(0.0));
+        let deadline = self
+            .time_budget
+            .map(|secs| Instant::now() + Duration::from_secs_f64(secs).saturating_sub(margin));
+        let mut expired = false;
+        candidates.take_while(move |_| {
+            let take = !expired;
+            expired = matches!(deadline, Some(deadline) if deadline <= Instant::now());
+            take
+        })
+    }
+}
+
+#[test]
+fn test_merge_config() {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        solve: SolveArgs,
+    }
+
+    let config: Config = "[search]\nphase1_beam_width = 10\nphase3_beam_width = 20\ndiversity_rows = 2\ncost_weighting = \"fewer-selects\"\nthreads = 3\n[budget]\nmatching = 5\nsearch = 10\n[gui]\nenabled = false\nrecovered_image = \"recovered.png\"\nscale = 2.0\nwindow_size = \"640x480\""
+        .parse()
+        .unwrap();
+    let mut solve = Cli::parse_from([
+        "test",
+        "--phase1-beam-width",
+        "30",
+        "--window-size",
+        "1024x768",
+        "--completion-budget",
+        "4",
+    ])
+    .solve;
+    solve.merge(&config);
+    assert_eq!(
+        BeamConfig {
+            phase1: 30,
+            phase3: 20,
+            diversity_rows: 2,
+            weighting: CostWeighting::FewerSelects,
+        },
+        solve.beam_config()
+    );
+    assert_eq!(Some(3), solve.threads);
+    // 画像の復元で余った 2 秒は phase1 と phase2 に回す
+    assert_eq!(
+        PhaseBudgets {
+            search: Some(Duration::from_secs(12)),
+            completion: Some(Duration::from_secs(4)),
+        },
+        solve.phase_budgets(Duration::from_secs(3))
+    );
+    assert_eq!(
+        Some(Duration::from_secs(10)),
+        solve.phase_budgets(Duration::from_secs(8)).search
+    );
+    assert!(solve.no_gui);
+    assert_eq!(Some(PathBuf::from("recovered.png")), solve.recovered_image);
+    assert_eq!(
+        GuiOptions {
+            window_size: "1024x768".parse().unwrap(),
+            scale: 2.0
+        },
+        solve.gui_options()
+    );
+}
+
+#[test]
+fn test_parse_seconds() {
+    assert_eq!(Ok(1.5), parse_seconds("1.5"));
+    assert_eq!(Ok(0.0), parse_seconds("0"));
+    for invalid in ["-1", "NaN", "inf", "1e30", "one"] {
+        assert!(parse_seconds(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn test_problem_overrides() {
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        overrides: ProblemOverrides,
+    }
+
+    let read = || {
+        let problem = include_bytes!("../test_cases/01_q.ppm");
+        image::read_problem(problem.as_ref()).unwrap()
+    };
+    let apply = |args: &[&str]| {
+        let mut problem = read();
+        Cli::parse_from(std::iter::once("test").chain(args.iter().copied()))
+            .overrides
+            .apply(&mut problem)
+            .map(|()| problem)
+    };
+
+    let problem = apply(&["--select-limit", "4", "--swap-cost", "5"]).unwrap();
+    assert_eq!(
+        (2, 2, 4, 3, 5),
+        (
+            problem.dims.width,
+            problem.dims.height,
+            problem.select_limit,
+            problem.select_cost,
+            problem.swap_cost
+        )
+    );
+    let problem = apply(&["--width", "4", "--height", "4"]).unwrap();
+    assert_eq!(
+        (4, 4, 1),
+        (
+            problem.dims.width,
+            problem.dims.height,
+            problem.select_limit
+        )
+    );
+
+    // 32x32 の画像は 3 つの正方形の断片には分割できない
+    assert!(apply(&["--width", "3"]).is_err());
+    assert!(apply(&["--height", "4"]).is_err());
+    assert!(apply(&["--select-limit", "0"]).is_err());
+}
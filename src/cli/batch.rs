@@ -0,0 +1,371 @@
+//! ディレクトリ内の問題をすべて GUI なしで解き, コストや不一致数, 所要時間を一覧にする.
+//!
+//! `<name>.ppm` と同じディレクトリに `make-problem --truth` で作った正解の回答 `<name>.truth.txt` があれば,
+//! それを正解として不一致数を数える. 無ければ復元した画像を正解とみなすので, 不一致数は回転の誤りしか表さない.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{ensure, Context as _, Result};
+
+use super::SolveArgs;
+use crate::{
+    cancel::CancellationToken,
+    fragment,
+    grid::Dims,
+    image, kaitou,
+    memory::{self, RssSampler, RssUsage},
+    move_resolve::{self, ResolveParam},
+    simulator::{self, Score},
+};
+
+/// 結果の出力形式.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+/// `batch` の引数.
+#[derive(Debug, Clone, clap::Args)]
+pub struct BatchArgs {
+    #[clap(help = "The directory containing problem files (*.ppm)")]
+    pub dir: PathBuf,
+
+    #[clap(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Write the report to the file instead of stdout"
+    )]
+    pub output: Option<PathBuf>,
+
+    #[clap(long, value_enum, default_value = "csv", help = "The report format")]
+    pub format: ReportFormat,
+
+    // GUI は開かず, 時間の指定はどれも問題ごとに測る
+    #[clap(flatten)]
+    pub solve: SolveArgs,
+}
+
+/// 1 つの問題を解いた結果.
+#[derive(Debug, Clone)]
+pub struct CaseReport {
+    pub name: String,
+    /// 問題のヘッダにある分割数. 読み込めなかったときは 0
+    pub dims: Dims,
+    /// 正解のファイルを使って採点したか
+    pub has_truth: bool,
+    /// move_resolve が出した回答候補の数
+    pub candidates: usize,
+    /// 最後に得られた, 最もコストの小さい回答の評価
+    pub score: Option<Score>,
+    pub pixel_match_secs: f64,
+    pub move_resolve_secs: f64,
+    /// 解いている間に測った RSS. 最大値はプロセス全体のもので, 前の問題で確保したままのメモリも含む
+    pub memory: RssUsage,
+    pub error: Option<String>,
+}
+
+/// `dir` の問題を名前順にすべて解き, 結果を書き出す.
+pub fn run(mut args: BatchArgs) -> Result<()> {
+    ensure!(
+        args.solve.resume_from.is_none(),
+        "batch solves each problem from scratch, so --resume-from cannot be used"
+    );
+    args.solve.load_config()?;
+    super::init_thread_pool(args.solve.threads)?;
+    let mut paths: Vec<_> = std::fs::read_dir(&args.dir)
+        .with_context(|| format!("failed to read {}", args.dir.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    paths.retain(|path| matches!(path.extension(), Some(ext) if ext == "ppm"));
+    paths.sort();
+
+    let reports: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            let report = solve_case(path, &args);
+            tracing::info!(?report, "solved a case");
+            report
+        })
+        .collect();
+
+    let report = match args.format {
+        ReportFormat::Csv => to_csv(&reports),
+        ReportFormat::Json => to_json(&reports),
+    };
+    match &args.output {
+        Some(path) => std::fs::write(path, report)
+            .with_context(|| format!("failed to write {}", path.display())),
+        None => {
+            print!("{}", report);
+            Ok(())
+        }
+    }
+}
+
+fn solve_case(path: &Path, args: &BatchArgs) -> CaseReport {
+    let mut report = CaseReport {
+        name: path
+            .file_stem()
+            .map_or(String::new(), |s| s.to_string_lossy().into_owned()),
+        dims: Dims {
+            width: 0,
+            height: 0,
+        },
+        has_truth: false,
+        candidates: 0,
+        score: None,
+        pixel_match_secs: 0.0,
+        move_resolve_secs: 0.0,
+        memory: RssUsage::default(),
+        error: None,
+    };
+    let _span = tracing::info_span!("case", name = %report.name).entered();
+    let sampler = RssSampler::start(super::RSS_SAMPLE_INTERVAL);
+    if let Err(e) = solve_case_inner(path, args, &mut report) {
+        report.error = Some(format!("{:#}", e));
+    }
+    report.memory = sampler.finish();
+    report
+}
+
+fn solve_case_inner(path: &Path, args: &BatchArgs, report: &mut CaseReport) -> Result<()> {
+    let mut problem = {
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        image::read_problem(BufReader::new(file))?
+    };
+    let solve = &args.solve;
+    solve.overrides.apply(&mut problem)?;
+    report.dims = problem.dims;
+
+    let grid = problem.grid();
+    let fragments = fragment::Fragment::new_all(&problem)?;
+
+    let truth_path = path.with_extension("truth.txt");
+    let truth = if truth_path.exists() {
+        report.has_truth = true;
+        let answer = std::fs::read_to_string(&truth_path)
+            .with_context(|| format!("failed to read {}", truth_path.display()))?;
+        let truth = simulator::truth_from_answer(grid, &answer)
+            .with_context(|| format!("failed to parse {}", truth_path.display()))?;
+        Some(truth)
+    } else {
+        None
+    };
+
+    let start = Instant::now();
+    let arrangement = solve.resolver(fragments, grid)?.resolve_without_gui()?;
+    report.pixel_match_secs = start.elapsed().as_secs_f64();
+
+    let (movements, rots) = arrangement.movements_and_rotations();
+    let truth = truth.unwrap_or_else(|| arrangement.truth().clone());
+
+    let start = Instant::now();
+    let candidates = move_resolve::resolve_budgeted(
+        grid,
+        &movements,
+        ResolveParam {
+            select_limit: problem.select_limit,
+            swap_cost: problem.swap_cost,
+            select_cost: problem.select_cost,
+        },
+        solve.beam_config(),
+        solve.phase_budgets(Duration::from_secs_f64(report.pixel_match_secs)),
+        CancellationToken::new(),
+    )?;
+    let mut best = None;
+    for (ops, _) in solve.limit_time(candidates) {
+        report.candidates += 1;
+        best = Some(ops);
+    }
+    report.move_resolve_secs = start.elapsed().as_secs_f64();
+
+    let ops = best.context("move_resolve found no answer")?;
+    let answer = kaitou::ans(&ops, &rots);
+    report.score = Some(simulator::simulate(&problem, &truth, &answer)?);
+    Ok(())
+}
+
+const CSV_HEADER: &str = concat!(
+    "name,width,height,truth,candidates,",
+    "cost,select_count,swap_count,pos_mismatch,rot_mismatch,",
+    "pixel_match_secs,move_resolve_secs,process_peak_rss_mib,rss_growth_mib,error",
+);
+
+fn to_csv(reports: &[CaseReport]) -> String {
+    let mut result = format!("{}\n", CSV_HEADER);
+    for r in reports {
+        let score = score_fields(r.score).map(|x| x.map_or(String::new(), |x| x.to_string()));
+        let _ = writeln!(
+            result,
+            "{},{},{},{},{},{},{:.3},{:.3},{},{},{}",
+            csv_escape(&r.name),
+            r.dims.width,
+            r.dims.height,
+            r.has_truth,
+            r.candidates,
+            score.join(","),
+            r.pixel_match_secs,
+            r.move_resolve_secs,
+            mib(r.memory.peak).unwrap_or_default(),
+            mib(r.memory.growth).unwrap_or_default(),
+            csv_escape(r.error.as_deref().unwrap_or(""))
+        );
+    }
+    result
+}
+
+fn to_json(reports: &[CaseReport]) -> String {
+    let names = [
+        "cost",
+        "select_count",
+        "swap_count",
+        "pos_mismatch",
+        "rot_mismatch",
+    ];
+    let cases: Vec<_> = reports
+        .iter()
+        .map(|r| {
+            let score: Vec<_> = names
+                .iter()
+                .zip(score_fields(r.score))
+                .map(|(name, value)| {
+                    let value = value.map_or("null".to_owned(), |x| x.to_string());
+                    format!("\"{}\":{}", name, value)
+                })
+                .collect();
+            format!(
+                concat!(
+                    "{{\"name\":{},\"width\":{},\"height\":{},\"truth\":{},\"candidates\":{},{},",
+                    "\"pixel_match_secs\":{:.3},\"move_resolve_secs\":{:.3},",
+                    "\"process_peak_rss_mib\":{},\"rss_growth_mib\":{},\"error\":{}}}",
+                ),
+                json_string(&r.name),
+                r.dims.width,
+                r.dims.height,
+                r.has_truth,
+                r.candidates,
+                score.join(","),
+                r.pixel_match_secs,
+                r.move_resolve_secs,
+                mib(r.memory.peak).unwrap_or_else(|| "null".to_owned()),
+                mib(r.memory.growth).unwrap_or_else(|| "null".to_owned()),
+                r.error.as_deref().map_or("null".to_owned(), json_string)
+            )
+        })
+        .collect();
+    format!("[\n  {}\n]\n", cases.join(",\n  "))
+}
+
+fn mib(bytes: Option<u64>) -> Option<String> {
+    bytes.map(|bytes| format!("{:.1}", memory::to_mib(bytes)))
+}
+
+fn score_fields(score: Option<Score>) -> [Option<u64>; 5] {
+    match score {
+        Some(s) => [
+            Some(s.cost),
+            Some(s.select_count as u64),
+            Some(s.swap_count as u64),
+            Some(s.pos_mismatch_count as u64),
+            Some(s.rot_mismatch_count as u64),
+        ],
+        None => [None; 5],
+    }
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(result, "\\u{:04x}", c as u32);
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[test]
+fn test_report() {
+    let reports = [
+        CaseReport {
+            name: "01".to_owned(),
+            dims: Dims {
+                width: 2,
+                height: 2,
+            },
+            has_truth: true,
+            candidates: 3,
+            score: Some(Score {
+                cost: 8,
+                select_count: 1,
+                swap_count: 5,
+                pos_mismatch_count: 0,
+                rot_mismatch_count: 1,
+            }),
+            pixel_match_secs: 0.5,
+            move_resolve_secs: 1.25,
+            memory: RssUsage {
+                peak: Some(48 * 1024 * 1024),
+                growth: Some(16 * 1024 * 1024),
+            },
+            error: None,
+        },
+        CaseReport {
+            name: "broken".to_owned(),
+            dims: Dims {
+                width: 0,
+                height: 0,
+            },
+            has_truth: false,
+            candidates: 0,
+            score: None,
+            pixel_match_secs: 0.0,
+            move_resolve_secs: 0.0,
+            memory: RssUsage::default(),
+            error: Some("bad \"header\", line 1".to_owned()),
+        },
+    ];
+
+    let csv = to_csv(&reports);
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(CSV_HEADER, lines[0]);
+    assert_eq!("01,2,2,true,3,8,1,5,0,1,0.500,1.250,48.0,16.0,", lines[1]);
+    assert_eq!(
+        "broken,0,0,false,0,,,,,,0.000,0.000,,,\"bad \"\"header\"\", line 1\"",
+        lines[2]
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&to_json(&reports)).unwrap();
+    assert_eq!(2, json[0]["width"]);
+    assert_eq!(2, json[0]["height"]);
+    assert_eq!(8, json[0]["cost"]);
+    assert_eq!(true, json[0]["truth"]);
+    assert_eq!(48.0, json[0]["process_peak_rss_mib"]);
+    assert_eq!(16.0, json[0]["rss_growth_mib"]);
+    assert!(json[1]["cost"].is_null());
+    assert!(json[1]["process_peak_rss_mib"].is_null());
+    assert!(json[1]["rss_growth_mib"].is_null());
+    assert_eq!("bad \"header\", line 1", json[1]["error"]);
+}
@@ -0,0 +1,86 @@
+//! 回答の候補を提出する前に, 端末で人に確かめる.
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+};
+
+/// `Prompt` は回答の候補を見せて, 提出するかを 1 つずつ尋ねる.
+///
+/// `a` (以降もすべて提出する) か `q` (以降はすべて提出しない) を選んだら, それからは尋ねずに同じように答える.
+/// 入力が終わったときも, それ以降は提出しない.
+#[derive(Debug)]
+pub struct Prompt<R, W> {
+    input: R,
+    output: W,
+    decided: Option<bool>,
+}
+
+impl Prompt<BufReader<io::Stdin>, io::Stderr> {
+    /// 標準入力から答えを読み, 標準エラー出力に候補を書く.
+    pub fn stdio() -> Self {
+        Self::new(BufReader::new(io::stdin()), io::stderr())
+    }
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            decided: None,
+        }
+    }
+
+    /// `candidate` を見せて, 提出するかを返す.
+    pub fn confirm(&mut self, candidate: impl fmt::Display) -> io::Result<bool> {
+        if let Some(decided) = self.decided {
+            return Ok(decided);
+        }
+        writeln!(self.output, "{}", candidate)?;
+        loop {
+            write!(self.output, "submit this answer? [y]es/[n]o/[a]ll/[q]uit: ")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                self.decided = Some(false);
+                return Ok(false);
+            }
+            match line.trim().to_ascii_lowercase().as_str() {
+                "y" | "yes" => return Ok(true),
+                "n" | "no" | "" => return Ok(false),
+                "a" | "all" => {
+                    self.decided = Some(true);
+                    return Ok(true);
+                }
+                "q" | "quit" => {
+                    self.decided = Some(false);
+                    return Ok(false);
+                }
+                other => writeln!(self.output, "unknown answer '{}'", other)?,
+            }
+        }
+    }
+}
+
+#[test]
+fn test_prompt() {
+    let mut output = vec![];
+    let mut prompt = Prompt::new("y\nhoge\nN\n\na\n".as_bytes(), &mut output);
+    assert!(prompt.confirm("#1").unwrap());
+    assert!(!prompt.confirm("#2").unwrap());
+    assert!(!prompt.confirm("#3").unwrap());
+    assert!(prompt.confirm("#4").unwrap());
+    // `a` の後は尋ねない
+    assert!(prompt.confirm("#5").unwrap());
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("unknown answer 'hoge'"));
+    assert!(!output.contains("#5"));
+
+    // 入力が終わったら, それ以降は提出しない
+    let mut prompt = Prompt::new("".as_bytes(), io::sink());
+    assert!(!prompt.confirm("#1").unwrap());
+    let mut prompt = Prompt::new("q\ny\n".as_bytes(), io::sink());
+    assert!(!prompt.confirm("#1").unwrap());
+    assert!(!prompt.confirm("#2").unwrap());
+}
@@ -0,0 +1,84 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::{
+    fragment::Fragment,
+    image,
+    pixel_match::{EdgeScores, Metric},
+};
+
+/// 行列の出力形式.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MatrixFormat {
+    Csv,
+    Npy,
+}
+
+/// `edge-scores` の引数.
+#[derive(Debug, Clone, clap::Args)]
+pub struct EdgeScoresArgs {
+    #[clap(help = "The problem file to analyze")]
+    pub problem: PathBuf,
+
+    #[clap(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Write the matrix to the file instead of stdout"
+    )]
+    pub output: Option<PathBuf>,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "csv",
+        help = "The matrix format. rows and columns of npy follow the order of the csv labels"
+    )]
+    pub format: MatrixFormat,
+
+    #[clap(
+        long,
+        default_value = "euclidean",
+        help = "Color distance to match edges with: euclidean or manhattan"
+    )]
+    pub metric: Metric,
+}
+
+/// 問題の断片のすべての辺の組について一致度を求め, 行列として書き出す.
+pub fn run(args: EdgeScoresArgs) -> Result<()> {
+    let problem = {
+        let file = File::open(&args.problem)
+            .with_context(|| format!("failed to open {}", args.problem.display()))?;
+        image::read_problem(BufReader::new(file))?
+    };
+    let scores = EdgeScores::new(&Fragment::new_all(&problem)?, args.metric);
+    tracing::info!(edges = scores.len(), "calculated edge scores");
+
+    match &args.output {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            write_scores(&scores, args.format, BufWriter::new(file))
+                .with_context(|| format!("failed to write {}", path.display()))
+        }
+        None => {
+            let stdout = std::io::stdout();
+            write_scores(&scores, args.format, BufWriter::new(stdout.lock()))
+                .context("failed to output the matrix")
+        }
+    }
+}
+
+fn write_scores(scores: &EdgeScores, format: MatrixFormat, mut out: impl Write) -> Result<()> {
+    match format {
+        MatrixFormat::Csv => out.write_all(scores.to_csv().as_bytes())?,
+        MatrixFormat::Npy => scores.write_npy(&mut out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,231 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use ::image::io::Reader;
+use anyhow::{bail, ensure, Context as _, Result};
+
+use crate::{
+    basis::{Color, Image, Problem, Rot},
+    grid::{Dims, VecOnGrid, MAX_GRID_SIDE},
+    image, scramble, simulator,
+};
+
+/// `make-problem` の引数.
+#[derive(Debug, Clone, clap::Args)]
+pub struct MakeProblemArgs {
+    #[clap(help = "The number of fragments in a row")]
+    pub rows: u8,
+
+    #[clap(help = "The number of fragments in a column")]
+    pub cols: u8,
+
+    #[clap(help = "The source image to split")]
+    pub source: PathBuf,
+
+    #[clap(
+        short,
+        long,
+        value_name = "FILE",
+        help = "Write the problem to the file instead of stdout"
+    )]
+    pub output: Option<PathBuf>,
+
+    #[clap(long, default_value_t = 3, help = "The maximum number of selections")]
+    pub select_limit: u8,
+
+    #[clap(long, default_value_t = 2, help = "The cost rate of a selection")]
+    pub select_cost: u16,
+
+    #[clap(long, default_value_t = 1, help = "The cost rate of a swap")]
+    pub swap_cost: u16,
+
+    #[clap(
+        long,
+        value_name = "SEED",
+        help = "Shuffle positions and rotations of the fragments with the seed"
+    )]
+    pub scramble: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Write an answer that puts every fragment back in place to the file, which batch uses as the ground truth"
+    )]
+    pub truth: Option<PathBuf>,
+}
+
+/// 画像を分割した問題を `output` か標準出力に書き出す. `scramble` があれば断片の位置と向きをばらばらにする.
+pub fn run(args: MakeProblemArgs) -> Result<()> {
+    let MakeProblemArgs {
+        rows,
+        cols,
+        source,
+        output,
+        select_limit,
+        select_cost,
+        swap_cost,
+        scramble,
+        truth: truth_path,
+    } = args;
+    ensure!(0 < select_limit, "the select limit must be positive");
+
+    let src =
+        File::open(&source).with_context(|| format!("failed to open {}", source.display()))?;
+    let reader = Reader::new(BufReader::new(src))
+        .with_guessed_format()
+        .context("failed to guess the source image format")?;
+    let img = reader
+        .decode()
+        .context("the source image format is not supported")?;
+    let rgb = img.to_rgb8();
+    let width = rgb.width();
+    let height = rgb.height();
+    let rgb_pixels = rgb.into_raw();
+
+    validate_split(width, height, rows, cols)?;
+
+    let problem = Problem {
+        select_limit,
+        select_cost,
+        swap_cost,
+        dims: Dims {
+            width: rows,
+            height: cols,
+        },
+        image: Image {
+            width: width.try_into().context("the image is too wide")?,
+            height: height.try_into().context("the image is too tall")?,
+            pixels: rgb_pixels
+                .chunks(3)
+                .map(|c| Color {
+                    r: c[0],
+                    g: c[1],
+                    b: c[2],
+                })
+                .collect(),
+        },
+    };
+
+    let (problem, truth) = match scramble {
+        Some(seed) => {
+            let scrambled = scramble::scramble(&problem, seed);
+            (scrambled.problem, scrambled.truth)
+        }
+        None => {
+            let grid = problem.grid();
+            let mut truth = VecOnGrid::with_init(grid, (grid.pos(0, 0), Rot::R0));
+            for pos in grid.all_pos() {
+                truth[pos] = (pos, Rot::R0);
+            }
+            (problem, truth)
+        }
+    };
+
+    if let Some(path) = truth_path {
+        let answer = simulator::truth_answer(&problem, &truth)?;
+        std::fs::write(&path, answer)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        tracing::info!(path = %path.display(), "saved the ground truth");
+    }
+
+    match output {
+        Some(path) => {
+            let file = File::create(&path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            let mut out = BufWriter::new(file);
+            image::write_problem(&problem, &mut out)?;
+            out.flush()
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            tracing::info!(path = %path.display(), "saved the problem");
+            Ok(())
+        }
+        None => {
+            let stdout = std::io::stdout();
+            let mut out = BufWriter::new(stdout.lock());
+            image::write_problem(&problem, &mut out)?;
+            out.flush().context("failed to output binary")
+        }
+    }
+}
+
+/// `width` x `height` の画像を `rows` x `cols` の正方形の断片に分割できるか確かめる.
+///
+/// できないときは, 代わりに使える分割数をエラーメッセージで示す.
+fn validate_split(width: u32, height: u32, rows: u8, cols: u8) -> Result<()> {
+    let splits = |len: u32| -> Vec<_> {
+        (2..=MAX_GRID_SIDE)
+            .filter(|&n| split_len(len, n).is_some())
+            .map(|n| n.to_string())
+            .collect()
+    };
+    ensure!(
+        (2..=MAX_GRID_SIDE).contains(&rows) && (2..=MAX_GRID_SIDE).contains(&cols),
+        "split rows and cols must be between 2 and {}, but got {} x {}",
+        MAX_GRID_SIDE,
+        rows,
+        cols
+    );
+    let side = split_len(width, rows).with_context(|| {
+        format!(
+            "width {} is not divisible by {} rows. try one of [{}]",
+            width,
+            rows,
+            splits(width).join(", ")
+        )
+    })?;
+    let side_y = split_len(height, cols).with_context(|| {
+        format!(
+            "height {} is not divisible by {} cols. try one of [{}]",
+            height,
+            cols,
+            splits(height).join(", ")
+        )
+    })?;
+    if side != side_y {
+        let hint = (2..=MAX_GRID_SIDE)
+            .find(|&n| split_len(height, n) == Some(side))
+            .map_or(String::new(), |n| {
+                format!(". try {} cols for {} rows", n, rows)
+            });
+        bail!(
+            "fragments must be square, but they are {} x {}{}",
+            side,
+            side_y,
+            hint
+        );
+    }
+    ensure!(
+        width <= u16::MAX as u32 && height <= u16::MAX as u32,
+        "the image must be smaller than {} x {}",
+        u16::MAX,
+        u16::MAX
+    );
+    Ok(())
+}
+
+/// `len` をちょうど `n` 等分できれば, その長さを返す.
+fn split_len(len: u32, n: u8) -> Option<u32> {
+    let rest = len % n as u32;
+    (rest == 0).then(|| len / n as u32)
+}
+
+#[test]
+fn test_validate_split() {
+    assert!(validate_split(1280, 512, 10, 4).is_ok());
+    assert!(validate_split(1280, 512, 1, 4).is_err());
+    assert!(validate_split(1280, 512, 17, 4).is_err());
+
+    let message = validate_split(1280, 512, 3, 4).unwrap_err().to_string();
+    assert_eq!(
+        "width 1280 is not divisible by 3 rows. try one of [2, 4, 5, 8, 10, 16]",
+        message
+    );
+    let message = validate_split(1280, 512, 10, 2).unwrap_err().to_string();
+    assert_eq!(
+        "fragments must be square, but they are 128 x 256. try 4 cols for 10 rows",
+        message
+    );
+}
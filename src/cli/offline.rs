@@ -0,0 +1,97 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::PathBuf,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context as _, Result};
+
+use super::SolveArgs;
+use crate::{
+    answer_sink::AnswerSink, cancel::CancellationToken, fragment, image, kaitou,
+    memory::RssSampler, move_resolve, move_resolve::ResolveParam, report::Report, simulator,
+};
+
+/// `offline` の引数.
+#[derive(Debug, Clone, clap::Args)]
+pub struct OfflineArgs {
+    #[clap(help = "The problem file to solve")]
+    pub problem: PathBuf,
+
+    #[clap(flatten)]
+    pub solve: SolveArgs,
+}
+
+/// 手元の問題ファイルを解き, 良くなった回答を順にファイルへ保存する.
+pub fn run(args: OfflineArgs) -> Result<()> {
+    let OfflineArgs { problem, mut solve } = args;
+    solve.load_config()?;
+    super::init_thread_pool(solve.threads)?;
+    let epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let mut problem = {
+        let file = File::open(&problem)
+            .with_context(|| format!("failed to open {}", problem.display()))?;
+        image::read_problem(BufReader::new(file))?
+    };
+    solve.overrides.apply(&mut problem)?;
+
+    tracing::info!(?problem, "problem case");
+
+    let grid = problem.grid();
+    let fragments = fragment::Fragment::new_all(&problem)?;
+
+    let sampler = RssSampler::start(super::RSS_SAMPLE_INTERVAL);
+    let matching = Instant::now();
+    let mut resolver = solve.resolver(fragments, grid)?;
+    let arrangement = solve.recover(&mut resolver, epoch)?;
+    let budgets = solve.phase_budgets(matching.elapsed());
+    let (movements, rots) = arrangement.movements_and_rotations();
+    tracing::info!("pixel_match::resolve() done");
+
+    let truth = arrangement.truth();
+
+    let param = ResolveParam {
+        select_limit: problem.select_limit,
+        swap_cost: problem.swap_cost,
+        select_cost: problem.select_cost,
+    };
+    let mut sink = AnswerSink::new(".", format!("answer-{}", epoch), param)?;
+
+    let operations_candidate = move_resolve::resolve_budgeted(
+        grid,
+        &movements,
+        param,
+        solve.beam_config(),
+        budgets,
+        CancellationToken::new(),
+    )?;
+
+    let metric = solve.metric.unwrap_or_default();
+    let _span = tracing::info_span!("move_resolve").entered();
+    solve.limit_time(operations_candidate).for_each(|(ops, _)| {
+        let answer = kaitou::ans(&ops, &rots);
+        match simulator::simulate(&problem, truth, &answer) {
+            Ok(score) => tracing::info!(?score, "simulated"),
+            Err(e) => tracing::warn!(?e, "failed to simulate"),
+        }
+        // 正しい配置を使わずに, 並べた画像の継ぎ目だけから取り違えていそうかを見る
+        match simulator::simulate_seams(&problem, &answer, metric) {
+            Ok(seams) => tracing::info!(?seams, "simulated seams"),
+            Err(e) => tracing::warn!(?e, "failed to simulate seams"),
+        }
+        println!("{}", Report::new(&problem, truth, &rots, &ops));
+
+        match sink.offer(&ops, &rots) {
+            Ok(Some(path)) => tracing::info!(path = %path.display(), "saved answer"),
+            Ok(None) => tracing::info!("skipped an answer not better than the saved one"),
+            Err(e) => tracing::warn!(?e, "failed to save answer"),
+        }
+    });
+    super::log_memory_usage(sampler.finish());
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use std::{
+    fs::File,
+    io::Write,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context as _, Result};
+use bytes::Buf;
+
+use super::{confirm::Prompt, SolveArgs};
+use crate::{
+    best_submission::BestSubmission,
+    cancel::CancellationToken,
+    client::{Client, PollOptions},
+    feedback::{Feedback, Verdict},
+    fragment, image, kaitou,
+    memory::RssSampler,
+    move_resolve,
+    move_resolve::ResolveParam,
+    report::Report,
+    submitter::{Schedule, Submitter},
+};
+
+/// 不一致が残ったときに画像の復元をやり直す回数の上限 (最初の 1 回を含む)
+const MAX_RESOLVE_ROUNDS: usize = 5;
+
+/// `--review-threshold` がないときに, 提出を確かめる候補に添える怪しい継ぎ目を数える基準
+const DEFAULT_REVIEW_THRESHOLD: f64 = 3.0;
+
+/// `online` の引数.
+#[derive(Debug, Clone, clap::Args)]
+pub struct OnlineArgs {
+    #[clap(
+        long,
+        env = "SERVER_ENDPOINT",
+        help = "The endpoint of the contest server"
+    )]
+    pub endpoint: Option<String>,
+
+    #[clap(
+        long,
+        env = "TOKEN",
+        hide_env_values = true,
+        help = "The token for the contest server"
+    )]
+    pub token: Option<String>,
+
+    #[clap(
+        long,
+        env = "SUBMIT_INTERVAL",
+        value_name = "SECONDS",
+        help = "The minimum interval between submissions [default: 1]"
+    )]
+    pub submit_interval: Option<f64>,
+
+    #[clap(
+        long,
+        env = "SUBMIT_QUOTA",
+        value_name = "COUNT",
+        help = "The number of submissions left in the contest [default: unlimited]"
+    )]
+    pub submit_quota: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Show each answer with its cost and ask whether to submit it, instead of submitting automatically. The answers are shown once the search has finished"
+    )]
+    pub confirm: bool,
+
+    #[clap(flatten)]
+    pub solve: SolveArgs,
+}
+
+/// 競技サーバから問題を取得して解き, 良くなった回答を提出し続ける.
+pub fn run(args: OnlineArgs) -> Result<()> {
+    let OnlineArgs {
+        endpoint,
+        token,
+        submit_interval,
+        submit_quota,
+        confirm,
+        mut solve,
+    } = args;
+    let config = solve.load_config()?;
+    super::init_thread_pool(solve.threads)?;
+    let endpoint = endpoint
+        .or(config.server.endpoint)
+        .context("the endpoint must be given by --endpoint, SERVER_ENDPOINT or the config")?;
+    let token = token
+        .or(config.server.token)
+        .context("the token must be given by --token, TOKEN or the config")?;
+    let submit_interval = submit_interval
+        .or(config.server.submit_interval)
+        .unwrap_or(1.0);
+    let mut schedule = Schedule::new(Duration::from_secs_f64(submit_interval));
+    if let Some(quota) = submit_quota.or(config.server.submit_quota) {
+        schedule = schedule.with_quota(quota);
+    }
+    let client = Client::new(endpoint, token);
+    // 長く解いた後で提出に失敗しないように, 接続とトークンを先に確かめる
+    client.preflight().with_context(|| {
+        format!(
+            "failed to reach {} or the token was rejected",
+            client.endpoint()
+        )
+    })?;
+    tracing::info!("client.preflight() done");
+
+    let epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let (problem, fingerprint, fetched) = {
+        // 競技開始前なら問題が公開されるまで待つ
+        let fetched = client.poll_problem(PollOptions::default())?;
+        tracing::info!("client.poll_problem() done");
+        let data = &fetched.data;
+
+        let mut problem = image::read_problem(data.slice(..).reader())?;
+        solve.overrides.apply(&mut problem)?;
+
+        let filename = format!("problem-{}.ppm", epoch);
+        File::create(&filename)
+            .and_then(|mut file| file.write_all(data))
+            .with_context(|| format!("failed to save the problem to {}", filename))?;
+        tracing::info!(%filename, "saved the problem");
+
+        (problem, fxhash::hash64(&data[..]), fetched)
+    };
+    tracing::info!(?problem, "problem case");
+
+    let grid = problem.grid();
+    let fragments = fragment::Fragment::new_all(&problem)?;
+
+    // 同じ問題を解き直したときも前回までの記録を引き継ぐ
+    let mut best = BestSubmission::load(format!("best-submission-{:016x}.txt", fingerprint))?;
+    if let Some(best) = best.best() {
+        tracing::info!(?best, "best submission so far");
+    }
+
+    let mut resolver = solve.resolver(fragments, grid)?;
+    let mut feedback = Feedback::new(MAX_RESOLVE_ROUNDS);
+    loop {
+        let _round = tracing::info_span!("round", round = feedback.round()).entered();
+        let sampler = RssSampler::start(super::RSS_SAMPLE_INTERVAL);
+        let matching = Instant::now();
+        let arrangement = solve.recover(&mut resolver, epoch)?;
+        let budgets = solve.phase_budgets(matching.elapsed());
+        let (movements, rots) = arrangement.movements_and_rotations();
+        tracing::info!("pixel_match::resolve() done");
+
+        let operations_candidate = move_resolve::resolve_budgeted(
+            grid,
+            &movements,
+            ResolveParam {
+                select_limit: problem.select_limit,
+                swap_cost: problem.swap_cost,
+                select_cost: problem.select_cost,
+            },
+            solve.beam_config(),
+            budgets,
+            CancellationToken::new(),
+        )?;
+
+        let submitter = Submitter::spawn(
+            client.clone(),
+            schedule,
+            format!("submissions-{}.log", epoch),
+            "submissions",
+            best,
+        )?;
+        let queue = |ops: Vec<_>, predicted_cost| {
+            let answer = kaitou::ans(&ops, &rots);
+            let report = Report::new(&problem, arrangement.truth(), &rots, &ops);
+            tracing::info!(predicted_cost, %report, "queued an answer to submit");
+            submitter.submit(answer, predicted_cost);
+        };
+        let move_resolve_span = tracing::info_span!("move_resolve").entered();
+        if !confirm {
+            solve
+                .limit_time(operations_candidate)
+                .for_each(|(ops, predicted_cost)| queue(ops, predicted_cost));
+            move_resolve_span.exit();
+        } else {
+            // 尋ねている間も探索の時間が過ぎるので, 候補をすべて集めてから尋ねる
+            let candidates: Vec<_> = solve.limit_time(operations_candidate).collect();
+            move_resolve_span.exit();
+            // 復元を取り違えていそうなら, 候補を確かめるときにそれも見せる
+            let suspicious_seams = resolver
+                .check_seams(&arrangement)
+                .suspicious(solve.review_threshold.unwrap_or(DEFAULT_REVIEW_THRESHOLD))
+                .count();
+            let mut prompt = Prompt::stdio();
+            for (ops, predicted_cost) in candidates {
+                let report = Report::new(&problem, arrangement.truth(), &rots, &ops);
+                let candidate = format!(
+                    "predicted cost: {}, suspicious seams: {}\n{}",
+                    predicted_cost, suspicious_seams, report
+                );
+                match prompt.confirm(candidate) {
+                    Ok(true) => queue(ops, predicted_cost),
+                    Ok(false) => tracing::info!(predicted_cost, "skipped an answer"),
+                    Err(e) => {
+                        tracing::warn!(%e, "failed to ask whether to submit. skipped an answer")
+                    }
+                }
+            }
+        }
+        super::log_memory_usage(sampler.finish());
+        (best, schedule) = submitter
+            .finish()
+            .context("the submitter did not return the record")?;
+        if schedule.is_exhausted() {
+            tracing::warn!("used up the submission quota");
+            break;
+        }
+
+        match client.fetch_problem_if_changed(&fetched) {
+            Ok(Some(_)) => {
+                tracing::warn!("the problem has been replaced on the server. restart to solve it");
+                break;
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!(%e, "failed to check whether the problem was replaced"),
+        }
+
+        match feedback.judge(best.best()) {
+            Verdict::Solved => break,
+            // GUI を開かなければ同じ画像が復元されるだけなので, やり直さない
+            Verdict::Retry { mismatch_count, .. } if !solve.is_interactive() => {
+                tracing::warn!(
+                    mismatch_count,
+                    "mismatches remain. run with the GUI to fix them"
+                );
+                break;
+            }
+            Verdict::Retry {
+                round,
+                mismatch_count,
+            } => {
+                tracing::warn!(
+                    mismatch_count,
+                    "mismatches remain. reopening pixel_match (round {}/{})",
+                    round,
+                    MAX_RESOLVE_ROUNDS
+                );
+            }
+            Verdict::GiveUp => {
+                tracing::warn!(best = ?best.best(), "giving up");
+                break;
+            }
+        }
+    }
+    Ok(())
+}
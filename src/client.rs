@@ -0,0 +1,444 @@
+use std::{
+    fmt, thread,
+    time::{Duration, Instant},
+};
+
+use reqwest::{
+    blocking,
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+
+/// `Client` は競技サーバとの通信を行う. 5xx やタイムアウトで失敗した要求は間隔を倍にしながら再試行する.
+#[derive(Debug, Clone)]
+pub struct Client {
+    endpoint: String,
+    token: String,
+    retries: u32,
+    initial_backoff: Duration,
+    inner: blocking::Client,
+}
+
+/// `SubmitResult` は回答を提出したときにサーバから返される結果を表す.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmitResult {
+    pub status: SubmitStatus,
+    pub request_id: Option<String>,
+    /// 後から調べられるように残す, サーバの返答そのもの
+    pub body: String,
+}
+
+/// `SubmitStatus` はサーバが提出した回答をどう扱ったかを表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmitStatus {
+    /// 採点され, 位置と回転の不一致数が返された.
+    Accepted {
+        pos_mismatch_count: usize,
+        rot_mismatch_count: usize,
+    },
+    /// 受け付けられたが, まだ採点されていない.
+    Queued,
+}
+
+impl SubmitStatus {
+    /// 採点されていれば, 位置と回転の不一致数の合計を返す.
+    pub fn mismatch_count(&self) -> Option<usize> {
+        match self {
+            SubmitStatus::Accepted {
+                pos_mismatch_count,
+                rot_mismatch_count,
+            } => Some(pos_mismatch_count + rot_mismatch_count),
+            SubmitStatus::Queued => None,
+        }
+    }
+}
+
+/// `FetchedProblem` は取得した問題画像と, 差し替えを検出するためのヘッダの値を表す.
+#[derive(Debug, Clone)]
+pub struct FetchedProblem {
+    pub data: bytes::Bytes,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// `PollOptions` は問題が公開されるまで待つときの設定を表す.
+#[derive(Debug, Clone, Copy)]
+pub struct PollOptions {
+    /// 問題の取得を試みる間隔.
+    pub interval: Duration,
+    /// これだけ待っても公開されなければ諦める. `None` なら諦めない.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for PollOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: None,
+        }
+    }
+}
+
+/// `ClientError` は `Client` の要求が失敗した理由を表す.
+#[derive(Debug)]
+pub enum ClientError {
+    /// 接続できなかった, またはタイムアウトした.
+    Request(reqwest::Error),
+    /// サーバが成功以外のステータスを返した.
+    Status { status: StatusCode, body: String },
+    /// サーバの返答を解釈できなかった.
+    InvalidResponse { body: String, reason: String },
+    /// 待っている間に問題が公開されなかった.
+    PollTimedOut { elapsed: Duration },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Request(e) => write!(f, "failed to send request: {}", e),
+            ClientError::Status { status, body } => {
+                write!(f, "server returned {}: '{}'", status, body)
+            }
+            ClientError::InvalidResponse { body, reason } => {
+                write!(f, "invalid response ({}): '{}'", reason, body)
+            }
+            ClientError::PollTimedOut { elapsed } => {
+                write!(f, "the problem was not published in {:?}", elapsed)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Request(e)
+    }
+}
+
+impl ClientError {
+    /// 再試行すれば成功しうるかどうかを返す.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::Request(e) => e.is_timeout() || e.is_connect(),
+            ClientError::Status { status, .. } => status.is_server_error(),
+            ClientError::InvalidResponse { .. } | ClientError::PollTimedOut { .. } => false,
+        }
+    }
+
+    /// トークンを拒否されたことによる失敗かどうかを返す.
+    pub fn is_unauthorized(&self) -> bool {
+        matches!(
+            self,
+            ClientError::Status {
+                status: StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN,
+                ..
+            }
+        )
+    }
+
+    /// 問題がまだ公開されていないことによる失敗かどうかを返す.
+    pub fn is_not_published(&self) -> bool {
+        matches!(
+            self,
+            ClientError::Status {
+                status: StatusCode::NOT_FOUND | StatusCode::FORBIDDEN,
+                ..
+            }
+        )
+    }
+}
+
+impl Client {
+    pub fn new(endpoint: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            token: token.into(),
+            retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            inner: blocking::Client::new(),
+        }
+    }
+
+    /// 失敗したときに再試行する最大の回数を設定する.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// 最初の再試行までの待ち時間を設定する. 以降は再試行のたびに倍になる.
+    pub fn with_initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// 解き始める前に, サーバに接続できてトークンが拒否されないことを確かめる.
+    ///
+    /// 提出先に `HEAD` を送るだけなので提出としては数えられない. 提出先が `HEAD` を受け付けなくても,
+    /// トークンを拒否されなければ成功とする.
+    pub fn preflight(&self) -> Result<(), ClientError> {
+        self.with_retry(|| {
+            let res = self
+                .inner
+                .head(&self.endpoint)
+                .header("procon-token", &self.token)
+                .send()?;
+            match check_status(res) {
+                Err(e) if e.is_unauthorized() || e.is_retryable() => Err(e),
+                Err(e) => {
+                    tracing::debug!(%e, "the endpoint does not accept HEAD");
+                    Ok(())
+                }
+                Ok(_) => Ok(()),
+            }
+        })
+    }
+
+    /// 問題画像を取得する.
+    pub fn fetch_problem(&self) -> Result<FetchedProblem, ClientError> {
+        self.fetch_problem_since(None)?
+            .ok_or_else(|| ClientError::InvalidResponse {
+                body: String::new(),
+                reason: "not modified without any condition".to_owned(),
+            })
+    }
+
+    /// 問題が公開されるまで `options.interval` ごとに取得を試みる.
+    pub fn poll_problem(&self, options: PollOptions) -> Result<FetchedProblem, ClientError> {
+        let start = Instant::now();
+        loop {
+            match self.fetch_problem() {
+                Err(e) if e.is_not_published() => {
+                    let elapsed = start.elapsed();
+                    if matches!(options.timeout, Some(timeout) if timeout <= elapsed) {
+                        return Err(ClientError::PollTimedOut { elapsed });
+                    }
+                    tracing::info!(%e, "the problem is not published yet");
+                    thread::sleep(options.interval);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// `previous` を取得したあとに問題が差し替えられていれば, 新しい問題を返す.
+    pub fn fetch_problem_if_changed(
+        &self,
+        previous: &FetchedProblem,
+    ) -> Result<Option<FetchedProblem>, ClientError> {
+        self.fetch_problem_since(Some(previous))
+    }
+
+    fn fetch_problem_since(
+        &self,
+        previous: Option<&FetchedProblem>,
+    ) -> Result<Option<FetchedProblem>, ClientError> {
+        let url = format!("{}/problem.ppm", self.endpoint);
+        self.with_retry(|| {
+            let mut req = self.inner.get(&url);
+            if let Some(previous) = previous {
+                if let Some(etag) = &previous.etag {
+                    req = req.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &previous.last_modified {
+                    req = req.header(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+            let res = req.send()?;
+            if res.status() == StatusCode::NOT_MODIFIED {
+                return Ok(None);
+            }
+            let res = check_status(res)?;
+
+            let header = |name| {
+                res.headers()
+                    .get(name)
+                    .and_then(|x: &reqwest::header::HeaderValue| x.to_str().ok())
+                    .map(|x| x.to_string())
+            };
+            let etag = header(ETAG);
+            let last_modified = header(LAST_MODIFIED);
+            let data = res.bytes()?;
+
+            // ヘッダで判断できないサーバでは中身を比べる
+            if matches!(previous, Some(previous) if previous.data == data) {
+                return Ok(None);
+            }
+            Ok(Some(FetchedProblem {
+                data,
+                etag,
+                last_modified,
+            }))
+        })
+    }
+
+    /// 回答を提出する.
+    pub fn submit(&self, answer: &str) -> Result<SubmitResult, ClientError> {
+        self.with_retry(|| {
+            let res = self
+                .inner
+                .post(&self.endpoint)
+                .header("procon-token", &self.token)
+                .body(answer.to_owned())
+                .send()?;
+            let res = check_status(res)?;
+
+            let request_id = res
+                .headers()
+                .get("procon-request-id")
+                .and_then(|x| x.to_str().ok())
+                .map(|x| x.to_string());
+
+            let body = res.text()?;
+            let status =
+                parse_post_response(&body).map_err(|reason| ClientError::InvalidResponse {
+                    body: body.clone(),
+                    reason: reason.to_owned(),
+                })?;
+
+            Ok(SubmitResult {
+                status,
+                request_id,
+                body,
+            })
+        })
+    }
+
+    fn with_retry<T>(
+        &self,
+        mut request: impl FnMut() -> Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        let mut attempt = 0;
+        loop {
+            match request() {
+                Err(e) if e.is_retryable() && attempt < self.retries => {
+                    let wait = backoff(self.initial_backoff, attempt);
+                    tracing::warn!(?wait, %e, "request failed, retrying");
+                    thread::sleep(wait);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn backoff(initial: Duration, attempt: u32) -> Duration {
+    initial.saturating_mul(1 << attempt.min(16))
+}
+
+fn check_status(res: blocking::Response) -> Result<blocking::Response, ClientError> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let body = res.text().unwrap_or_default();
+    Err(ClientError::Status { status, body })
+}
+
+/// 提出への返答を読む. 採点待ちを表す `PROCESSING` や `QUEUED` も受け付け, 大文字と小文字は区別しない.
+fn parse_post_response(body: &str) -> Result<SubmitStatus, &'static str> {
+    let mut body_tokens = body.split_ascii_whitespace();
+
+    match body_tokens.next().map(str::to_ascii_uppercase).as_deref() {
+        Some("ACCEPTED") => {}
+        Some("PROCESSING" | "QUEUED") => return Ok(SubmitStatus::Queued),
+        _ => return Err("excepted 'ACCEPTED'"),
+    }
+
+    let pos = body_tokens
+        .next()
+        .ok_or("excepted pos_mismatch_count")?
+        .parse()
+        .map_err(|_| "failed to parse pos_mismatch_count")?;
+
+    let rot = body_tokens
+        .next()
+        .ok_or("excepted rot_mismatch_count")?
+        .parse()
+        .map_err(|_| "failed to parse rot_mismatch_count")?;
+
+    Ok(SubmitStatus::Accepted {
+        pos_mismatch_count: pos,
+        rot_mismatch_count: rot,
+    })
+}
+
+#[test]
+fn test_parse_post_response() {
+    let accepted = |pos_mismatch_count, rot_mismatch_count| SubmitStatus::Accepted {
+        pos_mismatch_count,
+        rot_mismatch_count,
+    };
+    assert_eq!(parse_post_response("ACCEPTED 2 3").unwrap(), accepted(2, 3));
+    assert_eq!(
+        parse_post_response("ACCEPTED 04 23").unwrap(),
+        accepted(4, 23)
+    );
+    assert_eq!(
+        parse_post_response("accepted 0 1\r\n").unwrap(),
+        accepted(0, 1)
+    );
+    assert_eq!(
+        parse_post_response("PROCESSING").unwrap(),
+        SubmitStatus::Queued
+    );
+    assert_eq!(
+        parse_post_response("queued 3").unwrap(),
+        SubmitStatus::Queued
+    );
+    assert_eq!(parse_post_response("HOGE FUGA").ok(), None);
+    assert_eq!(parse_post_response("ACCEPTED 2").ok(), None);
+    assert_eq!(parse_post_response("").ok(), None);
+}
+
+#[test]
+fn test_is_not_published() {
+    let status = |status| ClientError::Status {
+        status,
+        body: String::new(),
+    };
+    assert!(status(StatusCode::NOT_FOUND).is_not_published());
+    assert!(status(StatusCode::FORBIDDEN).is_not_published());
+    assert!(!status(StatusCode::INTERNAL_SERVER_ERROR).is_not_published());
+}
+
+#[test]
+fn test_is_unauthorized() {
+    let status = |status| ClientError::Status {
+        status,
+        body: String::new(),
+    };
+    assert!(status(StatusCode::UNAUTHORIZED).is_unauthorized());
+    assert!(status(StatusCode::FORBIDDEN).is_unauthorized());
+    assert!(!status(StatusCode::METHOD_NOT_ALLOWED).is_unauthorized());
+}
+
+#[test]
+fn test_backoff() {
+    let initial = Duration::from_millis(100);
+    assert_eq!(Duration::from_millis(100), backoff(initial, 0));
+    assert_eq!(Duration::from_millis(400), backoff(initial, 2));
+    assert!(ClientError::Status {
+        status: StatusCode::BAD_GATEWAY,
+        body: String::new(),
+    }
+    .is_retryable());
+    assert!(!ClientError::Status {
+        status: StatusCode::BAD_REQUEST,
+        body: String::new(),
+    }
+    .is_retryable());
+}
@@ -0,0 +1,414 @@
+//! 競技当日に再コンパイルせずに調整できるよう, 探索の設定などを TOML ファイルから読み込む.
+//!
+//! ```toml
+//! [search]
+//! phase1_beam_width = 200
+//! phase3_beam_width = 50
+//! diversity_rows = 2 # 0 なら選択回数だけで状態を分ける
+//! cost_weighting = "as-is" # または選択を 2 倍に見積もる "fewer-selects"
+//! threads = 4 # 省略すると CPU のコアを 1 つ残して使う
+//! time_budget = 60.0
+//!
+//! [budget]
+//! matching = 10.0 # 画像の復元をこの秒数で打ち切る. 焼きなましや遺伝的アルゴリズムを途中でやめる
+//! search = 30.0 # move_resolve の phase1 と phase2
+//! completion = 20.0 # move_resolve の phase3
+//! submit_margin = 2.0 # time_budget のうち最後の提出のために残す秒数
+//!
+//! [matching]
+//! metric = "euclidean" # または "manhattan"
+//! strategy = "shaker" # または "borders-first", ga フィーチャを有効にしていれば "genetic"
+//! double_side = "max" # または悪い方の辺の重み 0.0 から 1.0
+//! anneal_budget = 2.0 # 省略すると焼きなまししない
+//! ga_population = 50
+//! ga_generations = 200
+//! soft_penalty = 1.5
+//! fixed_rotation = false # true なら断片を回転させずに並べる
+//!
+//! [gui]
+//! enabled = true
+//! hints = "hints.txt"
+//! recovered_image = "recovered.png" # 省略すると recovered-<epoch>.png
+//! review_threshold = 3.0
+//! window_size = "800x800"
+//! scale = 1.0
+//!
+//! [server]
+//! endpoint = "http://localhost:8080"
+//! token = "..."
+//! submit_interval = 1.0
+//! submit_quota = 100 # 競技中に提出できる回数. 省略すると制限しない
+//! ```
+//!
+//! `[budget]` の各段階で余った時間は後の段階に回す.
+//! どの項目も省略できる. コマンドライン引数や環境変数で指定した値はファイルの値より優先する.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context as _, Result};
+use toml::value::{Table, Value};
+
+use crate::{
+    move_resolve::cost_model::CostWeighting,
+    pixel_match::{DoubleSideScoring, Metric, Strategy, WindowSize},
+};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    pub search: SearchConfig,
+    pub budget: BudgetConfig,
+    pub matching: MatchingConfig,
+    pub gui: GuiConfig,
+    pub server: ServerConfig,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchConfig {
+    pub phase1_beam_width: Option<usize>,
+    pub phase3_beam_width: Option<usize>,
+    /// まだ揃っていない上からこの行数の並びでもビームの状態を分ける
+    pub diversity_rows: Option<u8>,
+    /// 探索で選択と交換のコストをどう重み付けるか
+    pub cost_weighting: Option<CostWeighting>,
+    /// ビームサーチや辺の照合で使うスレッドの数
+    pub threads: Option<usize>,
+    pub time_budget: Option<f64>,
+}
+
+/// 段階ごとにかける秒数
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BudgetConfig {
+    pub matching: Option<f64>,
+    pub search: Option<f64>,
+    pub completion: Option<f64>,
+    pub submit_margin: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MatchingConfig {
+    pub metric: Option<Metric>,
+    pub strategy: Option<Strategy>,
+    /// 2 辺から断片を探すときに 2 つの辺の一致度をまとめる方法
+    pub double_side: Option<DoubleSideScoring>,
+    /// 並べた後に焼きなましで配置を改善する秒数
+    pub anneal_budget: Option<f64>,
+    /// 遺伝的アルゴリズムで並べるときの個体数
+    pub ga_population: Option<usize>,
+    /// 遺伝的アルゴリズムで並べるときの世代数
+    pub ga_generations: Option<usize>,
+    /// ソフトなブロックリストに入れた組の一致度に掛ける倍率
+    pub soft_penalty: Option<f64>,
+    /// 断片が回転していない問題として, 回転させずに並べるか
+    pub fixed_rotation: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GuiConfig {
+    pub enabled: Option<bool>,
+    pub hints: Option<PathBuf>,
+    /// 復元した画像を保存する PNG ファイル
+    pub recovered_image: Option<PathBuf>,
+    /// 継ぎ目の一致度が平均より標準偏差のこの倍を超えて悪いときだけ GUI を開く
+    pub review_threshold: Option<f64>,
+    /// 開いたときのウィンドウの大きさ
+    pub window_size: Option<WindowSize>,
+    /// 文字の拡大率
+    pub scale: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerConfig {
+    pub endpoint: Option<String>,
+    pub token: Option<String>,
+    pub submit_interval: Option<f64>,
+    pub submit_quota: Option<usize>,
+}
+
+impl Config {
+    /// `path` の設定ファイルを読み込む. `path` が無ければ何も設定しない.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        text.parse()
+            .with_context(|| format!("failed to parse {}", path.display()))
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut root: Table = toml::from_str(s)?;
+        let mut config = Config::default();
+
+        if let Some(mut search) = take_table(&mut root, "search")? {
+            config.search = SearchConfig {
+                phase1_beam_width: take_usize(&mut search, "phase1_beam_width")?,
+                phase3_beam_width: take_usize(&mut search, "phase3_beam_width")?,
+                diversity_rows: match search.remove("diversity_rows") {
+                    Some(Value::Integer(value)) if (0..=u8::MAX as i64).contains(&value) => {
+                        Some(value as u8)
+                    }
+                    Some(_) => bail!("search.diversity_rows must be an integer from 0 to 255"),
+                    None => None,
+                },
+                cost_weighting: take_str(&mut search, "cost_weighting")?
+                    .map(|x| x.parse())
+                    .transpose()?,
+                threads: take_usize(&mut search, "threads")?,
+                time_budget: take_seconds(&mut search, "time_budget")?,
+            };
+            ensure_empty(&search, "search")?;
+        }
+
+        if let Some(mut budget) = take_table(&mut root, "budget")? {
+            config.budget = BudgetConfig {
+                matching: take_seconds(&mut budget, "matching")?,
+                search: take_seconds(&mut budget, "search")?,
+                completion: take_seconds(&mut budget, "completion")?,
+                submit_margin: take_seconds(&mut budget, "submit_margin")?,
+            };
+            ensure_empty(&budget, "budget")?;
+        }
+
+        if let Some(mut matching) = take_table(&mut root, "matching")? {
+            config.matching = MatchingConfig {
+                metric: take_str(&mut matching, "metric")?
+                    .map(|x| x.parse())
+                    .transpose()?,
+                strategy: take_str(&mut matching, "strategy")?
+                    .map(|x| x.parse())
+                    .transpose()?,
+                double_side: match matching.remove("double_side") {
+                    Some(Value::String(value)) => Some(value.parse()?),
+                    Some(Value::Float(value)) => Some(value.to_string().parse()?),
+                    Some(Value::Integer(value)) => Some(value.to_string().parse()?),
+                    Some(_) => bail!("matching.double_side must be \"max\" or a number"),
+                    None => None,
+                },
+                anneal_budget: take_seconds(&mut matching, "anneal_budget")?,
+                ga_population: take_usize(&mut matching, "ga_population")?,
+                ga_generations: take_usize(&mut matching, "ga_generations")?,
+                soft_penalty: match matching.remove("soft_penalty") {
+                    Some(Value::Float(value)) if 0.0 < value => Some(value),
+                    Some(Value::Integer(value)) if 0 < value => Some(value as f64),
+                    Some(_) => bail!("matching.soft_penalty must be a positive number"),
+                    None => None,
+                },
+                fixed_rotation: match matching.remove("fixed_rotation") {
+                    Some(Value::Boolean(fixed)) => Some(fixed),
+                    Some(_) => bail!("matching.fixed_rotation must be a boolean"),
+                    None => None,
+                },
+            };
+            ensure_empty(&matching, "matching")?;
+        }
+
+        if let Some(mut gui) = take_table(&mut root, "gui")? {
+            config.gui = GuiConfig {
+                enabled: match gui.remove("enabled") {
+                    Some(Value::Boolean(enabled)) => Some(enabled),
+                    Some(_) => bail!("gui.enabled must be a boolean"),
+                    None => None,
+                },
+                hints: take_str(&mut gui, "hints")?.map(PathBuf::from),
+                recovered_image: take_str(&mut gui, "recovered_image")?.map(PathBuf::from),
+                review_threshold: match gui.remove("review_threshold") {
+                    Some(Value::Float(value)) if 0.0 <= value => Some(value),
+                    Some(Value::Integer(value)) if 0 <= value => Some(value as f64),
+                    Some(_) => bail!("gui.review_threshold must be a non-negative number"),
+                    None => None,
+                },
+                window_size: take_str(&mut gui, "window_size")?
+                    .map(|x| x.parse())
+                    .transpose()?,
+                scale: match gui.remove("scale") {
+                    Some(Value::Float(value)) if 0.0 < value => Some(value),
+                    Some(Value::Integer(value)) if 0 < value => Some(value as f64),
+                    Some(_) => bail!("gui.scale must be a positive number"),
+                    None => None,
+                },
+            };
+            ensure_empty(&gui, "gui")?;
+        }
+
+        if let Some(mut server) = take_table(&mut root, "server")? {
+            config.server = ServerConfig {
+                endpoint: take_str(&mut server, "endpoint")?,
+                token: take_str(&mut server, "token")?,
+                submit_interval: take_seconds(&mut server, "submit_interval")?,
+                submit_quota: take_usize(&mut server, "submit_quota")?,
+            };
+            ensure_empty(&server, "server")?;
+        }
+
+        ensure_empty(&root, "the root")?;
+        Ok(config)
+    }
+}
+
+fn take_table(table: &mut Table, key: &str) -> Result<Option<Table>> {
+    match table.remove(key) {
+        Some(Value::Table(table)) => Ok(Some(table)),
+        Some(_) => bail!("{} must be a table", key),
+        None => Ok(None),
+    }
+}
+
+fn take_str(table: &mut Table, key: &str) -> Result<Option<String>> {
+    match table.remove(key) {
+        Some(Value::String(value)) => Ok(Some(value)),
+        Some(_) => bail!("{} must be a string", key),
+        None => Ok(None),
+    }
+}
+
+fn take_usize(table: &mut Table, key: &str) -> Result<Option<usize>> {
+    match table.remove(key) {
+        Some(Value::Integer(value)) if 0 < value => Ok(Some(value as usize)),
+        Some(_) => bail!("{} must be a positive integer", key),
+        None => Ok(None),
+    }
+}
+
+fn take_seconds(table: &mut Table, key: &str) -> Result<Option<f64>> {
+    match table.remove(key) {
+        // NaN や無限大は `Duration` にできないので受け付けない
+        Some(Value::Float(value)) if Duration::try_from_secs_f64(value).is_ok() => Ok(Some(value)),
+        Some(Value::Integer(value)) if 0 <= value => Ok(Some(value as f64)),
+        Some(_) => bail!("{} must be a non-negative number of seconds", key),
+        None => Ok(None),
+    }
+}
+
+/// 綴り間違いに気付けるよう, 知らない項目があればエラーにする.
+fn ensure_empty(table: &Table, name: &str) -> Result<()> {
+    match table.keys().next() {
+        Some(key) => bail!("unknown key {} in {}", key, name),
+        None => Ok(()),
+    }
+}
+
+#[test]
+fn test_config() {
+    let config: Config = r#"
+        [search]
+        phase1_beam_width = 100
+        diversity_rows = 0
+        cost_weighting = "fewer-selects"
+        threads = 2
+        time_budget = 30
+
+        [budget]
+        search = 20
+        completion = 7.5
+        submit_margin = 2
+
+        [matching]
+        metric = "manhattan"
+        strategy = "borders-first"
+        double_side = 0.75
+        anneal_budget = 1.5
+        ga_generations = 100
+        soft_penalty = 2
+        fixed_rotation = true
+
+        [gui]
+        enabled = false
+        recovered_image = "out/recovered.png"
+        review_threshold = 2.5
+        window_size = "1200x900"
+        scale = 1.5
+
+        [server]
+        endpoint = "http://localhost:8080"
+        submit_interval = 0.5
+        submit_quota = 30
+    "#
+    .parse()
+    .unwrap();
+    assert_eq!(Some(100), config.search.phase1_beam_width);
+    assert_eq!(None, config.search.phase3_beam_width);
+    assert_eq!(Some(0), config.search.diversity_rows);
+    assert_eq!(
+        Some(CostWeighting::FewerSelects),
+        config.search.cost_weighting
+    );
+    assert_eq!(Some(2), config.search.threads);
+    assert_eq!(Some(30.0), config.search.time_budget);
+    assert_eq!(
+        BudgetConfig {
+            matching: None,
+            search: Some(20.0),
+            completion: Some(7.5),
+            submit_margin: Some(2.0),
+        },
+        config.budget
+    );
+    assert_eq!(Some(Metric::Manhattan), config.matching.metric);
+    assert_eq!(Some(Strategy::BordersFirst), config.matching.strategy);
+    assert_eq!(
+        Some(DoubleSideScoring::WeightedSum(0.75)),
+        config.matching.double_side
+    );
+    assert_eq!(Some(1.5), config.matching.anneal_budget);
+    assert_eq!(None, config.matching.ga_population);
+    assert_eq!(Some(100), config.matching.ga_generations);
+    assert_eq!(Some(2.0), config.matching.soft_penalty);
+    assert_eq!(Some(true), config.matching.fixed_rotation);
+    assert_eq!(Some(false), config.gui.enabled);
+    assert_eq!(None, config.gui.hints);
+    assert_eq!(
+        Some(PathBuf::from("out/recovered.png")),
+        config.gui.recovered_image
+    );
+    assert_eq!(Some(2.5), config.gui.review_threshold);
+    assert_eq!(Some("1200x900".parse().unwrap()), config.gui.window_size);
+    assert_eq!(Some(1.5), config.gui.scale);
+    assert_eq!(
+        Some("http://localhost:8080"),
+        config.server.endpoint.as_deref()
+    );
+    assert_eq!(Some(0.5), config.server.submit_interval);
+    assert_eq!(Some(30), config.server.submit_quota);
+
+    assert_eq!(Config::default(), "".parse().unwrap());
+    assert!("[search]\nphase1_beam_width = 0".parse::<Config>().is_err());
+    assert!("[search]\ndiversity_rows = 256".parse::<Config>().is_err());
+    assert!("[search]\nthreads = 0".parse::<Config>().is_err());
+    assert!("[search]\ncost_weighting = \"hoge\""
+        .parse::<Config>()
+        .is_err());
+    assert!("[budget]\ncompletion = -1".parse::<Config>().is_err());
+    assert!("[budget]\nphase3 = 1".parse::<Config>().is_err());
+    assert!("[matching]\nmetric = \"hoge\"".parse::<Config>().is_err());
+    assert!("[matching]\nstrategy = \"hoge\"".parse::<Config>().is_err());
+    assert!("[matching]\ndouble_side = 2".parse::<Config>().is_err());
+    assert_eq!(
+        Some(DoubleSideScoring::Max),
+        "[matching]\ndouble_side = \"max\""
+            .parse::<Config>()
+            .unwrap()
+            .matching
+            .double_side
+    );
+    assert!("[matching]\nsoft_penalty = 0".parse::<Config>().is_err());
+    assert!("[matching]\nanneal_budget = -1.0"
+        .parse::<Config>()
+        .is_err());
+    assert!("[matching]\nanneal_budget = nan".parse::<Config>().is_err());
+    assert!("[matching]\nanneal_budget = inf".parse::<Config>().is_err());
+    assert!("[matching]\nga_population = 0".parse::<Config>().is_err());
+    assert!("[matching]\nfixed_rotation = 1".parse::<Config>().is_err());
+    assert!("[gui]\nenable = true".parse::<Config>().is_err());
+    assert!("[gui]\nwindow_size = \"800\"".parse::<Config>().is_err());
+    assert!("[gui]\nscale = 0".parse::<Config>().is_err());
+    assert!("search = 1".parse::<Config>().is_err());
+}
@@ -0,0 +1,86 @@
+//! ライブラリの公開 API が panic の代わりに返すエラー.
+
+use thiserror::Error;
+
+use crate::grid::Pos;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// 問題ファイルが壊れている
+    #[error("invalid problem: {0}")]
+    InvalidProblem(String),
+
+    /// 選択回数の上限が 0 なので, 断片を動かせない
+    #[error("the select limit must be positive to move fragments")]
+    NoSelection,
+
+    /// 移動元か移動先が盤面の外にある
+    #[error("{0:?} is out of the grid")]
+    OutOfGrid(Pos),
+
+    /// 同じマスから 2 回以上移動している
+    #[error("the fragment at {0:?} is moved more than once")]
+    DuplicateSource(Pos),
+
+    /// 同じマスへ 2 回以上移動している
+    #[error("more than one fragment is moved to {0:?}")]
+    DuplicateTarget(Pos),
+
+    /// 移動先にあった断片がどこにも移動していないので, 置換になっていない
+    #[error("a fragment is moved to {0:?}, but the fragment there is not moved away")]
+    NotPermutation(Pos),
+
+    /// 盤面の端での回り込みを考えても, 2 つのマスが隣り合っていない
+    #[error("{to:?} is not next to {from:?}")]
+    NotAdjacent { from: Pos, to: Pos },
+
+    /// ロックされたマスを選択しようとした
+    #[error("the position was locked: {0:?}")]
+    Locked(Pos),
+
+    /// 断片の数が盤面のマスの数と合わない
+    #[error("expected {expected} fragments, but found {found}")]
+    FragmentCount { expected: usize, found: usize },
+
+    /// 問題画像のその位置にあるはずの断片が無い
+    #[error("the fragment at {0:?} is missing")]
+    MissingFragment(Pos),
+
+    /// 読み込んだ配置が壊れているか, 問題と合わない
+    #[error("invalid arrangement: {0}")]
+    InvalidArrangement(String),
+
+    /// 復元画像に断片が置かれなかったマスがある
+    #[error("no fragment was placed at {0:?}")]
+    Unfilled(Pos),
+
+    /// 組み立てたヒントの値が不正
+    #[error("invalid hint: {0}")]
+    InvalidHint(String),
+
+    /// ロックされた組で `locked` の断片の隣につなげた `blocked` の断片の辺が, ブロックリストに入っている
+    #[error("the fragment at {blocked:?} is locked next to {locked:?}, but blocked from it")]
+    ContradictoryHint { locked: Pos, blocked: Pos },
+
+    /// ブロックリストや回転の制限で, 隣に置ける断片が 1 つも残っていない
+    #[error("no fragment can be placed next to the fragment at {0:?}")]
+    NoCandidate(Pos),
+
+    /// より新しい再計算の要求が届いたので, 画像の復元を途中でやめた
+    #[error("abandoned for a newer request")]
+    Abandoned,
+
+    /// 画像の復元が一度も成功しなかった
+    #[error("pixel_match failed: {0}")]
+    SolverFailed(String),
+
+    /// `CancellationToken` でやめるよう求められたか, その期限を過ぎた
+    #[error("cancelled")]
+    Cancelled,
+
+    /// 操作列の候補が 1 つも見つからなかった
+    #[error("move_resolve found no answer")]
+    NoAnswer,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -0,0 +1,80 @@
+use crate::best_submission::Standing;
+
+/// `Verdict` は提出結果を受けてどうするべきかを表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// 不一致がなく, これ以上画像の復元をやり直す必要はない.
+    Solved,
+    /// 不一致が残っているので, 画像の復元をやり直す.
+    Retry { round: usize, mismatch_count: usize },
+    /// やり直しの上限に達した, または一度も受理されなかった.
+    GiveUp,
+}
+
+/// `Feedback` はサーバから返された不一致数を元に, 画像の復元をやり直すかどうかを判断する.
+#[derive(Debug, Clone)]
+pub struct Feedback {
+    max_rounds: usize,
+    round: usize,
+}
+
+impl Feedback {
+    /// 最初の復元も含めて最大 `max_rounds` 回まで復元を行う.
+    pub fn new(max_rounds: usize) -> Self {
+        Self {
+            max_rounds,
+            round: 1,
+        }
+    }
+
+    pub fn round(&self) -> usize {
+        self.round
+    }
+
+    /// これまでで最も良い提出結果 `best` から次にどうするかを決める.
+    pub fn judge(&mut self, best: Option<Standing>) -> Verdict {
+        match best {
+            Some(Standing {
+                mismatch_count: 0, ..
+            }) => Verdict::Solved,
+            Some(Standing { mismatch_count, .. }) if self.round < self.max_rounds => {
+                self.round += 1;
+                Verdict::Retry {
+                    round: self.round,
+                    mismatch_count,
+                }
+            }
+            _ => Verdict::GiveUp,
+        }
+    }
+}
+
+#[test]
+fn test_feedback() {
+    let standing = |mismatch_count| {
+        Some(Standing {
+            mismatch_count,
+            cost: 100,
+        })
+    };
+
+    let mut feedback = Feedback::new(3);
+    assert_eq!(
+        Verdict::Retry {
+            round: 2,
+            mismatch_count: 4
+        },
+        feedback.judge(standing(4))
+    );
+    assert_eq!(
+        Verdict::Retry {
+            round: 3,
+            mismatch_count: 2
+        },
+        feedback.judge(standing(2))
+    );
+    assert_eq!(Verdict::GiveUp, feedback.judge(standing(2)));
+    assert_eq!(Verdict::Solved, feedback.judge(standing(0)));
+
+    assert_eq!(Verdict::GiveUp, Feedback::new(3).judge(None));
+}
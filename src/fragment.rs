@@ -1,3 +1,4 @@
+pub mod arrangement;
 pub mod map_fragment;
 #[cfg(test)]
 mod tests;
@@ -6,6 +7,7 @@ use std::borrow::Cow;
 
 use crate::{
     basis::{Color, Dir, Image, Problem, Rot},
+    error::Error,
     grid::{Grid, Pos},
 };
 
@@ -63,6 +65,15 @@ impl Edges {
     }
 }
 
+/// `PixelRect` は断片画像の中の, 左上が `(x, y)` で幅 `width`, 高さ `height` の長方形の範囲を表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
 /// `Fragment` は原画像から切り取った断片画像を表す.
 #[derive(Debug, Clone)]
 pub struct Fragment {
@@ -89,6 +100,28 @@ impl Fragment {
         self.pixels.get(self.rot)
     }
 
+    /// 回転させた断片画像の, 上から `y` 番目の行のピクセル列.
+    pub fn row(&mut self, y: usize) -> &[Color] {
+        let side = self.side_length();
+        assert!(y < side, "the row {} is out of the fragment", y);
+        &self.pixels()[y * side..][..side]
+    }
+
+    /// 回転させた断片画像の `rect` の範囲を, 上の行から順に返す.
+    pub fn crop(&mut self, rect: PixelRect) -> impl Iterator<Item = &[Color]> + '_ {
+        let side = self.side_length();
+        assert!(
+            rect.x + rect.width <= side && rect.y + rect.height <= side,
+            "{:?} is out of the fragment",
+            rect
+        );
+        self.pixels()
+            .chunks_exact(side)
+            .skip(rect.y)
+            .take(rect.height)
+            .map(move |row| &row[rect.x..][..rect.width])
+    }
+
     pub fn apply_rotate(&mut self) {
         let _ = self.pixels.get(self.rot);
 
@@ -96,10 +129,12 @@ impl Fragment {
         self.rot = Rot::R0;
     }
 
+    /// 問題画像を横に `dims.width` 個, 縦に `dims.height` 個の断片に分割する. 断片は `Grid::all_pos` の順に並ぶ.
+    ///
+    /// 画像をその数の正方形の断片に分割できないか, ピクセルの数が画像の大きさと合わなければ `Error::InvalidProblem` を返す.
     pub fn new_all(
         &Problem {
-            rows,
-            cols,
+            dims,
             image:
                 Image {
                     width,
@@ -108,23 +143,24 @@ impl Fragment {
                 },
             ..
         }: &Problem,
-    ) -> Vec<Self> {
-        let frag_edge = width / rows as u16;
-        debug_assert_eq!(frag_edge, height / cols as u16, "Fragment must be a square");
-        let grid = Grid::new(rows, cols);
-
-        let mut frags = vec![];
-        for col in 0..cols {
-            for row in 0..rows {
-                frags.push(Self::new(
-                    pixels,
-                    grid.clamping_pos(row, col),
-                    width as usize,
-                    frag_edge,
-                ));
-            }
+    ) -> crate::Result<Vec<Self>> {
+        crate::image::validate_split((width, height), dims)
+            .map_err(|e| Error::InvalidProblem(format!("{:#}", e)))?;
+        if pixels.len() != width as usize * height as usize {
+            return Err(Error::InvalidProblem(format!(
+                "the image {}x{} must have {} pixels, but found {}",
+                width,
+                height,
+                width as usize * height as usize,
+                pixels.len()
+            )));
         }
-        frags
+        let frag_edge = width / dims.width as u16;
+        let grid = Grid::from(dims);
+        Ok(grid
+            .all_pos()
+            .map(|pos| Self::new(pixels, pos, width as usize, frag_edge))
+            .collect())
     }
 
     fn new(pixels: &[Color], pos: Pos, whole_width: usize, frag_edge: u16) -> Self {
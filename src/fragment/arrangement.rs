@@ -0,0 +1,258 @@
+#[cfg(feature = "serde")]
+use crate::error::Error;
+use crate::{
+    basis::Rot,
+    fragment::{map_fragment::map_fragment, Fragment},
+    grid::{Grid, Pos, VecOnGrid},
+    simulator,
+};
+
+/// `RecoveredArrangement` は pixel_match で復元した断片の配置を表す.
+///
+/// 復元画像は復元後の位置の順に断片を並べたものだが, 回答の回転情報は問題画像での位置の順に並べなければならない.
+/// 取り違えないように, 問題画像での位置から引く方法をここにまとめる.
+#[derive(Debug, Clone)]
+pub struct RecoveredArrangement {
+    recovered: VecOnGrid<Fragment>,
+    /// 問題画像の各位置にある断片の, 復元画像での位置と回転
+    by_original: VecOnGrid<(Pos, Rot)>,
+}
+
+impl RecoveredArrangement {
+    pub fn new(recovered: VecOnGrid<Fragment>) -> Self {
+        let by_original = simulator::truth_from_recovered(&recovered);
+        Self {
+            recovered,
+            by_original,
+        }
+    }
+
+    pub fn grid(&self) -> Grid {
+        self.recovered.grid
+    }
+
+    /// 復元後の位置の順に並べた断片.
+    pub fn recovered(&self) -> &VecOnGrid<Fragment> {
+        &self.recovered
+    }
+
+    pub fn into_recovered(self) -> VecOnGrid<Fragment> {
+        self.recovered
+    }
+
+    /// 問題画像で `original_pos` にある断片を回転させる量.
+    pub fn rotation_of(&self, original_pos: Pos) -> Rot {
+        self.by_original[original_pos].1
+    }
+
+    /// 問題画像で `original_pos` にある断片の, 復元画像での位置.
+    pub fn position_of(&self, original_pos: Pos) -> Pos {
+        self.by_original[original_pos].0
+    }
+
+    /// 問題画像での位置の順に並べた回転. `kaitou::ans` にそのまま渡せる.
+    pub fn rotations(&self) -> Vec<Rot> {
+        self.by_original.iter().map(|&(_, rot)| rot).collect()
+    }
+
+    /// 問題画像の各位置にある断片の, 復元画像での位置と回転. `simulator::simulate` の `truth` に使える.
+    pub fn truth(&self) -> &VecOnGrid<(Pos, Rot)> {
+        &self.by_original
+    }
+
+    /// 断片を問題画像の配置から復元画像の配置に動かす移動. `move_resolve::resolve` に渡せる.
+    pub fn movements(&self) -> Vec<(Pos, Pos)> {
+        map_fragment(&self.recovered)
+    }
+
+    /// `movements` と `rotations` を一緒に求める. 回答を作るときはこれを使えば, 移動と回転の順番を取り違えない.
+    pub fn movements_and_rotations(&self) -> (Vec<(Pos, Pos)>, Vec<Rot>) {
+        (self.movements(), self.rotations())
+    }
+
+    /// 復元画像の各位置に置いた断片の, 問題画像での位置と回転を JSON で書き出す.
+    ///
+    /// テキストエディタで直しやすいように, 1 マスを 1 行に書く.
+    ///
+    /// ```json
+    /// {
+    ///   "grid": {"width":2,"height":1},
+    ///   "cells": [
+    ///     {"pos":[0,0],"original":[1,0],"rot":"R0"},
+    ///     {"pos":[1,0],"original":[0,0],"rot":"R270"}
+    ///   ]
+    /// }
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        let cells: Vec<_> = self
+            .recovered
+            .iter_with_pos()
+            .map(|(pos, fragment)| {
+                let cell = ArrangedCell {
+                    pos,
+                    original: fragment.pos,
+                    rot: fragment.rot,
+                };
+                format!("    {}", serde_json::to_string(&cell).unwrap())
+            })
+            .collect();
+        format!(
+            "{{\n  \"grid\": {},\n  \"cells\": [\n{}\n  ]\n}}\n",
+            serde_json::to_string(&self.grid()).unwrap(),
+            cells.join(",\n")
+        )
+    }
+
+    /// `to_json` で書き出した配置を読み込み, 回転させていない `fragments` を並べ直す.
+    ///
+    /// `grid` の全てのマスに, 問題画像の全ての断片をちょうど 1 つずつ置いていなければエラーを返す.
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str, grid: Grid, fragments: &[Fragment]) -> crate::Result<Self> {
+        let ArrangementJson {
+            grid: json_grid,
+            cells,
+        } = serde_json::from_str(json).map_err(|e| Error::InvalidArrangement(e.to_string()))?;
+        if json_grid != grid {
+            return Err(Error::InvalidArrangement(format!(
+                "the arrangement is {:?}, but the problem is {:?}",
+                json_grid, grid
+            )));
+        }
+
+        let mut recovered = VecOnGrid::<Option<Fragment>>::with_default(grid);
+        let mut used = VecOnGrid::with_init(grid, false);
+        for ArrangedCell { pos, original, rot } in cells {
+            for p in [pos, original] {
+                if !grid.is_pos_valid(p) {
+                    return Err(Error::OutOfGrid(p));
+                }
+            }
+            if recovered[pos].is_some() || used[original] {
+                return Err(Error::InvalidArrangement(format!(
+                    "{:?} or {:?} appears more than once",
+                    pos, original
+                )));
+            }
+            used[original] = true;
+
+            let mut fragment = fragments
+                .iter()
+                .find(|f| f.pos == original)
+                .ok_or(Error::MissingFragment(original))?
+                .clone();
+            fragment.rotate(rot);
+            recovered[pos] = Some(fragment);
+        }
+        let recovered = recovered
+            .into_iter_with_pos()
+            .map(|(pos, cell)| cell.ok_or(Error::Unfilled(pos)))
+            .collect::<crate::Result<_>>()?;
+        Ok(Self::new(VecOnGrid::from_vec(grid, recovered).unwrap()))
+    }
+}
+
+/// JSON に書き出す, 復元画像の 1 マスに置いた断片.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ArrangedCell {
+    /// 復元画像での位置
+    pos: Pos,
+    /// 問題画像での位置
+    original: Pos,
+    rot: Rot,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ArrangementJson {
+    grid: Grid,
+    cells: Vec<ArrangedCell>,
+}
+
+#[test]
+fn test_ordering() {
+    let problem = include_bytes!("../../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let mut fragments = Fragment::new_all(&problem).unwrap();
+
+    // 問題画像の (0, 0) にある断片を復元画像の (1, 1) に 90 度回して置くような配置
+    // 復元画像: (01):0 (10):0
+    //           (11):0 (00):1
+    let order = [(0, 1), (1, 0), (1, 1), (0, 0)];
+    let mut recovered = Vec::new();
+    for (x, y) in order {
+        let index = fragments
+            .iter()
+            .position(|f| f.pos == grid.pos(x, y))
+            .unwrap();
+        recovered.push(fragments.remove(index));
+    }
+    recovered[3].rotate(Rot::R90);
+    let arrangement = RecoveredArrangement::new(VecOnGrid::from_vec(grid, recovered).unwrap());
+
+    assert_eq!(grid.pos(1, 1), arrangement.position_of(grid.pos(0, 0)));
+    assert_eq!(grid.pos(0, 0), arrangement.position_of(grid.pos(0, 1)));
+    assert_eq!(Rot::R90, arrangement.rotation_of(grid.pos(0, 0)));
+    assert_eq!(Rot::R0, arrangement.rotation_of(grid.pos(1, 1)));
+    // 回転は復元画像の順 (0, 0, 0, 1) ではなく問題画像の順に並ぶ
+    assert_eq!(
+        vec![Rot::R90, Rot::R0, Rot::R0, Rot::R0],
+        arrangement.rotations()
+    );
+    assert_eq!(
+        crate::simulator::truth_from_recovered(arrangement.recovered()),
+        *arrangement.truth()
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_json() {
+    let problem = include_bytes!("../../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+
+    let json = r#"{
+  "grid": {"width":2,"height":2},
+  "cells": [
+    {"pos":[0,0],"original":[1,1],"rot":"R0"},
+    {"pos":[1,0],"original":[0,1],"rot":"R270"},
+    {"pos":[0,1],"original":[1,0],"rot":"R90"},
+    {"pos":[1,1],"original":[0,0],"rot":"R180"}
+  ]
+}
+"#;
+    let arrangement = RecoveredArrangement::from_json(json, grid, &fragments).unwrap();
+    assert_eq!(grid.pos(1, 0), arrangement.position_of(grid.pos(0, 1)));
+    assert_eq!(Rot::R270, arrangement.rotation_of(grid.pos(0, 1)));
+    assert_eq!(json, arrangement.to_json());
+
+    let broken = [
+        // 盤面の大きさが違う
+        json.replace(r#""height":2"#, r#""height":3"#),
+        // 同じ断片を 2 回置いている
+        json.replace(r#""original":[0,0]"#, r#""original":[1,1]"#),
+        // 埋まっていないマスがある
+        json.replace(
+            ",\n    {\"pos\":[1,1],\"original\":[0,0],\"rot\":\"R180\"}",
+            "",
+        ),
+        json.replace(r#""rot":"R90""#, r#""rot":"R45""#),
+    ];
+    for json in broken {
+        assert!(RecoveredArrangement::from_json(&json, grid, &fragments).is_err());
+    }
+    assert!(matches!(
+        RecoveredArrangement::from_json(
+            &json.replace(r#""pos":[1,1]"#, r#""pos":[2,1]"#),
+            grid,
+            &fragments
+        ),
+        Err(Error::OutOfGrid(_))
+    ));
+}
@@ -1,5 +1,6 @@
 use crate::{
-    fragment::Fragment,
+    basis::Rot,
+    fragment::{arrangement::RecoveredArrangement, Fragment},
     grid::{Pos, VecOnGrid},
 };
 
@@ -17,6 +18,13 @@ pub fn map_fragment(matched: &VecOnGrid<Fragment>) -> Vec<(Pos, Pos)> {
     map
 }
 
+/// `map_fragment` の移動と一緒に, 各断片の回転を問題画像での位置の順に並べて求める.
+///
+/// 位置が正しく回転だけ違う断片は移動に含まれないが, 回転には含まれる. 回転は [`crate::kaitou::ans`] にそのまま渡せる.
+pub fn map_arrangement(matched: &VecOnGrid<Fragment>) -> (Vec<(Pos, Pos)>, Vec<Rot>) {
+    RecoveredArrangement::new(matched.clone()).movements_and_rotations()
+}
+
 #[test]
 fn test_map() {
     use crate::{
@@ -53,3 +61,35 @@ fn test_map() {
 
     assert_eq!(expected, actual);
 }
+
+#[test]
+fn test_map_arrangement() {
+    let problem = include_bytes!("../../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let mut fragments = Fragment::new_all(&problem).unwrap();
+
+    // (0, 0) と (1, 0) を入れ替え, 正しい位置にある (1, 1) を回転だけさせる
+    fragments.swap(0, 1);
+    fragments[0].rotate(Rot::R90);
+    fragments[3].rotate(Rot::R180);
+    let matched = VecOnGrid::from_vec(grid, fragments).unwrap();
+
+    let (movements, rots) = map_arrangement(&matched);
+    assert_eq!(
+        vec![
+            (grid.pos(0, 0), grid.pos(1, 0)),
+            (grid.pos(1, 0), grid.pos(0, 0))
+        ],
+        movements
+    );
+    // 回転は復元画像の順ではなく問題画像の順に並ぶ
+    assert_eq!(vec![Rot::R0, Rot::R90, Rot::R0, Rot::R180], rots);
+    assert_eq!(
+        crate::simulator::truth_from_recovered(&matched)
+            .into_iter()
+            .map(|(_, rot)| rot)
+            .collect::<Vec<_>>(),
+        rots
+    );
+}
@@ -1,8 +1,9 @@
 use {
     crate::{
-        basis::{Color, Dir, Rot},
-        fragment::Fragment,
-        grid::Grid,
+        basis::{Color, Dir, Image, Problem, Rot},
+        error::Error,
+        fragment::{Fragment, PixelRect},
+        grid::{Dims, Grid},
     },
     std::io::{self, Read, Result},
 };
@@ -26,13 +27,11 @@ fn pixels(to_skip: u64, width: usize, height: usize, path: &str) -> Result<Vec<C
 fn case1() -> Result<()> {
     let width = 180;
     let frag_edge = 60;
-    let rows = 3usize;
-    let cols = 2usize;
     let pixels = pixels(32, width, 120, "test_cases/02_sampled.ppm")?;
-    let grid = Grid::new(rows as u8, cols as u8);
+    let grid = Grid::new(3, 2);
 
-    for y in 0..cols {
-        for x in 0..rows {
+    for y in 0..grid.height() as usize {
+        for x in 0..grid.width() as usize {
             let pos = grid.pos(x as u8, y as u8);
             let frag = Fragment::new(&pixels, pos, width, frag_edge as u16);
 
@@ -76,3 +75,124 @@ fn case1() -> Result<()> {
     }
     Ok(())
 }
+
+#[test]
+fn row_and_crop() {
+    // 0 1 2
+    // 3 4 5
+    // 6 7 8
+    let pixels: Vec<_> = (0..9).map(|i| Color { r: i, g: 0, b: 0 }).collect();
+    let grid = Grid::new(1, 1);
+    let mut frag = Fragment::new(&pixels, grid.pos(0, 0), 3, 3);
+    let reds = |row: &[Color]| row.iter().map(|c| c.r).collect::<Vec<_>>();
+
+    assert_eq!(vec![3, 4, 5], reds(frag.row(1)));
+    let rect = PixelRect {
+        x: 1,
+        y: 1,
+        width: 2,
+        height: 2,
+    };
+    assert_eq!(
+        vec![vec![4, 5], vec![7, 8]],
+        frag.crop(rect).map(reds).collect::<Vec<_>>()
+    );
+
+    // 6 3 0
+    // 7 4 1
+    // 8 5 2
+    frag.rotate(Rot::R90);
+    assert_eq!(vec![6, 3, 0], reds(frag.row(0)));
+    assert_eq!(
+        vec![vec![4, 1], vec![5, 2]],
+        frag.crop(rect).map(reds).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        0,
+        frag.crop(PixelRect {
+            x: 3,
+            y: 0,
+            width: 0,
+            height: 3,
+        })
+        .map(<[Color]>::len)
+        .sum::<usize>()
+    );
+}
+
+#[test]
+fn new_all_rejects_bad_headers() {
+    let problem = |(grid_width, grid_height), (width, height), len| Problem {
+        select_limit: 2,
+        select_cost: 1,
+        swap_cost: 1,
+        dims: Dims {
+            width: grid_width,
+            height: grid_height,
+        },
+        image: Image {
+            width,
+            height,
+            pixels: vec![Color { r: 0, g: 0, b: 0 }; len],
+        },
+    };
+    let is_invalid =
+        |problem: Problem| matches!(Fragment::new_all(&problem), Err(Error::InvalidProblem(_)));
+
+    assert_eq!(
+        6,
+        Fragment::new_all(&problem((3, 2), (48, 32), 48 * 32))
+            .unwrap()
+            .len()
+    );
+    // 幅が分割数で割り切れない
+    assert!(is_invalid(problem((3, 2), (50, 32), 50 * 32)));
+    // 断片が正方形にならない
+    assert!(is_invalid(problem((3, 2), (48, 40), 48 * 40)));
+    // ピクセルが足りない
+    assert!(is_invalid(problem((3, 2), (48, 32), 48 * 31)));
+    assert!(is_invalid(problem((0, 2), (48, 32), 48 * 32)));
+}
+
+#[test]
+fn new_all_non_square_grid() {
+    // 断片の各ピクセルに, 問題画像での断片の位置を書いておく
+    let dims = Dims {
+        width: 3,
+        height: 2,
+    };
+    let side = 4;
+    let (width, height) = (side * dims.width as usize, side * dims.height as usize);
+    let pixels = (0..width * height)
+        .map(|i| Color {
+            r: ((i % width) / side) as u8,
+            g: ((i / width) / side) as u8,
+            b: 0,
+        })
+        .collect();
+    let problem = Problem {
+        select_limit: 2,
+        select_cost: 1,
+        swap_cost: 1,
+        dims,
+        image: Image {
+            width: width as u16,
+            height: height as u16,
+            pixels,
+        },
+    };
+
+    let grid = problem.grid();
+    assert_eq!((3, 2), (grid.width(), grid.height()));
+    assert_eq!(dims, grid.dims());
+    let mut fragments = Fragment::new_all(&problem).unwrap();
+    assert_eq!(6, fragments.len());
+    for fragment in &mut fragments {
+        let expected = Color {
+            r: fragment.pos.x(),
+            g: fragment.pos.y(),
+            b: 0,
+        };
+        assert!(fragment.pixels().iter().all(|&c| c == expected));
+    }
+}
@@ -1,9 +1,16 @@
+pub use traversal::{Corner, Traversal};
 pub use vec_on_grid::*;
 
+use crate::basis::Movement;
+
 pub mod board;
 pub mod on_grid;
+mod traversal;
 mod vec_on_grid;
 
+/// `Pos` は座標を 4 ビットずつで表すので, `Grid` の幅と高さはこれ以下でなければならない.
+pub const MAX_GRID_SIDE: u8 = 16;
+
 /// `Pos` は `Grid` に存在する座標を表す.
 ///
 /// フィールドの `u8` の上位 4 ビットに X 座標, 下位 4 ビットに Y 座標を格納する. それぞれは必ず `Grid` の `width` と `height` 未満になる.
@@ -37,50 +44,168 @@ impl Pos {
     }
 }
 
+/// `Pos` は外部のツールから読みやすいように `[x, y]` の組としてシリアライズする.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Pos {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.x(), self.y()).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Pos {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (x, y) = <(u8, u8)>::deserialize(deserializer)?;
+        if 0xf < x || 0xf < y {
+            return Err(serde::de::Error::custom(format_args!(
+                "coordinate out of range: ({}, {})",
+                x, y
+            )));
+        }
+        Ok(Pos::new(x, y))
+    }
+}
+
 /// `RangePos` は `Grid` 上の矩形領域を表し, `Iterator` で走査できる.
+///
+/// 走査は行優先で, 両端から取り出せる.
 #[derive(Debug, Clone)]
 pub struct RangePos {
     start: Pos,
     end: Pos,
-    x: usize,
-    y: usize,
+    front: usize,
+    back: usize,
 }
 
 impl RangePos {
+    fn new(start: Pos, end: Pos) -> Self {
+        let mut range = Self {
+            start,
+            end,
+            front: 0,
+            back: 0,
+        };
+        range.back = range.width() * range.height();
+        range
+    }
+
     pub fn single(pos: Pos) -> Self {
-        Self {
-            start: pos,
-            end: pos,
-            x: pos.x() as _,
-            y: pos.y() as _,
-        }
+        Self::new(pos, pos)
+    }
+
+    /// 矩形領域の左上の座標を返す.
+    pub fn start(&self) -> Pos {
+        self.start
     }
 
-    pub fn is_in(&self, pos: Pos) -> bool {
+    /// 矩形領域の右下の座標を返す.
+    pub fn end(&self) -> Pos {
+        self.end
+    }
+
+    /// `pos` が矩形領域に含まれるかどうかを返す. 走査の進み具合には依らない.
+    pub fn contains(&self, pos: Pos) -> bool {
         (self.start.x()..=self.end.x()).contains(&pos.x())
             && (self.start.y()..=self.end.y()).contains(&pos.y())
     }
+
+    fn width(&self) -> usize {
+        (self.end.x() - self.start.x()) as usize + 1
+    }
+
+    fn height(&self) -> usize {
+        (self.end.y() - self.start.y()) as usize + 1
+    }
+
+    fn pos_at(&self, index: usize) -> Pos {
+        let width = self.width();
+        Pos::new(
+            self.start.x() + (index % width) as u8,
+            self.start.y() + (index / width) as u8,
+        )
+    }
 }
 
 impl Iterator for RangePos {
     type Item = Pos;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if (self.end.y() as usize) < self.y {
+        if self.back <= self.front {
             return None;
         }
-        let ret = Pos::new(self.x as u8, self.y as u8);
-        self.x += 1;
-        if (self.end.x() as usize) < self.x {
-            self.y += 1;
-            self.x = self.start.x() as usize;
-        }
+        let ret = self.pos_at(self.front);
+        self.front += 1;
         Some(ret)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back.saturating_sub(self.front);
+        (len, Some(len))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.front = self.front.saturating_add(n).min(self.back);
+        self.next()
+    }
+}
+
+impl DoubleEndedIterator for RangePos {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.back <= self.front {
+            return None;
+        }
+        self.back -= 1;
+        Some(self.pos_at(self.back))
+    }
+}
+
+impl ExactSizeIterator for RangePos {}
+
+impl std::iter::FusedIterator for RangePos {}
+
+/// `Overflow` は `Grid::offset` で座標が盤面の端からはみ出したときの扱いを表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// 反対側の端に回り込む. 盤面を何周してもよい
+    Wrap,
+    /// はみ出した端で止まる
+    Saturate,
+    /// はみ出したら `None` にする
+    Checked,
+}
+
+impl Overflow {
+    /// 長さ `len` の軸の上の座標 `value` を盤面の中に収める.
+    fn apply(self, value: i32, len: u8) -> Option<u8> {
+        let len = len as i32;
+        match self {
+            Overflow::Wrap => Some(value.rem_euclid(len) as u8),
+            Overflow::Saturate => Some(value.clamp(0, len - 1) as u8),
+            Overflow::Checked => (0..len).contains(&value).then_some(value as u8),
+        }
+    }
+}
+
+/// `Dims` は盤面の横と縦に並ぶ断片の数を表す.
+///
+/// 行と列の数と呼ぶとどちらが横か取り違えやすいので, 幅と高さとして持つ. 問題のヘッダには幅を先に書く.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Dims {
+    /// 横に並ぶ断片の数
+    pub width: u8,
+    /// 縦に並ぶ断片の数
+    pub height: u8,
+}
+
+impl From<Dims> for Grid {
+    fn from(Dims { width, height }: Dims) -> Self {
+        Self::new(width, height)
+    }
 }
 
 /// `Grid` は原画像を断片画像に分ける時の分割グリッドを表す. `Pos` はこれを介してのみ作成できる.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Grid {
     width: u8,
     height: u8,
@@ -99,6 +224,13 @@ impl Grid {
         self.height
     }
 
+    pub fn dims(&self) -> Dims {
+        Dims {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
     pub fn is_pos_valid(&self, pos: Pos) -> bool {
         pos.x() < self.width && pos.y() < self.height
     }
@@ -116,21 +248,36 @@ impl Grid {
     pub fn range(&self, up_left: Pos, down_right: Pos) -> RangePos {
         assert!(up_left.x() <= down_right.x());
         assert!(up_left.y() <= down_right.y());
-        RangePos {
-            start: up_left,
-            end: down_right,
-            x: up_left.x() as usize,
-            y: up_left.y() as usize,
-        }
+        RangePos::new(up_left, down_right)
     }
 
     pub fn all_pos(&self) -> RangePos {
-        RangePos {
-            start: Pos::new(0, 0),
-            end: Pos::new(self.width - 1, self.height - 1),
-            x: 0,
-            y: 0,
-        }
+        RangePos::new(Pos::new(0, 0), Pos::new(self.width - 1, self.height - 1))
+    }
+
+    /// `pos` を `movement` の方向に 1 つ動かした位置. 端からはみ出すと反対側に回り込む.
+    pub fn looping_move(&self, pos: Pos, movement: Movement) -> Pos {
+        let (dx, dy) = movement.delta();
+        self.offset(pos, dx, dy, Overflow::Wrap)
+            .expect("wrapping offset never leaves the grid")
+    }
+
+    /// `pos` の上, 右, 下, 左の位置を順に返す. 端では反対側に回り込む.
+    pub fn looping_neighbors(&self, pos: Pos) -> impl Iterator<Item = Pos> {
+        let grid = *self;
+        Movement::ALL
+            .into_iter()
+            .map(move |movement| grid.looping_move(pos, movement))
+    }
+
+    /// `pos` から `(dx, dy)` だけ動かした位置. 盤面の端からはみ出したときは `overflow` に従う.
+    ///
+    /// `Overflow::Checked` ではみ出したときだけ `None` を返す.
+    pub fn offset(&self, pos: Pos, dx: i32, dy: i32, overflow: Overflow) -> Option<Pos> {
+        debug_assert!(self.is_pos_valid(pos), "{:?} is out of {:?}", pos, self);
+        let x = overflow.apply(pos.x() as i32 + dx, self.width)?;
+        let y = overflow.apply(pos.y() as i32 + dy, self.height)?;
+        Some(Pos::new(x, y))
     }
 
     fn pos_as_index(&self, pos: Pos) -> usize {
@@ -142,39 +289,30 @@ impl Grid {
         manhattan_dist(vec) as u32
     }
 
+    /// `from` から `to` へ, 端を回り込むことも含めて最短で向かうときの移動量 `(dx, dy)` を返す.
+    ///
+    /// 各成分の絶対値は盤面の幅と高さの半分以下になる. 両回りで同じ距離になるときは回り込まない向きを返す.
     pub fn looping_min_vec(&self, from: Pos, to: Pos) -> (i32, i32) {
-        let width = self.width as i32;
-        let height = self.height as i32;
-        let to_x = to.x() as i32;
-        let to_y = to.y() as i32;
-        let other_points = match (from.x() < self.width / 2, from.y() < self.height / 2) {
-            (true, true) => [
-                (to_x - width, to_y),
-                (to_x, to_y - height),
-                (to_x - width, to_y - height),
-            ],
-            (true, false) => [
-                (to_x - width, to_y),
-                (to_x, to_y + height),
-                (to_x - width, to_y + height),
-            ],
-            (false, true) => [
-                (to_x + width, to_y),
-                (to_x, to_y - height),
-                (to_x + width, to_y - height),
-            ],
-            (false, false) => [
-                (to_x + width, to_y),
-                (to_x, to_y + height),
-                (to_x + width, to_y + height),
-            ],
-        };
-        std::iter::once(&(to_x, to_y))
-            .chain(other_points.iter())
-            .cloned()
-            .map(|(to_x, to_y)| (to_x - from.x() as i32, to_y - from.y() as i32))
-            .min_by(|&a, &b| manhattan_dist(a).cmp(&manhattan_dist(b)))
-            .unwrap()
+        (
+            looping_min_delta(from.x(), to.x(), self.width),
+            looping_min_delta(from.y(), to.y(), self.height),
+        )
+    }
+}
+
+/// 長さ `len` でループする軸上で `from` から `to` へ最短で向かう移動量.
+fn looping_min_delta(from: u8, to: u8, len: u8) -> i32 {
+    let direct = to as i32 - from as i32;
+    let len = len as i32;
+    let wrapped = if direct < 0 {
+        direct + len
+    } else {
+        direct - len
+    };
+    if wrapped.abs() < direct.abs() {
+        wrapped
+    } else {
+        direct
     }
 }
 
@@ -205,6 +343,53 @@ fn test_looping_manhattan_dist() {
     }
 }
 
+#[test]
+fn test_offset() {
+    let grid = Grid::new(4, 3);
+    let pos = grid.pos(3, 0);
+
+    assert_eq!(
+        Some(grid.pos(2, 1)),
+        grid.offset(pos, -1, 1, Overflow::Checked)
+    );
+    assert_eq!(None, grid.offset(pos, 1, 0, Overflow::Checked));
+    assert_eq!(None, grid.offset(pos, 0, -1, Overflow::Checked));
+
+    assert_eq!(
+        Some(grid.pos(0, 2)),
+        grid.offset(pos, 1, -1, Overflow::Wrap)
+    );
+    // 何周しても回り込む
+    assert_eq!(
+        Some(grid.pos(1, 1)),
+        grid.offset(pos, 10, -5, Overflow::Wrap)
+    );
+    assert_eq!(Some(pos), grid.offset(pos, -8, 6, Overflow::Wrap));
+
+    assert_eq!(
+        Some(grid.pos(3, 0)),
+        grid.offset(pos, 1, -1, Overflow::Saturate)
+    );
+    assert_eq!(
+        Some(grid.pos(0, 2)),
+        grid.offset(pos, -10, 10, Overflow::Saturate)
+    );
+
+    for pos in grid.all_pos() {
+        for movement in [
+            Movement::Up,
+            Movement::Right,
+            Movement::Down,
+            Movement::Left,
+        ] {
+            let moved = grid.looping_move(pos, movement);
+            assert!(grid.is_pos_valid(moved));
+            assert_eq!(1, grid.looping_manhattan_dist(pos, moved));
+            assert_eq!(pos, grid.looping_move(moved, movement.opposite()));
+        }
+    }
+}
+
 #[test]
 fn test_looping_min_vec() {
     let grid = Grid::new(5, 5);
@@ -212,4 +397,143 @@ fn test_looping_min_vec() {
         assert_eq!((0, 0), grid.looping_min_vec(p, p));
     }
     assert_eq!((1, 0), grid.looping_min_vec(grid.pos(4, 0), grid.pos(0, 0)));
+    assert_eq!(
+        (-1, 1),
+        grid.looping_min_vec(grid.pos(0, 4), grid.pos(4, 0))
+    );
+    assert_eq!(
+        (2, -2),
+        grid.looping_min_vec(grid.pos(0, 2), grid.pos(2, 0))
+    );
+
+    // 両回りで同じ距離なら回り込まない
+    let grid = Grid::new(4, 2);
+    assert_eq!((2, 1), grid.looping_min_vec(grid.pos(0, 0), grid.pos(2, 1)));
+    assert_eq!(
+        (-2, -1),
+        grid.looping_min_vec(grid.pos(2, 1), grid.pos(0, 0))
+    );
+}
+
+#[cfg(test)]
+fn arbitrary_grid_and_pos() -> impl proptest::strategy::Strategy<Value = (Grid, Pos, Pos)> {
+    use proptest::prelude::*;
+
+    (2u8..=16, 2u8..=16).prop_flat_map(|(width, height)| {
+        let grid = Grid::new(width, height);
+        let pos = (0..width, 0..height).prop_map(move |(x, y)| grid.pos(x, y));
+        (Just(grid), pos.clone(), pos)
+    })
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_looping_min_vec_reaches((grid, from, to) in arbitrary_grid_and_pos()) {
+        let (dx, dy) = grid.looping_min_vec(from, to);
+        proptest::prop_assert!(dx.abs() <= grid.width() as i32 / 2);
+        proptest::prop_assert!(dy.abs() <= grid.height() as i32 / 2);
+
+        let x_movement = if dx < 0 { Movement::Left } else { Movement::Right };
+        let y_movement = if dy < 0 { Movement::Up } else { Movement::Down };
+        let mut moved = from;
+        for _ in 0..dx.abs() {
+            moved = grid.looping_move(moved, x_movement);
+        }
+        for _ in 0..dy.abs() {
+            moved = grid.looping_move(moved, y_movement);
+        }
+        proptest::prop_assert_eq!(to, moved);
+    }
+
+    #[test]
+    fn test_looping_min_vec_is_shortest((grid, from, to) in arbitrary_grid_and_pos()) {
+        // 盤面を 3x3 に並べたときの `to` の写しのうち, 最も近いものまでの距離と比べる
+        let (width, height) = (grid.width() as i32, grid.height() as i32);
+        let shortest = (-1..=1)
+            .flat_map(|i| (-1..=1).map(move |j| (i * width, j * height)))
+            .map(|(ox, oy)| {
+                let dx = to.x() as i32 + ox - from.x() as i32;
+                let dy = to.y() as i32 + oy - from.y() as i32;
+                (dx.abs() + dy.abs()) as u32
+            })
+            .min()
+            .unwrap();
+        proptest::prop_assert_eq!(shortest, grid.looping_manhattan_dist(from, to));
+        proptest::prop_assert_eq!(
+            grid.looping_min_vec(from, to),
+            {
+                let (dx, dy) = grid.looping_min_vec(to, from);
+                (-dx, -dy)
+            }
+        );
+    }
+}
+
+#[test]
+fn test_range_pos() {
+    let grid = Grid::new(4, 3);
+
+    let all = grid.all_pos();
+    assert_eq!(12, all.len());
+    assert_eq!(grid.pos(0, 0), all.clone().next().unwrap());
+    assert_eq!(grid.pos(3, 2), all.clone().next_back().unwrap());
+    assert_eq!(
+        all.clone().collect::<Vec<_>>(),
+        all.clone()
+            .rev()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect::<Vec<_>>(),
+    );
+
+    let mut range = grid.range(grid.pos(1, 1), grid.pos(2, 2));
+    assert!(range.contains(grid.pos(2, 1)));
+    assert!(!range.contains(grid.pos(0, 1)));
+    assert!(!range.contains(grid.pos(3, 2)));
+    assert_eq!(Some(grid.pos(1, 1)), range.next());
+    assert_eq!(Some(grid.pos(2, 2)), range.next_back());
+    assert_eq!(2, range.len());
+    assert_eq!(Some(grid.pos(2, 1)), range.next());
+    assert_eq!(Some(grid.pos(1, 2)), range.next_back());
+    assert_eq!(0, range.len());
+    assert_eq!(None, range.next());
+    assert_eq!(None, range.next_back());
+    // 走査し終えても領域の判定は変わらない
+    assert!(range.contains(grid.pos(1, 1)));
+}
+
+#[test]
+fn test_range_pos_degenerate() {
+    let grid = Grid::new(4, 3);
+
+    let mut single = RangePos::single(grid.pos(2, 1));
+    assert_eq!(1, single.len());
+    assert!(single.contains(grid.pos(2, 1)));
+    assert!(!single.contains(grid.pos(1, 1)));
+    assert_eq!(Some(grid.pos(2, 1)), single.next_back());
+    assert_eq!(None, single.next());
+
+    let column = grid.range(grid.pos(3, 0), grid.pos(3, 2));
+    assert_eq!(
+        vec![grid.pos(3, 0), grid.pos(3, 1), grid.pos(3, 2)],
+        column.collect::<Vec<_>>()
+    );
+
+    let row = grid.range(grid.pos(0, 2), grid.pos(3, 2));
+    assert_eq!(
+        vec![
+            grid.pos(3, 2),
+            grid.pos(2, 2),
+            grid.pos(1, 2),
+            grid.pos(0, 2)
+        ],
+        row.rev().collect::<Vec<_>>()
+    );
+
+    let mut all = grid.all_pos();
+    assert_eq!(Some(grid.pos(1, 1)), all.nth(5));
+    assert_eq!(None, all.nth(100));
+    assert_eq!(None, all.next_back());
 }
@@ -3,6 +3,7 @@ use std::{hash::Hash, ops::Deref};
 use fxhash::FxHashSet as HashSet;
 
 use super::{Grid, Pos, VecOnGrid};
+use crate::basis::Movement;
 
 mod finder;
 
@@ -56,10 +57,18 @@ impl Board {
     }
 
     pub fn select(&mut self, to_select: Pos) {
+        if let Err(e) = self.try_select(to_select) {
+            panic!("{}", e);
+        }
+    }
+
+    /// `to_select` を選択する. ロックされていれば選択せずにエラーを返す.
+    pub fn try_select(&mut self, to_select: Pos) -> crate::Result<()> {
         if self.locked.contains(&to_select) {
-            panic!("the position was locked: {:?}", to_select);
+            return Err(crate::Error::Locked(to_select));
         }
         self.select.replace(to_select);
+        Ok(())
     }
 
     pub fn field(&'_ self) -> impl Deref<Target = VecOnGrid<Pos>> + std::fmt::Debug + '_ {
@@ -104,51 +113,33 @@ impl Board {
         }
     }
 
-    fn width(&self) -> u8 {
-        self.forward.grid.width()
-    }
-    fn height(&self) -> u8 {
-        self.forward.grid.height()
-    }
-
-    fn up_of(&self, pos: Pos) -> Pos {
-        if pos.y() == 0 {
-            Pos::new(pos.x(), self.height() - 1)
-        } else {
-            Pos::new(pos.x(), pos.y() - 1)
-        }
-    }
-    fn right_of(&self, pos: Pos) -> Pos {
-        if pos.x() + 1 == self.width() {
-            Pos::new(0, pos.y())
-        } else {
-            Pos::new(pos.x() + 1, pos.y())
-        }
-    }
-    fn down_of(&self, pos: Pos) -> Pos {
-        if pos.y() + 1 == self.height() {
-            Pos::new(pos.x(), 0)
-        } else {
-            Pos::new(pos.x(), pos.y() + 1)
-        }
-    }
-    fn left_of(&self, pos: Pos) -> Pos {
-        if pos.x() == 0 {
-            Pos::new(self.width() - 1, pos.y())
-        } else {
-            Pos::new(pos.x() - 1, pos.y())
-        }
+    /// `pos` の上下左右の位置のうち, ロックされていないもの. 端では反対側に回り込む.
+    ///
+    /// 幅か高さが 1 の盤面で `pos` 自身に回り込んだ位置は, 入れ替えても何も変わらないので含めない.
+    pub fn around_of(&'_ self, pos: Pos) -> impl Iterator<Item = Pos> + '_ {
+        self.grid()
+            .looping_neighbors(pos)
+            .filter(move |around| *around != pos && !self.locked.contains(around))
     }
 
-    pub fn around_of(&'_ self, pos: Pos) -> impl Iterator<Item = Pos> + '_ {
-        [
-            self.up_of(pos),
-            self.right_of(pos),
-            self.down_of(pos),
-            self.left_of(pos),
-        ]
-        .into_iter()
-        .filter(move |pos| !self.locked.contains(pos))
+    /// `around_of` と同じ位置を, `pos` からそこへ動かすときの向きと組にして返す.
+    ///
+    /// 向きは `Movement::between_adjacent` と同じく回り込まない方を選ぶ. 幅か高さが 2 の盤面で上下や左右が同じ位置になるときは, 1 度だけ返す.
+    pub fn movements_around(&'_ self, pos: Pos) -> impl Iterator<Item = (Movement, Pos)> + '_ {
+        let grid = self.grid();
+        let mut seen = Vec::with_capacity(4);
+        self.around_of(pos)
+            .filter(move |&around| {
+                if seen.contains(&around) {
+                    return false;
+                }
+                seen.push(around);
+                true
+            })
+            .map(move |around| {
+                let movement = Movement::between_adjacent(grid, pos, around).unwrap();
+                (movement, around)
+            })
     }
 
     pub fn is_locked(&self, pos: Pos) -> bool {
@@ -205,3 +196,112 @@ fn test_reverse() {
     assert_eq!(board.reverse(grid.pos(0, 1)), grid.pos(0, 1));
     assert_eq!(board.reverse(grid.pos(1, 1)), grid.pos(1, 0));
 }
+
+#[test]
+fn test_around_of() {
+    let grid = Grid::new(4, 3);
+    let field = VecOnGrid::from_vec(grid, grid.all_pos().collect()).unwrap();
+    let mut board = Board::new(None, field);
+    let around_of = |board: &Board, x, y| board.around_of(grid.pos(x, y)).collect::<Vec<_>>();
+
+    // 上, 右, 下, 左 の順に返す
+    assert_eq!(
+        vec![
+            grid.pos(1, 0),
+            grid.pos(2, 1),
+            grid.pos(1, 2),
+            grid.pos(0, 1)
+        ],
+        around_of(&board, 1, 1)
+    );
+    // 上端では下端に回り込む
+    assert_eq!(
+        vec![
+            grid.pos(2, 2),
+            grid.pos(3, 0),
+            grid.pos(2, 1),
+            grid.pos(1, 0)
+        ],
+        around_of(&board, 2, 0)
+    );
+    // 左上の角では上と左の両方で回り込む
+    assert_eq!(
+        vec![
+            grid.pos(0, 2),
+            grid.pos(1, 0),
+            grid.pos(0, 1),
+            grid.pos(3, 0)
+        ],
+        around_of(&board, 0, 0)
+    );
+    // 右下の角では右と下の両方で回り込む
+    assert_eq!(
+        vec![
+            grid.pos(3, 1),
+            grid.pos(0, 2),
+            grid.pos(3, 0),
+            grid.pos(2, 2)
+        ],
+        around_of(&board, 3, 2)
+    );
+
+    // ロックされた位置は回り込んだ先でも含めない
+    board.lock(grid.pos(3, 0));
+    assert_eq!(
+        vec![grid.pos(0, 2), grid.pos(1, 0), grid.pos(0, 1)],
+        around_of(&board, 0, 0)
+    );
+
+    // 高さ 1 の盤面では上下に回り込んでも同じ位置なので, 左右だけを返す
+    let grid = Grid::new(3, 1);
+    let field = VecOnGrid::from_vec(grid, grid.all_pos().collect()).unwrap();
+    let board = Board::new(None, field);
+    assert_eq!(
+        vec![grid.pos(2, 0), grid.pos(0, 0)],
+        board.around_of(grid.pos(1, 0)).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_movements_around() {
+    let grid = Grid::new(3, 2);
+    let field = VecOnGrid::from_vec(grid, grid.all_pos().collect()).unwrap();
+    let mut board = Board::new(None, field);
+    assert_eq!(
+        vec![
+            (Movement::Down, grid.pos(0, 1)),
+            (Movement::Right, grid.pos(1, 0)),
+            (Movement::Left, grid.pos(2, 0)),
+        ],
+        board.movements_around(grid.pos(0, 0)).collect::<Vec<_>>()
+    );
+
+    board.lock(grid.pos(2, 0));
+    assert_eq!(
+        vec![
+            (Movement::Down, grid.pos(0, 1)),
+            (Movement::Right, grid.pos(1, 0)),
+        ],
+        board.movements_around(grid.pos(0, 0)).collect::<Vec<_>>()
+    );
+
+    // 幅が 2 なら左右も同じ位置になる
+    let grid = Grid::new(2, 3);
+    let field = VecOnGrid::from_vec(grid, grid.all_pos().collect()).unwrap();
+    let board = Board::new(None, field);
+    assert_eq!(
+        vec![
+            (Movement::Up, grid.pos(1, 0)),
+            (Movement::Left, grid.pos(0, 1)),
+            (Movement::Down, grid.pos(1, 2)),
+        ],
+        board.movements_around(grid.pos(1, 1)).collect::<Vec<_>>()
+    );
+    for pos in grid.all_pos() {
+        let mut arounds: Vec<_> = board.movements_around(pos).map(|(_, p)| p).collect();
+        let len = arounds.len();
+        arounds.sort_unstable_by_key(|p| (p.x(), p.y()));
+        arounds.dedup();
+        assert_eq!(len, arounds.len(), "{:?}", pos);
+    }
+}
@@ -1,9 +1,25 @@
 use crate::{
     basis::Movement,
-    grid::{Grid, Pos},
+    grid::{Grid, Pos, RangePos},
 };
 
 /// `Board` に移動や回転を加えてアクセスするための覗き窓.
+///
+/// 窓の中の座標 (窓の座標) は, 窓の左上を `(0, 0)` とし, 回転した後の向きで右と下に増える.
+/// [`BoardFinder::to_original`] と [`BoardFinder::from_original`] で元の盤面の座標と行き来できる.
+///
+/// ```
+/// use procon2021_comp::grid::{board::BoardFinder, Grid};
+///
+/// let grid = Grid::new(3, 2);
+/// let mut finder = BoardFinder::new(grid);
+/// // 時計回りに 90 度回すと, 元の盤面の右上が窓の左上になる
+/// finder.rotate_to(1);
+/// assert_eq!((2, 3), (finder.width(), finder.height()));
+/// let pos = Grid::new(2, 3).pos(1, 0);
+/// assert_eq!(grid.pos(2, 1), finder.to_original(pos));
+/// assert_eq!(Some(pos), finder.from_original(grid.pos(2, 1)));
+/// ```
 #[derive(Debug, Clone)]
 pub struct BoardFinder {
     offset: Pos,
@@ -14,6 +30,7 @@ pub struct BoardFinder {
 }
 
 impl BoardFinder {
+    /// 盤面 `grid` の全体を回転せずに覗く窓を作る.
     pub fn new(grid: Grid) -> Self {
         Self {
             offset: grid.pos(0, 0),
@@ -30,6 +47,7 @@ impl BoardFinder {
     pub fn height(&self) -> u8 {
         self.height
     }
+    /// 窓の左上にあたる元の盤面の座標を返す.
     pub fn offset(&self) -> Pos {
         self.offset
     }
@@ -41,8 +59,53 @@ impl BoardFinder {
         Grid::new(self.width, self.height)
     }
 
-    pub fn move_pos_to(&self, pos: Pos, movement: Movement) -> Pos {
+    /// 窓の座標 `pos` を元の盤面の座標に変換する.
+    pub fn to_original(&self, pos: Pos) -> Pos {
+        debug_assert!(
+            pos.x() < self.width && pos.y() < self.height,
+            "{:?} is out of the finder",
+            pos
+        );
+        let (x, y) = (pos.x() as i32, pos.y() as i32);
+        let (dx, dy) = match self.rotation {
+            0 => (x, y),
+            1 => (-y, x),
+            2 => (-x, -y),
+            3 => (y, -x),
+            _ => unreachable!(),
+        };
         let grid = self.original;
+        grid.pos(
+            (self.offset.x() as i32 + dx).rem_euclid(grid.width() as i32) as u8,
+            (self.offset.y() as i32 + dy).rem_euclid(grid.height() as i32) as u8,
+        )
+    }
+
+    /// 元の盤面の座標 `pos` を窓の座標に変換する. 窓の外なら `None` を返す.
+    pub fn from_original(&self, pos: Pos) -> Option<Pos> {
+        let grid = self.original;
+        let dx = pos.x() as i32 - self.offset.x() as i32;
+        let dy = pos.y() as i32 - self.offset.y() as i32;
+        let (x, y) = match self.rotation {
+            0 => (dx, dy),
+            1 => (dy, -dx),
+            2 => (-dx, -dy),
+            3 => (-dy, dx),
+            _ => unreachable!(),
+        };
+        // 回転しても窓の辺は元の盤面のどちらかの辺に沿うので, その長さで折り返す
+        let (x_len, y_len) = if self.rotation.is_multiple_of(2) {
+            (grid.width(), grid.height())
+        } else {
+            (grid.height(), grid.width())
+        };
+        let x = x.rem_euclid(x_len as i32) as u8;
+        let y = y.rem_euclid(y_len as i32) as u8;
+        (x < self.width && y < self.height).then(|| self.as_grid().pos(x, y))
+    }
+
+    /// 元の盤面の座標 `pos` を, 窓の向きで見た `movement` の方向へ 1 つ動かした座標を返す.
+    pub fn move_pos_to(&self, pos: Pos, movement: Movement) -> Pos {
         let movement = match self.rotation {
             0 => movement,
             1 => movement.turn_right(),
@@ -50,36 +113,7 @@ impl BoardFinder {
             3 => movement.turn_left(),
             _ => unreachable!(),
         };
-        match movement {
-            Movement::Up => {
-                if pos.y() == 0 {
-                    grid.pos(pos.x(), grid.height() - 1)
-                } else {
-                    grid.pos(pos.x(), pos.y() - 1)
-                }
-            }
-            Movement::Right => {
-                if pos.x() == grid.width() - 1 {
-                    grid.pos(0, pos.y())
-                } else {
-                    grid.pos(pos.x() + 1, pos.y())
-                }
-            }
-            Movement::Down => {
-                if pos.y() == grid.height() - 1 {
-                    grid.pos(pos.x(), 0)
-                } else {
-                    grid.pos(pos.x(), pos.y() + 1)
-                }
-            }
-            Movement::Left => {
-                if pos.x() == 0 {
-                    grid.pos(grid.width() - 1, pos.y())
-                } else {
-                    grid.pos(pos.x() - 1, pos.y())
-                }
-            }
-        }
+        self.original.looping_move(pos, movement)
     }
 
     /// 時計回りに 90 度単位の `rotation` で回転する.
@@ -120,6 +154,35 @@ impl BoardFinder {
         self.offset = self.move_pos_to(self.offset, Movement::Down);
         self.height -= 1;
     }
+
+    /// 窓の下端を 1 つ削る.
+    pub fn slice_down(&mut self) {
+        self.height -= 1;
+    }
+
+    /// 窓の左端を 1 つ削る.
+    pub fn slice_left(&mut self) {
+        self.offset = self.move_pos_to(self.offset, Movement::Right);
+        self.width -= 1;
+    }
+
+    /// 窓の右端を 1 つ削る.
+    pub fn slice_right(&mut self) {
+        self.width -= 1;
+    }
+
+    /// 窓が覗いている元の盤面の矩形領域を返す. 回転していても, 元の盤面の向きで左上と右下を持つ.
+    ///
+    /// 窓は盤面の全体から端を削って作るので, 端を回り込むことはない. 窓の幅か高さが 0 のときは呼べない.
+    pub fn remaining_rect(&self) -> RangePos {
+        let a = self.to_original(self.as_grid().pos(0, 0));
+        let b = self.to_original(self.as_grid().pos(self.width - 1, self.height - 1));
+        let grid = self.original;
+        grid.range(
+            grid.pos(a.x().min(b.x()), a.y().min(b.y())),
+            grid.pos(a.x().max(b.x()), a.y().max(b.y())),
+        )
+    }
 }
 
 #[test]
@@ -173,6 +236,110 @@ fn test_finder() {
     assert_eq!(grid.pos(4, 4), finder.offset());
 }
 
+#[test]
+fn test_original_mapping() {
+    let grid = Grid::new(4, 3);
+    let mut finder = BoardFinder::new(grid);
+    finder.rotate_to(2);
+    // 窓の左上は元の盤面の右下になる
+    assert_eq!(grid.pos(3, 2), finder.to_original(grid.pos(0, 0)));
+    assert_eq!(grid.pos(2, 2), finder.to_original(grid.pos(1, 0)));
+    assert_eq!(Some(grid.pos(0, 1)), finder.from_original(grid.pos(3, 1)));
+
+    finder.slice_up();
+    // 元の盤面の下端の行は窓から外れる
+    assert_eq!(None, finder.from_original(grid.pos(1, 2)));
+    assert_eq!(Some(grid.pos(2, 0)), finder.from_original(grid.pos(1, 1)));
+
+    for rotation in 0..4 {
+        let mut finder = BoardFinder::new(grid);
+        finder.rotate_to(rotation);
+        finder.slice_up();
+        let inside = finder.as_grid();
+        for pos in inside.all_pos() {
+            let original = finder.to_original(pos);
+            assert_eq!(Some(pos), finder.from_original(original), "{}", rotation);
+        }
+        // 窓の中の座標はそれぞれ違う位置に対応し, 残りは窓の外になる
+        let outside = grid
+            .all_pos()
+            .filter(|&pos| finder.from_original(pos).is_none())
+            .count();
+        assert_eq!(
+            grid.all_pos().count() - inside.all_pos().count(),
+            outside,
+            "{}",
+            rotation
+        );
+    }
+}
+
+#[test]
+fn test_slice_sides() {
+    let grid = Grid::new(5, 4);
+    let rect = |finder: &BoardFinder| {
+        let rect = finder.remaining_rect();
+        (rect.start(), rect.end())
+    };
+
+    let mut finder = BoardFinder::new(grid);
+    assert_eq!((grid.pos(0, 0), grid.pos(4, 3)), rect(&finder));
+    finder.slice_left();
+    finder.slice_down();
+    assert_eq!((grid.pos(1, 0), grid.pos(4, 2)), rect(&finder));
+    assert_eq!(grid.pos(1, 0), finder.offset());
+    finder.slice_right();
+    finder.slice_up();
+    assert_eq!((grid.pos(1, 1), grid.pos(3, 2)), rect(&finder));
+    assert_eq!((3, 2), (finder.width(), finder.height()));
+
+    // 回転した窓では, 窓の向きで見た端を削る
+    let mut finder = BoardFinder::new(grid);
+    finder.rotate_to(1);
+    finder.slice_left();
+    // 窓の左端は元の盤面の上端
+    assert_eq!((grid.pos(0, 1), grid.pos(4, 3)), rect(&finder));
+    finder.slice_up();
+    // 窓の上端は元の盤面の右端
+    assert_eq!((grid.pos(0, 1), grid.pos(3, 3)), rect(&finder));
+    finder.slice_right();
+    finder.slice_down();
+    // 窓の右端と下端は元の盤面の下端と左端
+    assert_eq!((grid.pos(1, 1), grid.pos(3, 2)), rect(&finder));
+
+    // どの向きでどの端を削っても, 回して上端を削ったのと同じ領域が残り, 窓の中だけが対応する
+    for rotation in 0..4 {
+        for side in 0..4 {
+            let mut sliced = BoardFinder::new(grid);
+            sliced.rotate_to(rotation);
+            // 上, 右, 下, 左の端を窓の上端に持ってくる回転
+            let mut rotated = sliced.clone();
+            rotated.rotate_to(side);
+            rotated.slice_up();
+            match side {
+                0 => sliced.slice_up(),
+                1 => sliced.slice_right(),
+                2 => sliced.slice_down(),
+                3 => sliced.slice_left(),
+                _ => unreachable!(),
+            }
+            assert_eq!(rect(&rotated), rect(&sliced), "{} {}", rotation, side);
+
+            let inside: Vec<_> = sliced.remaining_rect().collect();
+            for pos in grid.all_pos() {
+                assert_eq!(
+                    inside.contains(&pos),
+                    sliced.from_original(pos).is_some(),
+                    "{} {} {:?}",
+                    rotation,
+                    side,
+                    pos
+                );
+            }
+        }
+    }
+}
+
 /// 時計回りに 90 度単位の `rotation` で回転した位置を計算する.
 fn rotated_pos(rotation: u8, pos: Pos, grid: Grid) -> Pos {
     match rotation % 4 {
@@ -7,6 +7,12 @@ pub trait OnGrid: Index<Pos> + IndexMut<Pos> {
     /// この座標変換をした系のサイズである `Grid` を返す.
     fn grid(&self) -> Grid;
 
+    /// この座標変換をした系の `pos` が, 変換前の系でどの座標に当たるかを返す.
+    fn source_pos(&self, pos: Pos) -> Pos;
+
+    /// 変換前の系の `source` が, この座標変換をした系でどの座標に当たるかを返す.
+    fn view_pos(&self, source: Pos) -> Pos;
+
     fn get(&self, index: Pos) -> Option<&<Self as Index<Pos>>::Output> {
         self.grid().is_pos_valid(index).then(move || &self[index])
     }
@@ -17,6 +23,14 @@ pub trait OnGrid: Index<Pos> + IndexMut<Pos> {
             .then(move || &mut self[index])
     }
 
+    /// 所有権を奪わずに座標変換するための借用を作る.
+    fn by_ref(&mut self) -> ByRef<'_, Self>
+    where
+        Self: Sized,
+    {
+        ByRef(self)
+    }
+
     /// 系全体を転置するように座標変換する.
     fn transpose(self) -> Transpose<Self>
     where
@@ -62,6 +76,45 @@ impl<T> OnGrid for VecOnGrid<T> {
     fn grid(&self) -> Grid {
         self.grid
     }
+
+    fn source_pos(&self, pos: Pos) -> Pos {
+        pos
+    }
+
+    fn view_pos(&self, source: Pos) -> Pos {
+        source
+    }
+}
+
+#[derive(Debug)]
+pub struct ByRef<'a, V>(&'a mut V);
+
+impl<V: OnGrid + Index<Pos>> Index<Pos> for ByRef<'_, V> {
+    type Output = V::Output;
+
+    fn index(&self, index: Pos) -> &Self::Output {
+        self.0.index(index)
+    }
+}
+
+impl<V: OnGrid + IndexMut<Pos>> IndexMut<Pos> for ByRef<'_, V> {
+    fn index_mut(&mut self, index: Pos) -> &mut Self::Output {
+        self.0.index_mut(index)
+    }
+}
+
+impl<V: OnGrid + Index<Pos> + IndexMut<Pos>> OnGrid for ByRef<'_, V> {
+    fn grid(&self) -> Grid {
+        self.0.grid()
+    }
+
+    fn source_pos(&self, pos: Pos) -> Pos {
+        self.0.source_pos(pos)
+    }
+
+    fn view_pos(&self, source: Pos) -> Pos {
+        self.0.view_pos(source)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -91,6 +144,15 @@ impl<V: OnGrid + Index<Pos> + IndexMut<Pos>> OnGrid for Transpose<V> {
         let height = self.0.grid().height();
         Grid::new(height, width)
     }
+
+    fn source_pos(&self, pos: Pos) -> Pos {
+        self.0.source_pos(self.0.grid().pos(pos.y(), pos.x()))
+    }
+
+    fn view_pos(&self, source: Pos) -> Pos {
+        let inner = self.0.view_pos(source);
+        self.grid().pos(inner.y(), inner.x())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -117,6 +179,18 @@ impl<V: OnGrid + Index<Pos> + IndexMut<Pos>> OnGrid for FlipX<V> {
     fn grid(&self) -> Grid {
         self.0.grid()
     }
+
+    fn source_pos(&self, pos: Pos) -> Pos {
+        let width = self.0.grid().width();
+        self.0
+            .source_pos(self.0.grid().pos(width - pos.x() - 1, pos.y()))
+    }
+
+    fn view_pos(&self, source: Pos) -> Pos {
+        let width = self.0.grid().width();
+        let inner = self.0.view_pos(source);
+        self.grid().pos(width - inner.x() - 1, inner.y())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -143,6 +217,18 @@ impl<V: OnGrid + Index<Pos> + IndexMut<Pos>> OnGrid for FlipY<V> {
     fn grid(&self) -> Grid {
         self.0.grid()
     }
+
+    fn source_pos(&self, pos: Pos) -> Pos {
+        let height = self.0.grid().height();
+        self.0
+            .source_pos(self.0.grid().pos(pos.x(), height - pos.y() - 1))
+    }
+
+    fn view_pos(&self, source: Pos) -> Pos {
+        let height = self.0.grid().height();
+        let inner = self.0.view_pos(source);
+        self.grid().pos(inner.x(), height - inner.y() - 1)
+    }
 }
 
 #[test]
@@ -179,3 +265,30 @@ fn case1() {
         vec![11, 8, 5, 2, 10, 7, 4, 1, 9, 6, 3, 0],
     );
 }
+
+#[test]
+fn case2() {
+    let grid = Grid::new(3, 4);
+    let mut vec = VecOnGrid::with_default(grid);
+    vec.iter_mut()
+        .enumerate()
+        .for_each(|(idx, elem)| *elem = idx);
+
+    let view = vec.by_ref().rotate_to_right();
+    assert_eq!(view.grid(), Grid::new(4, 3));
+    // 時計回りに回すと左上の要素は右上に来る
+    assert_eq!(view[view.grid().pos(3, 0)], 0);
+    for pos in view.grid().all_pos() {
+        assert_eq!(view.view_pos(view.source_pos(pos)), pos);
+    }
+
+    let view = vec.by_ref().rotate_to_left();
+    assert_eq!(view[view.grid().pos(0, 2)], 0);
+    for pos in grid.all_pos() {
+        assert_eq!(view.source_pos(view.view_pos(pos)), pos);
+    }
+
+    let mut view = vec.by_ref().flip_x().flip_y();
+    view[grid.pos(0, 0)] = 100;
+    assert_eq!(vec[grid.pos(2, 3)], 100);
+}
@@ -0,0 +1,275 @@
+use super::{Grid, Pos};
+
+/// `Corner` は盤面の 4 隅のどれかを表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Corner {
+    UpLeft,
+    UpRight,
+    DownRight,
+    DownLeft,
+}
+
+impl Corner {
+    pub const ALL: [Corner; 4] = [
+        Corner::UpLeft,
+        Corner::UpRight,
+        Corner::DownRight,
+        Corner::DownLeft,
+    ];
+
+    /// `grid` でこの隅にあたる座標を返す.
+    pub fn pos(self, grid: Grid) -> Pos {
+        let (right, down) = self.flips();
+        grid.pos(
+            if right { grid.width() - 1 } else { 0 },
+            if down { grid.height() - 1 } else { 0 },
+        )
+    }
+
+    /// 右の隅か, 下の隅か.
+    fn flips(self) -> (bool, bool) {
+        match self {
+            Corner::UpLeft => (false, false),
+            Corner::UpRight => (true, false),
+            Corner::DownRight => (true, true),
+            Corner::DownLeft => (false, true),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Order {
+    ColMajor,
+    Spiral,
+    FromCorner(Corner),
+}
+
+/// `Traversal` は `Grid` のすべての座標を, 行優先以外の決まった順に走査する.
+///
+/// `RangePos` と同じく, 何番目の座標かから位置を計算するので, 両端から取り出せて残りの数も分かる.
+#[derive(Debug, Clone)]
+pub struct Traversal {
+    grid: Grid,
+    order: Order,
+    front: usize,
+    back: usize,
+}
+
+impl Traversal {
+    fn new(grid: Grid, order: Order) -> Self {
+        Self {
+            grid,
+            order,
+            front: 0,
+            back: grid.width() as usize * grid.height() as usize,
+        }
+    }
+
+    fn pos_at(&self, index: usize) -> Pos {
+        let (width, height) = (self.grid.width() as usize, self.grid.height() as usize);
+        let (x, y) = match self.order {
+            Order::ColMajor => (index / height, index % height),
+            Order::Spiral => spiral_at(width, height, index),
+            Order::FromCorner(corner) => {
+                let (x, y) = (index % width, index / width);
+                let (right, down) = corner.flips();
+                (
+                    if right { width - 1 - x } else { x },
+                    if down { height - 1 - y } else { y },
+                )
+            }
+        };
+        self.grid.pos(x as u8, y as u8)
+    }
+}
+
+/// 幅 `width` と高さ `height` の領域を, 左上から時計回りに内側へ渦を巻いて `index` 番目に通る位置.
+fn spiral_at(mut width: usize, mut height: usize, mut index: usize) -> (usize, usize) {
+    let mut ring = 0;
+    loop {
+        // 幅か高さが 1 の輪は 1 本の線になる
+        let perimeter = if width == 1 || height == 1 {
+            width * height
+        } else {
+            2 * (width + height) - 4
+        };
+        if index < perimeter {
+            break;
+        }
+        index -= perimeter;
+        width -= 2;
+        height -= 2;
+        ring += 1;
+    }
+    let (x, y) = if height == 1 {
+        (index, 0)
+    } else if width == 1 {
+        (0, index)
+    } else if index < width {
+        (index, 0)
+    } else if index < width + height - 1 {
+        (width - 1, index - (width - 1))
+    } else if index < 2 * width + height - 2 {
+        (2 * width + height - 3 - index, height - 1)
+    } else {
+        (0, 2 * (width + height) - 4 - index)
+    };
+    (ring + x, ring + y)
+}
+
+impl Iterator for Traversal {
+    type Item = Pos;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.back <= self.front {
+            return None;
+        }
+        let ret = self.pos_at(self.front);
+        self.front += 1;
+        Some(ret)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back.saturating_sub(self.front);
+        (len, Some(len))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.front = self.front.saturating_add(n).min(self.back);
+        self.next()
+    }
+}
+
+impl DoubleEndedIterator for Traversal {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.back <= self.front {
+            return None;
+        }
+        self.back -= 1;
+        Some(self.pos_at(self.back))
+    }
+}
+
+impl ExactSizeIterator for Traversal {}
+
+impl std::iter::FusedIterator for Traversal {}
+
+impl Grid {
+    /// すべての座標を列優先で, 左の列から上から順に走査する.
+    pub fn all_pos_col_major(&self) -> Traversal {
+        Traversal::new(*self, Order::ColMajor)
+    }
+
+    /// すべての座標を左上から時計回りに, 外周から内側へ渦を巻いて走査する.
+    pub fn spiral(&self) -> Traversal {
+        Traversal::new(*self, Order::Spiral)
+    }
+
+    /// すべての座標を `corner` から行優先で走査する. 各行は `corner` のある側の端から, 行は `corner` のある行から順に進む.
+    pub fn from_corner(&self, corner: Corner) -> Traversal {
+        Traversal::new(*self, Order::FromCorner(corner))
+    }
+}
+
+#[test]
+fn test_traversal() {
+    let grid = Grid::new(3, 2);
+    let pos = |list: &[(u8, u8)]| {
+        list.iter()
+            .map(|&(x, y)| grid.pos(x, y))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        pos(&[(0, 0), (0, 1), (1, 0), (1, 1), (2, 0), (2, 1)]),
+        grid.all_pos_col_major().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        pos(&[(0, 0), (1, 0), (2, 0), (2, 1), (1, 1), (0, 1)]),
+        grid.spiral().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        grid.all_pos().collect::<Vec<_>>(),
+        grid.from_corner(Corner::UpLeft).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        pos(&[(2, 1), (1, 1), (0, 1), (2, 0), (1, 0), (0, 0)]),
+        grid.from_corner(Corner::DownRight).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        pos(&[(2, 0), (1, 0), (0, 0), (2, 1), (1, 1), (0, 1)]),
+        grid.from_corner(Corner::UpRight).collect::<Vec<_>>()
+    );
+
+    // 5x4 の渦は外周 14 個の後に内側の 3x2 を回る
+    let grid = Grid::new(5, 4);
+    let spiral: Vec<_> = grid.spiral().collect();
+    assert_eq!(grid.pos(0, 1), spiral[13]);
+    assert_eq!(
+        vec![
+            grid.pos(1, 1),
+            grid.pos(2, 1),
+            grid.pos(3, 1),
+            grid.pos(3, 2),
+            grid.pos(2, 2),
+            grid.pos(1, 2)
+        ],
+        spiral[14..]
+    );
+
+    // 内側が 1 列だけ残る渦
+    let grid = Grid::new(3, 4);
+    let spiral: Vec<_> = grid.spiral().collect();
+    assert_eq!(vec![grid.pos(1, 1), grid.pos(1, 2)], spiral[10..]);
+
+    for corner in Corner::ALL {
+        let grid = Grid::new(4, 3);
+        assert_eq!(Some(corner.pos(grid)), grid.from_corner(corner).next());
+    }
+}
+
+#[test]
+fn test_traversal_visits_all() {
+    for (width, height) in [(1, 1), (1, 5), (5, 1), (2, 2), (4, 7), (7, 4), (16, 16)] {
+        let grid = Grid::new(width, height);
+        let orders = [
+            grid.all_pos_col_major(),
+            grid.spiral(),
+            grid.from_corner(Corner::UpLeft),
+            grid.from_corner(Corner::UpRight),
+            grid.from_corner(Corner::DownRight),
+            grid.from_corner(Corner::DownLeft),
+        ];
+        for traversal in orders {
+            let label = format!("{:?} {}x{}", traversal, width, height);
+            assert_eq!(grid.all_pos().len(), traversal.len(), "{}", label);
+
+            let forward: Vec<_> = traversal.clone().collect();
+            let mut sorted = forward.clone();
+            sorted.sort();
+            let mut all: Vec<_> = grid.all_pos().collect();
+            all.sort();
+            assert_eq!(all, sorted, "{}", label);
+
+            let mut backward: Vec<_> = traversal.clone().rev().collect();
+            backward.reverse();
+            assert_eq!(forward, backward, "{}", label);
+
+            // 渦は隣り合うマスを順にたどる
+            if matches!(traversal.order, Order::Spiral) {
+                for pair in forward.windows(2) {
+                    assert_eq!(1, pair[0].manhattan_distance(pair[1]), "{}", label);
+                }
+            }
+
+            let mut both = traversal.clone();
+            let mut taken = vec![];
+            while let Some(front) = both.next() {
+                taken.push(front);
+                taken.extend(both.next_back());
+            }
+            assert_eq!(None, both.next_back(), "{}", label);
+            assert_eq!(forward.len(), taken.len(), "{}", label);
+        }
+    }
+}
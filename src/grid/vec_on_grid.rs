@@ -4,11 +4,39 @@ use super::{Grid, Pos};
 
 /// `VecOnGrid` は `Grid` 上の `Pos` に対応付けた値を格納し `Pos` でアクセスできるコンテナを提供する.
 #[derive(Clone, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "RawVecOnGrid<T>")
+)]
 pub struct VecOnGrid<T> {
     vec: Vec<T>,
     pub grid: Grid,
 }
 
+/// デシリアライズ時に要素数を検査するための中間表現.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawVecOnGrid<T> {
+    vec: Vec<T>,
+    grid: Grid,
+}
+
+#[cfg(feature = "serde")]
+impl<T> TryFrom<RawVecOnGrid<T>> for VecOnGrid<T> {
+    type Error = String;
+
+    fn try_from(RawVecOnGrid { vec, grid }: RawVecOnGrid<T>) -> Result<Self, Self::Error> {
+        let len = vec.len();
+        Self::from_vec(grid, vec).ok_or_else(|| {
+            format!(
+                "the length of elements {} does not match to the grid {:?}",
+                len, grid
+            )
+        })
+    }
+}
+
 impl<T: PartialEq> PartialEq for VecOnGrid<T> {
     fn eq(&self, other: &Self) -> bool {
         self.vec == other.vec
@@ -227,3 +255,24 @@ impl<T> ops::IndexMut<Pos> for VecOnGrid<T> {
         &mut self.vec[self.grid.pos_as_index(index)]
     }
 }
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let grid = Grid::new(2, 3);
+    let mut vec = VecOnGrid::with_init(grid, grid.pos(0, 0));
+    vec[grid.pos(1, 2)] = grid.pos(1, 0);
+
+    let json = serde_json::to_string(&vec).unwrap();
+    assert_eq!(
+        r#"{"vec":[[0,0],[0,0],[0,0],[0,0],[0,0],[1,0]],"grid":{"width":2,"height":3}}"#,
+        json
+    );
+    assert_eq!(vec, serde_json::from_str(&json).unwrap());
+
+    assert!(serde_json::from_str::<VecOnGrid<u8>>(
+        r#"{"vec":[1,2],"grid":{"width":2,"height":3}}"#
+    )
+    .is_err());
+    assert!(serde_json::from_str::<Pos>("[16,0]").is_err());
+}
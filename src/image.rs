@@ -1,25 +1,43 @@
 use {
-    crate::basis::{Color, Image, Problem},
-    anyhow::{ensure, Context as _, Result},
+    crate::{
+        basis::{Color, Image, Problem},
+        grid::{Dims, MAX_GRID_SIDE},
+    },
+    anyhow::{bail, ensure, Context as _, Result},
     std::{
         error::Error,
-        io::{BufRead, Read},
+        io::{BufRead, Read, Write},
         str::FromStr,
     },
 };
 
-pub fn read_problem(mut data: impl BufRead) -> Result<Problem> {
+/// ヘッダの 1 行の長さの上限. 改行の無い巨大な入力で際限なくメモリを使わないようにする.
+const MAX_LINE_LEN: u64 = 256;
+
+/// PPM 形式の問題を読み込む. 壊れた入力に対しては panic せずにエラーを返す.
+pub fn read_problem(data: impl BufRead) -> crate::Result<Problem> {
+    parse_problem(data).map_err(|e| crate::Error::InvalidProblem(format!("{:#}", e)))
+}
+
+fn parse_problem(mut data: impl BufRead) -> Result<Problem> {
     let nl = &mut || {
         let mut buf = String::new();
-        data.read_line(&mut buf).context("failed to read line")?;
+        let len = (&mut data)
+            .take(MAX_LINE_LEN)
+            .read_line(&mut buf)
+            .context("failed to read line")?;
+        ensure!(len != 0, "unexpected end of file");
+        ensure!(
+            buf.ends_with('\n') || (len as u64) < MAX_LINE_LEN,
+            "the line is too long"
+        );
         Ok(buf.trim().to_string())
     };
 
     let magic = parse_line(nl, |x| Ok(x.to_string()), "magic number")?;
     ensure!(magic == "P6", "expected magic number, but found {}", magic);
 
-    let (horizontal_split_count, vertical_split_count) =
-        parse_line(nl, parse_split_count, "split count")?;
+    let dims = parse_line(nl, parse_split_count, "split count")?;
 
     let selectable_count = parse_line(nl, parse_selectable_count, "selectable count")?;
 
@@ -27,7 +45,9 @@ pub fn read_problem(mut data: impl BufRead) -> Result<Problem> {
         parse_line(nl, parse_cost, "cost convert rate")?;
 
     let (width, height) = parse_line(nl, parse_dim, "image dimensions")?;
-    let _max_color_value = parse_line(nl, parse_max_color_value, "max color value");
+    let _max_color_value = parse_line(nl, parse_max_color_value, "max color value")?;
+
+    validate_split((width, height), dims)?;
 
     let image = read_image(data, width, height).context("failed to read image")?;
 
@@ -35,8 +55,7 @@ pub fn read_problem(mut data: impl BufRead) -> Result<Problem> {
         select_limit: selectable_count,
         select_cost: selection_cost_convert_rate,
         swap_cost: swap_cost_convert_rate,
-        rows: horizontal_split_count,
-        cols: vertical_split_count,
+        dims,
         image,
     })
 }
@@ -63,15 +82,15 @@ where
         .with_context(|| format!("failed to parse {}", expect))
 }
 
-// returns: (horizontal, vertical)
-fn parse_split_count(line: &str) -> Result<(u8, u8)> {
+/// 横の分割数, 縦の分割数の順に書かれた行を読む.
+fn parse_split_count(line: &str) -> Result<Dims> {
     let tokens = &mut line.split_ascii_whitespace();
 
     ensure!(tokens.next() == Some("#"), "expected comment line");
-    let h = parse_token::<_, u8>(tokens, "horizontal split count")?;
-    let v = parse_token::<_, u8>(tokens, "vertical split count")?;
+    let width = parse_token(tokens, "horizontal split count")?;
+    let height = parse_token(tokens, "vertical split count")?;
 
-    Ok((h, v))
+    Ok(Dims { width, height })
 }
 
 fn parse_selectable_count(line: &str) -> Result<u8> {
@@ -108,56 +127,96 @@ fn parse_max_color_value(line: &str) -> Result<u16> {
         value <= (u8::MAX as _),
         "max color value is unexpectedly big. (doesn't fit to u8)"
     );
+    ensure!(value != 0, "max color value must be positive");
 
     Ok(value)
 }
 
+/// `width` x `height` ピクセルの画像を, 横に `dims.width` 個, 縦に `dims.height` 個の正方形の断片に分割できるか確かめる.
+pub(crate) fn validate_split((width, height): (u16, u16), dims: Dims) -> Result<()> {
+    ensure!(
+        (2..=MAX_GRID_SIDE).contains(&dims.width) && (2..=MAX_GRID_SIDE).contains(&dims.height),
+        "split count must be between 2 and {}, but found {} x {}",
+        MAX_GRID_SIDE,
+        dims.width,
+        dims.height
+    );
+    let side = (width / dims.width as u16) as u32;
+    if side == 0
+        || side * dims.width as u32 != width as u32
+        || side * dims.height as u32 != height as u32
+    {
+        bail!(
+            "the image {}x{} cannot be split into {}x{} square fragments",
+            width,
+            height,
+            dims.width,
+            dims.height
+        );
+    }
+    Ok(())
+}
+
 // http://netpbm.sourceforge.net/doc/ppm.html
 fn read_image(data: impl Read, width: u16, height: u16) -> Result<Image> {
-    let bytes_iter = data.bytes();
-
-    let mut image_data = Vec::with_capacity(width as usize * height as usize);
-
-    let mut r = None;
-    let mut g = None;
-
-    for byte_result in bytes_iter {
-        let byte = byte_result.context("failed to read image body byte")?;
-
-        match (r, g) {
-            (None, None) => r = Some(byte),
-            (Some(_), None) => g = Some(byte),
-            (Some(ar), Some(ag)) => {
-                image_data.push(Color {
-                    r: ar,
-                    g: ag,
-                    b: byte,
-                });
-
-                r = None;
-                g = None;
-            }
-            _ => unreachable!(),
-        }
-    }
+    let expected = width as u64 * height as u64 * 3;
+
+    // 宣言された大きさだけ先に確保すると, 嘘の大きさで大量のメモリを使ってしまうので, 読めた分だけ確保する
+    let mut bytes = vec![];
+    data.take(expected + 1)
+        .read_to_end(&mut bytes)
+        .context("failed to read image body")?;
 
     ensure!(
-        r.is_none() && g.is_none(),
-        "there were trailing bytes (rg buffers are not none)"
+        bytes.len() as u64 <= expected,
+        "there were trailing bytes after the image body"
     );
-
     ensure!(
-        image_data.len() == width as usize * height as usize,
-        "image pixel count mismatch"
+        bytes.len() as u64 == expected,
+        "image body is truncated. expected {} bytes, but found {}",
+        expected,
+        bytes.len()
     );
 
+    let pixels = bytes
+        .chunks(3)
+        .map(|c| Color {
+            r: c[0],
+            g: c[1],
+            b: c[2],
+        })
+        .collect();
+
     Ok(Image {
         width,
         height,
-        pixels: image_data,
+        pixels,
     })
 }
 
+/// `problem` を `read_problem` で読める PPM 形式で書き出す.
+pub fn write_problem(problem: &Problem, mut out: impl Write) -> Result<()> {
+    write!(
+        out,
+        "P6\n# {} {}\n# {}\n# {} {}\n{} {}\n255\n",
+        problem.dims.width,
+        problem.dims.height,
+        problem.select_limit,
+        problem.select_cost,
+        problem.swap_cost,
+        problem.image.width,
+        problem.image.height
+    )
+    .context("failed to write header")?;
+    let bytes: Vec<u8> = problem
+        .image
+        .pixels
+        .iter()
+        .flat_map(|c| [c.r, c.g, c.b])
+        .collect();
+    out.write_all(&bytes).context("failed to write image body")
+}
+
 #[test]
 fn problem_read_test() {
     let problem = include_bytes!("../test_cases/01_q.ppm");
@@ -166,6 +225,125 @@ fn problem_read_test() {
     assert_eq!(result.select_limit, 1);
     assert_eq!(result.select_cost, 3);
     assert_eq!(result.swap_cost, 1);
-    assert_eq!(result.rows, 2);
-    assert_eq!(result.cols, 2);
+    assert_eq!(
+        result.dims,
+        Dims {
+            width: 2,
+            height: 2
+        }
+    );
+}
+
+#[test]
+fn problem_write_test() {
+    let problem = include_bytes!("../test_cases/01_q.ppm");
+    let problem = read_problem(problem.as_ref()).unwrap();
+    let mut written = vec![];
+    write_problem(&problem, &mut written).unwrap();
+    let result = read_problem(written.as_slice()).unwrap();
+    assert_eq!(problem.select_limit, result.select_limit);
+    assert_eq!(problem.select_cost, result.select_cost);
+    assert_eq!(problem.swap_cost, result.swap_cost);
+    assert_eq!(problem.dims, result.dims);
+    assert!(problem.image.pixels == result.image.pixels);
+}
+
+#[test]
+fn problem_read_hostile_test() {
+    let header = |dims: &str| format!("P6\n# 2 2\n# 3\n# 2 1\n{}\n255\n", dims);
+    // 宣言だけ巨大な画像
+    assert!(read_problem(header("65534 65534").as_bytes()).is_err());
+    // 正方形に分割できない
+    assert!(read_problem(header("4 6").as_bytes()).is_err());
+    assert!(read_problem(header("1 1").as_bytes()).is_err());
+    // 改行の無い巨大な行
+    let long = format!("P6\n#{}", " 2".repeat(1 << 16));
+    assert!(read_problem(long.as_bytes()).is_err());
+    assert!(read_problem(b"P6\n#2 2\n".as_ref()).is_err());
+    assert!(read_problem(b"".as_ref()).is_err());
+}
+
+#[cfg(test)]
+fn arbitrary_problem() -> impl proptest::strategy::Strategy<Value = Problem> {
+    use proptest::{collection::vec, prelude::*};
+
+    (2u8..=4, 2u8..=4, 1u16..=3, any::<(u8, u16, u16)>())
+        .prop_flat_map(|(grid_width, grid_height, side, costs)| {
+            let dims = Dims {
+                width: grid_width,
+                height: grid_height,
+            };
+            let (width, height) = (grid_width as u16 * side, grid_height as u16 * side);
+            let pixels = vec(any::<(u8, u8, u8)>(), width as usize * height as usize);
+            (Just((dims, width, height, costs)), pixels)
+        })
+        .prop_map(|((dims, width, height, costs), pixels)| Problem {
+            select_limit: costs.0,
+            select_cost: costs.1,
+            swap_cost: costs.2,
+            dims,
+            image: Image {
+                width,
+                height,
+                pixels: pixels
+                    .into_iter()
+                    .map(|(r, g, b)| Color { r, g, b })
+                    .collect(),
+            },
+        })
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn problem_roundtrip_prop(problem in arbitrary_problem()) {
+        let mut written = vec![];
+        write_problem(&problem, &mut written).unwrap();
+        let result = read_problem(written.as_slice()).unwrap();
+        proptest::prop_assert_eq!(problem.dims, result.dims);
+        proptest::prop_assert!(problem.image.pixels == result.image.pixels);
+    }
+
+    #[test]
+    fn problem_truncated_prop(problem in arbitrary_problem(), cut in 0.0..1.0f64) {
+        let mut written = vec![];
+        write_problem(&problem, &mut written).unwrap();
+        let len = (written.len() as f64 * cut) as usize;
+        proptest::prop_assert!(read_problem(&written[..len]).is_err());
+        written.push(0);
+        proptest::prop_assert!(read_problem(written.as_slice()).is_err());
+    }
+
+    #[test]
+    fn problem_corrupted_prop(
+        problem in arbitrary_problem(),
+        corruptions in proptest::collection::vec((0usize..64, proptest::prelude::any::<u8>()), 1..4),
+    ) {
+        // ヘッダ付近を壊しても panic しない
+        let mut written = vec![];
+        write_problem(&problem, &mut written).unwrap();
+        for (index, byte) in corruptions {
+            let index = index % written.len();
+            written[index] = byte;
+        }
+        let _ = read_problem(written.as_slice());
+    }
+
+    #[test]
+    fn problem_malformed_header_prop(
+        lines in proptest::collection::vec("(#|P6)?[ #0-9a-z]{0,12}", 0..7),
+        width in proptest::prelude::any::<u16>(),
+        height in proptest::prelude::any::<u16>(),
+        body in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..64),
+    ) {
+        let mut data = lines.join("\n").into_bytes();
+        data.extend(format!("\n{} {}\n255\n", width, height).bytes());
+        data.extend(body);
+        let _ = read_problem(data.as_slice());
+    }
+
+    #[test]
+    fn problem_arbitrary_bytes_prop(data in proptest::collection::vec(proptest::prelude::any::<u8>(), 0..512)) {
+        let _ = read_problem(data.as_slice());
+    }
 }
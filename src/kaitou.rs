@@ -1,4 +1,9 @@
-use crate::basis::{Movement, Operation, Rot};
+use anyhow::{bail, ensure, Context as _, Result};
+
+use crate::{
+    basis::{Movement, Operation, Rot},
+    grid::{Grid, Pos},
+};
 
 const NEW_LINE: &str = "\r\n";
 
@@ -32,21 +37,115 @@ pub fn ans(ope: &[Operation], rot: &[Rot]) -> String {
         result += NEW_LINE;
 
         //交換操作
-        for j in &i.movements {
-            let m = match j {
-                Movement::Up => 'U',
-                Movement::Right => 'R',
-                Movement::Down => 'D',
-                Movement::Left => 'L',
-            };
-            result.push(m);
-        }
+        result.extend(i.movements.iter().map(|m| m.as_char()));
         result += NEW_LINE;
     }
 
     result
 }
 
+/// `ans` で出力した回答を `grid` 上の回転情報と操作列に読み戻す. 改行は `\r\n` と `\n` のどちらも受け付ける.
+pub fn parse(grid: Grid, answer: &str) -> Result<(Vec<Rot>, Vec<Operation>)> {
+    let mut lines = answer.lines();
+    let mut next_line = |expect: &str| {
+        lines
+            .next()
+            .with_context(|| format!("expected {}, but no more lines found", expect))
+    };
+
+    //回転情報
+    let rots = next_line("rotations")?
+        .chars()
+        .map(parse_rot)
+        .collect::<Result<Vec<_>>>()
+        .context("failed to parse rotations")?;
+    ensure!(
+        rots.len() == grid.width() as usize * grid.height() as usize,
+        "expected {} rotations, but found {}",
+        grid.width() as usize * grid.height() as usize,
+        rots.len()
+    );
+
+    //選択回数
+    let select_count: usize = next_line("select count")?
+        .parse()
+        .context("failed to parse select count")?;
+
+    let mut ops = Vec::with_capacity(select_count);
+    for i in 0..select_count {
+        //選択画像位置
+        let select = parse_select(grid, next_line("selected position")?)
+            .with_context(|| format!("failed to parse selected position of operation {}", i))?;
+
+        //交換回数
+        let swap_count: usize = next_line("swap count")?
+            .parse()
+            .with_context(|| format!("failed to parse swap count of operation {}", i))?;
+
+        //交換操作
+        let movements = next_line("movements")?
+            .chars()
+            .map(parse_movement)
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("failed to parse movements of operation {}", i))?;
+        ensure!(
+            movements.len() == swap_count,
+            "expected {} movements in operation {}, but found {}",
+            swap_count,
+            i,
+            movements.len()
+        );
+
+        ops.push(Operation { select, movements });
+    }
+
+    ensure!(
+        lines.all(|line| line.trim().is_empty()),
+        "there were trailing lines after the operations"
+    );
+
+    Ok((rots, ops))
+}
+
+fn parse_rot(c: char) -> Result<Rot> {
+    Ok(match c {
+        '0' => Rot::R0,
+        '1' => Rot::R90,
+        '2' => Rot::R180,
+        '3' => Rot::R270,
+        _ => bail!("unexpected rotation: {:?}", c),
+    })
+}
+
+fn parse_movement(c: char) -> Result<Movement> {
+    Ok(match c {
+        'U' => Movement::Up,
+        'R' => Movement::Right,
+        'D' => Movement::Down,
+        'L' => Movement::Left,
+        _ => bail!("unexpected movement: {:?}", c),
+    })
+}
+
+fn parse_select(grid: Grid, line: &str) -> Result<Pos> {
+    let mut digits = line.chars().map(|c| {
+        c.to_digit(16)
+            .with_context(|| format!("expected hex digit, but found {:?}", c))
+    });
+    let (x, y) = match (digits.next(), digits.next(), digits.next()) {
+        (Some(x), Some(y), None) => (x? as u8, y? as u8),
+        _ => bail!("expected 2 hex digits, but found {:?}", line),
+    };
+    ensure!(
+        x < grid.width() && y < grid.height(),
+        "selected position ({}, {}) is out of {:?}",
+        x,
+        y,
+        grid
+    );
+    Ok(grid.pos(x, y))
+}
+
 #[test]
 fn case1() {
     use crate::grid::Grid;
@@ -90,3 +189,75 @@ fn case1() {
 
     assert_eq!(expected, actual);
 }
+
+#[test]
+fn parse_round_trip() {
+    let grid = Grid::new(16, 2);
+    let rots = (0..32).map(|i| Rot::from_num(i % 4)).collect::<Vec<_>>();
+    let ops = vec![
+        Operation {
+            select: grid.pos(15, 1),
+            movements: vec![Movement::Left, Movement::Up, Movement::Left],
+        },
+        Operation {
+            select: grid.pos(10, 0),
+            movements: vec![],
+        },
+        Operation {
+            select: grid.pos(0, 1),
+            movements: vec![Movement::Right, Movement::Down],
+        },
+    ];
+
+    let answer = ans(&ops, &rots);
+    let (actual_rots, actual_ops) = parse(grid, &answer).unwrap();
+
+    assert_eq!(rots, actual_rots);
+    assert_eq!(ops, actual_ops);
+}
+
+#[test]
+fn parse_test_cases() {
+    let grid = Grid::new(2, 2);
+    let (rots, ops) = parse(grid, include_str!("../test_cases/01_a.txt")).unwrap();
+    assert_eq!(vec![Rot::R0; 4], rots);
+    assert_eq!(
+        vec![Operation {
+            select: grid.pos(1, 0),
+            movements: vec![Movement::Down],
+        }],
+        ops
+    );
+
+    let grid = Grid::new(3, 2);
+    let (rots, ops) = parse(grid, include_str!("../test_cases/02_a.txt")).unwrap();
+    assert_eq!(
+        vec![Rot::R0, Rot::R0, Rot::R0, Rot::R90, Rot::R180, Rot::R0],
+        rots
+    );
+    assert_eq!(
+        vec![Operation {
+            select: grid.pos(2, 1),
+            movements: vec![Movement::Left, Movement::Up, Movement::Left, Movement::Down],
+        }],
+        ops
+    );
+}
+
+#[test]
+fn parse_errors() {
+    let grid = Grid::new(2, 2);
+    // 回転情報の数が合わない
+    assert!(parse(grid, "000\r\n0\r\n").is_err());
+    // 範囲外の選択
+    assert!(parse(grid, "0000\r\n1\r\n20\r\n1\r\nD\r\n").is_err());
+    // 交換回数と交換操作の数が合わない
+    assert!(parse(grid, "0000\r\n1\r\n10\r\n2\r\nD\r\n").is_err());
+    // 操作が足りない
+    assert!(parse(grid, "0000\r\n2\r\n10\r\n1\r\nD\r\n").is_err());
+    // 余計な行がある
+    assert!(parse(grid, "0000\r\n0\r\n10\r\n").is_err());
+    // 未知の文字
+    assert!(parse(grid, "0004\r\n0\r\n").is_err());
+    assert!(parse(grid, "0000\r\n1\r\n10\r\n1\r\nX\r\n").is_err());
+}
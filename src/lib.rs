@@ -1,9 +1,38 @@
 #![allow(dead_code)]
 
+pub mod answer_diff;
+#[cfg(feature = "fs")]
+pub mod answer_sink;
 pub mod basis;
+#[cfg(feature = "fs")]
+pub mod best_submission;
+pub mod cancel;
+#[cfg(feature = "fs")]
+pub mod checkpoint;
+#[cfg(all(feature = "fs", feature = "gui"))]
+pub mod cli;
+#[cfg(feature = "net")]
+pub mod client;
+#[cfg(feature = "fs")]
+pub mod config;
+pub mod error;
+#[cfg(feature = "fs")]
+pub mod feedback;
 pub mod fragment;
 pub mod grid;
 pub mod image;
 pub mod kaitou;
+pub mod memory;
 pub mod move_resolve;
+pub mod pipeline;
 pub mod pixel_match;
+pub mod render;
+pub mod report;
+pub mod scramble;
+pub mod simulator;
+#[cfg(feature = "net")]
+pub mod submitter;
+
+pub use cancel::CancellationToken;
+pub use error::{Error, Result};
+pub use pipeline::{solve_problem, Answer, SolveOptions};
@@ -0,0 +1,139 @@
+//! プロセスの使っているメモリ (RSS) を測る.
+//!
+//! ビームサーチがメモリを使い果たしても OOM で殺されるだけで何も残らないので, 別のスレッドで定期的に測って
+//! 最大値を `tracing` で知らせ, 最後にまとめて報告できるようにする. `/proc` の無い環境では何も測れない.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+/// 今のプロセスの RSS をバイト数で返す. 測れなければ `None`.
+pub fn current_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_vm_rss(&status)
+}
+
+/// `/proc/self/status` の `VmRSS:   1234 kB` の行を読む.
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let mut fields = line["VmRSS:".len()..].split_whitespace();
+    let kib: u64 = fields.next()?.parse().ok()?;
+    match fields.next() {
+        Some("kB") => Some(kib * 1024),
+        _ => None,
+    }
+}
+
+/// バイト数を MiB にする.
+pub fn to_mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+/// `RssUsage` は `RssSampler` で測ったメモリの使い方.
+///
+/// RSS はプロセス全体で 1 つなので, `peak` にはそれより前の処理で確保したままのメモリも含まれる.
+/// 測っていた処理だけで増えた分の目安には `growth` を使う.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RssUsage {
+    /// 測っている間のプロセスの RSS の最大値
+    pub peak: Option<u64>,
+    /// 測り始めたときの RSS から `peak` までの増加
+    pub growth: Option<u64>,
+}
+
+/// `RssSampler` は作ってから止めるまで, 別のスレッドで `interval` ごとに RSS を測り, その最大値を覚える.
+///
+/// 最大値を更新するたびに `tracing::debug!` で知らせる. 落としたときもスレッドを止める.
+#[derive(Debug)]
+pub struct RssSampler {
+    baseline: Option<u64>,
+    peak: Arc<AtomicU64>,
+    stop: Option<(mpsc::Sender<()>, JoinHandle<()>)>,
+}
+
+impl RssSampler {
+    pub fn start(interval: Duration) -> Self {
+        let baseline = current_rss();
+        let peak = Arc::new(AtomicU64::new(baseline.unwrap_or(0)));
+        let (tx, rx) = mpsc::channel();
+        let handle = {
+            let peak = Arc::clone(&peak);
+            std::thread::spawn(move || loop {
+                if let Some(rss) = current_rss() {
+                    if peak.fetch_max(rss, Ordering::Relaxed) < rss {
+                        tracing::debug!(rss_mib = rss / (1024 * 1024), "memory: new peak");
+                    }
+                }
+                match rx.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            })
+        };
+        Self {
+            baseline,
+            peak,
+            stop: Some((tx, handle)),
+        }
+    }
+
+    /// これまでに測った RSS の最大値. 1 度も測れていなければ `None`.
+    pub fn peak(&self) -> Option<u64> {
+        match self.peak.load(Ordering::Relaxed) {
+            0 => None,
+            peak => Some(peak),
+        }
+    }
+
+    /// 測るのをやめ, それまでの最大値と測り始めてからの増加を返す.
+    pub fn finish(mut self) -> RssUsage {
+        self.shutdown();
+        let peak = self.peak();
+        RssUsage {
+            peak,
+            growth: peak
+                .zip(self.baseline)
+                .map(|(peak, baseline)| peak.saturating_sub(baseline)),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        if let Some((tx, handle)) = self.stop.take() {
+            let _ = tx.send(());
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for RssSampler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[test]
+fn test_rss() {
+    let status = "Name:\tbatch\nVmPeak:\t  20000 kB\nVmRSS:\t   1234 kB\nThreads:\t4\n";
+    assert_eq!(Some(1234 * 1024), parse_vm_rss(status));
+    assert_eq!(None, parse_vm_rss("Name:\tbatch\n"));
+
+    if cfg!(target_os = "linux") {
+        // 最初の 1 回は作ってすぐに測る
+        let sampler = RssSampler::start(Duration::from_millis(10));
+        let buffer = vec![1u8; 64 * 1024 * 1024];
+        std::thread::sleep(Duration::from_millis(100));
+        let usage = sampler.finish();
+        std::hint::black_box(&buffer);
+        drop(buffer);
+        assert!(matches!(usage.peak, Some(peak) if peak > 0));
+        // 測り始めてから確保した分だけ増えている
+        assert!(matches!(usage.growth, Some(growth) if growth >= 32 * 1024 * 1024));
+        assert!(usage.growth <= usage.peak);
+    }
+}
@@ -1,23 +1,44 @@
-use self::{edges_nodes::Nodes, state::actions_to_operations};
+use std::{
+    cmp::Reverse,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use self::{
+    cycles::Cycles,
+    edges_nodes::Nodes,
+    state::{actions_to_operations, merge_selects},
+};
 use crate::{
     basis::Operation,
-    grid::{
-        board::{Board, BoardFinder},
-        Grid, Pos,
-    },
+    cancel::CancellationToken,
+    error::{Error, Result},
+    grid::{board::Board, Grid, Pos, VecOnGrid},
     move_resolve::{
-        approx::{gen::FromOutside, Solver},
-        beam_search::beam_search,
-        state::{completer::Completer, cost_reducer::CostReducer, GridAction},
+        approx::{gen::FromOutside, SliceStrategy, Solver},
+        beam_search::{beam_search, SearchLimits},
+        cost_model::{CostModel, CostWeighting},
+        partial::Reduction,
+        state::{
+            completer::Completer, cost_reducer::CostReducer, DistanceTable, GridAction, SqManhattan,
+        },
     },
+    simulator,
 };
 
 pub mod approx;
 pub mod beam_search;
+pub mod cost_model;
+pub mod cycles;
 pub mod dijkstra;
 pub mod edges_nodes;
 pub mod ida_star;
 pub mod least_movements;
+mod partial;
+pub mod seg_tree;
 mod state;
 #[cfg(test)]
 mod tests;
@@ -40,6 +61,11 @@ pub struct ResolveParam {
 
 /// 完成形から `movements` のとおりに移動されているとき, それを解消する移動手順の近似解を複数求める.
 ///
+/// 近似解はそのコストと組にして, それまでに返したものよりコストが小さいものだけを返す.
+/// 動かす断片が無ければ, 探索せずに空の操作列だけを返す.
+/// `movements` が断片の置換になっていなければ, それを説明するエラーを返す.
+/// 動かす断片があるのに選択回数の上限が 0 なら [`Error::NoSelection`] を返す.
+///
 /// ```
 /// use procon2021_comp::basis::Operation;
 /// use procon2021_comp::grid::{Grid, VecOnGrid};
@@ -49,7 +75,7 @@ pub struct ResolveParam {
 /// let mut field = VecOnGrid::with_init(grid, grid.pos(0, 0));
 /// field[grid.pos(0, 0)] = grid.pos(1, 0);
 /// field[grid.pos(1, 0)] = grid.pos(0, 0);
-/// let path = resolve(
+/// let (path, cost) = resolve(
 ///     grid,
 ///     &[
 ///         (grid.pos(0, 0), grid.pos(1, 0)),
@@ -60,9 +86,10 @@ pub struct ResolveParam {
 ///         swap_cost: 1,
 ///         select_cost: 1,
 ///     },
-/// ).next().expect("the solution must be found");
+/// ).unwrap().next().expect("the solution must be found");
 ///
 /// use procon2021_comp::basis::Movement;
+/// assert_eq!(cost, 2);
 /// assert_eq!(path.len(), 1);
 /// assert_eq!(
 ///     Operation {
@@ -76,51 +103,257 @@ pub fn resolve(
     grid: Grid,
     movements: &'_ [(Pos, Pos)],
     param: ResolveParam,
-) -> impl Iterator<Item = Vec<Operation>> + '_ {
-    phase1(grid, movements, param, 200)
-        .flat_map(phase2)
-        .flat_map(phase3(param, 50))
+) -> Result<impl Iterator<Item = (Vec<Operation>, u64)> + '_> {
+    resolve_with_config(grid, movements, param, BeamConfig::default())
+}
+
+/// `BeamConfig` は `resolve` の各段階で行うビームサーチの幅と, 状態の分け方やコストの重み付けを表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BeamConfig {
+    pub phase1: usize,
+    pub phase3: usize,
+    /// ビームが似た盤面ばかりにならないよう, まだ揃っていない上からこの行数の並びでも状態を分ける. 0 なら分けない.
+    pub diversity_rows: u8,
+    /// 探索で選択と交換のコストをどう重み付けるか.
+    pub weighting: CostWeighting,
+}
+
+impl Default for BeamConfig {
+    fn default() -> Self {
+        Self {
+            phase1: 200,
+            phase3: 50,
+            diversity_rows: 0,
+            weighting: CostWeighting::AsIs,
+        }
+    }
+}
+
+/// ビームサーチの設定を `beam` にして `resolve` を行う.
+pub fn resolve_with_config(
+    grid: Grid,
+    movements: &'_ [(Pos, Pos)],
+    param: ResolveParam,
+    beam: BeamConfig,
+) -> Result<impl Iterator<Item = (Vec<Operation>, u64)> + '_> {
+    resolve_cancellable(grid, movements, param, beam, CancellationToken::new())
+}
+
+/// `resolve_with_config` と同じく探索し, `cancel` でやめるよう求められたらビームサーチや端を揃える途中でやめる.
+///
+/// やめるまでに返した候補はそのまま使える. やめた後はそれ以上候補を返さない.
+pub fn resolve_cancellable(
+    grid: Grid,
+    movements: &'_ [(Pos, Pos)],
+    param: ResolveParam,
+    beam: BeamConfig,
+    cancel: CancellationToken,
+) -> Result<impl Iterator<Item = (Vec<Operation>, u64)> + '_> {
+    resolve_budgeted(
+        grid,
+        movements,
+        param,
+        beam,
+        PhaseBudgets::default(),
+        cancel,
+    )
+}
+
+/// `PhaseBudgets` は `resolve_budgeted` の段階ごとにかける時間を表す. `None` ならその段階を時間で区切らない.
+///
+/// 各段階の期限は探索を始めてからその段階までの時間を足した時刻にするので, 早く終わった段階の残りは後の段階に回る.
+/// `search` を区切らなければ, `completion` は探索を始めてからの時間になる.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PhaseBudgets {
+    /// `phase1` と `phase2` で途中までの盤面を作る時間
+    pub search: Option<Duration>,
+    /// `phase3` で盤面を揃え切る時間
+    pub completion: Option<Duration>,
+}
+
+/// `resolve_cancellable` と同じく探索し, 各段階を `budgets` の時間で打ち切る.
+///
+/// `phase1` のビームサーチは `search` の時間で打ち切り, その後は 1 回だけ選択した状態から盤面を作る.
+/// それ以外の期限は最初の候補を返すまで守らず, 答えが 1 つも無いまま終わらないようにする.
+pub fn resolve_budgeted(
+    grid: Grid,
+    movements: &'_ [(Pos, Pos)],
+    param: ResolveParam,
+    beam: BeamConfig,
+    budgets: PhaseBudgets,
+    cancel: CancellationToken,
+) -> Result<impl Iterator<Item = (Vec<Operation>, u64)> + '_> {
+    Nodes::validate(grid, movements)?;
+    if param.select_limit == 0 && !movements.is_empty() {
+        return Err(Error::NoSelection);
+    }
+    // すべての断片が正しい位置にあれば, 探索せずに回転だけの回答を返す
+    let in_place = movements.is_empty();
+    if in_place {
+        tracing::info!("all fragments are in place. only rotations are needed");
+    }
+    let search = (!in_place).then(|| {
+        // 距離の表は盤面の大きさだけで決まるので, 1 回だけ作ってすべての状態で共有する
+        let pre_calc = Arc::new(SqManhattan::table(grid));
+        let (stop, phase2_cancel) = (cancel.clone(), cancel.clone());
+        let deadlines = Deadlines::new(budgets);
+        let phase1_deadlines = deadlines.clone();
+        phase1(
+            grid,
+            movements,
+            param,
+            beam,
+            Arc::clone(&pre_calc),
+            (budgets.search, &cancel),
+        )
+        .take_while(move |_| !stop.is_cancelled() && !phase1_deadlines.search_expired())
+        .flat_map(move |state| phase2(state, &phase2_cancel))
+        .flat_map(phase3(param, beam, pre_calc, (deadlines, cancel)))
+    });
+    Ok(search
+        .into_iter()
+        .flatten()
+        .chain(in_place.then(Vec::new))
+        .filter_map(rank_candidates(param)))
+}
+
+/// `Deadlines` は `PhaseBudgets` から求めた各段階の期限と, 最初の候補が見つかったかを覚える.
+#[derive(Debug, Clone)]
+struct Deadlines {
+    search: Option<Instant>,
+    completion: Option<Instant>,
+    found: Arc<AtomicBool>,
+}
+
+impl Deadlines {
+    fn new(budgets: PhaseBudgets) -> Self {
+        let start = Instant::now();
+        let search = budgets.search.map(|budget| start + budget);
+        Self {
+            search,
+            completion: budgets
+                .completion
+                .map(|budget| search.unwrap_or(start) + budget),
+            found: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn is_found(&self) -> bool {
+        self.found.load(Ordering::Relaxed)
+    }
+
+    /// 候補が見つかっていて, `phase1` と `phase2` の期限を過ぎたか.
+    fn search_expired(&self) -> bool {
+        self.is_found() && matches!(self.search, Some(deadline) if deadline <= Instant::now())
+    }
+
+    /// `phase3` のビームサーチにかけられる残りの時間. 候補が見つかるまでは区切らない.
+    fn completion_limit(&self) -> Option<Duration> {
+        self.completion
+            .filter(|_| self.is_found())
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// 候補の操作列にコストを付け, それまでの候補よりコストが小さいものだけを通す.
+///
+/// 同じ操作列は同じコストになるので, 別の `phase1` の分岐から同じ答えが出ても 1 度しか通さない.
+fn rank_candidates(
+    param: ResolveParam,
+) -> impl FnMut(Vec<Operation>) -> Option<(Vec<Operation>, u64)> {
+    let mut best = None;
+    move |ops| {
+        let cost = simulator::cost(param.select_cost, param.swap_cost, &ops);
+        if matches!(best, Some(best) if best <= cost) {
+            tracing::debug!(cost, "skipped a candidate not better than the previous one");
+            return None;
+        }
+        best = Some(cost);
+        Some((ops, cost))
+    }
 }
 
 fn phase1(
     grid: Grid,
     movements: &[(Pos, Pos)],
     param: ResolveParam,
-    beam_width: usize,
+    beam: BeamConfig,
+    pre_calc: Arc<DistanceTable>,
+    (time_limit, cancel): (Option<Duration>, &CancellationToken),
 ) -> impl Iterator<Item = (Vec<GridAction>, Board)> {
     let Nodes { nodes, .. } = Nodes::new(grid, movements);
     let empty = Board::new(None, nodes.clone());
     let phase1 = empty.clone();
     let chain = empty.clone();
 
-    beam_search(CostReducer::new(empty, param), beam_width)
+    let model = CostModel::new(param, beam.weighting);
+    let selects = chain_seeds(&nodes, model);
+
+    let initial = CostReducer::new(empty, param, model, pre_calc, beam.diversity_rows);
+    let limits = SearchLimits {
+        max_cost: initial.max_cost(),
+        max_iterations: None,
+        time_limit,
+        cancel: Some(cancel.clone()),
+    };
+    beam_search(initial, beam.phase1, limits)
         .map(move |(actions, _)| {
             let mut board = phase1.clone();
             apply_actions(&mut board, &actions);
             (actions, board)
         })
-        .chain(
-            grid.all_pos()
-                .filter(move |&p| p != nodes[p])
-                .map(move |select| {
-                    let mut board = chain.clone();
-                    board.select(select);
-                    (vec![GridAction::Select(select)], board)
-                }),
-        )
+        .chain(selects.into_iter().map(move |select| {
+            let mut board = chain.clone();
+            board.select(select);
+            (vec![GridAction::Select(select)], board)
+        }))
+}
+
+/// `phase1` のビームサーチの後に, 1 回だけ選択した状態として試すマスを決める.
+///
+/// 各巡回からは `CostModel::chain_quota` の数だけ, 正しい位置に遠いマスを残す. 散らばった巡回に属するマスほど先に試す.
+fn chain_seeds(nodes: &VecOnGrid<Pos>, model: CostModel) -> Vec<Pos> {
+    let grid = nodes.grid;
+    let cycles = Cycles::new(nodes);
+
+    let mut seeds = VecOnGrid::with_init(grid, false);
+    for cycle in cycles.iter() {
+        let mut cells = cycle.to_vec();
+        cells.sort_by_key(|&p| Reverse(grid.looping_manhattan_dist(p, nodes[p])));
+        for &cell in cells.iter().take(model.chain_quota(cycle.len())) {
+            seeds[cell] = true;
+        }
+    }
+    let mut selects: Vec<_> = grid.all_pos().filter(|&p| seeds[p]).collect();
+    selects.sort_by_key(|&p| Reverse(cycles.scatter_amount(p)));
+    selects
 }
 
-fn phase2((mut actions, mut board): (Vec<GridAction>, Board)) -> Option<(Vec<GridAction>, Board)> {
+fn phase2(
+    (mut actions, mut board): (Vec<GridAction>, Board),
+    cancel: &CancellationToken,
+) -> Option<(Vec<GridAction>, Board)> {
+    let _span = tracing::debug_span!("phase2").entered();
     let grid = board.grid();
     if grid.width() <= 4 && grid.height() <= 4 {
         return Some((actions, board));
     }
-    let mut solver = Solver {
-        threshold_x: 2,
-        threshold_y: 2,
-        targets_gen: FromOutside,
-    };
-    let second_actions = solver.solve(board.clone())?;
+    // 揃える端の選び方ごとに近似解を求め, 交換回数が最も少ないものを使う
+    let second_actions = SliceStrategy::ALL
+        .iter()
+        .filter_map(|&strategy| {
+            let mut solver = Solver {
+                threshold_x: 2,
+                threshold_y: 2,
+                targets_gen: FromOutside,
+                strategy,
+                cancel: Some(cancel.clone()),
+            };
+            let actions = solver.solve(board.clone())?;
+            tracing::debug!(?strategy, swaps = actions.len(), "phase2 estimate");
+            Some(actions)
+        })
+        .min_by_key(|actions| actions.len())?;
     apply_actions(&mut board, &second_actions);
     actions.extend(second_actions.into_iter());
     Some((actions, board))
@@ -128,19 +361,63 @@ fn phase2((mut actions, mut board): (Vec<GridAction>, Board)) -> Option<(Vec<Gri
 
 fn phase3(
     param: ResolveParam,
-    beam_width: usize,
+    beam: BeamConfig,
+    pre_calc: Arc<DistanceTable>,
+    (deadlines, cancel): (Deadlines, CancellationToken),
 ) -> impl FnMut((Vec<GridAction>, Board)) -> Option<Vec<Operation>> {
-    let mut min_cost = param.swap_cost as u64 * 16 + param.select_limit as u64 * 4;
+    let model = CostModel::new(param, beam.weighting);
+    let mut min_cost = model.completion_budget(param.select_limit);
     move |(mut actions, mut board): (Vec<GridAction>, Board)| {
+        let _span = tracing::debug_span!("phase3").entered();
+        let grid = board.grid();
         let mut param = param;
-        let (selects, _) = actions_counts(&actions);
+        // それまでの段階をまたいで選び直している分は, 答えにするときにまとめるので数えない
+        let (selects, _) = actions_counts(&merge_selects(grid, &actions));
         param.select_limit -= selects as u8;
+        // 揃っていないマスを囲む長方形だけを切り出して探索する. 選び直せなければ, 選択しているマスも含めて切り出す
+        let prev_action = actions.last().copied();
+        let reduction = Reduction::new(&board, param.select_limit == 0);
+        let completer = match reduction {
+            Some(reduction) => {
+                tracing::debug!(grid = ?reduction.grid(), "phase3 on the reduced board");
+                Completer::new(
+                    reduction.board(&board),
+                    param,
+                    model,
+                    reduction.prev_action(&board, prev_action),
+                    Arc::new(reduction.table(&pre_calc)),
+                    beam.diversity_rows,
+                )
+            }
+            None => Completer::new(
+                board.clone(),
+                param,
+                model,
+                prev_action,
+                Arc::clone(&pre_calc),
+                beam.diversity_rows,
+            ),
+        };
         beam_search(
-            Completer::new(board.clone(), param, actions.last().copied(), min_cost),
-            beam_width,
+            completer,
+            beam.phase3,
+            SearchLimits {
+                max_cost: min_cost,
+                max_iterations: None,
+                time_limit: deadlines.completion_limit(),
+                cancel: Some(cancel.clone()),
+            },
         )
         .next()
         .and_then(|(third_actions, cost)| {
+            tracing::trace!(cost, min_cost, "candidate");
+            let third_actions: Vec<_> = match reduction {
+                Some(reduction) => third_actions
+                    .into_iter()
+                    .map(|action| reduction.to_full_action(action))
+                    .collect(),
+                None => third_actions,
+            };
             apply_actions(&mut board, &third_actions);
             debug_assert!(
                 board
@@ -153,8 +430,18 @@ fn phase3(
             if cost < min_cost {
                 min_cost = cost;
                 actions.extend(third_actions.into_iter());
-                eprintln!("{:?}", actions);
-                Some(actions_to_operations(actions))
+                tracing::debug!(?actions);
+                let (selects, _) = actions_counts(&actions);
+                let ops = actions_to_operations(grid, actions);
+                let merged_selects = selects - ops.len();
+                tracing::info!(
+                    cost,
+                    merged_selects,
+                    saved_cost = merged_selects as u64 * param.select_cost as u64,
+                    "found a better answer"
+                );
+                deadlines.found.store(true, Ordering::Relaxed);
+                Some(ops)
             } else {
                 None
             }
@@ -163,11 +450,11 @@ fn phase3(
 }
 
 fn apply_actions(board: &mut Board, ops: &[GridAction]) {
+    let grid = board.grid();
     for &op in ops {
         match op {
             GridAction::Swap(mov) => {
-                let finder = BoardFinder::new(board.grid());
-                let moved = finder.move_pos_to(board.selected().unwrap(), mov);
+                let moved = grid.looping_move(board.selected().unwrap(), mov);
                 board.swap_to(moved);
             }
             GridAction::Select(sel) => board.select(sel),
@@ -1,10 +1,17 @@
+use std::cmp::Reverse;
+
 use self::estimate::estimate_solve_row;
-use super::GridAction;
+use super::{
+    cycles::Cycles,
+    seg_tree::{SegTree, Sum},
+    GridAction,
+};
 use crate::{
     basis::Movement,
+    cancel::CancellationToken,
     grid::{
         board::{Board, BoardFinder},
-        Pos,
+        Grid, Pos,
     },
 };
 
@@ -16,21 +23,59 @@ pub trait NextTargetsGenerator {
     fn next_targets(&mut self, finder: &BoardFinder) -> Vec<Pos>;
 }
 
+/// `SliceStrategy` は盤面の端から 1 列ずつ揃えるときに, どの端を削っていくかを表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SliceStrategy {
+    /// 常に長い方の辺を縮めるように, 上端の行を揃える.
+    #[default]
+    Rows,
+    /// 左上の角から内側へ, 上端の行と左端の列を交互に揃える. 横長の盤面で短くなりやすい.
+    RowsAndColumns,
+    /// 4 つの端のうち, 大きく散らばった巡回に属するマスが最も多い端から揃える.
+    /// 散らばった所を先に片付けるので, 最後に残る領域が小さな巡回だけになりやすい.
+    ScatteredFirst,
+    /// `Rows` と同じく長い方の辺を縮めるが, 向かい合う 2 つの端のうち, そこに来るべき断片の今の位置からの距離の和が小さい方から揃える.
+    Cheapest,
+}
+
+impl SliceStrategy {
+    pub const ALL: [SliceStrategy; 4] = [
+        SliceStrategy::Rows,
+        SliceStrategy::RowsAndColumns,
+        SliceStrategy::ScatteredFirst,
+        SliceStrategy::Cheapest,
+    ];
+}
+
 #[derive(Debug, Default)]
 pub struct Solver<G> {
     pub threshold_x: u8,
     pub threshold_y: u8,
     pub targets_gen: G,
+    pub strategy: SliceStrategy,
+    /// やめるよう求められたら, 次の端を揃える前に諦めて `None` を返す
+    pub cancel: Option<CancellationToken>,
 }
 
 impl<G: NextTargetsGenerator> Solver<G> {
     pub(super) fn solve(&mut self, mut board: Board) -> Option<Vec<GridAction>> {
-        let mut finder = BoardFinder::new(board.grid());
+        // まだ揃っていない領域. 窓は回さずに, 揃えた端を削っていく
+        let mut window = BoardFinder::new(board.grid());
+        // 次に揃える元の盤面の端. 上, 右, 下, 左を 0 から 3 で表し, 窓をこの回転にするとその端が上端になる
+        let mut side = 0;
         let mut actions = vec![];
+        // RowsAndColumns で次に揃えるのが行か. 最初は長い方の辺を縮める
+        let mut slice_row = window.width() <= window.height();
+        // 揃えられない端があって別の端を試している間は, 向きを決め直さない
+        let mut detour = false;
+        let mut costs = (self.strategy == SliceStrategy::Cheapest).then(|| SideCosts::new(&board));
         loop {
-            if finder.height() < finder.width() {
-                finder.rotate_to(3);
+            if matches!(&self.cancel, Some(cancel) if cancel.is_cancelled()) {
+                return None;
             }
+            side = self.next_side(&window, side, &board, costs.as_ref(), slice_row, detour);
+            let mut finder = window.clone();
+            finder.rotate_to(side);
             if finder.width() <= self.threshold_x && finder.height() <= self.threshold_y {
                 break;
             }
@@ -41,24 +86,261 @@ impl<G: NextTargetsGenerator> Solver<G> {
                 .filter(|&p| !board.is_locked(p))
                 .collect();
             if targets.is_empty() || targets.contains(&board.forward(board.selected().unwrap())) {
-                finder.rotate_to(3);
+                side = (side + 3) % 4;
+                detour = self.strategy != SliceStrategy::Rows;
                 continue;
             }
 
-            let estimate = estimate_solve_row(board.clone(), &finder, &targets)?;
+            let estimate =
+                estimate_solve_row(board.clone(), &finder, &targets, self.cancel.as_ref())?;
             for &pos in &estimate.moves {
+                let selected = board.selected().unwrap();
                 board.swap_to(pos);
+                if let Some(costs) = &mut costs {
+                    costs.update(&board, board.forward(selected));
+                    costs.update(&board, board.forward(pos));
+                }
             }
             for win in estimate.moves.windows(2) {
-                let mov = Movement::between_pos(win[0], win[1]);
+                let mov = Movement::between_adjacent(board.grid(), win[0], win[1])
+                    .expect("the estimated route must move to neighbors");
                 actions.push(GridAction::Swap(mov));
             }
             for pos in targets {
                 debug_assert_eq!(pos, board.forward(pos), "{:#?}", board);
                 board.lock(pos);
             }
-            finder.slice_up();
+            match side {
+                0 => window.slice_up(),
+                1 => window.slice_right(),
+                2 => window.slice_down(),
+                3 => window.slice_left(),
+                _ => unreachable!(),
+            }
+            // 右端か左端を揃えたときは, 元の盤面で列を揃えている
+            slice_row = side % 2 == 1;
+            detour = false;
         }
         Some(actions)
     }
+
+    /// `window` の次に揃える端を返す. `detour` の間は, 今の端 `side` から向きを選び直さない.
+    fn next_side(
+        &self,
+        window: &BoardFinder,
+        side: u8,
+        board: &Board,
+        costs: Option<&SideCosts>,
+        slice_row: bool,
+        detour: bool,
+    ) -> u8 {
+        // 短い列を長い帯に沿って何本も揃えるのは難しいので, 残りが細くなったら Rows と同じにする
+        let wide = MIN_ALTERNATING_SIDE <= window.width().min(window.height());
+        match self.strategy {
+            SliceStrategy::Rows => {}
+            _ if !wide => {}
+            _ if detour => return side,
+            // 上端の行は 0, 左端の列は 3
+            SliceStrategy::RowsAndColumns => return if slice_row { 0 } else { 3 },
+            SliceStrategy::ScatteredFirst => return most_scattered_side(window, board),
+            SliceStrategy::Cheapest => {
+                let costs = costs.expect("costs must be tracked for Cheapest");
+                return costs.cheapest_side(window);
+            }
+        }
+        // 長い方の辺を縮めるように, 窓の上端が短い辺なら左隣の端に替える
+        let mut finder = window.clone();
+        finder.rotate_to(side);
+        if finder.height() < finder.width() {
+            (side + 3) % 4
+        } else {
+            side
+        }
+    }
+}
+
+/// 回転していない窓 `window` の 4 つの端のうち, 属する巡回の散らばり具合の和が最も大きい端を上端にする回転を返す.
+///
+/// 同じなら, 長い方の辺を縮める端を選ぶ.
+fn most_scattered_side(window: &BoardFinder, board: &Board) -> u8 {
+    let cycles = Cycles::new(&board.field());
+    (0..4)
+        .max_by_key(|&rotation| {
+            let mut rotated = window.clone();
+            rotated.rotate_to(rotation);
+            let top = Grid::new(rotated.width(), rotated.height());
+            let scatter: u32 = (0..rotated.width())
+                .map(|x| cycles.scatter_amount(rotated.to_original(top.pos(x, 0))))
+                .sum();
+            // 同点なら回転の少ない方にするため, 回転を逆順の優先度にする
+            (scatter, rotated.height(), Reverse(rotation))
+        })
+        .unwrap()
+}
+
+/// `SideCosts` は盤面の各マスについて, そこに来るべき断片の今の位置からの距離を行ごとと列ごとのセグメント木で持つ.
+///
+/// 窓の端は元の盤面の行か列の区間なので, 端の距離の和をどれも O(log n) で求められる.
+#[derive(Debug, Clone)]
+struct SideCosts {
+    rows: Vec<SegTree<Sum<u32>>>,
+    cols: Vec<SegTree<Sum<u32>>>,
+}
+
+impl SideCosts {
+    fn new(board: &Board) -> Self {
+        let grid = board.grid();
+        let cost = |x, y| {
+            let target = grid.pos(x, y);
+            grid.looping_manhattan_dist(board.reverse(target), target)
+        };
+        Self {
+            rows: (0..grid.height())
+                .map(|y| SegTree::from_vec((0..grid.width()).map(|x| cost(x, y)).collect()))
+                .collect(),
+            cols: (0..grid.width())
+                .map(|x| SegTree::from_vec((0..grid.height()).map(|y| cost(x, y)).collect()))
+                .collect(),
+        }
+    }
+
+    /// `target` に来るべき断片が動いた後の `board` で, `target` の距離を更新する.
+    fn update(&mut self, board: &Board, target: Pos) {
+        let cost = board
+            .grid()
+            .looping_manhattan_dist(board.reverse(target), target);
+        self.rows[target.y() as usize].set(target.x() as usize, cost);
+        self.cols[target.x() as usize].set(target.y() as usize, cost);
+    }
+
+    /// 回転していない窓 `window` の端のうち長い方の辺を縮めるものから, 距離の和が最も小さい端を上端にする回転を返す.
+    ///
+    /// 正方形なら 4 つの端から選ぶ. 同じなら回転の少ない方にする.
+    fn cheapest_side(&self, window: &BoardFinder) -> u8 {
+        let rect = window.remaining_rect();
+        let (start, end) = (rect.start(), rect.end());
+        let xs = start.x() as usize..end.x() as usize + 1;
+        let ys = start.y() as usize..end.y() as usize + 1;
+        // 元の盤面で上, 右, 下, 左の端. 窓をこの回転にするとその端が上端になる
+        let sides = [
+            self.rows[start.y() as usize].query(xs.clone()),
+            self.cols[end.x() as usize].query(ys.clone()),
+            self.rows[end.y() as usize].query(xs),
+            self.cols[start.x() as usize].query(ys),
+        ];
+        // 上端と下端は行なので, 幅が高さ以下なら長い方の辺を縮める
+        let shrinks_longer = |rotation: u8| match rotation % 2 {
+            0 => window.width() <= window.height(),
+            _ => window.height() <= window.width(),
+        };
+        (0..4)
+            .filter(|&rotation| shrinks_longer(rotation))
+            .min_by_key(|&rotation| (sides[rotation as usize], rotation))
+            .unwrap()
+    }
+}
+
+/// RowsAndColumns で行と列を交互に揃えるのは, 残りの窓の短い方の辺がこれ以上のときだけ.
+const MIN_ALTERNATING_SIDE: u8 = 4;
+
+#[test]
+fn test_slice_strategies() {
+    use crate::grid::{Grid, VecOnGrid};
+
+    let grid = Grid::new(10, 5);
+    let field = VecOnGrid::from_vec(grid, grid.all_pos().collect()).unwrap();
+    let mut board = Board::new(None, field);
+    // 決まった順に選択中の断片を動かしてかき混ぜる
+    board.select(grid.pos(5, 2));
+    let finder = BoardFinder::new(grid);
+    let movements = [
+        Movement::Left,
+        Movement::Up,
+        Movement::Right,
+        Movement::Right,
+        Movement::Down,
+    ];
+    for i in 0..200 {
+        let mov = movements[(i * 7 + i / 3) % movements.len()];
+        let next = finder.move_pos_to(board.selected().unwrap(), mov);
+        board.swap_to(next);
+    }
+
+    for strategy in SliceStrategy::ALL {
+        let mut solver = Solver {
+            threshold_x: 2,
+            threshold_y: 2,
+            targets_gen: gen::FromOutside,
+            strategy,
+            cancel: None,
+        };
+        let actions = solver.solve(board.clone()).unwrap();
+        let mut solved = board.clone();
+        super::apply_actions(&mut solved, &actions);
+        // 最後に残る 2x2 以外はすべて揃う
+        let misplaced = solved
+            .field()
+            .iter_with_pos()
+            .filter(|&(pos, &cell)| pos != cell)
+            .count();
+        assert!(misplaced <= 4, "{:?}: {:#?}", strategy, solved);
+    }
+}
+
+#[test]
+fn test_most_scattered_side() {
+    use crate::grid::VecOnGrid;
+
+    let grid = Grid::new(6, 5);
+    let scrambled = |a: Pos, b: Pos| {
+        let mut field = VecOnGrid::from_vec(grid, grid.all_pos().collect()).unwrap();
+        field.swap(a, b);
+        Board::new(None, field)
+    };
+    let finder = BoardFinder::new(grid);
+
+    // 下端の行だけが散らばっていれば, 下端を上に持ってくる
+    let board = scrambled(grid.pos(0, 4), grid.pos(3, 4));
+    assert_eq!(2, most_scattered_side(&finder, &board));
+    // 左端の列だけなら左端を上に持ってくる
+    let board = scrambled(grid.pos(0, 0), grid.pos(0, 2));
+    assert_eq!(3, most_scattered_side(&finder, &board));
+    // 揃っていれば, 長い方の辺を縮める
+    let board = scrambled(grid.pos(1, 1), grid.pos(1, 1));
+    assert_eq!(1, most_scattered_side(&finder, &board));
+}
+
+#[test]
+fn test_cheapest_side() {
+    use crate::grid::VecOnGrid;
+
+    let grid = Grid::new(6, 5);
+    let solved = VecOnGrid::from_vec(grid, grid.all_pos().collect()).unwrap();
+    let mut board = Board::new(None, solved);
+    let mut finder = BoardFinder::new(grid);
+    let costs = SideCosts::new(&board);
+    // 揃っていれば, 長い方の辺を縮める端のうち回転の少ない方
+    assert_eq!(1, costs.cheapest_side(&finder));
+
+    // 右端の列を散らかすと, 左端の列の方が安い
+    board.select(grid.pos(5, 0));
+    for y in 1..5 {
+        board.swap_to(grid.pos(5, y));
+    }
+    let mut costs = SideCosts::new(&board);
+    assert_eq!(3, costs.cheapest_side(&finder));
+
+    // 1 マスずつ更新しても作り直したものと同じになる
+    let selected = board.selected().unwrap();
+    board.swap_to(grid.pos(0, 4));
+    costs.update(&board, board.forward(selected));
+    costs.update(&board, board.forward(grid.pos(0, 4)));
+    let rebuilt = SideCosts::new(&board);
+    for y in 0..grid.height() as usize {
+        assert_eq!(rebuilt.rows[y].query(0..6), costs.rows[y].query(0..6));
+    }
+
+    // 左端を削っても, 残りの窓の左端の方が安い
+    finder.slice_left();
+    assert_eq!(3, costs.cheapest_side(&finder));
 }
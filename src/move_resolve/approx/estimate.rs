@@ -1,5 +1,6 @@
 use crate::{
     basis::Movement,
+    cancel::CancellationToken,
     grid::{
         board::{Board, BoardFinder},
         Pos,
@@ -18,10 +19,12 @@ pub(super) struct RowSolveEstimate {
     pub(super) worst_swap_pos: Pos,
 }
 
+/// `targets` の行を揃える経路を見積もる. `cancel` でやめるよう求められたら, 断片を 1 つ運ぶごとに確かめて `None` を返す.
 pub(super) fn estimate_solve_row(
-    mut board: Board,
+    board: Board,
     finder: &BoardFinder,
     targets: &[Pos],
+    cancel: Option<&CancellationToken>,
 ) -> Option<RowSolveEstimate> {
     debug_assert_eq!(
         board.looping_manhattan_dist(targets[targets.len() - 2], *targets.last().unwrap()),
@@ -31,11 +34,47 @@ pub(super) fn estimate_solve_row(
         targets
     );
 
+    let without_corner = &targets[..targets.len() - 2];
+    let line_proc = match estimate_line_without_corner(board.clone(), without_corner, None, cancel)
+    {
+        Some(line_proc) => line_proc,
+        None if is_cancelled(cancel) => return None,
+        None => panic!("the route must be found"),
+    };
+    let (worst_route_size, worst_swap_pos) = (line_proc.worst_route_size, line_proc.worst_swap_pos);
+    let estimate = estimate_corner(board.clone(), finder, targets, line_proc);
+
+    // 最も長い経路で置いた断片を, この行に置いた断片を退かしてもよいことにして置き直してみる
+    if worst_route_size == 0 || without_corner.first() == Some(&worst_swap_pos) {
+        return estimate;
+    }
+    let relaxed =
+        estimate_line_without_corner(board.clone(), without_corner, Some(worst_swap_pos), cancel)
+            .and_then(|line_proc| estimate_corner(board, finder, targets, line_proc));
+    match (estimate, relaxed) {
+        (Some(estimate), Some(relaxed)) if relaxed.moves.len() < estimate.moves.len() => {
+            tracing::trace!(
+                before = estimate.moves.len(),
+                after = relaxed.moves.len(),
+                "rerouted the worst target"
+            );
+            Some(relaxed)
+        }
+        (None, relaxed) => relaxed,
+        (estimate, _) => estimate,
+    }
+}
+
+/// 角以外を揃える経路 `line_proc` に続けて, 行の角の 2 つを揃える経路を加える.
+fn estimate_corner(
+    mut board: Board,
+    finder: &BoardFinder,
+    targets: &[Pos],
+    mut line_proc: RowSolveEstimate,
+) -> Option<RowSolveEstimate> {
     let mut estimate = RowSolveEstimate::default();
 
     let without_corner = &targets[..targets.len() - 2];
-    let mut line_proc = estimate_line_without_corner(board.clone(), without_corner)
-        .expect("the route must be found");
     board.swap_many_to(&line_proc.moves);
     estimate.moves.append(&mut line_proc.moves);
     for &p in without_corner {
@@ -78,38 +117,72 @@ pub(super) fn estimate_solve_row(
     Some(estimate)
 }
 
-fn estimate_line_without_corner(mut board: Board, targets: &[Pos]) -> Option<RowSolveEstimate> {
+/// `targets` を順に置く経路を見積もる.
+///
+/// `relaxed` を置くときだけは, それより前に置いた断片のロックを外して経路に使えるようにし, 退かした断片は後で置き直す.
+/// `cancel` でやめるよう求められたら, 次の断片を運ぶ前に `None` を返す.
+fn estimate_line_without_corner(
+    mut board: Board,
+    targets: &[Pos],
+    relaxed: Option<Pos>,
+    cancel: Option<&CancellationToken>,
+) -> Option<RowSolveEstimate> {
     let mut estimate = RowSolveEstimate::default();
-    for &target in targets {
-        let pos = board.reverse(target);
-        if target == pos {
-            board.lock(pos);
+    for (i, &target) in targets.iter().enumerate() {
+        if is_cancelled(cancel) {
+            return None;
+        }
+        if Some(target) != relaxed {
+            place_target(&mut board, target, &mut estimate)?;
             continue;
         }
-        let route = route_target_to_pos(&board, pos, target)?;
-        let mut route_size = 0;
-        for win in route.windows(2) {
-            let way = win[0];
-            let next = win[1];
-            board.lock(way);
-            let mut route = route_select_to_target(&board, next)?;
-            board.swap_many_to(&route);
-            estimate.moves.append(&mut route);
-            route_size += route.len();
-            board.unlock(way);
-            estimate.moves.push(way);
-            board.swap_to(way);
+        let placed = &targets[..i];
+        for &p in placed {
+            board.unlock(p);
         }
-        if estimate.worst_route_size < route_size {
-            estimate.worst_route_size = route_size;
-            estimate.worst_swap_pos = target;
+        place_target(&mut board, target, &mut estimate)?;
+        for &p in placed {
+            place_target(&mut board, p, &mut estimate)?;
         }
-        board.lock(target);
     }
     estimate.moves.dedup();
     Some(estimate)
 }
 
+fn is_cancelled(cancel: Option<&CancellationToken>) -> bool {
+    cancel.is_some_and(CancellationToken::is_cancelled)
+}
+
+/// `target` の断片をその位置まで運んでロックする.
+#[must_use]
+fn place_target(board: &mut Board, target: Pos, estimate: &mut RowSolveEstimate) -> Option<()> {
+    let pos = board.reverse(target);
+    if target == pos {
+        board.lock(pos);
+        return Some(());
+    }
+    let route = route_target_to_pos(board, pos, target)?;
+    let mut route_size = 0;
+    for win in route.windows(2) {
+        let way = win[0];
+        let next = win[1];
+        board.lock(way);
+        let mut route = route_select_to_target(board, next)?;
+        board.swap_many_to(&route);
+        route_size += route.len();
+        estimate.moves.append(&mut route);
+        board.unlock(way);
+        estimate.moves.push(way);
+        board.swap_to(way);
+    }
+    if estimate.worst_route_size < route_size {
+        estimate.worst_route_size = route_size;
+        estimate.worst_swap_pos = target;
+    }
+    board.lock(target);
+    Some(())
+}
+
 /// ```text
 /// ... 選 a
 /// ... ** b
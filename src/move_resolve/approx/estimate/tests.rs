@@ -1,6 +1,9 @@
-use super::estimate_solve_row;
+use super::{estimate_corner, estimate_line_without_corner, estimate_solve_row};
 use crate::{
-    grid::{board::Board, Grid},
+    grid::{
+        board::{Board, BoardFinder},
+        Grid, VecOnGrid,
+    },
     move_resolve::edges_nodes::Nodes,
 };
 
@@ -30,6 +33,7 @@ fn test_estimate_solve_row() {
             grid.pos(3, 0),
             grid.pos(4, 0),
         ],
+        None,
     )
     .unwrap();
 
@@ -79,3 +83,46 @@ fn test_estimate_solve_row() {
         assert_eq!(pos, board.forward(pos));
     }
 }
+
+#[test]
+fn test_reroute_worst_target() {
+    // 決まった乱数列で並べ替えた 5x5 の盤面
+    let grid = Grid::new(5, 5);
+    let mut cells: Vec<_> = grid.all_pos().collect();
+    let mut x = 9 * 2654435761 + 12345_u64;
+    for i in (1..cells.len()).rev() {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        cells.swap(i, (x >> 33) as usize % (i + 1));
+    }
+    let mut board = Board::new(
+        Some(grid.pos(2, 2)),
+        VecOnGrid::from_vec(grid, cells).unwrap(),
+    );
+    let finder = BoardFinder::new(grid);
+    let targets: Vec<_> = (0..grid.width()).map(|x| grid.pos(x, 0)).collect();
+    let without_corner = &targets[..targets.len() - 2];
+
+    let line = estimate_line_without_corner(board.clone(), without_corner, None, None).unwrap();
+    let worst = line.worst_swap_pos;
+    assert_ne!(0, line.worst_route_size);
+    let plain = estimate_corner(board.clone(), &finder, &targets, line).unwrap();
+    let relaxed = estimate_line_without_corner(board.clone(), without_corner, Some(worst), None)
+        .and_then(|line| estimate_corner(board.clone(), &finder, &targets, line))
+        .unwrap();
+    assert!(relaxed.moves.len() < plain.moves.len());
+
+    // やめるよう求められていれば, 経路を見積もらない
+    let cancel = crate::cancel::CancellationToken::new();
+    cancel.cancel();
+    assert!(estimate_solve_row(board.clone(), &finder, &targets, Some(&cancel)).is_none());
+
+    // 置き直した方が短ければそちらを使う
+    let actual = estimate_solve_row(board.clone(), &finder, &targets, None).unwrap();
+    assert_eq!(relaxed.moves, actual.moves);
+    board.swap_many_to(&actual.moves);
+    for &pos in &targets {
+        assert_eq!(pos, board.forward(pos));
+    }
+}
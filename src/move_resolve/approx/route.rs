@@ -351,7 +351,7 @@ fn route_target_to_goal(board: &Board, target: Pos, range: RangePos) -> Option<V
         }
 
         fn is_goal(&self) -> bool {
-            self.range.is_in(self.as_pos())
+            self.range.contains(self.as_pos())
         }
 
         type AS = Vec<Pos>;
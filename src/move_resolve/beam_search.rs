@@ -1,7 +1,15 @@
-use std::{cmp::Ordering, collections::BinaryHeap, hash::Hash, ops::Add, sync::Mutex};
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::Entry, BinaryHeap},
+    hash::Hash,
+    ops::Add,
+    time::{Duration, Instant},
+};
 
 use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
-use rayon::iter::{ParallelBridge, ParallelIterator};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::cancel::CancellationToken;
 
 /// ビームサーチする状態が実装するべき trait.
 pub trait BeamSearchState: Clone + std::fmt::Debug + Hash + Eq + Send + Sync {
@@ -16,14 +24,34 @@ pub trait BeamSearchState: Clone + std::fmt::Debug + Hash + Eq + Send + Sync {
     type C: Copy + Ord + std::fmt::Debug + Send + Sync;
     fn cost_on(&self, action: Self::A) -> Self::C;
 
-    fn max_cost(&self) -> Self::C;
+    /// 目標までに残っていそうなコスト. ビームを絞るとき, 同じキーの状態どうしをこれと掛かったコストの和で比べる.
+    fn estimate(&self) -> Self::C;
 
     fn enrichment_key(&self) -> usize;
 }
 
+/// `SearchLimits` は `beam_search` の打ち切り条件を表す.
+#[derive(Debug, Clone)]
+pub struct SearchLimits<C> {
+    /// このコスト以上の状態からは先に進まない.
+    pub max_cost: C,
+    /// 1 つの答えを探すときに, ビームを広げる回数の上限.
+    pub max_iterations: Option<usize>,
+    /// 最初に答えを探し始めてからこの時間が経つと, それ以上答えを返さない.
+    pub time_limit: Option<Duration>,
+    /// やめるよう求められたら, ビームを広げる前に探索をやめて, それ以上答えを返さない.
+    pub cancel: Option<CancellationToken>,
+}
+
 pub fn beam_search<S, A, C>(
     initial_state: S,
     beam_width: usize,
+    SearchLimits {
+        max_cost,
+        max_iterations,
+        time_limit,
+        cancel,
+    }: SearchLimits<C>,
 ) -> impl Iterator<Item = (Vec<A>, C)>
 where
     S: BeamSearchState<C = C, A = A>,
@@ -31,16 +59,22 @@ where
     C: Ord + Add<Output = C> + Default + Copy + std::fmt::Debug + Send + Sync,
     <<S as BeamSearchState>::AS as IntoIterator>::IntoIter: Send,
 {
-    let max_cost = initial_state.max_cost();
-
+    let mut deadline = None;
     let mut heap = BinaryHeap::with_capacity(beam_width);
     let mut visited_goals = HashSet::default();
+    let mut initial_goal_returned = false;
 
     std::iter::from_fn(move || {
+        // 初期状態がすでに目標なら空の答えを一度だけ返す. 何度も返すと終わらなくなる.
         if initial_state.is_goal() {
+            if std::mem::replace(&mut initial_goal_returned, true) {
+                return None;
+            }
             return Some((vec![], C::default()));
         }
 
+        let deadline =
+            *deadline.get_or_insert_with(|| time_limit.map(|limit| Instant::now() + limit));
         let mut visited = HashSet::default();
 
         visited.insert(initial_state.clone());
@@ -50,17 +84,27 @@ where
             state: initial_state.clone(),
             answer: vec![],
             cost: C::default(),
+            rank: C::default(),
         });
 
+        let mut iterations = 0;
         'search: loop {
-            let mut nexts = HashMap::default();
-            nexts.reserve(beam_width);
-            let nexts = Mutex::new(nexts);
-            nexts.lock().unwrap().reserve(beam_width);
-            heap.iter()
-                .par_bridge()
-                .for_each(search_nexts(beam_width, max_cost, &visited, &nexts));
-            let nexts = nexts.into_inner().unwrap();
+            if matches!(max_iterations, Some(max) if max <= iterations)
+                || matches!(deadline, Some(deadline) if deadline <= Instant::now())
+                || matches!(&cancel, Some(cancel) if cancel.is_cancelled())
+            {
+                heap.clear();
+                break None;
+            }
+            iterations += 1;
+            // スレッドごとに次の状態を集めてから 1 つにまとめ, ロックを取り合わないようにする
+            let nexts = heap
+                .par_iter()
+                .fold(NextsMap::default, |mut nexts, node| {
+                    search_nexts(beam_width, max_cost, &visited, &mut nexts, node);
+                    nexts
+                })
+                .reduce(NextsMap::default, merge_nexts);
             if nexts.is_empty() {
                 break None;
             }
@@ -71,57 +115,76 @@ where
                 }
                 visited.insert(next.state.clone());
             }
+            // どの区分も捨てないよう, 区分ごとに見込みのよいものから同じ数ずつ残す
             let kinds_of_key = nexts.len();
-            let take_len = beam_width / kinds_of_key;
+            let take_len = (beam_width / kinds_of_key).max(1);
             heap.clear();
-            nexts.into_values().take(take_len).for_each(|mut next| {
-                heap.append(&mut next);
-            });
+            for mut next in nexts.into_values() {
+                heap.extend(std::iter::from_fn(|| next.pop()).take(take_len));
+            }
         }
     })
 }
 
 type NextsMap<S, A, C> = HashMap<usize, BinaryHeap<Node<S, A, C>>>;
 
-fn search_nexts<'a, S, A, C>(
+/// `node` から 1 手進めた状態のうち, まだ訪れていないものを `nexts` に加える.
+fn search_nexts<S, A, C>(
     beam_width: usize,
     max_cost: C,
-    visited: &'a HashSet<S>,
-    nexts: &'a Mutex<NextsMap<S, A, C>>,
-) -> impl Fn(&Node<S, A, C>) + 'a
-where
+    visited: &HashSet<S>,
+    nexts: &mut NextsMap<S, A, C>,
+    Node {
+        state,
+        answer,
+        cost,
+        ..
+    }: &Node<S, A, C>,
+) where
     S: BeamSearchState<C = C, A = A>,
     A: Copy + std::fmt::Debug + Hash + Eq,
     C: Ord + Add<Output = C> + Default + Copy + std::fmt::Debug,
 {
-    move |Node {
-              state,
-              answer,
-              cost,
-          }| {
-        if max_cost <= *cost {
-            return;
+    if max_cost <= *cost {
+        return;
+    }
+
+    for action in state.next_actions() {
+        let next_cost = *cost + state.cost_on(action);
+        let next_state = state.apply(action);
+        if !visited.contains(&next_state) {
+            let mut next_answer = answer.clone();
+            next_answer.push(action);
+            nexts
+                .entry(next_state.enrichment_key())
+                .or_insert_with(|| BinaryHeap::with_capacity(beam_width))
+                .push(Node {
+                    rank: next_cost + next_state.estimate(),
+                    state: next_state,
+                    answer: next_answer,
+                    cost: next_cost,
+                });
         }
+    }
+}
 
-        for action in state.next_actions() {
-            let next_cost = *cost + state.cost_on(action);
-            let next_state = state.apply(action);
-            if !visited.contains(&next_state) {
-                let mut next_answer = answer.clone();
-                next_answer.push(action);
-                nexts
-                    .lock()
-                    .unwrap()
-                    .entry(next_state.enrichment_key())
-                    .or_insert_with(|| BinaryHeap::with_capacity(beam_width))
-                    .push(Node {
-                        state: next_state,
-                        answer: next_answer,
-                        cost: next_cost,
-                    });
+/// 2 つのスレッドで集めた次の状態を, 同じキーどうしでまとめる.
+fn merge_nexts<S, A, C: Ord>(
+    mut a: NextsMap<S, A, C>,
+    mut b: NextsMap<S, A, C>,
+) -> NextsMap<S, A, C> {
+    if a.len() < b.len() {
+        std::mem::swap(&mut a, &mut b);
+    }
+    for (key, mut heap) in b {
+        match a.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut().append(&mut heap),
+            Entry::Vacant(entry) => {
+                entry.insert(heap);
             }
         }
     }
+    a
 }
 
 #[derive(Debug, Clone)]
@@ -129,6 +192,8 @@ struct Node<S, A, C> {
     state: S,
     answer: Vec<A>,
     cost: C,
+    /// `cost` に残りの見込みを足したもの. 小さいほど先に残す.
+    rank: C,
 }
 
 impl<S: Hash, A, C> Hash for Node<S, A, C> {
@@ -139,7 +204,7 @@ impl<S: Hash, A, C> Hash for Node<S, A, C> {
 
 impl<S, A, C: PartialEq> PartialEq for Node<S, A, C> {
     fn eq(&self, other: &Self) -> bool {
-        self.cost == other.cost
+        self.rank == other.rank
     }
 }
 
@@ -147,12 +212,12 @@ impl<S, A, C: PartialEq + Eq> Eq for Node<S, A, C> {}
 
 impl<S, A, C: PartialOrd> PartialOrd for Node<S, A, C> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        other.cost.partial_cmp(&self.cost)
+        other.rank.partial_cmp(&self.rank)
     }
 }
 
 impl<S, A, C: PartialOrd + PartialEq + Eq + Ord> Ord for Node<S, A, C> {
     fn cmp(&self, other: &Self) -> Ordering {
-        other.cost.cmp(&self.cost)
+        other.rank.cmp(&self.rank)
     }
 }
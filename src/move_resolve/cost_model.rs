@@ -0,0 +1,111 @@
+use super::ResolveParam;
+
+/// `CostWeighting` は探索で選択と交換のコストをどう重み付けるかを表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CostWeighting {
+    /// 問題のコストをそのまま使う.
+    #[default]
+    AsIs,
+    /// 選択のコストを 2 倍に見積もり, 選択の少ない手順を優先する. 選択のコストが極端に大きい問題向け.
+    FewerSelects,
+}
+
+impl std::str::FromStr for CostWeighting {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "as-is" => Ok(CostWeighting::AsIs),
+            "fewer-selects" => Ok(CostWeighting::FewerSelects),
+            _ => anyhow::bail!("expected as-is or fewer-selects, but found {}", s),
+        }
+    }
+}
+
+/// `CostModel` は探索のヒューリスティックが参照する, 選択と交換のコストの見積もりを表す.
+///
+/// 問題ごとのコストの比率に合わせた重み付けはここにまとめ, 探索の側では重みを直接扱わない.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CostModel {
+    swap_cost: u64,
+    select_cost: u64,
+}
+
+impl CostModel {
+    pub fn new(param: ResolveParam, weighting: CostWeighting) -> Self {
+        let select_weight = match weighting {
+            CostWeighting::AsIs => 1,
+            CostWeighting::FewerSelects => 2,
+        };
+        Self {
+            swap_cost: param.swap_cost as u64,
+            select_cost: param.select_cost as u64 * select_weight,
+        }
+    }
+
+    pub fn swap_cost(&self) -> u64 {
+        self.swap_cost
+    }
+
+    pub fn select_cost(&self) -> u64 {
+        self.select_cost
+    }
+
+    /// 初めの距離が `initial_dist` の盤面で, 距離を減らすために払ってもよいコストの上限を返す.
+    pub fn reducer_budget(&self, initial_dist: u32) -> u64 {
+        let cost_limit = self.select_cost + self.swap_cost * 3;
+        cost_limit.min(initial_dist as u64 / 10)
+    }
+
+    /// 長さ `cycle_len` の巡回のうち, 1 回だけ選択した状態として試すマスの数を返す.
+    ///
+    /// 選択が交換に比べて安いほど, 何度も選択する手順のほうが安くなりやすいので減らす.
+    pub fn chain_quota(&self, cycle_len: usize) -> usize {
+        let total_cost = (self.select_cost + self.swap_cost).max(1) as usize;
+        (cycle_len * self.select_cost as usize)
+            .div_ceil(total_cost)
+            .max(1)
+    }
+
+    /// 選択をあと `select_limit` 回できるときに, 最後の段階で最初に受け入れるコストの上限を返す.
+    pub fn completion_budget(&self, select_limit: u8) -> u64 {
+        self.swap_cost * 16 + select_limit as u64 * 4
+    }
+}
+
+#[test]
+fn test_cost_model() {
+    let param = ResolveParam {
+        select_limit: 4,
+        swap_cost: 1,
+        select_cost: 8,
+    };
+    let as_is = CostModel::new(param, CostWeighting::AsIs);
+    assert_eq!(8, as_is.select_cost());
+    assert_eq!(11, as_is.reducer_budget(1000));
+    assert_eq!(5, as_is.reducer_budget(50));
+    assert_eq!(5, as_is.chain_quota(5));
+    assert_eq!(32, as_is.completion_budget(4));
+
+    let fewer_selects = CostModel::new(param, CostWeighting::FewerSelects);
+    assert_eq!(16, fewer_selects.select_cost());
+    assert_eq!(1, fewer_selects.swap_cost());
+    assert_eq!(19, fewer_selects.reducer_budget(1000));
+
+    assert_eq!(CostWeighting::AsIs, "as-is".parse().unwrap());
+    assert_eq!(
+        CostWeighting::FewerSelects,
+        "fewer-selects".parse().unwrap()
+    );
+    assert!("fewer".parse::<CostWeighting>().is_err());
+
+    let cheap_select = CostModel::new(
+        ResolveParam {
+            select_cost: 1,
+            ..param
+        },
+        CostWeighting::AsIs,
+    );
+    assert_eq!(3, cheap_select.chain_quota(5));
+    assert_eq!(1, cheap_select.chain_quota(1));
+}
@@ -0,0 +1,96 @@
+use crate::grid::{Grid, Pos, VecOnGrid};
+
+/// `Cycles` は盤面の置換を巡回置換に分解したものを表す.
+///
+/// 各座標がどの巡回に属するかと, その巡回の散らばり具合 (各マスと正しい位置とのループ込みマンハッタン距離の和) を保持する.
+#[derive(Debug, Clone)]
+pub struct Cycles {
+    cycles: Vec<Vec<Pos>>,
+    belongs: VecOnGrid<Option<usize>>,
+    scatters: Vec<u32>,
+}
+
+impl Cycles {
+    /// `nodes[pos]` が `pos` にある断片の元の座標であるような盤面から巡回を求める.
+    pub fn new(nodes: &VecOnGrid<Pos>) -> Self {
+        let grid = nodes.grid;
+        let mut belongs = VecOnGrid::with_init(grid, None);
+        let mut cycles = vec![];
+        let mut scatters = vec![];
+        for start in grid.all_pos() {
+            if nodes[start] == start || belongs[start].is_some() {
+                continue;
+            }
+            let index = cycles.len();
+            let mut cycle = vec![];
+            let mut scatter = 0;
+            let mut current = start;
+            while belongs[current].is_none() {
+                belongs[current] = Some(index);
+                cycle.push(current);
+                scatter += grid.looping_manhattan_dist(current, nodes[current]);
+                current = nodes[current];
+            }
+            cycles.push(cycle);
+            scatters.push(scatter);
+        }
+        Self {
+            cycles,
+            belongs,
+            scatters,
+        }
+    }
+
+    pub fn grid(&self) -> Grid {
+        self.belongs.grid
+    }
+
+    /// 長さ 2 以上の巡回の数を返す.
+    pub fn len(&self) -> usize {
+        self.cycles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cycles.is_empty()
+    }
+
+    /// 巡回を構成する座標の列を走査する.
+    pub fn iter(&self) -> impl Iterator<Item = &[Pos]> {
+        self.cycles.iter().map(|cycle| cycle.as_slice())
+    }
+
+    /// `pos` が属する巡回の散らばり具合を返す. 正しい位置にあるマスでは 0 になる.
+    pub fn scatter_amount(&self, pos: Pos) -> u32 {
+        self.belongs[pos].map_or(0, |index| self.scatters[index])
+    }
+
+    /// 盤面全体の散らばり具合を返す.
+    pub fn total_scatter_amount(&self) -> u32 {
+        self.scatters.iter().sum()
+    }
+}
+
+#[test]
+fn test_cycles() {
+    use crate::move_resolve::edges_nodes::Nodes;
+
+    // 10 00 20
+    // 01 21 11
+    let grid = Grid::new(3, 2);
+    let Nodes { nodes, .. } = Nodes::new(
+        grid,
+        &[
+            (grid.pos(0, 0), grid.pos(1, 0)),
+            (grid.pos(1, 0), grid.pos(0, 0)),
+            (grid.pos(1, 1), grid.pos(2, 1)),
+            (grid.pos(2, 1), grid.pos(1, 1)),
+        ],
+    );
+    let cycles = Cycles::new(&nodes);
+
+    assert_eq!(2, cycles.len());
+    assert_eq!(0, cycles.scatter_amount(grid.pos(2, 0)));
+    assert_eq!(2, cycles.scatter_amount(grid.pos(0, 0)));
+    assert_eq!(2, cycles.scatter_amount(grid.pos(2, 1)));
+    assert_eq!(4, cycles.total_scatter_amount());
+}
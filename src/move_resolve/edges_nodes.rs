@@ -1,4 +1,8 @@
-use crate::grid::{Grid, Pos, VecOnGrid};
+use crate::{
+    basis::Operation,
+    error::{Error, Result},
+    grid::{board::BoardFinder, Grid, Pos, VecOnGrid},
+};
 
 pub struct Nodes {
     pub nodes: VecOnGrid<Pos>,
@@ -6,6 +10,33 @@ pub struct Nodes {
 }
 
 impl Nodes {
+    /// `movements` が盤面の中の断片の置換を表しているか確かめる. そうでなければ `new` は壊れた `Nodes` を作ってしまう.
+    pub fn validate(grid: Grid, movements: &[(Pos, Pos)]) -> Result<()> {
+        let mut moved_from = VecOnGrid::with_init(grid, false);
+        let mut moved_to = VecOnGrid::with_init(grid, false);
+        for &(from, to) in movements {
+            for pos in [from, to] {
+                if !grid.is_pos_valid(pos) {
+                    return Err(Error::OutOfGrid(pos));
+                }
+            }
+            if std::mem::replace(&mut moved_from[from], true) {
+                return Err(Error::DuplicateSource(from));
+            }
+            if std::mem::replace(&mut moved_to[to], true) {
+                return Err(Error::DuplicateTarget(to));
+            }
+        }
+        // 重複が無いので, 移動元と移動先が一致しなければ移動先にだけ現れるマスがある
+        match grid
+            .all_pos()
+            .find(|&pos| moved_to[pos] && !moved_from[pos])
+        {
+            Some(pos) => Err(Error::NotPermutation(pos)),
+            None => Ok(()),
+        }
+    }
+
     /// 頂点の移動元と移動先からグラフの重み付き辺と頂点に対する移動先を格納したものを作る.
     pub fn new(grid: Grid, movements: &[(Pos, Pos)]) -> Self {
         let w = grid.width();
@@ -28,4 +59,96 @@ impl Nodes {
             reversed_nodes,
         }
     }
+
+    /// 操作列 `ops` のとおりに断片を入れ替える. 移動先と移動元の両方を更新する.
+    pub fn apply(&mut self, ops: &[Operation]) {
+        let finder = BoardFinder::new(self.nodes.grid);
+        for Operation { select, movements } in ops {
+            let mut current = *select;
+            for &movement in movements {
+                let to_swap = finder.move_pos_to(current, movement);
+                self.nodes.swap(current, to_swap);
+                self.reversed_nodes[self.nodes[current]] = current;
+                self.reversed_nodes[self.nodes[to_swap]] = to_swap;
+                current = to_swap;
+            }
+        }
+    }
+
+    /// すべての断片が正しい位置にあるかどうか.
+    pub fn is_identity(&self) -> bool {
+        self.nodes.iter_with_pos().all(|(pos, &node)| pos == node)
+    }
+
+    /// `new` に渡すと同じ `Nodes` になる, 動いている断片の移動元と移動先の組を返す.
+    pub fn to_movements(&self) -> Vec<(Pos, Pos)> {
+        self.nodes
+            .iter_with_pos()
+            .filter(|&(to, &from)| to != from)
+            .map(|(to, &from)| (from, to))
+            .collect()
+    }
+}
+
+#[test]
+fn test_validate() {
+    let grid = Grid::new(3, 2);
+    let (a, b, c) = (grid.pos(0, 0), grid.pos(1, 0), grid.pos(2, 1));
+    assert!(Nodes::validate(grid, &[]).is_ok());
+    assert!(Nodes::validate(grid, &[(a, b), (b, c), (c, a)]).is_ok());
+    assert!(matches!(
+        Nodes::validate(grid, &[(a, b), (b, a), (a, c)]),
+        Err(Error::DuplicateSource(pos)) if pos == a
+    ));
+    assert!(matches!(
+        Nodes::validate(grid, &[(a, b), (c, b)]),
+        Err(Error::DuplicateTarget(pos)) if pos == b
+    ));
+    assert!(matches!(
+        Nodes::validate(grid, &[(a, b), (b, c)]),
+        Err(Error::NotPermutation(pos)) if pos == c
+    ));
+    let outside = Grid::new(4, 2).pos(3, 0);
+    assert!(matches!(
+        Nodes::validate(grid, &[(a, outside), (outside, a)]),
+        Err(Error::OutOfGrid(pos)) if pos == outside
+    ));
+}
+
+#[test]
+fn test_apply() {
+    use crate::basis::Movement;
+
+    // 10 00 20
+    let grid = Grid::new(3, 1);
+    let (a, b, c) = (grid.pos(0, 0), grid.pos(1, 0), grid.pos(2, 0));
+    let movements = [(a, b), (b, a)];
+    let mut nodes = Nodes::new(grid, &movements);
+    assert!(!nodes.is_identity());
+    assert_eq!(movements.to_vec(), {
+        let mut to_movements = nodes.to_movements();
+        to_movements.sort();
+        to_movements
+    });
+
+    nodes.apply(&[Operation {
+        select: b,
+        movements: vec![Movement::Right],
+    }]);
+    // 10 20 00
+    assert_eq!(vec![(b, a), (c, b), (a, c)], {
+        let mut to_movements = nodes.to_movements();
+        to_movements.sort_by_key(|&(_, to)| to);
+        to_movements
+    });
+    for pos in grid.all_pos() {
+        assert_eq!(pos, nodes.reversed_nodes[nodes.nodes[pos]]);
+    }
+
+    nodes.apply(&[Operation {
+        select: c,
+        movements: vec![Movement::Left, Movement::Left],
+    }]);
+    assert!(nodes.is_identity());
+    assert!(nodes.to_movements().is_empty());
 }
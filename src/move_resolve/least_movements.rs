@@ -1,8 +1,17 @@
-use std::ops::{self, Deref};
+use std::{
+    collections::VecDeque,
+    ops::{self, Deref},
+};
 
 use super::dijkstra::DijkstraCost;
-use crate::grid::{Pos, VecOnGrid};
+use crate::{
+    basis::Movement,
+    grid::{Grid, Pos, VecOnGrid},
+};
 
+/// 選択中の断片を隣に置いた状態から, 別の断片を `(dx, dy)` だけ動かすのに必要な交換回数を式で見積もる.
+///
+/// ループしない広い盤面では正確だが, 回り込める小さい盤面では多めに見積もることがある.
 fn least_movements((dx, dy): (i32, i32)) -> u32 {
     if dx == 0 && dy == 0 {
         return 0;
@@ -18,6 +27,85 @@ fn least_movements((dx, dy): (i32, i32)) -> u32 {
     ret
 }
 
+/// 断片を `from` から `to` まで動かすのに必要な交換回数を見積もる方法.
+pub trait MovementsEstimator {
+    fn estimate(&self, grid: Grid, from: Pos, to: Pos) -> u32;
+}
+
+/// `Formula` は `least_movements` の式で見積もる.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Formula;
+
+impl MovementsEstimator for Formula {
+    fn estimate(&self, grid: Grid, from: Pos, to: Pos) -> u32 {
+        least_movements(grid.looping_min_vec(from, to))
+    }
+}
+
+/// `MovementsTable` は, ある盤面で断片を動かすのに必要な交換回数を, ループも含めて幅優先探索で求めた表を表す.
+///
+/// 盤面の大きさの 2 乗の状態を探索するので, 式がずれやすい小さい盤面で使う.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovementsTable {
+    grid: Grid,
+    /// 移動量 `(dx, dy)` を盤面の大きさで割った余りごとの交換回数
+    table: Vec<u32>,
+}
+
+impl MovementsTable {
+    pub fn new(grid: Grid) -> Self {
+        let (width, height) = (grid.width() as usize, grid.height() as usize);
+        let cells = width * height;
+        let index = |pos: Pos| pos.x() as usize * height + pos.y() as usize;
+
+        // 状態は (動かす断片の位置, 選択中の断片の位置). 動かす断片は原点から, 選択中の断片はその隣から始める
+        let origin = grid.pos(0, 0);
+        let mut dists = vec![u32::MAX; cells * cells];
+        let mut queue = VecDeque::new();
+        let around_of = |pos: Pos| {
+            [
+                Movement::Up,
+                Movement::Right,
+                Movement::Down,
+                Movement::Left,
+            ]
+            .into_iter()
+            .map(move |mov| grid.looping_move(pos, mov))
+        };
+        for selected in around_of(origin) {
+            dists[index(origin) * cells + index(selected)] = 0;
+            queue.push_back((origin, selected));
+        }
+        let mut table = vec![u32::MAX; cells];
+        while let Some((target, selected)) = queue.pop_front() {
+            let dist = dists[index(target) * cells + index(selected)];
+            table[index(target)] = table[index(target)].min(dist);
+            for next_selected in around_of(selected) {
+                let next_target = if next_selected == target {
+                    selected
+                } else {
+                    target
+                };
+                let next = &mut dists[index(next_target) * cells + index(next_selected)];
+                if *next == u32::MAX {
+                    *next = dist + 1;
+                    queue.push_back((next_target, next_selected));
+                }
+            }
+        }
+        Self { grid, table }
+    }
+}
+
+impl MovementsEstimator for MovementsTable {
+    fn estimate(&self, grid: Grid, from: Pos, to: Pos) -> u32 {
+        debug_assert_eq!(self.grid, grid, "the table was made for another grid");
+        let dx = (to.x() + grid.width() - from.x()) % grid.width();
+        let dy = (to.y() + grid.height() - from.y()) % grid.height();
+        self.table[dx as usize * grid.height() as usize + dy as usize]
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct LeastMovements(u32);
 
@@ -26,24 +114,46 @@ impl LeastMovements {
         Self::default()
     }
 
+    /// `from` の断片を `to` と交換したときの見積もりを `Formula` で求める. 見積もりが負になれば panic する.
     pub fn swap_on(
         self,
         field: impl Deref<Target = VecOnGrid<Pos>> + std::fmt::Debug,
         from: Pos,
         to: Pos,
     ) -> Self {
-        let before_min_vec = field.grid.looping_min_vec(from, field[from]);
-        let before = least_movements(before_min_vec);
-        let after_min_vec = field.grid.looping_min_vec(to, field[from]);
-        let after = least_movements(after_min_vec);
-        let res = 5 + self.0 as i32 + after as i32 - before as i32;
+        let res = self.swap_delta(&field, from, to, &Formula);
         if res < 0 {
-            eprintln!("{:?} -> {:?}", before_min_vec, after_min_vec);
-            eprintln!("5 + {} + {} - {} = {}", self.0, after, before, res);
+            let before_min_vec = field.grid.looping_min_vec(from, field[from]);
+            let after_min_vec = field.grid.looping_min_vec(to, field[from]);
+            tracing::error!("{:?} -> {:?}", before_min_vec, after_min_vec);
+            tracing::error!("{} + 5 + after - before = {}", self.0, res);
             panic!("invalid swap on: {:?} -> {:?}\n{:#?}", from, to, field);
         }
         Self(res as u32)
     }
+
+    /// `swap_on` と同じ見積もりを `estimator` で求める. 見積もりが負になるときは panic せずに 0 にする.
+    pub fn saturating_swap_on(
+        self,
+        field: impl Deref<Target = VecOnGrid<Pos>> + std::fmt::Debug,
+        from: Pos,
+        to: Pos,
+        estimator: &impl MovementsEstimator,
+    ) -> Self {
+        Self(self.swap_delta(&field, from, to, estimator).max(0) as u32)
+    }
+
+    fn swap_delta(
+        self,
+        field: &VecOnGrid<Pos>,
+        from: Pos,
+        to: Pos,
+        estimator: &impl MovementsEstimator,
+    ) -> i32 {
+        let before = estimator.estimate(field.grid, from, field[from]);
+        let after = estimator.estimate(field.grid, to, field[from]);
+        5 + self.0 as i32 + after as i32 - before as i32
+    }
 }
 
 impl ops::Add for LeastMovements {
@@ -67,3 +177,64 @@ impl DijkstraCost for LeastMovements {
         Self(self.0 + other.0)
     }
 }
+
+#[test]
+fn test_movements_table() {
+    // 回り込んでも得をしない広い盤面の近くでは, 式と表が一致する
+    let grid = Grid::new(16, 16);
+    let table = MovementsTable::new(grid);
+    let origin = grid.pos(0, 0);
+    for x in (0..=4).chain(12..16) {
+        for y in (0..=4).chain(12..16) {
+            let to = grid.pos(x, y);
+            assert_eq!(
+                Formula.estimate(grid, origin, to),
+                table.estimate(grid, origin, to),
+                "{:?}",
+                to
+            );
+        }
+    }
+    assert_eq!(0, table.estimate(grid, origin, origin));
+    assert_eq!(1, table.estimate(grid, origin, grid.pos(1, 0)));
+    assert_eq!(4, table.estimate(grid, origin, grid.pos(1, 1)));
+    assert_eq!(6, table.estimate(grid, origin, grid.pos(2, 0)));
+    assert_eq!(
+        table.estimate(grid, grid.pos(3, 5), grid.pos(5, 6)),
+        table.estimate(grid, origin, grid.pos(2, 1))
+    );
+
+    // 高さ 4 の盤面では, 2 つ下へは上下どちらからも回り込めるので式より少なく済む
+    let grid = Grid::new(2, 4);
+    let table = MovementsTable::new(grid);
+    let (origin, to) = (grid.pos(0, 0), grid.pos(0, 2));
+    assert_eq!(6, Formula.estimate(grid, origin, to));
+    assert_eq!(4, table.estimate(grid, origin, to));
+}
+
+#[test]
+fn test_saturating_swap_on() {
+    let grid = Grid::new(2, 4);
+    let mut field = VecOnGrid::with_init(grid, grid.pos(0, 0));
+    for pos in grid.all_pos() {
+        field[pos] = pos;
+    }
+    // (0, 2) にある断片の正しい位置は (0, 0)
+    field[grid.pos(0, 2)] = grid.pos(0, 0);
+    field[grid.pos(0, 0)] = grid.pos(0, 2);
+
+    let (from, to) = (grid.pos(0, 2), grid.pos(0, 1));
+    assert_eq!(
+        LeastMovements(0),
+        LeastMovements::new().swap_on(&field, from, to)
+    );
+    assert_eq!(
+        LeastMovements(2),
+        LeastMovements::new().saturating_swap_on(&field, from, to, &MovementsTable::new(grid))
+    );
+    // 式による見積もりが負になっても panic しない
+    assert_eq!(
+        LeastMovements(0),
+        LeastMovements::new().saturating_swap_on(&field, from, grid.pos(0, 0), &Formula)
+    );
+}
@@ -0,0 +1,218 @@
+use crate::grid::{board::Board, Grid, Overflow, Pos, VecOnGrid};
+
+use super::state::{DistanceTable, GridAction};
+
+/// `Reduction` は盤面のうち揃っていないマスを囲む長方形だけを切り出した, 小さな盤面との対応を表す.
+///
+/// `phase2` の後には中央付近の小さな長方形しか揃っていないので, `phase3` ではそこだけを探索すれば状態の大きさもハッシュの計算も小さくて済む.
+/// 切り出した長方形の右と下には 1 列ずつロックしたマスを足し, 小さな盤面で端から回り込む移動を元の盤面で隣り合う移動にだけ限る.
+/// 長方形が盤面の幅 (高さ) いっぱいに広がっていれば, その向きには元の盤面と同じく回り込める.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reduction {
+    full: Grid,
+    reduced: Grid,
+    /// 切り出した長方形の左上の, 元の盤面での位置
+    offset: (u8, u8),
+    /// 切り出した長方形の幅と高さ. ロックしたマスは含まない
+    block: (u8, u8),
+}
+
+impl Reduction {
+    /// `board` の揃っていないマスを囲む最小の長方形を, 盤面の端での回り込みも考えて求める.
+    ///
+    /// `keep_selected` なら選択しているマスも長方形に含める. すべて揃っているときと, 切り出しても元の盤面より小さくならないときは `None` を返す.
+    pub fn new(board: &Board, keep_selected: bool) -> Option<Self> {
+        let full = board.grid();
+        let field = board.field();
+        let mut cells: Vec<_> = full.all_pos().filter(|&pos| pos != field[pos]).collect();
+        if cells.is_empty() {
+            return None;
+        }
+        if keep_selected {
+            cells.extend(board.selected());
+        }
+        let (left, width) = covering_arc(cells.iter().map(|pos| pos.x()), full.width());
+        let (top, height) = covering_arc(cells.iter().map(|pos| pos.y()), full.height());
+        let with_margin = |span: u8, len: u8| if span < len { span + 1 } else { len };
+        let reduced = Grid::new(
+            with_margin(width, full.width()),
+            with_margin(height, full.height()),
+        );
+        if reduced == full {
+            return None;
+        }
+        Some(Self {
+            full,
+            reduced,
+            offset: (left, top),
+            block: (width, height),
+        })
+    }
+
+    /// 切り出した盤面の大きさ.
+    pub fn grid(&self) -> Grid {
+        self.reduced
+    }
+
+    /// 切り出した盤面での位置 `pos` を元の盤面での位置に直す.
+    pub fn to_full(self, pos: Pos) -> Pos {
+        let (left, top) = self.offset;
+        self.full
+            .offset(pos, left as i32, top as i32, Overflow::Wrap)
+            .unwrap()
+    }
+
+    /// 元の盤面での位置 `pos` を切り出した盤面での位置に直す. 切り出した盤面の外なら `None`.
+    pub fn to_reduced(self, pos: Pos) -> Option<Pos> {
+        let (left, top) = self.offset;
+        let moved = self
+            .full
+            .offset(pos, -(left as i32), -(top as i32), Overflow::Wrap)
+            .unwrap();
+        self.reduced
+            .is_pos_valid(moved)
+            .then(|| self.reduced.pos(moved.x(), moved.y()))
+    }
+
+    /// 切り出した盤面での位置 `pos` が, 足したロックのマスではなく長方形の中にあるかどうか.
+    fn in_block(&self, pos: Pos) -> bool {
+        pos.x() < self.block.0 && pos.y() < self.block.1
+    }
+
+    /// `board` から長方形を切り出した盤面を作る. 選択しているマスが長方形の外なら, 何も選択していない盤面にする.
+    pub fn board(&self, board: &Board) -> Board {
+        let mut field = VecOnGrid::with_init(self.reduced, self.reduced.pos(0, 0));
+        for pos in self.reduced.all_pos() {
+            let cell = board.forward(self.to_full(pos));
+            field[pos] = self
+                .to_reduced(cell)
+                .expect("fragments in the block must stay in the block");
+        }
+        let selected = board
+            .selected()
+            .and_then(|selected| self.to_reduced(selected))
+            .filter(|&selected| self.in_block(selected));
+        let mut reduced = Board::new(selected, field);
+        for pos in self.reduced.all_pos() {
+            if !self.in_block(pos) {
+                reduced.lock(pos);
+            }
+        }
+        reduced
+    }
+
+    /// 直前の操作 `prev` を切り出した盤面でのものに直す. `board` で選択しているマスが長方形の外なら, 続けて交換できないので `None` を返す.
+    pub fn prev_action(&self, board: &Board, prev: Option<GridAction>) -> Option<GridAction> {
+        let selected = self.to_reduced(board.selected()?)?;
+        if !self.in_block(selected) {
+            return None;
+        }
+        prev.map(|action| match action {
+            GridAction::Swap(mov) => GridAction::Swap(mov),
+            GridAction::Select(_) => GridAction::Select(selected),
+        })
+    }
+
+    /// 切り出した盤面での操作を元の盤面での操作に直す.
+    pub fn to_full_action(self, action: GridAction) -> GridAction {
+        match action {
+            GridAction::Swap(mov) => GridAction::Swap(mov),
+            GridAction::Select(pos) => GridAction::Select(self.to_full(pos)),
+        }
+    }
+
+    /// 元の盤面の距離の表 `table` から, 切り出した盤面の位置で引ける表を作る. 距離は元の盤面でのものになる.
+    pub fn table(&self, table: &DistanceTable) -> DistanceTable {
+        table.restricted(self.reduced, |pos| self.to_full(pos))
+    }
+}
+
+/// 長さ `len` の輪の上で, `coords` をすべて含む最短の区間を `(始まり, 長さ)` で返す.
+///
+/// 使われていない位置が最も長く続くところを除いた残りになる. 同じ長さなら始まりが小さい方を選ぶ.
+fn covering_arc(coords: impl Iterator<Item = u8>, len: u8) -> (u8, u8) {
+    let mut used = vec![false; len as usize];
+    for coord in coords {
+        used[coord as usize] = true;
+    }
+    let used: Vec<u8> = (0..len).filter(|&i| used[i as usize]).collect();
+    let (gap, start) = used
+        .iter()
+        .zip(used.iter().cycle().skip(1))
+        .map(|(&current, &next)| ((next + len - current - 1) % len, std::cmp::Reverse(next)))
+        .max()
+        .expect("coords must not be empty");
+    (start.0, len - gap)
+}
+
+#[test]
+fn test_covering_arc() {
+    assert_eq!((2, 1), covering_arc([2].into_iter(), 8));
+    assert_eq!((1, 4), covering_arc([1, 4, 2].into_iter(), 8));
+    // 端をまたぐ区間
+    assert_eq!((6, 4), covering_arc([0, 7, 1, 6].into_iter(), 8));
+    assert_eq!((0, 8), covering_arc(0..8, 8));
+    // 空きが同じ長さなら始まりの小さい方
+    assert_eq!((0, 3), covering_arc([0, 2].into_iter(), 4));
+}
+
+#[test]
+fn test_reduction() {
+    use crate::basis::Movement;
+
+    let grid = Grid::new(8, 6);
+    let solved = VecOnGrid::from_vec(grid, grid.all_pos().collect()).unwrap();
+    assert!(Reduction::new(&Board::new(None, solved.clone()), false).is_none());
+
+    // 左右の端をまたぐ 3x2 の長方形だけが揃っていない
+    let mut board = Board::new(None, solved);
+    board.select(grid.pos(7, 2));
+    board.swap_to(grid.pos(0, 2));
+    board.swap_to(grid.pos(0, 3));
+    board.swap_to(grid.pos(1, 3));
+
+    let reduction = Reduction::new(&board, false).unwrap();
+    assert_eq!(Grid::new(4, 3), reduction.grid());
+    assert_eq!(
+        grid.pos(7, 2),
+        reduction.to_full(reduction.grid().pos(0, 0))
+    );
+    assert_eq!(
+        grid.pos(1, 3),
+        reduction.to_full(reduction.grid().pos(2, 1))
+    );
+    assert_eq!(None, reduction.to_reduced(grid.pos(4, 0)));
+    for pos in reduction.grid().all_pos() {
+        assert_eq!(Some(pos), reduction.to_reduced(reduction.to_full(pos)));
+    }
+
+    let reduced = reduction.board(&board);
+    assert_eq!(
+        reduction.to_reduced(board.selected().unwrap()),
+        reduced.selected()
+    );
+    for pos in reduction.grid().all_pos() {
+        assert_eq!(
+            board.forward(reduction.to_full(pos)),
+            reduction.to_full(reduced.forward(pos))
+        );
+        assert_eq!(!reduction.in_block(pos), reduced.is_locked(pos));
+    }
+    // 足したロックのマスがあるので, 左上の角から上と左へは回り込まない
+    assert_eq!(2, reduced.around_of(reduction.grid().pos(0, 0)).count());
+
+    let swap = GridAction::Swap(Movement::Left);
+    assert_eq!(Some(swap), reduction.prev_action(&board, Some(swap)));
+    assert_eq!(
+        GridAction::Select(grid.pos(7, 3)),
+        reduction.to_full_action(GridAction::Select(reduction.grid().pos(0, 1)))
+    );
+
+    // 選択しているマスが長方形の外なら, 続けて交換できない
+    let mut outside = board.clone();
+    outside.select(grid.pos(4, 4));
+    assert_eq!(None, reduction.prev_action(&outside, Some(swap)));
+    assert_eq!(None, reduction.board(&outside).selected());
+    let kept = Reduction::new(&outside, true).unwrap();
+    assert!(kept.to_reduced(grid.pos(4, 4)).is_some());
+}
@@ -0,0 +1,140 @@
+use std::{marker::PhantomData, ops::Range};
+
+/// `Monoid` は `SegTree` に載せる値の結合と単位元を表す.
+///
+/// `op` は結合的で, `identity` はどちらから結合しても相手を変えない値でなければならない.
+pub trait Monoid {
+    type Item: Copy;
+
+    fn identity() -> Self::Item;
+
+    fn op(left: Self::Item, right: Self::Item) -> Self::Item;
+}
+
+/// 和を取る `Monoid`. 単位元は `Default` の値にする.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sum<T>(PhantomData<T>);
+
+impl<T: Copy + Default + std::ops::Add<Output = T>> Monoid for Sum<T> {
+    type Item = T;
+
+    fn identity() -> T {
+        T::default()
+    }
+
+    fn op(left: T, right: T) -> T {
+        left + right
+    }
+}
+
+/// `SegTree` は列の 1 点の更新と, 区間を `M::op` で結合した値の取得をどちらも O(log n) で行う.
+#[derive(Debug, Clone)]
+pub struct SegTree<M: Monoid> {
+    len: usize,
+    /// `nodes[len..]` が葉で, `nodes[i]` は `nodes[2 * i]` と `nodes[2 * i + 1]` を結合したもの
+    nodes: Vec<M::Item>,
+}
+
+impl<M: Monoid> SegTree<M> {
+    /// 長さ `len` のすべてが単位元の列を作る.
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            nodes: vec![M::identity(); 2 * len],
+        }
+    }
+
+    pub fn from_vec(values: Vec<M::Item>) -> Self {
+        let len = values.len();
+        let mut nodes = vec![M::identity(); len];
+        nodes.extend(values);
+        for i in (1..len).rev() {
+            nodes[i] = M::op(nodes[2 * i], nodes[2 * i + 1]);
+        }
+        Self { len, nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> M::Item {
+        self.nodes[self.len + index]
+    }
+
+    /// `index` 番目の値を `value` にする.
+    pub fn set(&mut self, index: usize, value: M::Item) {
+        assert!(index < self.len, "index {} out of {}", index, self.len);
+        let mut i = self.len + index;
+        self.nodes[i] = value;
+        while 1 < i {
+            i /= 2;
+            self.nodes[i] = M::op(self.nodes[2 * i], self.nodes[2 * i + 1]);
+        }
+    }
+
+    /// `range` の値を前から順に結合したものを返す. 空の区間なら単位元になる.
+    pub fn query(&self, range: Range<usize>) -> M::Item {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "range {:?} out of {}",
+            range,
+            self.len
+        );
+        let (mut left_acc, mut right_acc) = (M::identity(), M::identity());
+        let (mut left, mut right) = (range.start + self.len, range.end + self.len);
+        while left < right {
+            if left % 2 == 1 {
+                left_acc = M::op(left_acc, self.nodes[left]);
+                left += 1;
+            }
+            if right % 2 == 1 {
+                right -= 1;
+                right_acc = M::op(self.nodes[right], right_acc);
+            }
+            left /= 2;
+            right /= 2;
+        }
+        M::op(left_acc, right_acc)
+    }
+}
+
+#[test]
+fn test_monoids() {
+    assert_eq!(0u32, Sum::<u32>::identity());
+    assert_eq!(5u64, Sum::<u64>::op(2, 3));
+    assert_eq!(7u32, Sum::<u32>::op(Sum::<u32>::identity(), 7));
+}
+
+#[test]
+fn test_seg_tree() {
+    let values: Vec<u32> = vec![5, 3, 8, 1, 9, 2, 7];
+    let mut sum = SegTree::<Sum<u32>>::from_vec(values.clone());
+    assert_eq!(7, sum.len());
+
+    let check = |sum: &SegTree<Sum<u32>>, values: &[u32]| {
+        for start in 0..=values.len() {
+            for end in start..=values.len() {
+                let slice = &values[start..end];
+                assert_eq!(slice.iter().sum::<u32>(), sum.query(start..end));
+            }
+        }
+    };
+    check(&sum, &values);
+
+    let mut values = values;
+    for (index, value) in [(3, 10), (0, 0), (6, 4)] {
+        values[index] = value;
+        sum.set(index, value);
+        assert_eq!(value, sum.get(index));
+        check(&sum, &values);
+    }
+
+    let empty = SegTree::<Sum<u32>>::new(4);
+    assert_eq!(0, empty.query(0..4));
+    assert!(SegTree::<Sum<u32>>::new(0).is_empty());
+}
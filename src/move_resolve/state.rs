@@ -1,8 +1,14 @@
-use std::{iter::Sum, ops};
+use std::{
+    hash::{Hash, Hasher},
+    iter::Sum,
+    ops,
+};
+
+use fxhash::FxHasher;
 
 use crate::{
     basis::{Movement, Operation},
-    grid::{Grid, Pos, VecOnGrid},
+    grid::{board::Board, Grid, Pos, VecOnGrid},
 };
 
 pub mod completer;
@@ -21,6 +27,15 @@ impl SqManhattan {
         })
     }
 
+    /// `pre_calc` の結果を, 詰めた座標の組で引ける表にまとめる.
+    pub fn table(grid: Grid) -> DistanceTable {
+        let mut dists = vec![Self(0); 1 << 16];
+        for ((from, to), dist) in Self::pre_calc(grid) {
+            dists[pair_index(from, to)] = dist;
+        }
+        DistanceTable { dists }
+    }
+
     pub fn as_u32(self) -> u32 {
         self.0
     }
@@ -37,6 +52,40 @@ impl SqManhattan {
     }
 }
 
+/// `DistanceTable` は 2 つのマスの間の `SqManhattan` を, ハッシュを使わずに引ける表を表す.
+///
+/// `resolve` ごとに 1 回だけ作り, `Arc` で探索のすべての状態から共有する.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DistanceTable {
+    dists: Vec<SqManhattan>,
+}
+
+impl DistanceTable {
+    /// 盤面 `grid` の位置を `to_full` でこの表の位置に直して引ける, 新しい表を作る.
+    pub fn restricted(&self, grid: Grid, to_full: impl Fn(Pos) -> Pos) -> Self {
+        let mut dists = vec![SqManhattan(0); 1 << 16];
+        for from in grid.all_pos() {
+            for to in grid.all_pos() {
+                dists[pair_index(from, to)] = self.dists[pair_index(to_full(from), to_full(to))];
+            }
+        }
+        Self { dists }
+    }
+}
+
+impl ops::Index<&(Pos, Pos)> for DistanceTable {
+    type Output = SqManhattan;
+
+    fn index(&self, &(from, to): &(Pos, Pos)) -> &Self::Output {
+        &self.dists[pair_index(from, to)]
+    }
+}
+
+/// `Pos` はそれぞれ 8 ビットに収まるので, 組を 16 ビットに詰めて添字にする.
+fn pair_index(from: Pos, to: Pos) -> usize {
+    (from.x() as usize) << 12 | (from.y() as usize) << 8 | (to.x() as usize) << 4 | to.y() as usize
+}
+
 impl ops::Add<SqManhattan> for SqManhattan {
     type Output = Self;
 
@@ -59,20 +108,73 @@ impl Sum for SqManhattan {
     }
 }
 
+/// 盤面の違いで分ける区分の数. 細かく分けすぎると 1 つの区分に入る状態が少なくなりすぎる.
+pub const DIVERSITY_BUCKETS: usize = 8;
+
+/// まだ揃っていない行を上から `rows` 行だけ見て, 盤面を `DIVERSITY_BUCKETS` 個の区分に大まかに分ける.
+///
+/// `rows` が 0 なら, どの盤面も同じ区分にする.
+pub fn diversity_key(board: &Board, rows: u8) -> usize {
+    if rows == 0 {
+        return 0;
+    }
+    let grid = board.grid();
+    let field = board.field();
+    let mut hasher = FxHasher::default();
+    (0..grid.height())
+        .map(|y| (0..grid.width()).map(move |x| grid.pos(x, y)))
+        .filter(|row| row.clone().any(|pos| pos != field[pos]))
+        .take(rows as usize)
+        .flatten()
+        .for_each(|pos| field[pos].hash(&mut hasher));
+    hasher.finish() as usize % DIVERSITY_BUCKETS
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum GridAction {
     Swap(Movement),
     Select(Pos),
 }
 
-/// 操作の履歴 Vec<GridAction> を Vec<Operation> に変換する.
-pub fn actions_to_operations(actions: Vec<GridAction>) -> Vec<Operation> {
-    if actions.is_empty() {
-        return vec![];
+/// 操作の履歴から, 意味の無い選択を除く. 段階をまたいで続けた履歴にも使える.
+///
+/// 選んでいる断片をそのまま選び直す選択と, 何も入れ替えずに次の選択に移る選択を除く.
+/// 最後の選択はそのまま残すので, 続きを探索する前の選択回数を数えるのにも使える.
+pub fn merge_selects(grid: Grid, actions: &[GridAction]) -> Vec<GridAction> {
+    let mut merged: Vec<GridAction> = Vec::with_capacity(actions.len());
+    let mut selected = None;
+    // 最後に断片を動かした操作で, その断片が最後にあった位置
+    let mut moved_to = None;
+    for &action in actions {
+        match action {
+            GridAction::Swap(mov) => {
+                let moved = grid.looping_move(selected.unwrap(), mov);
+                selected = Some(moved);
+                moved_to = Some(moved);
+            }
+            GridAction::Select(select) => {
+                if let Some(GridAction::Select(_)) = merged.last() {
+                    merged.pop();
+                    selected = moved_to;
+                }
+                if Some(select) == selected {
+                    continue;
+                }
+                selected = Some(select);
+            }
+        }
+        merged.push(action);
     }
+    merged
+}
+
+/// 操作の履歴 Vec<GridAction> を Vec<Operation> に変換する.
+///
+/// `merge_selects` で意味の無い選択を除いてから変換し, 何も入れ替えない最後の操作も除く.
+pub fn actions_to_operations(grid: Grid, actions: Vec<GridAction>) -> Vec<Operation> {
     let mut current_operation: Option<Operation> = None;
     let mut operations = vec![];
-    for state in actions {
+    for state in merge_selects(grid, &actions) {
         match state {
             GridAction::Swap(mov) => {
                 current_operation.as_mut().unwrap().movements.push(mov);
@@ -87,8 +189,51 @@ pub fn actions_to_operations(actions: Vec<GridAction>) -> Vec<Operation> {
             }
         }
     }
-    if let Some(op) = current_operation {
-        operations.push(op);
-    }
+    operations.extend(current_operation.filter(|op| !op.movements.is_empty()));
     operations
 }
+
+#[test]
+fn test_merge_selects() {
+    let grid = Grid::new(3, 3);
+    let actions = [
+        GridAction::Select(grid.pos(0, 0)),
+        GridAction::Swap(Movement::Right),
+        GridAction::Swap(Movement::Down),
+        // 何も入れ替えない選択
+        GridAction::Select(grid.pos(2, 2)),
+        // 直前の操作で動かした断片をそのまま選び直している
+        GridAction::Select(grid.pos(1, 1)),
+        GridAction::Swap(Movement::Left),
+        GridAction::Select(grid.pos(2, 0)),
+        GridAction::Swap(Movement::Right),
+        // 右へ回り込んで (0, 0) にある断片を選び直している
+        GridAction::Select(grid.pos(0, 0)),
+        GridAction::Select(grid.pos(1, 2)),
+    ];
+    let merged = vec![
+        GridAction::Select(grid.pos(0, 0)),
+        GridAction::Swap(Movement::Right),
+        GridAction::Swap(Movement::Down),
+        GridAction::Swap(Movement::Left),
+        GridAction::Select(grid.pos(2, 0)),
+        GridAction::Swap(Movement::Right),
+        GridAction::Select(grid.pos(1, 2)),
+    ];
+    assert_eq!(merged, merge_selects(grid, &actions));
+
+    assert_eq!(
+        vec![
+            Operation {
+                select: grid.pos(0, 0),
+                movements: vec![Movement::Right, Movement::Down, Movement::Left],
+            },
+            Operation {
+                select: grid.pos(2, 0),
+                movements: vec![Movement::Right],
+            },
+        ],
+        actions_to_operations(grid, actions.to_vec())
+    );
+    assert!(actions_to_operations(grid, vec![]).is_empty());
+}
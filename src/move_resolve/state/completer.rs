@@ -1,36 +1,34 @@
 use std::{hash::Hash, sync::Arc};
 
-use fxhash::FxHashMap as HashMap;
-
 use crate::{
-    basis::Movement,
-    grid::{
-        board::{Board, BoardFinder},
-        Pos,
+    grid::board::Board,
+    move_resolve::{
+        beam_search::BeamSearchState, cost_model::CostModel, ida_star::IdaSearchState, ResolveParam,
     },
-    move_resolve::{beam_search::BeamSearchState, ida_star::IdaSearchState, ResolveParam},
 };
 
-use super::{GridAction, SqManhattan};
+use super::{diversity_key, DistanceTable, GridAction, SqManhattan, DIVERSITY_BUCKETS};
 
 #[derive(Eq)]
 pub struct Completer {
     board: Board,
     prev_action: Option<GridAction>,
     dist: SqManhattan,
-    pre_calc: Arc<HashMap<(Pos, Pos), SqManhattan>>,
+    pre_calc: Arc<DistanceTable>,
     param: ResolveParam,
-    max_cost: u64,
+    model: CostModel,
+    diversity_rows: u8,
 }
 
 impl Completer {
     pub fn new(
         board: Board,
         param: ResolveParam,
+        model: CostModel,
         prev_action: Option<GridAction>,
-        max_cost: u64,
+        pre_calc: Arc<DistanceTable>,
+        diversity_rows: u8,
     ) -> Self {
-        let pre_calc: HashMap<_, _> = SqManhattan::pre_calc(board.grid()).collect();
         let dist = board
             .field()
             .iter_with_pos()
@@ -40,16 +38,17 @@ impl Completer {
             board,
             prev_action,
             dist,
-            pre_calc: Arc::new(pre_calc),
+            pre_calc,
             param,
-            max_cost,
+            model,
+            diversity_rows,
         }
     }
 
     fn cost_on(&self, action: GridAction) -> u64 {
         match action {
-            GridAction::Swap(_) => self.param.swap_cost as u64,
-            GridAction::Select(_) => self.param.select_cost as u64,
+            GridAction::Swap(_) => self.model.swap_cost(),
+            GridAction::Select(_) => self.model.select_cost(),
         }
     }
 
@@ -60,8 +59,7 @@ impl Completer {
         match action {
             GridAction::Swap(mov) => {
                 let selected = self.board.selected().unwrap();
-                let finder = BoardFinder::new(self.board.grid());
-                let next_swap = finder.move_pos_to(selected, mov);
+                let next_swap = self.board.grid().looping_move(selected, mov);
 
                 cloned.board.swap_to(next_swap);
                 cloned.dist =
@@ -90,8 +88,8 @@ impl Completer {
         let prev = self.prev_action.unwrap();
         let swapping_states = self
             .board
-            .around_of(selected)
-            .map(|to| Movement::between_pos(selected, to))
+            .movements_around(selected)
+            .map(|(movement, _)| movement)
             .filter(|&around| {
                 if let GridAction::Swap(dir) = prev {
                     around != dir.opposite()
@@ -191,11 +189,12 @@ impl BeamSearchState for Completer {
         self.cost_on(action)
     }
 
-    fn max_cost(&self) -> Self::C {
-        self.max_cost
+    fn estimate(&self) -> Self::C {
+        self.dist.as_u32() as u64 * self.model.swap_cost()
     }
 
     fn enrichment_key(&self) -> usize {
-        self.param.select_limit as _
+        self.param.select_limit as usize * DIVERSITY_BUCKETS
+            + diversity_key(&self.board, self.diversity_rows)
     }
 }
@@ -1,17 +1,11 @@
 use std::{hash::Hash, sync::Arc};
 
-use fxhash::FxHashMap as HashMap;
-
 use crate::{
-    basis::Movement,
-    grid::{
-        board::{Board, BoardFinder},
-        Pos,
-    },
-    move_resolve::{beam_search::BeamSearchState, ResolveParam},
+    grid::board::Board,
+    move_resolve::{beam_search::BeamSearchState, cost_model::CostModel, ResolveParam},
 };
 
-use super::{GridAction, SqManhattan};
+use super::{diversity_key, DistanceTable, GridAction, SqManhattan, DIVERSITY_BUCKETS};
 
 #[derive(Debug)]
 pub struct CostReducer {
@@ -19,13 +13,20 @@ pub struct CostReducer {
     prev_action: Option<GridAction>,
     initial_dist: SqManhattan,
     dist: SqManhattan,
-    pre_calc: Arc<HashMap<(Pos, Pos), SqManhattan>>,
+    pre_calc: Arc<DistanceTable>,
     param: ResolveParam,
+    model: CostModel,
+    diversity_rows: u8,
 }
 
 impl CostReducer {
-    pub fn new(board: Board, param: ResolveParam) -> Self {
-        let pre_calc: Arc<HashMap<_, _>> = Arc::new(SqManhattan::pre_calc(board.grid()).collect());
+    pub fn new(
+        board: Board,
+        param: ResolveParam,
+        model: CostModel,
+        pre_calc: Arc<DistanceTable>,
+        diversity_rows: u8,
+    ) -> Self {
         let dist = board
             .field()
             .iter_with_pos()
@@ -38,8 +39,15 @@ impl CostReducer {
             dist,
             pre_calc,
             param,
+            model,
+            diversity_rows,
         }
     }
+
+    /// 距離を減らすために払ってもよいコストの上限.
+    pub fn max_cost(&self) -> u64 {
+        self.model.reducer_budget(self.initial_dist.as_u32())
+    }
 }
 
 impl Clone for CostReducer {
@@ -76,8 +84,7 @@ impl BeamSearchState for CostReducer {
         match action {
             GridAction::Swap(mov) => {
                 let selected = self.board.selected().unwrap();
-                let finder = BoardFinder::new(self.board.grid());
-                let next_swap = finder.move_pos_to(selected, mov);
+                let next_swap = self.board.grid().looping_move(selected, mov);
 
                 cloned.board.swap_to(next_swap);
                 cloned.dist =
@@ -107,8 +114,8 @@ impl BeamSearchState for CostReducer {
         let prev = self.prev_action.unwrap();
         let swapping_states = self
             .board
-            .around_of(selected)
-            .map(|to| Movement::between_pos(selected, to))
+            .movements_around(selected)
+            .map(|(movement, _)| movement)
             .filter(|&around| {
                 if let GridAction::Swap(dir) = prev {
                     around != dir.opposite()
@@ -138,17 +145,17 @@ impl BeamSearchState for CostReducer {
     type C = u64;
     fn cost_on(&self, action: Self::A) -> Self::C {
         match action {
-            GridAction::Swap(_) => self.param.swap_cost as u64,
-            GridAction::Select(_) => self.param.select_cost as u64,
+            GridAction::Swap(_) => self.model.swap_cost(),
+            GridAction::Select(_) => self.model.select_cost(),
         }
     }
 
-    fn max_cost(&self) -> Self::C {
-        let cost_limit = self.param.select_cost as u64 + self.param.swap_cost as u64 * 3;
-        cost_limit.min(self.initial_dist.as_u32() as u64 / 10)
+    fn estimate(&self) -> Self::C {
+        self.dist.as_u32() as u64 * self.model.swap_cost()
     }
 
     fn enrichment_key(&self) -> usize {
-        self.param.select_limit as usize
+        self.param.select_limit as usize * DIVERSITY_BUCKETS
+            + diversity_key(&self.board, self.diversity_rows)
     }
 }
@@ -1,8 +1,21 @@
-use super::{edges_nodes::Nodes, resolve};
+use std::{sync::Arc, time::Duration};
+
+use proptest::{collection::vec, prelude::*};
+
+use super::{
+    chain_seeds, edges_nodes::Nodes, resolve, resolve_budgeted, resolve_cancellable,
+    resolve_with_config, BeamConfig, PhaseBudgets,
+};
 use crate::{
-    basis::Operation,
-    grid::{board::BoardFinder, Grid, Pos},
-    move_resolve::{state::SqManhattan, ResolveParam},
+    basis::{Operation, Rot},
+    cancel::CancellationToken,
+    grid::{board::Board, Grid, Pos, VecOnGrid},
+    move_resolve::{
+        beam_search::{beam_search, SearchLimits},
+        cost_model::{CostModel, CostWeighting},
+        state::{completer::Completer, diversity_key, SqManhattan},
+        ResolveParam,
+    },
 };
 
 #[test]
@@ -15,6 +28,20 @@ fn test_sq_manhattan() {
     assert_eq!(pre_calc[&(grid.pos(0, 0), grid.pos(1, 1))].as_u32(), 4);
 }
 
+#[test]
+fn test_distance_table() {
+    use std::collections::HashMap;
+
+    let grid = Grid::new(16, 16);
+    let pre_calc: HashMap<_, _> = SqManhattan::pre_calc(grid).collect();
+    let table = SqManhattan::table(grid);
+    for (pair, dist) in pre_calc {
+        assert_eq!(dist, table[&pair], "{:?}", pair);
+    }
+    // 盤面の端をまたいで回り込む距離
+    assert_eq!(4, table[&(grid.pos(0, 0), grid.pos(15, 15))].as_u32());
+}
+
 #[test]
 fn smallest_case() {
     // 10 00
@@ -32,7 +59,8 @@ fn smallest_case() {
             swap_cost: 1,
             select_cost: 1,
         },
-    );
+    )
+    .unwrap();
     test_answers(1, 1, actual);
 }
 
@@ -55,16 +83,230 @@ fn simple_case() {
             swap_cost: 1,
             select_cost: 1,
         },
-    );
+    )
+    .unwrap();
     test_answers(1, 2, actual);
 }
 
+#[test]
+fn test_diversity_key() {
+    let grid = Grid::new(4, 4);
+    let identity = VecOnGrid::from_vec(grid, grid.all_pos().collect()).unwrap();
+    let swapped = |swaps: &[(Pos, Pos)]| {
+        let mut field = identity.clone();
+        for &(a, b) in swaps {
+            field.swap(a, b);
+        }
+        Board::new(None, field)
+    };
+    // 上から 1 行目と 2 行目が揃っているので, 1 行だけ見るなら 3 行目だけが区分に効く
+    let base = swapped(&[(grid.pos(0, 2), grid.pos(0, 3))]);
+    let lower = swapped(&[
+        (grid.pos(0, 2), grid.pos(0, 3)),
+        (grid.pos(1, 3), grid.pos(2, 3)),
+    ]);
+    assert_eq!(diversity_key(&base, 1), diversity_key(&lower, 1));
+    assert_eq!(0, diversity_key(&base, 0));
+    assert_eq!(0, diversity_key(&lower, 0));
+
+    let param = ResolveParam {
+        select_limit: 2,
+        swap_cost: 1,
+        select_cost: 4,
+    };
+    let pieces: Vec<_> = base.into_field().into_iter().collect();
+    let movements = movements_from_pieces(grid, &pieces);
+    // 区分の数よりビームが狭くても, 区分ごとに 1 つは残して探索を続ける
+    for phase3 in [BeamConfig::default().phase3, 10] {
+        let beam = BeamConfig {
+            phase3,
+            diversity_rows: 2,
+            ..BeamConfig::default()
+        };
+        let mut found = 0;
+        for (ops, _) in resolve_with_config(grid, &movements, param, beam).unwrap() {
+            let mut nodes = Nodes::new(grid, &movements);
+            nodes.apply(&ops);
+            assert!(nodes.is_identity());
+            found += 1;
+        }
+        assert!(0 < found, "no answer with phase3 width {}", phase3);
+    }
+}
+
+#[test]
+fn test_search_limits() {
+    // 10 00
+    let grid = Grid::new(2, 1);
+    let mut field = VecOnGrid::with_init(grid, grid.pos(0, 0));
+    field[grid.pos(0, 0)] = grid.pos(1, 0);
+    field[grid.pos(1, 0)] = grid.pos(0, 0);
+    let param = ResolveParam {
+        select_limit: 1,
+        swap_cost: 1,
+        select_cost: 1,
+    };
+    let completer = Completer::new(
+        Board::new(None, field),
+        param,
+        CostModel::new(param, CostWeighting::AsIs),
+        None,
+        Arc::new(SqManhattan::table(grid)),
+        0,
+    );
+    let search = |limits| beam_search(completer.clone(), 10, limits).next();
+    let unlimited = SearchLimits {
+        max_cost: 10,
+        max_iterations: None,
+        time_limit: None,
+        cancel: None,
+    };
+    assert_eq!(Some(2), search(unlimited.clone()).map(|(_, cost)| cost));
+    // 選択と交換の 2 手が要るので, 1 回しか広げなければ見つからない
+    assert_eq!(
+        None,
+        search(SearchLimits {
+            max_iterations: Some(1),
+            ..unlimited.clone()
+        })
+    );
+    assert_eq!(
+        None,
+        search(SearchLimits {
+            time_limit: Some(Duration::ZERO),
+            ..unlimited.clone()
+        })
+    );
+    assert_eq!(
+        None,
+        search(SearchLimits {
+            max_cost: 1,
+            ..unlimited.clone()
+        })
+    );
+    let cancel = CancellationToken::new();
+    cancel.cancel();
+    assert_eq!(
+        None,
+        search(SearchLimits {
+            cancel: Some(cancel),
+            ..unlimited
+        })
+    );
+}
+
+#[test]
+fn test_resolve_cancellable() {
+    // 00 10 20 を 20 00 10 にずらしたもの
+    let grid = Grid::new(3, 1);
+    let movements = [
+        (grid.pos(0, 0), grid.pos(1, 0)),
+        (grid.pos(1, 0), grid.pos(2, 0)),
+        (grid.pos(2, 0), grid.pos(0, 0)),
+    ];
+    let param = ResolveParam {
+        select_limit: 2,
+        swap_cost: 1,
+        select_cost: 1,
+    };
+    let beam = BeamConfig::default();
+    let cancel = CancellationToken::new();
+    assert!(
+        resolve_cancellable(grid, &movements, param, beam, cancel.clone())
+            .unwrap()
+            .next()
+            .is_some()
+    );
+    cancel.cancel();
+    assert!(resolve_cancellable(grid, &movements, param, beam, cancel)
+        .unwrap()
+        .next()
+        .is_none());
+}
+
+#[test]
+fn test_resolve_budgeted() {
+    // 10 20 00 30
+    // 01 11 31 21
+    let grid = Grid::new(4, 2);
+    let movements = [
+        (grid.pos(0, 0), grid.pos(2, 0)),
+        (grid.pos(1, 0), grid.pos(0, 0)),
+        (grid.pos(2, 0), grid.pos(1, 0)),
+        (grid.pos(2, 1), grid.pos(3, 1)),
+        (grid.pos(3, 1), grid.pos(2, 1)),
+    ];
+    let param = ResolveParam {
+        select_limit: 3,
+        swap_cost: 1,
+        select_cost: 2,
+    };
+    let beam = BeamConfig::default();
+    let budgeted = |budgets| {
+        resolve_budgeted(
+            grid,
+            &movements,
+            param,
+            beam,
+            budgets,
+            CancellationToken::new(),
+        )
+        .unwrap()
+        .collect::<Vec<_>>()
+    };
+
+    let unlimited = budgeted(PhaseBudgets::default());
+    assert!(!unlimited.is_empty());
+    // 時間が無くても最初の候補は返し, その後は探さない
+    let expired = budgeted(PhaseBudgets {
+        search: Some(Duration::ZERO),
+        completion: Some(Duration::ZERO),
+    });
+    assert_eq!(1, expired.len());
+    assert!(unlimited.last().unwrap().1 <= expired[0].1);
+    for (ops, _) in unlimited.iter().chain(&expired) {
+        let mut nodes = Nodes::new(grid, &movements);
+        nodes.apply(ops);
+        assert!(nodes.is_identity());
+    }
+}
+
+#[test]
+fn test_chain_seeds() {
+    // 10 20 30 00
+    // 11 01 21 31
+    let grid = Grid::new(4, 2);
+    let Nodes { nodes, .. } = Nodes::new(
+        grid,
+        &[
+            (grid.pos(0, 0), grid.pos(3, 0)),
+            (grid.pos(1, 0), grid.pos(0, 0)),
+            (grid.pos(2, 0), grid.pos(1, 0)),
+            (grid.pos(3, 0), grid.pos(2, 0)),
+            (grid.pos(0, 1), grid.pos(1, 1)),
+            (grid.pos(1, 1), grid.pos(0, 1)),
+        ],
+    );
+    let param = |select_cost| ResolveParam {
+        select_limit: 2,
+        swap_cost: 1,
+        select_cost,
+    };
+    // 選択が高ければ 1 回の選択で揃えたいので, すべてのマスを試す
+    let seeds = chain_seeds(&nodes, CostModel::new(param(8), CostWeighting::AsIs));
+    assert_eq!(6, seeds.len());
+    // 選択が交換と同じ程度なら, 各巡回の半分だけを散らばった巡回から試す
+    let seeds = chain_seeds(&nodes, CostModel::new(param(1), CostWeighting::AsIs));
+    assert_eq!(3, seeds.len());
+    assert_eq!(grid.pos(0, 1), seeds[2]);
+}
+
 fn test_answers(
     select_count: usize,
     swap_count: usize,
-    actual_gen: impl Iterator<Item = Vec<Operation>>,
+    actual_gen: impl Iterator<Item = (Vec<Operation>, u64)>,
 ) {
-    assert!(actual_gen.into_iter().any(|actual| {
+    assert!(actual_gen.into_iter().any(|(actual, _)| {
         actual.into_iter().fold((0, 0), |(selects, swaps), op| {
             (selects + 1, swaps + op.movements.len())
         }) == (select_count, swap_count)
@@ -91,7 +333,8 @@ fn case1() {
             swap_cost: 1,
             select_cost: 2,
         },
-    );
+    )
+    .unwrap();
     test_answers(2, 5, actual);
 }
 
@@ -115,7 +358,8 @@ fn case2() {
             swap_cost: 1,
             select_cost: 1,
         },
-    );
+    )
+    .unwrap();
     test_answers(2, 3, actual);
 }
 
@@ -141,7 +385,8 @@ fn case3() {
             swap_cost: 2,
             select_cost: 3,
         },
-    );
+    )
+    .unwrap();
     test_answers(2, 5, actual);
 }
 
@@ -167,7 +412,8 @@ fn case4() {
             swap_cost: 1,
             select_cost: 8,
         },
-    );
+    )
+    .unwrap();
     test_answers(1, 8, actual);
 }
 
@@ -185,25 +431,17 @@ fn large_case1() {
         (grid.pos(3, 0), grid.pos(1, 1)),
         (grid.pos(1, 1), grid.pos(5, 5)),
     ];
-    let Nodes { mut nodes, .. } = Nodes::new(grid, case);
+    let mut nodes = Nodes::new(grid, case);
     const PARAM: ResolveParam = ResolveParam {
         select_limit: 3,
         swap_cost: 1,
         select_cost: 8,
     };
 
-    let result = resolve(grid, case, PARAM).next().unwrap();
+    let (result, _) = resolve(grid, case, PARAM).unwrap().next().unwrap();
 
-    let finder = BoardFinder::new(grid);
-    for Operation { select, movements } in result {
-        let mut current = select;
-        for movement in movements {
-            let to_swap = finder.move_pos_to(current, movement);
-            nodes.swap(current, to_swap);
-            current = to_swap;
-        }
-    }
-    assert!(grid.all_pos().zip(nodes.into_iter()).all(|(p, n)| p == n));
+    nodes.apply(&result);
+    assert!(nodes.is_identity());
 }
 
 #[test]
@@ -253,25 +491,17 @@ fn large_case2() {
         (grid.pos(0, 4), grid.pos(5, 4)),
         (grid.pos(0, 5), grid.pos(5, 5)),
     ];
-    let Nodes { mut nodes, .. } = Nodes::new(grid, case);
+    let mut nodes = Nodes::new(grid, case);
     const PARAM: ResolveParam = ResolveParam {
         select_limit: 3,
         swap_cost: 1,
         select_cost: 8,
     };
 
-    let result = resolve(grid, case, PARAM).next().unwrap();
+    let (result, _) = resolve(grid, case, PARAM).unwrap().next().unwrap();
 
-    let finder = BoardFinder::new(grid);
-    for Operation { select, movements } in result {
-        let mut current = select;
-        for movement in movements {
-            let to_swap = finder.move_pos_to(current, movement);
-            nodes.swap(current, to_swap);
-            current = to_swap;
-        }
-    }
-    assert!(grid.all_pos().zip(nodes.into_iter()).all(|(p, n)| p == n));
+    nodes.apply(&result);
+    assert!(nodes.is_identity());
 }
 
 #[test]
@@ -319,72 +549,183 @@ fn large_case3() {
         (grid.pos(8, 3), grid.pos(1, 3)),
         (grid.pos(9, 3), grid.pos(3, 3)),
     ];
-    let Nodes { mut nodes, .. } = Nodes::new(grid, case);
+    let mut nodes = Nodes::new(grid, case);
     const PARAM: ResolveParam = ResolveParam {
         select_limit: 10,
         swap_cost: 10,
         select_cost: 4,
     };
 
-    let result = resolve(grid, case, PARAM).next().unwrap();
+    let (result, _) = resolve(grid, case, PARAM).unwrap().next().unwrap();
 
-    let finder = BoardFinder::new(grid);
-    for Operation { select, movements } in result {
-        let mut current = select;
-        for movement in movements {
-            let to_swap = finder.move_pos_to(current, movement);
-            nodes.swap(current, to_swap);
-            current = to_swap;
-        }
-    }
-    assert!(grid.all_pos().zip(nodes.into_iter()).all(|(p, n)| p == n));
+    nodes.apply(&result);
+    assert!(nodes.is_identity());
 }
 
 #[test]
-fn rand_case() {
-    fn gen_circular(grid: Grid, rng: &mut rand::rngs::ThreadRng) -> Vec<Pos> {
-        use rand::{
-            distributions::{Distribution, Uniform},
-            seq::SliceRandom,
-        };
-        let mut points: Vec<_> = grid.all_pos().collect();
-        points.shuffle(rng);
-        let between = Uniform::from(2..points.len());
-        let taking = between.sample(rng);
-        points.into_iter().take(taking).collect()
-    }
-    const WIDTH: u8 = 16;
-    const HEIGHT: u8 = 16;
-    const PARAM: ResolveParam = ResolveParam {
+fn no_selection_case() {
+    let grid = Grid::new(2, 1);
+    let param = ResolveParam {
+        select_limit: 0,
+        swap_cost: 1,
+        select_cost: 1,
+    };
+    let movements = &[
+        (grid.pos(0, 0), grid.pos(1, 0)),
+        (grid.pos(1, 0), grid.pos(0, 0)),
+    ];
+    assert!(matches!(
+        resolve(grid, movements, param),
+        Err(crate::Error::NoSelection)
+    ));
+    // 動かす断片が無ければ, 何もしない回答が得られる
+    let answers: Vec<_> = resolve(grid, &[], param).unwrap().collect();
+    assert_eq!(vec![(Vec::<Operation>::new(), 0)], answers);
+}
+
+#[test]
+fn rotation_only_case() {
+    let grid = Grid::new(16, 16);
+    let param = ResolveParam {
         select_limit: 8,
         swap_cost: 1,
         select_cost: 8,
     };
-    let mut rng = rand::thread_rng();
+    let answers: Vec<_> = resolve(grid, &[], param).unwrap().collect();
+    assert_eq!(vec![(Vec::<Operation>::new(), 0)], answers);
+    assert_eq!(
+        "0123\r\n0\r\n",
+        crate::kaitou::ans(&answers[0].0, &[Rot::R0, Rot::R90, Rot::R180, Rot::R270])
+    );
+}
 
-    let grid = Grid::new(WIDTH, HEIGHT);
-    let circular = gen_circular(grid, &mut rng);
-    let mut case = vec![];
-    for pair in circular.windows(2) {
-        case.push((pair[0], pair[1]));
-    }
-    case.push((*circular.last().unwrap(), *circular.first().unwrap()));
+/// 各マスに `pieces` の順で断片が置かれている盤面を表す移動を作る. 動いていないマスは含めない.
+fn movements_from_pieces(grid: Grid, pieces: &[Pos]) -> Vec<(Pos, Pos)> {
+    grid.all_pos()
+        .zip(pieces)
+        .filter(|&(to, &from)| to != from)
+        .map(|(to, &from)| (from, to))
+        .collect()
+}
 
-    let Nodes { mut nodes, .. } = Nodes::new(grid, &case);
-    eprintln!("before: {:#?}", nodes);
+fn arbitrary_param() -> impl Strategy<Value = ResolveParam> {
+    (2u8..=8, 1u16..=10, 1u16..=20).prop_map(|(select_limit, swap_cost, select_cost)| {
+        ResolveParam {
+            select_limit,
+            swap_cost,
+            select_cost,
+        }
+    })
+}
 
-    let result = resolve(grid, &case, PARAM).next().unwrap();
+/// 断片が 6 枚以下の小さな盤面の大きさ. move_resolve が取りうる盤面をほぼ調べ尽くせる.
+fn small_grid() -> impl Strategy<Value = Grid> {
+    prop_oneof![(2u8..=3, Just(2u8)), (Just(2u8), 2u8..=3)]
+        .prop_map(|(width, height)| Grid::new(width, height))
+}
+
+/// 小さな盤面で, 断片をランダムに並べ替える.
+fn arbitrary_pieces() -> impl Strategy<Value = (Grid, Vec<Pos>)> {
+    small_grid().prop_flat_map(|grid| {
+        Just(grid.all_pos().collect::<Vec<_>>())
+            .prop_shuffle()
+            .prop_map(move |pieces| (grid, pieces))
+    })
+}
+
+/// 小さな盤面全体を端をまたいでずらしてから, いくつかの断片を入れ替える. ほとんどの断片が端をまたいで動く.
+fn wrapping_pieces() -> impl Strategy<Value = (Grid, Vec<Pos>)> {
+    small_grid().prop_flat_map(|grid| {
+        let (width, height) = (grid.width(), grid.height());
+        let len = width as usize * height as usize;
+        (1..width, 0..height, vec((0..len, 0..len), 0..3)).prop_map(move |(dx, dy, swaps)| {
+            let mut pieces: Vec<_> = grid
+                .all_pos()
+                .map(|p| grid.pos((p.x() + dx) % width, (p.y() + dy) % height))
+                .collect();
+            for (a, b) in swaps {
+                pieces.swap(a, b);
+            }
+            (grid, pieces)
+        })
+    })
+}
+
+/// 16x16 の盤面で, ランダムに選んだマスを 1 つの巡回で動かす.
+fn large_cycle_pieces() -> impl Strategy<Value = (Grid, Vec<Pos>)> {
+    let grid = Grid::new(16, 16);
+    (
+        Just(grid.all_pos().collect::<Vec<_>>()).prop_shuffle(),
+        2..256usize,
+    )
+        .prop_map(move |(circular, taking)| {
+            let mut pieces = VecOnGrid::with_init(grid, grid.pos(0, 0));
+            for pos in grid.all_pos() {
+                pieces[pos] = pos;
+            }
+            let circular = &circular[..taking];
+            for (i, &to) in circular.iter().enumerate() {
+                pieces[to] = circular[(i + 1) % taking];
+            }
+            (grid, pieces.into_iter().collect())
+        })
+}
 
-    let finder = BoardFinder::new(grid);
-    eprintln!("operations: {:?}", result);
-    for Operation { select, movements } in result {
-        let mut current = select;
-        for movement in movements {
-            let to_swap = finder.move_pos_to(current, movement);
-            nodes.swap(current, to_swap);
-            current = to_swap;
+/// `resolve` が出すすべての候補が選択回数の上限を守り, 盤面を元に戻すことを確かめる.
+fn check_candidates(grid: Grid, pieces: &[Pos], param: ResolveParam) -> Result<(), TestCaseError> {
+    let movements = movements_from_pieces(grid, pieces);
+    let beam = BeamConfig {
+        phase1: 20,
+        phase3: 10,
+        ..BeamConfig::default()
+    };
+    let mut prev_cost = None;
+    for (ops, cost) in resolve_with_config(grid, &movements, param, beam).unwrap() {
+        prop_assert_eq!(
+            cost,
+            crate::simulator::cost(param.select_cost, param.swap_cost, &ops)
+        );
+        if let Some(prev) = prev_cost {
+            prop_assert!(cost < prev, "{} is not better than {}", cost, prev);
         }
+        prev_cost = Some(cost);
+        prop_assert!(ops.len() <= param.select_limit as usize);
+        let mut nodes = Nodes::new(grid, &movements);
+        nodes.apply(&ops);
+        prop_assert!(
+            nodes.is_identity(),
+            "the board was not restored by {:?}",
+            ops
+        );
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn resolve_random_permutation_prop((grid, pieces) in arbitrary_pieces(), param in arbitrary_param()) {
+        check_candidates(grid, &pieces, param)?;
+    }
+
+    #[test]
+    fn resolve_wrapping_prop((grid, pieces) in wrapping_pieces(), param in arbitrary_param()) {
+        check_candidates(grid, &pieces, param)?;
+    }
+}
+
+proptest! {
+    // 大きな盤面は時間がかかるので, パラメータを固定して少しだけ試す
+    #![proptest_config(ProptestConfig::with_cases(2))]
+
+    #[test]
+    fn resolve_large_cycle_prop((grid, pieces) in large_cycle_pieces()) {
+        let param = ResolveParam {
+            select_limit: 8,
+            swap_cost: 1,
+            select_cost: 8,
+        };
+        check_candidates(grid, &pieces, param)?;
     }
-    eprintln!("after: {:#?}", nodes);
-    assert!(grid.all_pos().zip(nodes.into_iter()).all(|(p, n)| p == n));
 }
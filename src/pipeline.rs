@@ -0,0 +1,134 @@
+//! 問題を受け取り, GUI を開かずに断片の切り出しから回答の作成までを通して行う.
+//!
+//! 他のツールに組み込むときに, バイナリと同じ手順を書き直さずに済むようにする.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    basis::{Operation, Problem, Rot},
+    cancel::CancellationToken,
+    error::{Error, Result},
+    fragment::{arrangement::RecoveredArrangement, Fragment},
+    kaitou,
+    move_resolve::{self, BeamConfig, PhaseBudgets, ResolveParam},
+    pixel_match::{Metric, Resolver, Strategy},
+};
+
+/// `SolveOptions` は `solve_problem` で画像を復元し, 操作列を探すときの設定.
+#[derive(Debug, Clone, Default)]
+pub struct SolveOptions {
+    pub metric: Metric,
+    pub strategy: Strategy,
+    /// 断片を回転させずに並べるか
+    pub fixed_rotation: bool,
+    pub beam_config: BeamConfig,
+    /// `move_resolve` の段階ごとにかける時間
+    pub phase_budgets: PhaseBudgets,
+    /// 操作列の候補を探す時間. 候補が見つかるたびに確かめ, 過ぎていたらそれまでで最もコストの小さい候補を返す.
+    /// `None` なら探し終えるまで待つ
+    pub time_limit: Option<Duration>,
+    /// 別のスレッドから復元や探索をやめさせるための token
+    pub cancel: Option<CancellationToken>,
+}
+
+/// `Answer` は `solve_problem` で作った回答と, その元になった復元結果.
+#[derive(Debug, Clone)]
+pub struct Answer {
+    /// サーバに提出する回答
+    pub text: String,
+    pub operations: Vec<Operation>,
+    /// 問題画像での位置の順に並べた回転
+    pub rotations: Vec<Rot>,
+    /// 復元した配置が正しいとしたときのコスト
+    pub cost: u64,
+    pub arrangement: RecoveredArrangement,
+}
+
+/// `problem` の断片を GUI を開かずに並べ, その配置にする操作列から回答を作る.
+///
+/// `options.cancel` でやめるよう求められたら, それまでに見つけた最もコストの小さい回答を返す.
+/// 断片を並べ終える前か, 操作列の候補が 1 つも見つからないうちにやめたら `Error::Cancelled` を返す.
+pub fn solve_problem(problem: Problem, options: SolveOptions) -> Result<Answer> {
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem)?;
+
+    let mut resolver = Resolver::new(fragments, grid);
+    resolver.set_metric(options.metric);
+    resolver.set_strategy(options.strategy);
+    resolver.set_fixed_rotation(options.fixed_rotation);
+    let cancel = options.cancel.unwrap_or_default();
+    resolver.set_cancellation_token(cancel.clone());
+    let arrangement = resolver.resolve_without_gui()?;
+    let (movements, rotations) = arrangement.movements_and_rotations();
+
+    let param = ResolveParam {
+        select_limit: problem.select_limit,
+        swap_cost: problem.swap_cost,
+        select_cost: problem.select_cost,
+    };
+    let deadline = options.time_limit.map(|limit| Instant::now() + limit);
+    let mut best = None;
+    let candidates = move_resolve::resolve_budgeted(
+        grid,
+        &movements,
+        param,
+        options.beam_config,
+        options.phase_budgets,
+        cancel.clone(),
+    )?;
+    for (ops, cost) in candidates {
+        if best.as_ref().is_none_or(|(_, best)| cost < *best) {
+            best = Some((ops, cost));
+        }
+        if matches!(deadline, Some(deadline) if deadline <= Instant::now()) {
+            break;
+        }
+    }
+    let (operations, cost) = match best {
+        Some(best) => best,
+        None if cancel.is_cancelled() => return Err(Error::Cancelled),
+        None => return Err(Error::NoAnswer),
+    };
+
+    Ok(Answer {
+        text: kaitou::ans(&operations, &rotations),
+        operations,
+        rotations,
+        cost,
+        arrangement,
+    })
+}
+
+#[test]
+fn test_solve_problem() {
+    let original = crate::scramble::smooth_problem(6, 4);
+    let scrambled = || crate::scramble::scramble(&original, 1).problem;
+    let options = SolveOptions {
+        beam_config: BeamConfig {
+            phase1: 20,
+            phase3: 10,
+            ..BeamConfig::default()
+        },
+        // 最初の候補で打ち切る
+        time_limit: Some(Duration::ZERO),
+        ..SolveOptions::default()
+    };
+    let answer = solve_problem(scrambled(), options.clone()).unwrap();
+
+    let score =
+        crate::simulator::simulate(&scrambled(), answer.arrangement.truth(), &answer.text).unwrap();
+    assert!(score.is_perfect(), "{:?}\n{}", score, answer.text);
+    assert_eq!(answer.cost, score.cost);
+    assert_eq!(answer.operations.len(), score.select_count);
+
+    let cancel = CancellationToken::new();
+    cancel.cancel();
+    let cancelled = SolveOptions {
+        cancel: Some(cancel),
+        ..options
+    };
+    assert!(matches!(
+        solve_problem(scrambled(), cancelled),
+        Err(Error::Cancelled)
+    ));
+}
@@ -1,101 +1,686 @@
-use std::collections::HashMap;
-use std::sync::mpsc;
+use std::collections::{HashMap, HashSet};
+use std::panic::AssertUnwindSafe;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "gui")]
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "fs")]
+use anyhow::Context as _;
+use anyhow::Result;
 
 use crate::basis::{Color, Dir, Rot};
-use crate::fragment::Fragment;
+use crate::cancel::CancellationToken;
+use crate::error::Error;
+use crate::fragment::{arrangement::RecoveredArrangement, Fragment};
 use crate::grid::{Grid, Pos, VecOnGrid};
-use crate::pixel_match::gui::{EdgePos, GuiRequest, GuiResponse};
+#[cfg(feature = "gui")]
+use crate::pixel_match::gui::{GuiRequest, GuiResponse};
 
+mod anneal;
+mod assembly;
+mod borders_first;
 mod double_side;
+mod duplicates;
+mod edge_scores;
+#[cfg(feature = "ga")]
+mod genetic;
+#[cfg(feature = "gui")]
 mod gui;
+mod hints_builder;
+mod hints_file;
+mod pool;
+mod seam_check;
 mod shaker;
 
+use self::assembly::Assembly;
+pub use self::edge_scores::EdgeScores;
+#[cfg(feature = "ga")]
+pub use self::genetic::GeneticParam;
+#[cfg(feature = "gui")]
 use self::gui::RecalculateArtifact;
+pub use self::hints_builder::ResolveHintsBuilder;
+use self::pool::FragmentPool;
+pub use self::seam_check::{Seam, SeamReport};
+
+#[cfg(feature = "gui")]
+pub fn resolve(fragments: Vec<Fragment>, grid: Grid) -> crate::Result<RecoveredArrangement> {
+    Resolver::new(fragments, grid).resolve()
+}
 
-pub fn resolve(fragments: Vec<Fragment>, grid: Grid) -> VecOnGrid<Fragment> {
-    let (gtx, rx) = mpsc::channel();
-    let (tx, grx) = mpsc::channel();
+/// GUI を開かずにヒント無しで画像を復元する.
+pub fn resolve_headless(
+    fragments: Vec<Fragment>,
+    grid: Grid,
+) -> crate::Result<RecoveredArrangement> {
+    Resolver::new(fragments, grid).resolve_without_gui()
+}
 
-    let solver_thread = std::thread::Builder::new()
-        .name("pixel matcher".into())
-        .spawn(move || {
-            let (recovered_image, root_pos) =
-                solve(fragments.clone(), grid, ResolveHints::default());
+/// `Resolver` は GUI で与えたヒントを保持し, 何度でも画像の復元をやり直せるようにする.
+///
+/// 提出した回答に不一致があったときなどに, 前回の修正を引き継いだまま GUI を開き直すために使う.
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    fragments: Vec<Fragment>,
+    grid: Grid,
+    hints: ResolveHints,
+    options: SolveOptions,
+    soft_penalty: f64,
+    gui_options: GuiOptions,
+    cancel: Option<CancellationToken>,
+}
 
-            let mut result = recovered_image.clone();
+impl Resolver {
+    pub fn new(fragments: Vec<Fragment>, grid: Grid) -> Self {
+        Self {
+            fragments,
+            grid,
+            hints: ResolveHints::default(),
+            options: SolveOptions::default(),
+            soft_penalty: DEFAULT_SOFT_PENALTY,
+            gui_options: GuiOptions::default(),
+            cancel: None,
+        }
+    }
 
-            tx.send(GuiResponse::Recalculated(RecalculateArtifact {
-                recovered_image,
-                root_pos,
-            }))
-            .unwrap();
-
-            loop {
-                match rx.recv() {
-                    Ok(GuiRequest::Recalculate(hint)) => {
-                        println!(
-                            "recalculating. blocklists: {} entries",
-                            hint.blocklist.len()
-                        );
-                        println!("whitelists: {} entries", hint.locked_pairs.len());
-
-                        let (recovered_image, root_pos) = solve(fragments.clone(), grid, hint);
-
-                        result = recovered_image.clone();
-
-                        tx.send(GuiResponse::Recalculated(RecalculateArtifact {
-                            recovered_image,
-                            root_pos,
-                        }))
-                        .unwrap();
+    pub fn grid(&self) -> Grid {
+        self.grid
+    }
+
+    pub fn fragments(&self) -> &[Fragment] {
+        &self.fragments
+    }
+
+    /// 辺同士の一致度を測る尺度を `metric` にする.
+    pub fn set_metric(&mut self, metric: Metric) {
+        self.options.metric = metric;
+    }
+
+    /// 断片を並べる手順を `strategy` にする.
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.options.strategy = strategy;
+    }
+
+    /// 2 辺から断片を探すときに 2 つの辺の一致度をまとめる方法を `scoring` にする.
+    pub fn set_double_side_scoring(&mut self, scoring: DoubleSideScoring) {
+        self.options.double_side = scoring;
+    }
+
+    /// 並べた後に焼きなましで断片の配置を改善する時間を `budget` にする. `None` なら焼きなまししない.
+    pub fn set_anneal_budget(&mut self, budget: Option<Duration>) {
+        self.options.anneal_budget = budget;
+    }
+
+    /// 画像を 1 回復元するのにかける時間の上限を `budget` にする. 焼きなましや遺伝的アルゴリズムは残った時間だけ行う.
+    pub fn set_match_budget(&mut self, budget: Option<Duration>) {
+        self.options.match_budget = budget;
+    }
+
+    /// 遺伝的アルゴリズムで並べるときの個体数と世代数を `param` にする.
+    #[cfg(feature = "ga")]
+    pub fn set_genetic_param(&mut self, param: GeneticParam) {
+        self.options.genetic = param;
+    }
+
+    /// `fixed` なら, 断片が回転していない問題として回転させずに並べる.
+    pub fn set_fixed_rotation(&mut self, fixed: bool) {
+        self.options.fixed_rotation = fixed;
+    }
+
+    /// GUI でソフトなブロックリストに入れた組の一致度に掛ける倍率を `penalty` にする.
+    pub fn set_soft_penalty(&mut self, penalty: f64) {
+        self.soft_penalty = penalty;
+    }
+
+    /// GUI を開かずに復元している間に `token` でやめるよう求められたら, 断片を並べる途中でやめて `Error::Cancelled` を返す.
+    ///
+    /// 焼きなましの途中なら, それまでに良くした配置を返す.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancel = Some(token);
+    }
+
+    /// 次の復元で使うヒント. GUI で編集したものや, 読み込んだヒントファイルの内容もここに入る.
+    pub fn hints(&self) -> &ResolveHints {
+        &self.hints
+    }
+
+    /// 現在のヒントを `hints` と置き換える.
+    pub fn set_hints(&mut self, hints: ResolveHints) {
+        self.hints = hints;
+    }
+
+    /// `path` に保存されたヒントを読み込み, 現在のヒントと置き換える.
+    #[cfg(feature = "fs")]
+    pub fn load_hints(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        self.hints = hints_file::read_hints(self.grid, &text)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        Ok(())
+    }
+
+    /// 現在のヒントを `path` に保存する.
+    #[cfg(feature = "fs")]
+    pub fn save_hints(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, hints_file::write_hints(&self.hints))
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// 断片が盤面のマスと同じ数だけあり, 左上の断片を含むか確かめる.
+    fn check_fragments(&self) -> crate::Result<()> {
+        let expected = self.grid.width() as usize * self.grid.height() as usize;
+        if self.fragments.len() != expected {
+            return Err(Error::FragmentCount {
+                expected,
+                found: self.fragments.len(),
+            });
+        }
+        let root = self.grid.pos(0, 0);
+        if !self.fragments.iter().any(|f| f.pos == root) {
+            return Err(Error::MissingFragment(root));
+        }
+        Ok(())
+    }
+
+    /// GUI を開かずに現在のヒントで画像を復元する.
+    pub fn resolve_without_gui(&self) -> crate::Result<RecoveredArrangement> {
+        self.check_fragments()?;
+        let ((result, _, _), _) = solve_relaxing(
+            &self.fragments,
+            self.grid,
+            self.hints.clone(),
+            self.options,
+            Abandon::on_cancel(self.cancel.as_ref()),
+            &mut None,
+        )?;
+        into_filled(result).map(RecoveredArrangement::new)
+    }
+
+    /// GUI を開くときのウィンドウの大きさと文字の拡大率を `options` にする.
+    pub fn set_gui_options(&mut self, options: GuiOptions) {
+        self.gui_options = options;
+    }
+
+    /// GUI の設定. GUI を閉じた後は, 閉じたときのウィンドウの大きさになる.
+    pub fn gui_options(&self) -> GuiOptions {
+        self.gui_options
+    }
+
+    /// 復元した配置の継ぎ目の一致度を, 復元に使うのと同じ尺度で調べる.
+    pub fn check_seams(&self, arrangement: &RecoveredArrangement) -> SeamReport {
+        SeamReport::new(arrangement.recovered(), self.options.metric)
+    }
+
+    /// GUI を開かずに復元し, 一致度が平均より標準偏差の `k` 倍を超えて悪い継ぎ目があるときだけ GUI で修正する.
+    #[cfg(feature = "gui")]
+    pub fn resolve_if_suspicious(&mut self, k: f64) -> crate::Result<RecoveredArrangement> {
+        let arrangement = self.resolve_without_gui()?;
+        if self.check_seams(&arrangement).warn_suspicious(k) {
+            tracing::info!("found suspicious seams. opening the gui");
+            return self.resolve();
+        }
+        tracing::info!("no suspicious seams. skipping the gui");
+        Ok(arrangement)
+    }
+
+    /// 現在のヒントで復元した画像を GUI に表示し, 修正が終わったら最終的な復元画像を返す.
+    #[cfg(feature = "gui")]
+    pub fn resolve(&mut self) -> crate::Result<RecoveredArrangement> {
+        self.check_fragments()?;
+        let (gtx, rx) = mpsc::channel();
+        let (tx, grx) = mpsc::channel();
+        let fragments = self.fragments.clone();
+        let grid = self.grid;
+        let initial_hints = self.hints.clone();
+        let options = self.options;
+        // GUI が最後に送った再計算の要求の番号. 最初の復元は 0 番とする
+        let latest_request = Arc::new(AtomicU64::new(0));
+        let latest = Arc::clone(&latest_request);
+        // GUI から最後に届いたヒント. 復元するスレッドが panic しても, それまでの編集を失わないように外に置く
+        let session_hints = Arc::new(Mutex::new(self.hints.clone()));
+        let last_hints = Arc::clone(&session_hints);
+
+        let solver_thread = std::thread::Builder::new()
+            .name("pixel matcher".into())
+            .spawn(move || {
+                let mut result = None;
+                let mut last_failure = None;
+                // ブロックリストを加えただけなら, 前回並べた盤面を影響の無い所まで使い回す
+                let mut previous = None;
+
+                // より新しい要求が届いて途中でやめたときは, 応答を送らない
+                let mut recalculate = |id: u64, hints: ResolveHints| {
+                    let abandon = Abandon::unless_latest(&latest, id);
+                    match solve_relaxing(&fragments, grid, hints, options, abandon, &mut previous) {
+                        Ok(((recovered_image, root_pos, log), discarded_hints)) => {
+                            result = Some(recovered_image.clone());
+                            last_failure = None;
+                            Some(GuiResponse::Recalculated(
+                                id,
+                                RecalculateArtifact {
+                                    recovered_image,
+                                    root_pos,
+                                    suggestions: log.suggestions,
+                                    provenances: log.provenances,
+                                    discarded_hints,
+                                },
+                            ))
+                        }
+                        Err(Error::Abandoned) => {
+                            tracing::info!(id, "abandoned the recalculation for a newer request");
+                            None
+                        }
+                        Err(e) => {
+                            let reason = e.to_string();
+                            tracing::error!(%reason, "pixel_match failed");
+                            last_failure = Some(reason.clone());
+                            Some(GuiResponse::Failed(id, reason))
+                        }
                     }
+                };
+
+                // GUI が先に閉じていたら送れないが, そのときは Quit が届いているので無視してよい
+                if let Some(response) = recalculate(0, initial_hints) {
+                    let _ = tx.send(response);
+                }
+
+                loop {
+                    let request = match rx.recv() {
+                        Ok(request) => gui::coalesce_requests(request, &rx),
+                        Err(_) => {
+                            tracing::error!(
+                                "main thread channel unexpectedly closed. maybe it has panicked"
+                            );
+                            break;
+                        }
+                    };
+                    match request {
+                        GuiRequest::Recalculate(id, hint) => {
+                            tracing::info!(
+                                id,
+                                blocklist = hint.blocklist().count(),
+                                soft_blocklist = hint.soft_blocklist().count(),
+                                locked_pairs = hint.locked_pairs().count(),
+                                "recalculating"
+                            );
 
-                    Ok(GuiRequest::Quit) => break,
+                            *last_hints.lock().unwrap_or_else(PoisonError::into_inner) =
+                                hint.clone();
+                            if let Some(response) = recalculate(id, hint) {
+                                let _ = tx.send(response);
+                            }
+                        }
 
-                    Err(_) => {
-                        eprintln!("main thread channel unexpectedly closed. maybe it has panicked");
-                        break;
+                        GuiRequest::Quit => break,
                     }
                 }
-            }
-            result
-        })
-        .expect("failed to launch pixel matcher thread");
+                // 最後の再計算が失敗していたら, 成功した中で最後の配置を使う
+                result.ok_or_else(|| {
+                    Error::SolverFailed(last_failure.unwrap_or_else(|| "unknown reason".into()))
+                })
+            })
+            .expect("failed to launch pixel matcher thread");
 
-    gui::begin(gui::GuiContext { tx: gtx, rx: grx });
+        self.gui_options.window_size = gui::begin(gui::GuiContext {
+            tx: gtx,
+            rx: grx,
+            initial_hints: self.hints.clone(),
+            soft_penalty: self.soft_penalty,
+            options: self.gui_options,
+            fixed_rotation: self.options.fixed_rotation,
+            latest_request,
+        });
 
-    let result = solver_thread
-        .join()
-        .unwrap_or_else(|e| std::panic::resume_unwind(e));
+        // 復元の失敗は送り返しているので, ここで panic しているのはそれ以外の不具合. それでも GUI での編集は残しておく
+        let result = solver_thread.join().unwrap_or_else(|panic| {
+            let reason = panic_message(panic);
+            tracing::error!(%reason, "pixel matcher thread panicked");
+            Err(Error::SolverFailed(reason))
+        });
+        self.hints = session_hints
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
 
-    VecOnGrid::from_vec(
-        grid,
-        result
-            .into_iter()
-            .map(|x| x.expect("there were not filled fragment on grid"))
-            .collect(),
-    )
-    .unwrap()
+        into_filled(result?).map(RecoveredArrangement::new)
+    }
 }
 
-// returns: (recovered_image, root_pos)
+/// `catch_unwind` で受け取った panic の理由を文字列にする.
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".into(),
+        },
+    }
+}
+
+fn into_filled(result: VecOnGrid<Option<Fragment>>) -> crate::Result<VecOnGrid<Fragment>> {
+    let grid = result.grid;
+    let filled = result
+        .into_iter_with_pos()
+        .map(|(pos, x)| x.ok_or(Error::Unfilled(pos)))
+        .collect::<crate::Result<_>>()?;
+    Ok(VecOnGrid::from_vec(grid, filled).unwrap())
+}
+
+type Solved = (VecOnGrid<Option<Fragment>>, Pos, MatchLog);
+
+/// `SolveOptions` は画像を復元する手順の設定.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct SolveOptions {
+    metric: Metric,
+    strategy: Strategy,
+    /// 2 辺から断片を探すときに 2 つの辺の一致度をまとめる方法
+    double_side: DoubleSideScoring,
+    /// 並べた後に焼きなましで改善する時間. `None` なら焼きなまししない
+    anneal_budget: Option<Duration>,
+    /// 並べてから焼きなまし終えるまでの時間の上限. `None` なら制限しない
+    match_budget: Option<Duration>,
+    /// 断片を回転させずに並べるか
+    fixed_rotation: bool,
+    #[cfg(feature = "ga")]
+    genetic: GeneticParam,
+}
+
+/// 断片を置くときに試す回転
+const ROTS: [Rot; 4] = [Rot::R0, Rot::R90, Rot::R180, Rot::R270];
+
+impl SolveOptions {
+    /// 断片を置いたときの回転として許すもの. 回転させないなら `Rot::R0` だけになる.
+    fn rots(&self) -> &'static [Rot] {
+        if self.fixed_rotation {
+            &ROTS[..1]
+        } else {
+            &ROTS
+        }
+    }
+}
+
+/// `Abandon` は, より新しい再計算の要求が届いたり呼び出し元にやめさせられたりして, 今の復元が要らなくなったかを調べる.
+#[derive(Debug, Clone, Copy, Default)]
+struct Abandon<'a> {
+    latest: Option<(&'a AtomicU64, u64)>,
+    cancel: Option<&'a CancellationToken>,
+}
+
+impl<'a> Abandon<'a> {
+    /// 最新の要求の番号 `latest` が `id` でなくなったらやめる.
+    fn unless_latest(latest: &'a AtomicU64, id: u64) -> Self {
+        Self {
+            latest: Some((latest, id)),
+            cancel: None,
+        }
+    }
+
+    /// `cancel` でやめるよう求められたらやめる.
+    fn on_cancel(cancel: Option<&'a CancellationToken>) -> Self {
+        Self {
+            latest: None,
+            cancel,
+        }
+    }
+
+    fn is_requested(&self) -> bool {
+        self.is_superseded() || matches!(self.cancel, Some(cancel) if cancel.is_cancelled())
+    }
+
+    /// より新しい要求が届いたか.
+    fn is_superseded(&self) -> bool {
+        matches!(self.latest, Some((latest, id)) if latest.load(Ordering::Relaxed) != id)
+    }
+
+    /// やめたときに返すエラー.
+    fn error(&self) -> Error {
+        if self.is_superseded() {
+            Error::Abandoned
+        } else {
+            Error::Cancelled
+        }
+    }
+}
+
+/// `FillContext` は断片を並べるあいだ変わらない設定をまとめたもの.
+#[derive(Debug, Clone, Copy)]
+struct FillContext<'a> {
+    metric: Metric,
+    scoring: DoubleSideScoring,
+    /// 断片を置いたときの回転として許すもの
+    rots: &'static [Rot],
+    /// 並べるのをやめるよう求められたかを調べる
+    abandon: Abandon<'a>,
+}
+
+impl FillContext<'_> {
+    /// 途中でやめることのない設定を作る.
+    fn new(metric: Metric, scoring: DoubleSideScoring, rots: &'static [Rot]) -> Self {
+        Self {
+            metric,
+            scoring,
+            rots,
+            abandon: Abandon::default(),
+        }
+    }
+}
+
+/// `hints` で復元し, ロックされた組を守れなかったり復元に失敗したりしたら, 最後に加えたヒントから 1 つずつ捨ててやり直す.
+///
+/// 捨てたヒントを捨てた順に一緒に返す. ヒントをすべて捨てても守れない組が無くならなければ, その結果を返す.
+/// ヒントをすべて捨てても失敗するなら, そのエラーを返す. 復元中に panic したときは `Error::SolverFailed` にする.
+/// `abandon` でやめるよう求められたら, 断片を並べる途中かやり直しの合間か焼きなましの途中でやめて `Abandon::error` を返す.
+/// 呼び出し元にやめさせられたときは, 並べ終えていれば焼きなましの途中の配置を返す.
+/// `Strategy::Shaker` なら, `previous` に残した前回の盤面を `solve_reusing` で使い回す.
+/// `options.match_budget` はやり直しも含めた全体にかかり, 焼きなましは `anneal_budget` と残りの時間の短い方だけ行う.
+fn solve_relaxing(
+    fragments: &[Fragment],
+    grid: Grid,
+    mut hints: ResolveHints,
+    options: SolveOptions,
+    abandon: Abandon<'_>,
+    previous: &mut Option<Assembly>,
+) -> crate::Result<(Solved, Vec<HintKey>)> {
+    let SolveOptions {
+        metric,
+        strategy,
+        double_side,
+        anneal_budget,
+        match_budget,
+        ..
+    } = options;
+    let deadline = match_budget.map(|budget| Instant::now() + budget);
+    let rots = options.rots();
+    let context = FillContext {
+        metric,
+        scoring: double_side,
+        rots,
+        abandon,
+    };
+    let mut discarded = vec![];
+    loop {
+        let attempt = hints.clone();
+        // panic したときは盤面を残す前に抜けるので, `previous` が中途半端になることはない
+        let solved = std::panic::catch_unwind(AssertUnwindSafe(|| -> crate::Result<Solved> {
+            let (mut recovered, root_pos, mut log) = match strategy {
+                Strategy::Shaker => {
+                    solve_reusing(fragments.to_vec(), grid, attempt, context, previous)?
+                }
+                Strategy::BordersFirst => {
+                    borders_first::solve(fragments.to_vec(), grid, attempt, context)?
+                }
+                #[cfg(feature = "ga")]
+                Strategy::Genetic => genetic::solve(
+                    fragments.to_vec(),
+                    grid,
+                    attempt,
+                    options,
+                    abandon,
+                    deadline,
+                )?,
+            };
+            let anneal_budget = match (anneal_budget, deadline) {
+                (Some(budget), Some(deadline)) => {
+                    Some(budget.min(deadline.saturating_duration_since(Instant::now())))
+                }
+                (budget, _) => budget,
+            };
+            if let Some(budget) = anneal_budget.filter(|budget| !budget.is_zero()) {
+                let schedule = anneal::Schedule {
+                    abandon,
+                    ..anneal::Schedule::new(budget)
+                };
+                anneal::anneal(&mut recovered, &mut log, &hints, (metric, rots), schedule);
+            }
+            Ok((recovered, root_pos, log))
+        }))
+        .unwrap_or_else(|panic| Err(Error::SolverFailed(panic_message(panic))));
+        if abandon.is_requested() {
+            return match solved {
+                Ok(solved) if !abandon.is_superseded() => Ok((solved, discarded)),
+                Ok(_) => Err(abandon.error()),
+                Err(e) => Err(e),
+            };
+        }
+        let failure = match &solved {
+            Ok((_, _, log)) if log.unapplied_locks.is_empty() => None,
+            Ok((_, _, log)) => Some(format!("unapplied locked pairs {:?}", log.unapplied_locks)),
+            Err(e) => Some(e.to_string()),
+        };
+        let failure = match failure {
+            Some(failure) if !hints.is_empty() => failure,
+            _ => {
+                if !discarded.is_empty() {
+                    let discarded: Vec<_> = discarded.iter().map(HintKey::to_string).collect();
+                    tracing::warn!(?discarded, "discarded hints to recover the image");
+                }
+                return solved.map(|solved| (solved, discarded));
+            }
+        };
+        let dropped = hints.drop_newest().unwrap();
+        tracing::warn!(%failure, hint = %dropped, "retrying without the newest hint");
+        discarded.push(dropped);
+    }
+}
+
+// returns: (recovered_image, root_pos, log)
+// ブロックリストなどのせいで置ける断片が無くなったら Error::NoCandidate を返す
 fn solve(
-    mut fragments: Vec<Fragment>,
+    fragments: Vec<Fragment>,
+    grid: Grid,
+    hints: ResolveHints,
+    (metric, scoring): (Metric, DoubleSideScoring),
+    rots: &'static [Rot],
+) -> crate::Result<Solved> {
+    let context = FillContext::new(metric, scoring, rots);
+    solve_reusing(fragments, grid, hints, context, &mut None)
+}
+
+/// `solve` と同じく復元する. `previous` に同じ断片と設定で前回並べた盤面があれば, 使える所までそれを使う.
+///
+/// 2 辺から埋め終えた盤面を `previous` に残し, 次の再計算で使えるようにする.
+/// 十字に置く途中か 2 辺から埋める途中で `context.abandon` にやめるよう求められたら, 盤面を残さずにやめる.
+/// 見分けのつかない断片を置き直す途中なら, 2 辺から埋め終えた盤面だけを残してやめる.
+#[tracing::instrument(name = "pixel_match", skip_all)]
+fn solve_reusing(
+    fragments: Vec<Fragment>,
     grid: Grid,
     mut hints: ResolveHints,
-) -> (VecOnGrid<Option<Fragment>>, Pos) {
+    context: FillContext<'_>,
+    previous: &mut Option<Assembly>,
+) -> crate::Result<Solved> {
+    let duplicates = duplicates::find_duplicates(&fragments);
+    for cluster in &duplicates {
+        tracing::info!(
+            ?cluster,
+            "found indistinguishable fragments. placing them last"
+        );
+    }
+
+    let base_hints = hints.clone();
+    let resumed = previous
+        .as_ref()
+        .and_then(|previous| previous.resume(&fragments, &hints));
+    let (mut fragment_grid, root_pos, mut log, mut fragments) = match resumed {
+        Some(resumed) => {
+            tracing::info!(remaining = resumed.3.len(), "reusing the previous assembly");
+            resumed
+        }
+        None => fill_cross(fragments, grid, &mut hints, context)?,
+    };
+
+    double_side::fill_by_double_side(
+        root_pos,
+        &mut hints,
+        context,
+        &mut log,
+        &mut fragments,
+        &mut fragment_grid,
+    )?;
+    *previous = Some(Assembly {
+        fragment_grid: fragment_grid.clone(),
+        root_pos,
+        log: log.clone(),
+        hints: base_hints,
+    });
+
+    if !duplicates.is_empty() {
+        duplicates::refill_duplicates(&duplicates, &mut fragment_grid, &hints, context, &mut log)?;
+    }
+
+    for s in &log.suggestions {
+        tracing::info!(
+            pos = ?s.pos,
+            against = ?s.against,
+            runner_up = ?s.runner_up,
+            ratio = s.ratio(),
+            "ambiguous seam. consider adding it to the blocklist"
+        );
+    }
+
+    Ok((fragment_grid, root_pos, log))
+}
+
+/// root から上下左右に 1 辺ずつ断片をつなげて十字に置き, 盤面と root の位置と残りの断片を返す.
+fn fill_cross(
+    fragments: Vec<Fragment>,
+    grid: Grid,
+    hints: &mut ResolveHints,
+    context: FillContext<'_>,
+) -> crate::Result<assembly::Resumed> {
     let mut fragment_grid = VecOnGrid::<Option<Fragment>>::with_default(grid);
+    let mut log = MatchLog::default();
+    let mut fragments = FragmentPool::new(fragments);
 
     // 必ず向きの正しい左上の断片を取得
     let root = fragments
-        .find_and_remove(|x| x.pos == grid.pos(0, 0))
-        .unwrap();
+        .take(grid.pos(0, 0))
+        .ok_or(Error::MissingFragment(grid.pos(0, 0)))?;
+    log.provenances.insert(root.pos, Provenance::Root);
 
     // そこから上下左右に伸ばす形で探索
-    let (up, down) =
-        shaker::shaker_fill(grid.height(), &mut fragments, Dir::North, &root, &mut hints);
-    let (left, right) =
-        shaker::shaker_fill(grid.width(), &mut fragments, Dir::West, &root, &mut hints);
+    let (up, down) = shaker::shaker_fill(
+        grid.height(),
+        &mut fragments,
+        Dir::North,
+        &root,
+        hints,
+        context,
+        &mut log,
+    )?;
+    let (left, right) = shaker::shaker_fill(
+        grid.width(),
+        &mut fragments,
+        Dir::West,
+        &root,
+        hints,
+        context,
+        &mut log,
+    )?;
 
     // root から上下左右に何個断片が有るかわかったので、rootのあるべき座標が分かる
     let root_pos = grid.pos(left.len() as _, up.len() as _);
@@ -111,10 +696,7 @@ fn solve(
     // ------------
     // この 1,2,3,4 で示したスペースをそれぞれ root に近い断片から埋めていく。
     // 2辺わかった状態で探索できるため、精度向上が期待できる。
-
-    double_side::fill_by_double_side(root_pos, &mut hints, &mut fragments, &mut fragment_grid);
-
-    (fragment_grid, root_pos)
+    Ok((fragment_grid, root_pos, log, fragments))
 }
 
 #[inline]
@@ -160,51 +742,437 @@ struct DiffEntry {
     score: f64,
 }
 
-/// f から返される DiffEntry たちから最も最適なものを返す
+/// 最も合う候補と, それとは別の断片で最も合う次点の候補
+#[derive(Debug)]
+struct Candidates {
+    best: DiffEntry,
+    runner_up: Option<DiffEntry>,
+}
+
+/// f から返される DiffEntry たちから最も最適なものと, 別の断片で次点のものを返す. 1 つも無ければ `None` を返す.
 #[inline]
-fn find_with<'a, F, I>(fragments: &'a [Fragment], f: F) -> DiffEntry
+fn find_with<'a, P, F, I>(fragments: P, f: F) -> Option<Candidates>
 where
+    P: IntoIterator<Item = &'a Fragment>,
     F: FnMut(&'a Fragment) -> I,
     I: Iterator<Item = DiffEntry> + 'a,
 {
-    fragments
-        .iter()
-        .flat_map(f)
-        .min_by(|a, b| a.score.partial_cmp(&b.score).unwrap())
-        .expect("there were no fragments")
+    let mut best: Option<DiffEntry> = None;
+    let mut runner_up: Option<DiffEntry> = None;
+    for entry in fragments.into_iter().flat_map(f) {
+        match best {
+            Some(ref b) if entry.score < b.score => {
+                if entry.pos != b.pos {
+                    runner_up = best.take();
+                }
+                best = Some(entry);
+            }
+            Some(ref b) if entry.pos != b.pos => match runner_up {
+                Some(ref r) if r.score <= entry.score => {}
+                _ => runner_up = Some(entry),
+            },
+            Some(_) => {}
+            None => best = Some(entry),
+        }
+    }
+    Some(Candidates {
+        best: best?,
+        runner_up,
+    })
+}
+
+/// 次点の一致度が最良の一致度のこの倍率以内なら, 取り違えている可能性があるとみなす.
+const AMBIGUOUS_RATIO: f64 = 1.1;
+
+/// `BlockSuggestion` は最良の候補と次点の候補の一致度が近く, ブロックリストに入れることを勧める継ぎ目を表す.
+///
+/// `pos` と `against` はそのままブロックリストの組になる.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BlockSuggestion {
+    /// 基準にした断片の問題画像での位置
+    pos: Pos,
+    /// 採用した辺
+    against: EdgePos,
+    /// 次点の辺
+    runner_up: EdgePos,
+    score: f64,
+    runner_up_score: f64,
+}
+
+impl BlockSuggestion {
+    /// 次点が最良の候補に近ければ提案を作る. `edge_of` は候補から対応するブロックリストの辺を求める.
+    fn from_candidates(
+        pos: Pos,
+        candidates: &Candidates,
+        edge_of: impl Fn(&DiffEntry) -> EdgePos,
+    ) -> Option<Self> {
+        let Candidates { best, runner_up } = candidates;
+        let runner_up = runner_up.as_ref()?;
+        if runner_up.score > best.score * AMBIGUOUS_RATIO {
+            return None;
+        }
+        Some(Self {
+            pos,
+            against: edge_of(best),
+            runner_up: edge_of(runner_up),
+            score: best.score,
+            runner_up_score: runner_up.score,
+        })
+    }
+
+    /// 次点の一致度と最良の一致度の比. 1 に近いほど紛らわしい.
+    fn ratio(&self) -> f64 {
+        if self.score == 0. {
+            return if self.runner_up_score == 0. {
+                1.
+            } else {
+                f64::INFINITY
+            };
+        }
+        self.runner_up_score / self.score
+    }
 }
 
-/// reference と challenge 間の色距離の平均を求める
+/// `Provenance` は断片をそのマスに置いた根拠を表す. どの継ぎ目をブロックリストに入れるか決める手がかりにする.
+///
+/// 基準にした辺は, 基準の断片の問題画像での位置と, 回転させた後の向きで表す.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Provenance {
+    /// 左上の断片なので, 比べずに置いた.
+    Root,
+    /// `reference` から続くロックされた組として置いた.
+    Locked { reference: EdgePos },
+    /// `reference` の 1 辺との一致度 `score` で選んだ.
+    SingleSide { reference: EdgePos, score: f64 },
+    /// `references` の 2 辺との一致度 `score` で選んだ.
+    DoubleSide {
+        references: [EdgePos; 2],
+        score: f64,
+    },
+    /// 見分けのつかない断片なので後回しにし, 埋まっていた `neighbors` 個の隣の辺との一致度 `score` で選んだ.
+    Deferred { neighbors: u8, score: f64 },
+    /// 外周から順に並べるときに, 埋まっていた `neighbors` 個の隣の辺との一致度 `score` で選んだ.
+    Surrounded { neighbors: u8, score: f64 },
+    /// 並べた後に, 焼きなましや遺伝的アルゴリズムで継ぎ目の一致度の合計が良くなるように動かした.
+    Refined,
+}
+
+/// `MatchLog` は画像を復元する間に見つけたブロックリストの候補と, 各断片を置いた根拠を集めたもの.
+#[derive(Debug, Default, Clone)]
+struct MatchLog {
+    suggestions: Vec<BlockSuggestion>,
+    /// 断片の問題画像での位置ごとの, その断片を置いた根拠
+    provenances: HashMap<Pos, Provenance>,
+    /// 盤面からはみ出したり断片を取り合ったりして, 守れなかったロックされた組の辺
+    unapplied_locks: Vec<EdgePos>,
+    /// 2 辺から埋めた断片の問題画像での位置を, 埋めた順に並べたもの
+    placed: Vec<Pos>,
+}
+
+impl MatchLog {
+    fn extend(&mut self, other: MatchLog) {
+        self.suggestions.extend(other.suggestions);
+        self.provenances.extend(other.provenances);
+        self.unapplied_locks.extend(other.unapplied_locks);
+        self.placed.extend(other.placed);
+    }
+
+    /// 並べた後に動かしてはいけない断片の, 問題画像での位置を返す.
+    ///
+    /// root の断片と, `hints` のロックされた組の基準の断片と続く断片をすべて含む.
+    fn pinned_fragments(&self, hints: &ResolveHints) -> HashSet<Pos> {
+        self.provenances
+            .iter()
+            .filter(|(_, provenance)| {
+                matches!(provenance, Provenance::Root | Provenance::Locked { .. })
+            })
+            .map(|(&pos, _)| pos)
+            .chain(hints.locked_pairs().flat_map(|(edge, pairs)| {
+                std::iter::once(edge.pos).chain(pairs.tail.iter().map(|&(pos, _)| pos))
+            }))
+            .collect()
+    }
+}
+
+/// `WindowSize` は GUI のウィンドウの大きさを表す. `800x600` のように書く.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl std::fmt::Display for WindowSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl std::str::FromStr for WindowSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parsed = s
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)));
+        match parsed {
+            Some((width, height)) if 0 < width && 0 < height => Ok(Self { width, height }),
+            _ => anyhow::bail!("expected a size like 800x600, but found {}", s),
+        }
+    }
+}
+
+/// `GuiOptions` は GUI のウィンドウの大きさと文字の拡大率を表す.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GuiOptions {
+    /// 開いたときのウィンドウの大きさ. HiDPI の画面では画面の倍率だけ大きく描かれる.
+    pub window_size: WindowSize,
+    /// 文字の拡大率. HiDPI の画面ではさらに画面の倍率を掛ける.
+    pub scale: f64,
+}
+
+impl Default for GuiOptions {
+    fn default() -> Self {
+        Self {
+            window_size: WindowSize {
+                width: 800,
+                height: 800,
+            },
+            scale: 1.0,
+        }
+    }
+}
+
+/// `Metric` は辺同士がどれだけ合っているかを測る色距離の種類を表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Metric {
+    #[default]
+    Euclidean,
+    Manhattan,
+}
+
+impl Metric {
+    #[inline]
+    fn distance(self, a: Color, b: Color) -> f64 {
+        match self {
+            Metric::Euclidean => a.euclidean_distance(b),
+            Metric::Manhattan => a.manhattan_distance(b),
+        }
+    }
+}
+
+impl std::str::FromStr for Metric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "euclidean" => Ok(Metric::Euclidean),
+            "manhattan" => Ok(Metric::Manhattan),
+            _ => anyhow::bail!("expected euclidean or manhattan, but found {}", s),
+        }
+    }
+}
+
+/// `Strategy` は断片を並べる手順を表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// 左上の断片から上下左右に伸ばし, 残りを 2 辺から埋める.
+    #[default]
+    Shaker,
+    /// 外周に来そうな断片で枠を作ってから, 内側を埋める.
+    BordersFirst,
+    /// shaker で並べた配置を種にして, 遺伝的アルゴリズムで配置を探す.
+    #[cfg(feature = "ga")]
+    Genetic,
+}
+
+impl std::str::FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "shaker" => Ok(Strategy::Shaker),
+            "borders-first" => Ok(Strategy::BordersFirst),
+            #[cfg(feature = "ga")]
+            "genetic" => Ok(Strategy::Genetic),
+            #[cfg(not(feature = "ga"))]
+            "genetic" => anyhow::bail!("genetic strategy requires the `ga` feature"),
+            _ => anyhow::bail!("expected shaker, borders-first or genetic, but found {}", s),
+        }
+    }
+}
+
+/// `DoubleSideScoring` は 2 辺から断片を探すときに, 2 つの辺の一致度をまとめる方法を表す.
+///
+/// 2 つの辺を繋げて平均すると, 片方の辺がよく合えばもう片方がまったく合わなくても選ばれてしまうので, 辺ごとに測ってからまとめる.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DoubleSideScoring {
+    /// 悪い方の辺の一致度を使う.
+    #[default]
+    Max,
+    /// 悪い方の辺の一致度にこの重みを, 良い方に 1 から重みを引いた残りを掛けて足す. 重みが 0.5 なら 2 つの辺を繋げて平均するのと同じ.
+    WeightedSum(f64),
+}
+
+impl DoubleSideScoring {
+    fn combine(self, a: f64, b: f64) -> f64 {
+        match self {
+            DoubleSideScoring::Max => a.max(b),
+            DoubleSideScoring::WeightedSum(worse) => worse * a.max(b) + (1.0 - worse) * a.min(b),
+        }
+    }
+}
+
+impl std::str::FromStr for DoubleSideScoring {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "max" => Ok(DoubleSideScoring::Max),
+            _ => match s.parse::<f64>() {
+                Ok(worse) if (0.0..=1.0).contains(&worse) => {
+                    Ok(DoubleSideScoring::WeightedSum(worse))
+                }
+                _ => anyhow::bail!(
+                    "expected max or a weight of the worse edge from 0 to 1, but found {}",
+                    s
+                ),
+            },
+        }
+    }
+}
+
+/// 辺の色のばらつきがこれより小さければ平坦な辺とみなす
+const CONTRAST_FLOOR: f64 = 8.0;
+/// 平坦な辺どうしの色距離の平均を大きくする倍率の上限
+const MAX_FLAT_PENALTY: f64 = 8.0;
+
+/// reference と challenge 間の色距離の平均を, 2 つの辺の色のばらつきで正規化して求める
+///
+/// 平坦な辺どうしは取り違えていても色距離が小さくなり, 模様のある辺の正しい組より良く見えてしまう.
+/// そこで色のばらつき (平均の色からの距離の二乗平均平方根) が大きい方の辺でも `CONTRAST_FLOOR` に満たなければ,
+/// 足りない割合だけ色距離を大きくする. 模様のある辺の色距離は変えない.
 #[inline]
 fn average_distance<'a>(
+    metric: Metric,
     reference: impl Iterator<Item = &'a Color>,
     challenge: impl Iterator<Item = &'a Color>,
 ) -> f64 {
     let mut count = 0;
     let mut sum_of_distance: f64 = 0.;
+    let (mut reference_spread, mut challenge_spread) = (Spread::default(), Spread::default());
 
     for (r, c) in reference.zip(challenge) {
-        let distance = r.euclidean_distance(*c);
+        let distance = metric.distance(*r, *c);
         sum_of_distance += distance;
         count += 1;
+        reference_spread.push(*r);
+        challenge_spread.push(*c);
     }
 
-    sum_of_distance / count as f64
+    let contrast = reference_spread.contrast().max(challenge_spread.contrast());
+    let penalty = (CONTRAST_FLOOR / contrast).clamp(1.0, MAX_FLAT_PENALTY);
+    sum_of_distance / count as f64 * penalty
+}
+
+/// `Spread` は辺の色のばらつきを求めるために, 色の各成分の和と二乗和を数える.
+#[derive(Debug, Default)]
+struct Spread {
+    count: usize,
+    sum: [f64; 3],
+    square_sum: [f64; 3],
+}
+
+impl Spread {
+    #[inline]
+    fn push(&mut self, color: Color) {
+        self.count += 1;
+        for (i, v) in [color.r, color.g, color.b].into_iter().enumerate() {
+            self.sum[i] += v as f64;
+            self.square_sum[i] += (v as f64).powi(2);
+        }
+    }
+
+    /// 平均の色からの距離の二乗平均平方根.
+    fn contrast(&self) -> f64 {
+        let n = self.count as f64;
+        (0..3)
+            .map(|i| (self.square_sum[i] / n - (self.sum[i] / n).powi(2)).max(0.0))
+            .sum::<f64>()
+            .sqrt()
+    }
 }
 
+/// `soft_blocklist` のうち `pos` の断片の `dir` の辺に当てはまる倍率をすべて掛け合わせる
+#[inline]
+fn soft_penalty<'a>(
+    soft_blocklist: impl Iterator<Item = &'a (EdgePos, f64)>,
+    pos: Pos,
+    dir: Dir,
+) -> f64 {
+    soft_blocklist
+        .filter(|(e, _)| e.pos == pos && e.dir == dir)
+        .map(|&(_, penalty)| penalty)
+        .product()
+}
+
+/// ソフトなブロックリストに入れた組の一致度に掛ける倍率の既定値.
+pub const DEFAULT_SOFT_PENALTY: f64 = 1.5;
+
+/// `EdgePos` は問題画像での断片の位置 `pos` と, 回転させる前のその断片の辺の向き `dir` で辺を表す.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EdgePos {
+    pub pos: Pos,
+    pub dir: Dir,
+}
+
+impl EdgePos {
+    #[inline]
+    pub fn new(pos: Pos, dir: Dir) -> Self {
+        Self { pos, dir }
+    }
+}
+
+/// `ResolveHints` は画像を復元するときに従わせる, ブロックリストやロックされた組などのヒントを表す.
+///
+/// GUI やヒントファイルから作るほか, `ResolveHints::builder` で組み立てられる.
 #[derive(Debug, Default, Clone)]
-struct ResolveHints {
+pub struct ResolveHints {
+    /// 候補を探すたびに引くので, 断片の位置ごとにまとめておく. 空になった位置は取り除く.
     blocklist: HashMap<Pos, Vec<EdgePos>>,
+    /// 除外はせず, 一致度に倍率を掛けて選ばれにくくするだけの組. 自信が無いときに使う.
+    /// 倍率を掛けるだけなので, 完全に一致している (一致度が 0 の) 組には効かない.
+    soft_blocklist: HashMap<Pos, Vec<(EdgePos, f64)>>,
     locked_pairs: HashMap<EdgePos, LockedPairs>,
+    /// 加えた順のヒント. 復元できないときは新しいものから捨てる.
+    order: Vec<HintKey>,
+}
+
+/// `HintKey` は `ResolveHints` の中の 1 つのヒントを指す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HintKey {
+    Blocklist(Pos, EdgePos),
+    SoftBlocklist(Pos, EdgePos),
+    LockedPairs(EdgePos),
 }
 
+/// `LockedPairs` は 1 つの辺から順につなげる断片と, そのときの回転の列を表す.
 #[derive(Debug, Clone)]
-struct LockedPairs {
+pub struct LockedPairs {
     tail: Vec<(Pos, Rot)>,
     continue_after_apply: bool,
 }
 
 impl LockedPairs {
+    /// つなげる断片の問題画像での位置と回転. 先頭の断片から順に並ぶ.
+    pub fn tail(&self) -> &[(Pos, Rot)] {
+        &self.tail
+    }
+
+    /// 組をつなげ終えた後も, その先を同じ向きに普通に探して並べ続けるか.
+    pub fn continues_after_apply(&self) -> bool {
+        self.continue_after_apply
+    }
+
     fn new(tail: Vec<(Pos, Rot)>) -> Self {
         Self {
             tail,
@@ -220,34 +1188,131 @@ impl LockedPairs {
 impl ResolveHints {
     fn push_blocklist(&mut self, pos: Pos, against: EdgePos) {
         self.blocklist.entry(pos).or_default().push(against);
+        self.order.push(HintKey::Blocklist(pos, against));
+    }
+
+    fn push_soft_blocklist(&mut self, pos: Pos, against: EdgePos, penalty: f64) {
+        self.soft_blocklist
+            .entry(pos)
+            .or_default()
+            .push((against, penalty));
+        self.order.push(HintKey::SoftBlocklist(pos, against));
     }
 
     fn push_locked_pair(&mut self, pos: EdgePos, pairs: LockedPairs) {
-        self.locked_pairs.insert(pos, pairs);
+        // 同じ辺の組は置き換えるので, 加えた順でも新しいほうだけを残す
+        if self.locked_pairs.insert(pos, pairs).is_some() {
+            self.order
+                .find_and_remove(|&k| k == HintKey::LockedPairs(pos));
+        }
+        self.order.push(HintKey::LockedPairs(pos));
     }
 
-    fn remove_blocklist(&mut self, pos: Pos, against: EdgePos) {
-        self.blocklist
-            .entry(pos)
-            .or_default()
-            .find_and_remove(|&x| x == against);
+    /// `key` の指すヒントを 1 つ取り除く.
+    fn remove(&mut self, key: HintKey) {
+        self.order.find_and_remove(|&k| k == key);
+        match key {
+            HintKey::Blocklist(pos, against) => {
+                if let Some(edges) = self.blocklist.get_mut(&pos) {
+                    edges.find_and_remove(|&x| x == against);
+                    if edges.is_empty() {
+                        self.blocklist.remove(&pos);
+                    }
+                }
+            }
+            HintKey::SoftBlocklist(pos, against) => {
+                if let Some(edges) = self.soft_blocklist.get_mut(&pos) {
+                    edges.find_and_remove(|&(x, _)| x == against);
+                    if edges.is_empty() {
+                        self.soft_blocklist.remove(&pos);
+                    }
+                }
+            }
+            HintKey::LockedPairs(pos) => {
+                self.locked_pairs.remove(&pos);
+            }
+        }
     }
 
-    fn remove_locked_pair(&mut self, pos: EdgePos) {
-        self.locked_pairs.remove(&pos);
+    /// 最後に加えたヒントを取り除き, それを指すものを返す.
+    fn drop_newest(&mut self) -> Option<HintKey> {
+        let key = *self.order.last()?;
+        self.remove(key);
+        Some(key)
     }
 
-    fn lock_pair_as_end(&mut self, pos: EdgePos) {
+    /// 辺 `pos` から続くロックされた組を, つなげた後はその先を普通に探すようにする.
+    fn stop_locked_pairs_after_apply(&mut self, pos: EdgePos) {
         if let Some(r) = self.locked_pairs.get_mut(&pos) {
-            r.continue_after_apply = false;
+            r.stop_after_apply();
+        }
+    }
+
+    /// `self` が `base` の後にブロックリストだけを加えたものなら, 加えた組を返す.
+    fn added_blocklist_since(&self, base: &ResolveHints) -> Option<Vec<(Pos, EdgePos)>> {
+        if !self.order.starts_with(&base.order)
+            || self.soft_blocklist != base.soft_blocklist
+            || !self.locked_pairs.is_empty()
+        {
+            return None;
         }
+        self.order[base.order.len()..]
+            .iter()
+            .map(|&key| match key {
+                HintKey::Blocklist(pos, against) => Some((pos, against)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// ヒントが 1 つも無いか.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
     }
 
-    fn take_blacklist(&self, pos: Pos) -> impl Iterator<Item = &EdgePos> + Clone {
+    /// すべてのブロックリストを, 断片の位置と置かないようにした辺の組で返す.
+    pub fn blocklist(&self) -> impl Iterator<Item = (Pos, EdgePos)> + '_ {
+        self.blocklist
+            .iter()
+            .flat_map(|(&pos, edges)| edges.iter().map(move |&edge| (pos, edge)))
+    }
+
+    /// `pos` の断片の隣に置かないようにした辺.
+    pub fn blocklist_of(&self, pos: Pos) -> impl Iterator<Item = &EdgePos> + Clone {
         self.blocklist.get(&pos).into_iter().flatten()
     }
 
-    fn take_locked_pairs(&mut self, pos: EdgePos) -> Option<LockedPairs> {
+    /// `pos` の断片の隣に辺 `against` を置かないようにしているか.
+    pub fn is_blocked(&self, pos: Pos, against: EdgePos) -> bool {
+        self.blocklist_of(pos).any(|&e| e == against)
+    }
+
+    /// すべてのソフトなブロックリストを, 断片の位置と辺と倍率の組で返す.
+    pub fn soft_blocklist(&self) -> impl Iterator<Item = (Pos, EdgePos, f64)> + '_ {
+        self.soft_blocklist.iter().flat_map(|(&pos, edges)| {
+            edges
+                .iter()
+                .map(move |&(edge, penalty)| (pos, edge, penalty))
+        })
+    }
+
+    /// `pos` の断片の隣に置くと倍率を掛ける辺と, その倍率.
+    pub fn soft_blocklist_of(&self, pos: Pos) -> impl Iterator<Item = &(EdgePos, f64)> + Clone {
+        self.soft_blocklist.get(&pos).into_iter().flatten()
+    }
+
+    /// すべてのロックされた組を, それが続く辺と一緒に返す.
+    pub fn locked_pairs(&self) -> impl Iterator<Item = (&EdgePos, &LockedPairs)> {
+        self.locked_pairs.iter()
+    }
+
+    /// 辺 `pos` から続くロックされた組.
+    pub fn locked_pairs_of(&self, pos: EdgePos) -> Option<&LockedPairs> {
+        self.locked_pairs.get(&pos)
+    }
+
+    /// 辺 `pos` から続くロックされた組を取り出す. 復元中に同じ組を二度つなげないよう, つなげるときに使う.
+    fn consume_locked_pairs(&mut self, pos: EdgePos) -> Option<LockedPairs> {
         self.locked_pairs.remove(&pos)
     }
 }
@@ -261,3 +1326,437 @@ impl<T> FindAndRemove<T> for Vec<T> {
         Some(self.remove(self.iter().position(pred)?))
     }
 }
+
+#[test]
+fn test_resolve_headless_errors() {
+    let problem = include_bytes!("../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let mut fragments = Fragment::new_all(&problem).unwrap();
+    assert!(resolve_headless(fragments.clone(), grid).is_ok());
+
+    fragments.retain(|f| f.pos != grid.pos(0, 0));
+    assert!(matches!(
+        resolve_headless(fragments.clone(), grid),
+        Err(Error::FragmentCount {
+            expected: 4,
+            found: 3
+        })
+    ));
+    fragments.push(fragments[0].clone());
+    assert!(matches!(
+        resolve_headless(fragments, grid),
+        Err(Error::MissingFragment(pos)) if pos == grid.pos(0, 0)
+    ));
+}
+
+#[test]
+fn test_average_distance_contrast() {
+    let gray = |v: u8| Color { r: v, g: v, b: v };
+    let distance =
+        |a: &[Color], b: &[Color]| average_distance(Metric::Euclidean, a.iter(), b.iter());
+
+    // 平坦で暗い辺どうしは色距離が小さくても, 模様のある辺の組より悪くなる
+    let flat: Vec<_> = (0..8).map(|_| gray(10)).collect();
+    let flat_other: Vec<_> = (0..8).map(|_| gray(12)).collect();
+    let textured: Vec<_> = (0..8)
+        .map(|i| gray(if i % 2 == 0 { 40 } else { 160 }))
+        .collect();
+    let textured_other: Vec<_> = textured.iter().map(|c| gray(c.r + 4)).collect();
+
+    let raw = |a: &[Color], b: &[Color]| {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| a.euclidean_distance(*b))
+            .sum::<f64>()
+            / a.len() as f64
+    };
+    assert!(raw(&flat, &flat_other) < raw(&textured, &textured_other));
+    assert!(distance(&textured, &textured_other) < distance(&flat, &flat_other));
+
+    // 模様のある辺の色距離は変えず, どちらを reference にしても同じ
+    assert_eq!(
+        raw(&textured, &textured_other),
+        distance(&textured, &textured_other)
+    );
+    assert_eq!(distance(&flat, &flat_other), distance(&flat_other, &flat));
+}
+
+#[test]
+fn test_find_with_runner_up() {
+    let problem = include_bytes!("../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+
+    let find = |runner_up_score: f64| {
+        find_with(&fragments, |fragment| {
+            let scores = match (fragment.pos.x(), fragment.pos.y()) {
+                (0, 0) => vec![1.0, 1.05],
+                (1, 0) => vec![runner_up_score, 3.0],
+                _ => vec![5.0],
+            };
+            scores.into_iter().map(move |score| DiffEntry {
+                pos: fragment.pos,
+                dir: Dir::North,
+                score,
+            })
+        })
+        .unwrap()
+    };
+    let edge_of = |d: &DiffEntry| EdgePos::new(d.pos, d.dir);
+
+    // 次点は同じ断片の 1.05 ではなく別の断片の中で最も良いもの
+    let candidates = find(1.08);
+    assert_eq!(grid.pos(0, 0), candidates.best.pos);
+    let runner_up = candidates.runner_up.as_ref().unwrap();
+    assert_eq!(grid.pos(1, 0), runner_up.pos);
+    assert_eq!(1.08, runner_up.score);
+
+    let suggestion =
+        BlockSuggestion::from_candidates(grid.pos(1, 1), &candidates, edge_of).unwrap();
+    assert_eq!(grid.pos(1, 1), suggestion.pos);
+    assert_eq!(EdgePos::new(grid.pos(0, 0), Dir::North), suggestion.against);
+    assert_eq!(
+        EdgePos::new(grid.pos(1, 0), Dir::North),
+        suggestion.runner_up
+    );
+    assert!((suggestion.ratio() - 1.08).abs() < 1e-9);
+
+    let candidates = find(2.0);
+    assert!(BlockSuggestion::from_candidates(grid.pos(1, 1), &candidates, edge_of).is_none());
+
+    assert!(find_with(&fragments, |_| std::iter::empty()).is_none());
+}
+
+#[test]
+fn test_soft_blocklist() {
+    let problem = include_bytes!("../test_cases/03.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+    let solve_with = |hints| {
+        solve(
+            fragments.clone(),
+            grid,
+            hints,
+            (Metric::Euclidean, DoubleSideScoring::default()),
+            &ROTS,
+        )
+        .unwrap()
+        .0
+    };
+
+    let (recovered, root_pos, _) = solve(
+        fragments.clone(),
+        grid,
+        ResolveHints::default(),
+        (Metric::Euclidean, DoubleSideScoring::default()),
+        &ROTS,
+    )
+    .unwrap();
+    let neighbor_x = if root_pos.x() + 1 < grid.width() {
+        root_pos.x() + 1
+    } else {
+        root_pos.x() - 1
+    };
+    let neighbor_pos = grid.pos(neighbor_x, root_pos.y());
+    let neighbor = recovered[neighbor_pos].as_ref().unwrap();
+    // 回転させた後に root を向いている辺の, 元の向き
+    let facing_root = if neighbor_pos.x() < root_pos.x() {
+        Dir::East
+    } else {
+        Dir::West
+    };
+    let facing_root = facing_root.rotate(neighbor.rot.inverse());
+    let against = EdgePos::new(neighbor.pos, facing_root);
+
+    // 倍率が 1 なら何も変わらない
+    let mut hints = ResolveHints::default();
+    hints.push_soft_blocklist(grid.pos(0, 0), against, 1.0);
+    let result = solve_with(hints);
+    assert_eq!(neighbor.pos, result[neighbor_pos].as_ref().unwrap().pos);
+    assert_eq!(neighbor.rot, result[neighbor_pos].as_ref().unwrap().rot);
+
+    // 十分に大きければブロックリストと同じく別の候補が選ばれる
+    let mut hints = ResolveHints::default();
+    hints.push_soft_blocklist(grid.pos(0, 0), against, 1e9);
+    let result = solve_with(hints);
+    let placed = result
+        .iter_with_pos()
+        .find(|(_, f)| f.as_ref().unwrap().pos == neighbor.pos)
+        .map(|(pos, f)| (pos, f.as_ref().unwrap().rot))
+        .unwrap();
+    assert_ne!((neighbor_pos, neighbor.rot), placed);
+}
+
+#[test]
+fn test_provenances() {
+    let problem = include_bytes!("../test_cases/03.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let (recovered, root_pos, log) = solve(
+        Fragment::new_all(&problem).unwrap(),
+        grid,
+        ResolveHints::default(),
+        (Metric::Euclidean, DoubleSideScoring::default()),
+        &ROTS,
+    )
+    .unwrap();
+
+    // すべての断片に根拠があり, root の行と列は 1 辺, それ以外は 2 辺から選ばれる
+    assert_eq!(
+        grid.width() as usize * grid.height() as usize,
+        log.provenances.len()
+    );
+    for (pos, fragment) in recovered.iter_with_pos() {
+        let provenance = log.provenances[&fragment.as_ref().unwrap().pos];
+        match provenance {
+            Provenance::Root => assert_eq!(root_pos, pos),
+            Provenance::SingleSide { score, .. } => {
+                assert!(pos != root_pos && (pos.x() == root_pos.x() || pos.y() == root_pos.y()));
+                assert!(0.0 <= score);
+            }
+            Provenance::DoubleSide { score, .. } => {
+                assert!(pos.x() != root_pos.x() && pos.y() != root_pos.y());
+                assert!(0.0 <= score);
+            }
+            Provenance::Locked { .. } => panic!("no pairs were locked"),
+            Provenance::Deferred { .. } => panic!("no fragments are indistinguishable"),
+            Provenance::Surrounded { .. } => panic!("the shaker strategy was used"),
+            Provenance::Refined => panic!("no refinement was enabled"),
+        }
+    }
+
+    // root の隣の断片は, root の断片のその断片を向いた辺を基準に選ばれる
+    let (neighbor_pos, dir) = if root_pos.y() + 1 < grid.height() {
+        (grid.pos(root_pos.x(), root_pos.y() + 1), Dir::South)
+    } else {
+        (grid.pos(root_pos.x(), root_pos.y() - 1), Dir::North)
+    };
+    let neighbor = recovered[neighbor_pos].as_ref().unwrap().pos;
+    assert!(matches!(
+        log.provenances[&neighbor],
+        Provenance::SingleSide { reference, .. } if reference == EdgePos::new(grid.pos(0, 0), dir)
+    ));
+}
+
+#[test]
+fn test_window_size() {
+    let size: WindowSize = "1200x900".parse().unwrap();
+    assert_eq!(
+        WindowSize {
+            width: 1200,
+            height: 900
+        },
+        size
+    );
+    assert_eq!("1200x900", size.to_string());
+    assert!("1200".parse::<WindowSize>().is_err());
+    assert!("0x900".parse::<WindowSize>().is_err());
+    assert!("ax900".parse::<WindowSize>().is_err());
+}
+
+#[test]
+fn test_panic_message() {
+    let panic = std::panic::catch_unwind(|| panic!("fragment {} is missing", 3)).unwrap_err();
+    assert_eq!("fragment 3 is missing", panic_message(panic));
+    let panic = std::panic::catch_unwind(|| panic!("no root")).unwrap_err();
+    assert_eq!("no root", panic_message(panic));
+}
+
+#[test]
+fn test_solve_relaxing() {
+    let problem = include_bytes!("../test_cases/03.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+    let arranged = |solved: &Solved| -> Vec<_> {
+        solved
+            .0
+            .iter()
+            .map(|f| f.as_ref().map(|f| (f.pos, f.rot)))
+            .collect()
+    };
+
+    let mut hints = ResolveHints::default();
+    hints.push_blocklist(grid.pos(1, 0), EdgePos::new(grid.pos(2, 0), Dir::North));
+    let expected = solve(
+        fragments.clone(),
+        grid,
+        hints.clone(),
+        (Metric::Euclidean, DoubleSideScoring::default()),
+        &ROTS,
+    )
+    .unwrap();
+
+    // root の上に盤面の高さ以上の断片をつなげた組は置けないので, その組だけを捨てて復元する
+    let root_north = EdgePos::new(grid.pos(0, 0), Dir::North);
+    let tail = (0..grid.height())
+        .map(|y| (grid.pos(1, y), Rot::R0))
+        .collect();
+    hints.push_locked_pair(root_north, LockedPairs::new(tail));
+    let (solved, discarded) = solve_relaxing(
+        &fragments,
+        grid,
+        hints,
+        SolveOptions::default(),
+        Abandon::default(),
+        &mut None,
+    )
+    .unwrap();
+    assert_eq!(vec![HintKey::LockedPairs(root_north)], discarded);
+    assert!(solved.2.unapplied_locks.is_empty());
+    assert_eq!(arranged(&expected), arranged(&solved));
+}
+
+#[test]
+fn test_no_candidate() {
+    let problem = crate::scramble::smooth_problem(3, 2);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+
+    // root の隣にはどの断片のどの辺もつなげられない
+    let root = grid.pos(0, 0);
+    let mut hints = ResolveHints::default();
+    for pos in grid.all_pos().filter(|&pos| pos != root) {
+        for dir in [Dir::North, Dir::East, Dir::South, Dir::West] {
+            hints.push_blocklist(root, EdgePos::new(pos, dir));
+        }
+    }
+    let solved = solve(
+        fragments.clone(),
+        grid,
+        hints.clone(),
+        (Metric::Euclidean, DoubleSideScoring::default()),
+        &ROTS,
+    );
+    assert!(matches!(solved, Err(Error::NoCandidate(pos)) if pos == root));
+
+    // panic せずに, ブロックリストを捨てて復元し直す
+    let (solved, discarded) = solve_relaxing(
+        &fragments,
+        grid,
+        hints,
+        SolveOptions::default(),
+        Abandon::default(),
+        &mut None,
+    )
+    .unwrap();
+    assert!(!discarded.is_empty());
+    assert!(into_filled(solved.0).is_ok());
+}
+
+#[test]
+fn test_abandon() {
+    let problem = crate::scramble::smooth_problem(3, 2);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+
+    let latest = AtomicU64::new(1);
+    let solve_as = |id, anneal_budget| {
+        let options = SolveOptions {
+            anneal_budget,
+            ..SolveOptions::default()
+        };
+        solve_relaxing(
+            &fragments,
+            grid,
+            ResolveHints::default(),
+            options,
+            Abandon::unless_latest(&latest, id),
+            &mut None,
+        )
+    };
+    assert!(solve_as(1, None).is_ok());
+    // より新しい要求が届いていれば, 焼きなましの時間を使い切らずにやめる
+    let start = std::time::Instant::now();
+    let abandoned = solve_as(0, Some(Duration::from_secs(60)));
+    assert!(matches!(abandoned, Err(Error::Abandoned)));
+    assert!(start.elapsed() < Duration::from_secs(60));
+
+    // 呼び出し元にやめさせられたら, 並べる前ならエラーを返し, 焼きなましの途中ならそれまでの配置を返す
+    let mut resolver = Resolver::new(fragments, grid);
+    let cancel = CancellationToken::new();
+    cancel.cancel();
+    resolver.set_cancellation_token(cancel);
+    assert!(matches!(
+        resolver.resolve_without_gui(),
+        Err(Error::Cancelled)
+    ));
+    resolver.set_anneal_budget(Some(Duration::from_secs(60)));
+    resolver
+        .set_cancellation_token(CancellationToken::new().with_timeout(Duration::from_millis(500)));
+    let start = std::time::Instant::now();
+    assert!(resolver.resolve_without_gui().is_ok());
+    assert!(start.elapsed() < Duration::from_secs(60));
+}
+
+#[test]
+fn test_match_budget() {
+    let problem = crate::scramble::smooth_problem(3, 2);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+
+    // 焼きなましは並べた残りの時間だけ行う
+    let mut resolver = Resolver::new(fragments, grid);
+    resolver.set_anneal_budget(Some(Duration::from_secs(60)));
+    resolver.set_match_budget(Some(Duration::from_millis(200)));
+    let start = std::time::Instant::now();
+    assert!(resolver.resolve_without_gui().is_ok());
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn test_fixed_rotation() {
+    let problem = crate::scramble::smooth_problem(4, 3);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+    for strategy in [Strategy::Shaker, Strategy::BordersFirst] {
+        let options = SolveOptions {
+            strategy,
+            anneal_budget: Some(Duration::from_millis(100)),
+            fixed_rotation: true,
+            ..SolveOptions::default()
+        };
+        let ((recovered, _, _), _) = solve_relaxing(
+            &fragments,
+            grid,
+            ResolveHints::default(),
+            options,
+            Abandon::default(),
+            &mut None,
+        )
+        .unwrap();
+        for (pos, cell) in recovered.iter_with_pos() {
+            let fragment = cell.as_ref().unwrap();
+            assert_eq!(
+                (pos, Rot::R0),
+                (fragment.pos, fragment.rot),
+                "{:?}",
+                strategy
+            );
+        }
+    }
+}
+
+#[test]
+fn test_remove_hints_clears_index() {
+    let grid = Grid::new(3, 2);
+    let against = EdgePos::new(grid.pos(2, 0), Dir::North);
+    let mut hints = ResolveHints::default();
+    hints.push_blocklist(grid.pos(1, 0), against);
+    hints.push_soft_blocklist(grid.pos(1, 0), against, 2.0);
+    hints.push_blocklist(grid.pos(1, 0), EdgePos::new(grid.pos(0, 1), Dir::East));
+    assert!(hints.is_blocked(grid.pos(1, 0), against));
+
+    hints.remove(HintKey::Blocklist(grid.pos(1, 0), against));
+    assert!(!hints.is_blocked(grid.pos(1, 0), against));
+    assert_eq!(1, hints.blocklist_of(grid.pos(1, 0)).count());
+
+    // 新しいものから取り除き, すべて無くなれば位置ごとの索引も残らない
+    assert!(hints.drop_newest().is_some());
+    assert!(hints.drop_newest().is_some());
+    assert!(hints.is_empty());
+    assert!(hints.blocklist.is_empty() && hints.soft_blocklist.is_empty());
+}
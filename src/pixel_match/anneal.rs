@@ -0,0 +1,357 @@
+//! 貪欲に並べた復元画像の断片を入れ替えたり回転させたりして, 継ぎ目の一致度の合計を焼きなましで小さくする.
+//!
+//! 貪欲法は一度置いた断片を動かさないので, 序盤に取り違えるとそれに合わせて後の断片も取り違える. その局所解から抜け出すために使う.
+
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{
+    average_distance, duplicates::neighbor, soft_penalty, Abandon, EdgePos, MatchLog, Metric,
+    Provenance, ResolveHints,
+};
+use crate::{
+    basis::{Dir, Rot},
+    fragment::Fragment,
+    grid::{Pos, VecOnGrid},
+};
+
+const DIRS: [Dir; 4] = [Dir::North, Dir::East, Dir::South, Dir::West];
+
+/// 最初の温度を, 継ぎ目の一致度の平均のこの倍にする
+const INITIAL_TEMPERATURE: f64 = 0.3;
+/// 最後の温度を, 最初の温度のこの倍にする
+const FINAL_TEMPERATURE: f64 = 1e-3;
+
+/// `Schedule` は焼きなましを打ち切るまでの時間と試行回数, 乱数の種を表す.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Schedule<'a> {
+    pub(super) budget: Duration,
+    pub(super) max_iterations: usize,
+    pub(super) seed: u64,
+    /// やめるよう求められたら, 時間が残っていても打ち切る
+    pub(super) abandon: Abandon<'a>,
+}
+
+impl Schedule<'_> {
+    pub(super) fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            max_iterations: usize::MAX,
+            seed: 0,
+            abandon: Abandon::default(),
+        }
+    }
+}
+
+/// 断片の `dir` を向く辺の, 回転させる前の向き. ブロックリストはこの向きで持っている.
+fn original_dir(fragment: &Fragment, dir: Dir) -> Dir {
+    dir.rotate(fragment.rot.inverse())
+}
+
+/// `a` の `dir` 向きの隣に `b` を置いたときの継ぎ目の一致度. ブロックリストに入った組なら `None` を返す.
+fn seam(a: &Fragment, b: &Fragment, dir: Dir, hints: &ResolveHints, metric: Metric) -> Option<f64> {
+    let (a_dir, b_dir) = (original_dir(a, dir), original_dir(b, dir.opposite()));
+    let blocked = hints.is_blocked(a.pos, EdgePos::new(b.pos, b_dir))
+        || hints.is_blocked(b.pos, EdgePos::new(a.pos, a_dir));
+    if blocked {
+        return None;
+    }
+    Some(
+        average_distance(
+            metric,
+            a.edges.edge(dir).pixels.iter(),
+            b.edges.edge(dir.opposite()).pixels.iter().rev(),
+        ) * soft_penalty(hints.soft_blocklist_of(a.pos), b.pos, b_dir)
+            * soft_penalty(hints.soft_blocklist_of(b.pos), a.pos, a_dir),
+    )
+}
+
+/// `cells` のいずれかに接する継ぎ目の一致度の合計. ブロックリストに入った組が隣り合えば `None` を返す.
+fn local_energy(
+    fragment_grid: &VecOnGrid<Option<Fragment>>,
+    cells: &[Pos],
+    hints: &ResolveHints,
+    metric: Metric,
+) -> Option<f64> {
+    let grid = fragment_grid.grid;
+    let mut energy = 0.0;
+    for (i, &pos) in cells.iter().enumerate() {
+        let a = fragment_grid[pos].as_ref()?;
+        for dir in DIRS {
+            let other = match neighbor(grid, pos, dir) {
+                Some(other) => other,
+                None => continue,
+            };
+            // 2 つのマスの間の継ぎ目を二重に数えない
+            if cells[..i].contains(&other) {
+                continue;
+            }
+            if let Some(b) = &fragment_grid[other] {
+                energy += seam(a, b, dir, hints, metric)?;
+            }
+        }
+    }
+    Some(energy)
+}
+
+/// `fragment_grid` のすべての継ぎ目の一致度の合計.
+fn total_energy(
+    fragment_grid: &VecOnGrid<Option<Fragment>>,
+    hints: &ResolveHints,
+    metric: Metric,
+) -> Option<f64> {
+    let cells: Vec<_> = fragment_grid.grid.all_pos().collect();
+    local_energy(fragment_grid, &cells, hints, metric)
+}
+
+/// 断片を 1 つ回転させるか, 2 つ入れ替える操作.
+#[derive(Debug, Clone, Copy)]
+enum Move {
+    Rotate(Pos, Rot),
+    Swap(Pos, Pos),
+}
+
+impl Move {
+    fn apply(self, fragment_grid: &mut VecOnGrid<Option<Fragment>>) {
+        match self {
+            Move::Rotate(pos, rot) => fragment_grid[pos].as_mut().unwrap().rotate(rot),
+            Move::Swap(a, b) => {
+                let fragment = fragment_grid[a].take();
+                fragment_grid[a] = std::mem::replace(&mut fragment_grid[b], fragment);
+            }
+        }
+    }
+
+    fn undo(self, fragment_grid: &mut VecOnGrid<Option<Fragment>>) {
+        match self {
+            Move::Rotate(pos, rot) => Move::Rotate(pos, rot.inverse()),
+            Move::Swap(..) => self,
+        }
+        .apply(fragment_grid)
+    }
+
+    fn cells(self) -> Vec<Pos> {
+        match self {
+            Move::Rotate(pos, _) => vec![pos],
+            Move::Swap(a, b) => vec![a, b],
+        }
+    }
+}
+
+/// root の断片とロックされた組の両端の断片を除いたマスの断片を, 焼きなましで入れ替えたり回転させたりする.
+///
+/// 動かした断片の根拠は `Provenance::Refined` にする. 継ぎ目の一致度の合計が最も良かった配置を残す.
+/// `rots` に `Rot::R0` しか無ければ, 断片を回転させずに入れ替えるだけにする.
+pub(super) fn anneal(
+    fragment_grid: &mut VecOnGrid<Option<Fragment>>,
+    log: &mut MatchLog,
+    hints: &ResolveHints,
+    (metric, rots): (Metric, &[Rot]),
+    schedule: Schedule<'_>,
+) {
+    let grid = fragment_grid.grid;
+    let pinned = log.pinned_fragments(hints);
+    let movable: Vec<_> = grid
+        .all_pos()
+        .filter(|&pos| match &fragment_grid[pos] {
+            Some(fragment) => !pinned.contains(&fragment.pos),
+            None => false,
+        })
+        .collect();
+    let rotatable = 1 < rots.len();
+    // 回転させないなら, 入れ替えるために動かせる断片が 2 つ要る
+    let min_movable = if rotatable { 1 } else { 2 };
+    let mut energy = match total_energy(fragment_grid, hints, metric) {
+        Some(energy) if min_movable <= movable.len() => energy,
+        _ => return,
+    };
+    let initial = fragment_grid.clone();
+    let initial_energy = energy;
+    let (mut best, mut best_energy) = (fragment_grid.clone(), energy);
+
+    let seams = grid.width() as usize * (grid.height() as usize - 1)
+        + (grid.width() as usize - 1) * grid.height() as usize;
+    let initial_temperature = INITIAL_TEMPERATURE * energy / seams.max(1) as f64;
+    let mut rng = StdRng::seed_from_u64(schedule.seed);
+    let start = Instant::now();
+    let mut iterations = 0;
+    while iterations < schedule.max_iterations {
+        let progress = (start.elapsed().as_secs_f64() / schedule.budget.as_secs_f64())
+            .max(iterations as f64 / schedule.max_iterations as f64);
+        if 1.0 <= progress || schedule.abandon.is_requested() {
+            break;
+        }
+        iterations += 1;
+        let temperature = initial_temperature * FINAL_TEMPERATURE.powf(progress);
+
+        let a = movable[rng.gen_range(0..movable.len())];
+        let candidate = if rotatable && (movable.len() == 1 || rng.gen_bool(0.5)) {
+            Move::Rotate(a, Rot::from_num(rng.gen_range(1..4)))
+        } else {
+            let b = movable[rng.gen_range(0..movable.len())];
+            if a == b {
+                continue;
+            }
+            Move::Swap(a, b)
+        };
+
+        let cells = candidate.cells();
+        let before = local_energy(fragment_grid, &cells, hints, metric).unwrap();
+        candidate.apply(fragment_grid);
+        let delta = match local_energy(fragment_grid, &cells, hints, metric) {
+            Some(after) => after - before,
+            None => {
+                candidate.undo(fragment_grid);
+                continue;
+            }
+        };
+        if delta <= 0.0 || rng.gen::<f64>() < (-delta / temperature).exp() {
+            energy += delta;
+            if energy < best_energy {
+                best = fragment_grid.clone();
+                best_energy = energy;
+            }
+        } else {
+            candidate.undo(fragment_grid);
+        }
+    }
+
+    *fragment_grid = best;
+    for (pos, cell) in fragment_grid.iter_with_pos() {
+        let (fragment, before) = match (cell, &initial[pos]) {
+            (Some(fragment), Some(before)) => (fragment, before),
+            _ => continue,
+        };
+        if (fragment.pos, fragment.rot) != (before.pos, before.rot) {
+            log.provenances.insert(fragment.pos, Provenance::Refined);
+        }
+    }
+    tracing::info!(
+        iterations,
+        initial_energy,
+        best_energy,
+        "annealed the recovered image"
+    );
+}
+
+#[test]
+fn test_anneal() {
+    let problem = crate::scramble::smooth_problem(4, 3);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem)
+        .unwrap()
+        .into_iter()
+        .map(Some)
+        .collect();
+    let mut fragment_grid = VecOnGrid::from_vec(grid, fragments).unwrap();
+    let mut log = MatchLog::default();
+    log.provenances.insert(grid.pos(0, 0), Provenance::Root);
+
+    // 正しい配置から 2 つの断片を入れ替え, 1 つを回転させておく
+    Move::Swap(grid.pos(1, 1), grid.pos(3, 2)).apply(&mut fragment_grid);
+    Move::Rotate(grid.pos(2, 0), Rot::R90).apply(&mut fragment_grid);
+    let hints = ResolveHints::default();
+    let shuffled = total_energy(&fragment_grid, &hints, Metric::Euclidean).unwrap();
+
+    anneal(
+        &mut fragment_grid,
+        &mut log,
+        &hints,
+        (Metric::Euclidean, &super::ROTS),
+        Schedule {
+            budget: Duration::from_secs(60),
+            max_iterations: 20000,
+            seed: 1,
+            abandon: Abandon::default(),
+        },
+    );
+    assert!(total_energy(&fragment_grid, &hints, Metric::Euclidean).unwrap() < shuffled);
+    for (pos, cell) in fragment_grid.iter_with_pos() {
+        let fragment = cell.as_ref().unwrap();
+        assert_eq!((pos, Rot::R0), (fragment.pos, fragment.rot));
+    }
+    for pos in [grid.pos(1, 1), grid.pos(3, 2), grid.pos(2, 0)] {
+        assert_eq!(Some(&Provenance::Refined), log.provenances.get(&pos));
+    }
+    assert_eq!(
+        Some(&Provenance::Root),
+        log.provenances.get(&grid.pos(0, 0))
+    );
+}
+
+#[test]
+fn test_anneal_keeps_locked_pairs() {
+    use super::EdgePos;
+
+    let problem = crate::scramble::smooth_problem(4, 3);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem)
+        .unwrap()
+        .into_iter()
+        .map(Some)
+        .collect();
+    let mut fragment_grid = VecOnGrid::from_vec(grid, fragments).unwrap();
+    let mut log = MatchLog::default();
+    log.provenances.insert(grid.pos(0, 0), Provenance::Root);
+
+    // (1, 1) の東に (2, 1) をつなげた組を, 1 つ下の行に置き違えておく
+    let (head, tail) = (grid.pos(1, 1), grid.pos(2, 1));
+    Move::Swap(head, grid.pos(1, 2)).apply(&mut fragment_grid);
+    Move::Swap(tail, grid.pos(2, 2)).apply(&mut fragment_grid);
+    let edge = EdgePos::new(head, Dir::East);
+    log.provenances
+        .insert(tail, Provenance::Locked { reference: edge });
+    let hints = ResolveHints::builder(grid)
+        .lock(edge, vec![(tail, Rot::R0)], true)
+        .build()
+        .unwrap();
+
+    anneal(
+        &mut fragment_grid,
+        &mut log,
+        &hints,
+        (Metric::Euclidean, &super::ROTS),
+        Schedule {
+            budget: Duration::from_secs(60),
+            max_iterations: 20000,
+            seed: 1,
+            abandon: Abandon::default(),
+        },
+    );
+    // 組の基準の断片も動かさないので, 置き違えたままつながっている
+    for (pos, expected) in [(grid.pos(1, 2), head), (grid.pos(2, 2), tail)] {
+        let fragment = fragment_grid[pos].as_ref().unwrap();
+        assert_eq!((expected, Rot::R0), (fragment.pos, fragment.rot));
+    }
+}
+
+#[test]
+fn test_anneal_keeps_blocklist() {
+    let problem = crate::scramble::smooth_problem(3, 2);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem)
+        .unwrap()
+        .into_iter()
+        .map(Some)
+        .collect();
+    let mut fragment_grid = VecOnGrid::from_vec(grid, fragments).unwrap();
+    Move::Swap(grid.pos(1, 0), grid.pos(2, 1)).apply(&mut fragment_grid);
+
+    // 正しい配置の継ぎ目をブロックしておくと, その組は隣り合わない
+    let mut hints = ResolveHints::default();
+    hints.push_blocklist(grid.pos(0, 0), EdgePos::new(grid.pos(1, 0), Dir::West));
+    anneal(
+        &mut fragment_grid,
+        &mut MatchLog::default(),
+        &hints,
+        (Metric::Euclidean, &super::ROTS),
+        Schedule {
+            budget: Duration::from_secs(60),
+            max_iterations: 5000,
+            seed: 1,
+            abandon: Abandon::default(),
+        },
+    );
+    assert!(total_energy(&fragment_grid, &hints, Metric::Euclidean).is_some());
+}
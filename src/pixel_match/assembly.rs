@@ -0,0 +1,163 @@
+//! 前回の復元で並べた盤面を, ブロックリストを加えただけの再計算で使い回す.
+
+use std::collections::HashSet;
+
+use super::{EdgePos, FragmentPool, MatchLog, Provenance, ResolveHints};
+use crate::{
+    fragment::Fragment,
+    grid::{Pos, VecOnGrid},
+};
+
+/// `Assembly` は `Strategy::Shaker` で 2 辺から埋め終え, 見分けのつかない断片を埋め直したり焼きなましたりする前の盤面.
+///
+/// 同じ断片と設定で再計算するときにだけ使う.
+#[derive(Debug, Clone)]
+pub(super) struct Assembly {
+    pub(super) fragment_grid: VecOnGrid<Option<Fragment>>,
+    pub(super) root_pos: Pos,
+    pub(super) log: MatchLog,
+    /// 並べる前のヒント. ロックされた組は並べる間に消費されるので, 渡されたものを残しておく
+    pub(super) hints: ResolveHints,
+}
+
+/// 途中から埋め直す盤面と root の位置, それまでの記録, 残りの断片
+pub(super) type Resumed = (VecOnGrid<Option<Fragment>>, Pos, MatchLog, FragmentPool);
+
+impl Assembly {
+    /// `hints` がこの盤面のヒントにブロックリストを加えただけなら, 加えた組で置いた断片のうち最も早く置いたものより前の断片を残す.
+    ///
+    /// 2 辺から埋めた断片は埋めた順に同じ候補から選ぶので, 加えた組を使う前までは前回と同じ配置になる.
+    /// 1 辺から並べた root の十字が変わるときや, ブロックリスト以外のヒントが変わったときは `None` を返す.
+    pub(super) fn resume(&self, fragments: &[Fragment], hints: &ResolveHints) -> Option<Resumed> {
+        let blocked = hints.added_blocklist_since(&self.hints)?;
+        let provenances = &self.log.provenances;
+        let is_affected = |pos: Pos| {
+            provenances.get(&pos).is_some_and(|provenance| {
+                blocked.iter().any(|&(reference, against)| {
+                    against.pos == pos && refers_to(provenance, reference)
+                })
+            })
+        };
+
+        if provenances
+            .iter()
+            .any(|(&pos, p)| matches!(p, Provenance::SingleSide { .. }) && is_affected(pos))
+        {
+            tracing::debug!("the blocklist changes the cross from root. solving from scratch");
+            return None;
+        }
+
+        let placed = &self.log.placed;
+        let first = placed
+            .iter()
+            .position(|&pos| is_affected(pos))
+            .unwrap_or(placed.len());
+        let redo: HashSet<_> = placed[first..].iter().copied().collect();
+
+        let mut fragment_grid = self.fragment_grid.clone();
+        for cell in fragment_grid.iter_mut() {
+            if matches!(cell, Some(fragment) if redo.contains(&fragment.pos)) {
+                *cell = None;
+            }
+        }
+        // 同じ一致度の候補は探す順番で選ぶので, 残りの断片も最初に渡された順に並べる
+        let pool = fragments
+            .iter()
+            .filter(|fragment| redo.contains(&fragment.pos))
+            .cloned()
+            .collect();
+        let log = MatchLog {
+            suggestions: self
+                .log
+                .suggestions
+                .iter()
+                .filter(|s| !redo.contains(&s.against.pos))
+                .copied()
+                .collect(),
+            provenances: provenances
+                .iter()
+                .filter(|(pos, _)| !redo.contains(pos))
+                .map(|(&pos, &p)| (pos, p))
+                .collect(),
+            unapplied_locks: vec![],
+            placed: placed[..first].to_vec(),
+        };
+        Some((fragment_grid, self.root_pos, log, pool))
+    }
+}
+
+/// `provenance` が問題画像で `reference` にあった断片の辺を基準にしているか.
+fn refers_to(provenance: &Provenance, reference: Pos) -> bool {
+    match provenance {
+        Provenance::SingleSide {
+            reference: EdgePos { pos, .. },
+            ..
+        } => *pos == reference,
+        Provenance::DoubleSide { references, .. } => references.iter().any(|r| r.pos == reference),
+        _ => false,
+    }
+}
+
+#[test]
+fn test_resume() {
+    use super::{solve, solve_reusing, DoubleSideScoring, FillContext, Metric, ROTS};
+    use crate::basis::{Dir, Rot};
+
+    let problem = crate::scramble::smooth_problem(4, 3);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+    let scoring = (Metric::default(), DoubleSideScoring::default());
+    let context = FillContext::new(scoring.0, scoring.1, &ROTS);
+
+    let mut previous = None;
+    solve_reusing(
+        fragments.clone(),
+        grid,
+        ResolveHints::default(),
+        context,
+        &mut previous,
+    )
+    .unwrap();
+    let assembly = previous.clone().unwrap();
+
+    // 途中で 2 辺から埋めた断片を, 基準にした辺のどちらにもつなげないようにする
+    let k = assembly.log.placed.len() / 2;
+    let target = assembly.log.placed[k];
+    let references = match assembly.log.provenances[&target] {
+        Provenance::DoubleSide { references, .. } => references,
+        p => panic!("unexpected provenance {:?}", p),
+    };
+    let mut hints = ResolveHints::default();
+    for reference in references {
+        for dir in [Dir::North, Dir::East, Dir::South, Dir::West] {
+            hints.push_blocklist(reference.pos, EdgePos::new(target, dir));
+        }
+    }
+    let (_, _, log, pool) = assembly.resume(&fragments, &hints).unwrap();
+    assert_eq!(&assembly.log.placed[..k], &log.placed[..]);
+    assert_eq!(assembly.log.placed.len() - k, pool.len());
+
+    // 使い回しても, 初めから並べ直したときと同じ配置になる
+    let cells = |recovered: VecOnGrid<Option<Fragment>>| -> Vec<Option<(Pos, Rot)>> {
+        recovered
+            .into_iter()
+            .map(|cell| cell.map(|f| (f.pos, f.rot)))
+            .collect()
+    };
+    let (reused, reused_root, _) = solve_reusing(
+        fragments.clone(),
+        grid,
+        hints.clone(),
+        context,
+        &mut previous,
+    )
+    .unwrap();
+    let (full, full_root, _) =
+        solve(fragments.clone(), grid, hints.clone(), scoring, &ROTS).unwrap();
+    assert_eq!(full_root, reused_root);
+    assert_eq!(cells(full), cells(reused));
+
+    // ブロックリスト以外のヒントが変わったら使い回さない
+    hints.push_soft_blocklist(target, references[0], 2.0);
+    assert!(assembly.resume(&fragments, &hints).is_none());
+}
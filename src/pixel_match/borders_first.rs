@@ -0,0 +1,429 @@
+//! 外周に来そうな断片で先に枠を作り, それから内側を埋める.
+//!
+//! 盤面の外に面する辺は他のどの断片の辺とも合わないので, 最もよく合う辺との一致度が悪い辺を外周の辺とみなす.
+//! 枠は左上の角から時計回りに, 内側は上の行から, 埋まっている隣の辺と最も合う断片で埋める.
+
+use std::collections::HashMap;
+
+use super::{
+    average_distance, double_side::take_locked_fragment, duplicates::neighbor,
+    duplicates::score_at, FillContext, FragmentPool, MatchLog, Metric, Provenance, ResolveHints,
+    Solved,
+};
+use crate::{
+    basis::{Dir, Rot},
+    error::Error,
+    fragment::Fragment,
+    grid::{Grid, Pos, VecOnGrid},
+};
+
+const DIRS: [Dir; 4] = [Dir::North, Dir::East, Dir::South, Dir::West];
+
+/// 枠の左上の角として試す断片と向きの数
+const CORNER_CANDIDATES: usize = 4;
+
+/// `fragment` に加える回転のうち, 加えた後の回転が `rots` にあるもの.
+fn placements<'a>(
+    fragment: &'a Fragment,
+    rots: &'a [Rot],
+) -> impl Iterator<Item = (&'a Fragment, Rot)> + 'a {
+    super::ROTS
+        .iter()
+        .filter(move |&&rot| rots.contains(&(fragment.rot + rot)))
+        .map(move |&rot| (fragment, rot))
+}
+
+/// `Outline` は各断片の辺が外周の辺らしいかを表す.
+struct Outline {
+    /// 断片の辺ごとの, 他の断片の辺と最もよく合うときの一致度. 大きいほど外周の辺らしい.
+    isolations: HashMap<(Pos, Dir), f64>,
+    /// 盤面の外周の辺の数だけ, 最も外周の辺らしい辺を選んだときの境目
+    threshold: f64,
+}
+
+impl Outline {
+    fn new(fragments: &[Fragment], grid: Grid, metric: Metric) -> Self {
+        let mut isolations = HashMap::new();
+        for fragment in fragments {
+            for edge in fragment.edges.iter() {
+                let best = fragments
+                    .iter()
+                    .filter(|other| other.pos != fragment.pos)
+                    .flat_map(|other| other.edges.iter())
+                    .map(|other| {
+                        average_distance(metric, edge.pixels.iter(), other.pixels.iter().rev())
+                    })
+                    .fold(f64::INFINITY, f64::min);
+                isolations.insert((fragment.pos, edge.dir), best);
+            }
+        }
+
+        let mut sorted: Vec<_> = isolations.values().copied().collect();
+        sorted.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        let outer_edges = 2 * (grid.width() as usize + grid.height() as usize);
+        let threshold = sorted[outer_edges.min(sorted.len()) - 1];
+        Self {
+            isolations,
+            threshold,
+        }
+    }
+
+    fn isolation(&self, fragment: &Fragment, rot: Rot, dir: Dir) -> f64 {
+        self.isolations[&(fragment.pos, dir.rotate(rot.inverse()))]
+    }
+
+    /// `fragment` を `rot` だけ回転させたとき, `outward` を向く辺がすべて外周の辺らしいか.
+    fn fits(&self, fragment: &Fragment, rot: Rot, outward: &[Dir]) -> bool {
+        outward
+            .iter()
+            .all(|&dir| self.threshold <= self.isolation(fragment, rot, dir))
+    }
+
+    /// `fragment` を `rot` だけ回転させたときの, 左上の角らしさ.
+    fn corner_score(&self, fragment: &Fragment, rot: Rot) -> f64 {
+        self.isolation(fragment, rot, Dir::North)
+            .min(self.isolation(fragment, rot, Dir::West))
+    }
+}
+
+/// 左上の角から時計回りに外周のマスを並べ, 続けて内側のマスを上の行から並べる.
+fn fill_order(grid: Grid) -> Vec<Pos> {
+    let (width, height) = (grid.width(), grid.height());
+    let mut order = vec![];
+    let mut push = |x, y| {
+        let pos = grid.pos(x, y);
+        if !order.contains(&pos) {
+            order.push(pos);
+        }
+    };
+    for x in 0..width {
+        push(x, 0);
+    }
+    for y in 0..height {
+        push(width - 1, y);
+    }
+    for x in (0..width).rev() {
+        push(x, height - 1);
+    }
+    for y in (0..height).rev() {
+        push(0, y);
+    }
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            push(x, y);
+        }
+    }
+    order
+}
+
+/// `corner` の断片を回転させて左上に置き, `fill_order` の順に残りのマスを埋める.
+///
+/// 埋めた盤面と, 隣の辺との一致度を継ぎ目ごとに足したものを返す.
+/// ブロックリストなどで置ける断片が無いマスがあれば `Error::NoCandidate` を返す.
+/// `context.abandon` でやめるよう求められたら, 1 マス埋めるごとに確かめてやめる.
+fn assemble(
+    fragments: Vec<Fragment>,
+    grid: Grid,
+    mut hints: ResolveHints,
+    context: FillContext<'_>,
+    outline: &Outline,
+    (corner, corner_rot): (Pos, Rot),
+) -> crate::Result<(VecOnGrid<Option<Fragment>>, MatchLog, f64)> {
+    let mut fragment_grid = VecOnGrid::<Option<Fragment>>::with_default(grid);
+    let mut log = MatchLog::default();
+    let mut total = 0.0;
+
+    let order = fill_order(grid);
+    let mut fragments = FragmentPool::new(fragments);
+    let mut first = fragments.take(corner).unwrap();
+    first.rotate(corner_rot);
+    log.provenances.insert(
+        first.pos,
+        Provenance::Surrounded {
+            neighbors: 0,
+            score: 0.0,
+        },
+    );
+    fragment_grid[order[0]] = Some(first);
+
+    let FillContext {
+        metric,
+        rots,
+        abandon,
+        ..
+    } = context;
+    for &pos in &order[1..] {
+        if abandon.is_requested() {
+            return Err(abandon.error());
+        }
+        let refs: Vec<_> = DIRS
+            .iter()
+            .filter_map(|&dir| {
+                let reference = neighbor(grid, pos, dir)?;
+                fragment_grid[reference].as_ref()?;
+                Some((reference, dir.opposite()))
+            })
+            .collect();
+        let locked = take_locked_fragment(
+            &mut fragments,
+            &fragment_grid,
+            &mut hints,
+            &mut log.unapplied_locks,
+            &refs,
+        );
+        if let Some((fragment, reference)) = locked {
+            log.provenances
+                .insert(fragment.pos, Provenance::Locked { reference });
+            fragment_grid[pos] = Some(fragment);
+            continue;
+        }
+
+        // 外周のマスには外に面する辺が外周の辺らしい断片を選ぶ. そのような断片が残っていなければすべてから選ぶ
+        let outward: Vec<_> = DIRS
+            .iter()
+            .copied()
+            .filter(|&dir| neighbor(grid, pos, dir).is_none())
+            .collect();
+        let candidates = || {
+            fragments
+                .iter()
+                .flat_map(|fragment| placements(fragment, rots))
+        };
+        let any_fits = candidates().any(|(fragment, rot)| outline.fits(fragment, rot, &outward));
+        let (fragment_pos, rot, neighbors, score) = candidates()
+            .filter(|&(fragment, rot)| !any_fits || outline.fits(fragment, rot, &outward))
+            .filter_map(|(fragment, rot)| {
+                let (neighbors, score) =
+                    score_at(&fragment_grid, &hints, metric, pos, (fragment, rot))?;
+                Some((fragment.pos, rot, neighbors, score))
+            })
+            .min_by(|a, b| a.3.partial_cmp(&b.3).unwrap())
+            .ok_or_else(|| {
+                let (reference, _) = refs[0];
+                Error::NoCandidate(fragment_grid[reference].as_ref().unwrap().pos)
+            })?;
+
+        let mut fragment = fragments.take(fragment_pos).unwrap();
+        fragment.rotate(rot);
+        log.provenances
+            .insert(fragment.pos, Provenance::Surrounded { neighbors, score });
+        total += score * neighbors as f64;
+        fragment_grid[pos] = Some(fragment);
+    }
+
+    Ok((fragment_grid, log, total))
+}
+
+/// `root` の断片の向きが正しくなるように盤面全体を回転させる.
+///
+/// 長方形の盤面は 90 度回すと形が変わるので, そうしなければならないときは `None` を返す.
+fn upright(
+    fragment_grid: VecOnGrid<Option<Fragment>>,
+    log: &mut MatchLog,
+    root: Pos,
+) -> Option<VecOnGrid<Option<Fragment>>> {
+    let grid = fragment_grid.grid;
+    let rot = fragment_grid.iter().flatten().find(|f| f.pos == root)?.rot;
+    let turns = rot.inverse().as_num();
+    if turns % 2 == 1 && grid.width() != grid.height() {
+        return None;
+    }
+
+    let (width, height) = (grid.width(), grid.height());
+    let mut turned = VecOnGrid::<Option<Fragment>>::with_default(grid);
+    for (pos, cell) in fragment_grid.into_iter_with_pos() {
+        let (x, y) = (pos.x(), pos.y());
+        // 時計回りに turns * 90 度回したときに移る先のマス
+        let to = match turns {
+            0 => pos,
+            1 => grid.pos(height - 1 - y, x),
+            2 => grid.pos(width - 1 - x, height - 1 - y),
+            _ => grid.pos(y, width - 1 - x),
+        };
+        turned[to] = cell.map(|mut fragment| {
+            fragment.rotate(Rot::from_num(turns));
+            fragment
+        });
+    }
+    for provenance in log.provenances.values_mut() {
+        if let Provenance::Locked { reference } = provenance {
+            reference.dir = reference.dir.rotate(Rot::from_num(turns));
+        }
+    }
+    Some(turned)
+}
+
+/// 左上の角らしい断片と向きから順に枠を作って盤面を埋め, root の断片の向きが正しくなるものの中で,
+/// 継ぎ目の一致度の合計が最も良いものを返す. どの角からも埋められなければ, 最後に試した角のエラーを返す.
+/// `context.abandon` でやめるよう求められたら, 埋める途中でやめて `Abandon::error` を返す.
+#[tracing::instrument(name = "pixel_match_borders_first", skip_all)]
+pub(super) fn solve(
+    fragments: Vec<Fragment>,
+    grid: Grid,
+    hints: ResolveHints,
+    context: FillContext<'_>,
+) -> crate::Result<Solved> {
+    let FillContext { metric, rots, .. } = context;
+    let root = grid.pos(0, 0);
+    let outline = Outline::new(&fragments, grid, metric);
+
+    let mut corners: Vec<_> = fragments
+        .iter()
+        .flat_map(|fragment| placements(fragment, rots))
+        .map(|(fragment, rot)| (fragment.pos, rot, outline.corner_score(fragment, rot)))
+        .collect();
+    corners.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+
+    let mut best: Option<(VecOnGrid<Option<Fragment>>, MatchLog, f64)> = None;
+    let mut last_error = None;
+    let mut tried = 0;
+    for &(corner, rot, _) in &corners {
+        if tried == CORNER_CANDIDATES {
+            break;
+        }
+        let assembled = assemble(
+            fragments.clone(),
+            grid,
+            hints.clone(),
+            context,
+            &outline,
+            (corner, rot),
+        );
+        let (fragment_grid, mut log, total) = match assembled {
+            Ok(v) => v,
+            Err(e @ (Error::Abandoned | Error::Cancelled)) => return Err(e),
+            Err(e) => {
+                tracing::debug!(?corner, ?rot, %e, "failed to assemble from the corner");
+                tried += 1;
+                last_error = Some(e);
+                continue;
+            }
+        };
+        let fragment_grid = match upright(fragment_grid, &mut log, root) {
+            Some(v) => v,
+            None => {
+                tracing::debug!(?corner, ?rot, "the root would be placed sideways");
+                continue;
+            }
+        };
+        tried += 1;
+        tracing::debug!(?corner, ?rot, total, "assembled from the corner");
+        if best.as_ref().is_none_or(|(_, _, best)| total < *best) {
+            best = Some((fragment_grid, log, total));
+        }
+    }
+
+    let (fragment_grid, log, _) = best.ok_or_else(|| {
+        last_error
+            .unwrap_or_else(|| Error::SolverFailed("no corner keeps the root upright".to_owned()))
+    })?;
+    let root_pos = fragment_grid
+        .iter_with_pos()
+        .find(|(_, cell)| matches!(cell, Some(f) if f.pos == root))
+        .map(|(pos, _)| pos)
+        .unwrap();
+    Ok((fragment_grid, root_pos, log))
+}
+
+#[test]
+fn test_fill_order() {
+    let grid = Grid::new(4, 3);
+    let order: Vec<_> = fill_order(grid)
+        .into_iter()
+        .map(|p| (p.x(), p.y()))
+        .collect();
+    assert_eq!(
+        vec![
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (3, 0),
+            (3, 1),
+            (3, 2),
+            (2, 2),
+            (1, 2),
+            (0, 2),
+            (0, 1),
+            (1, 1),
+            (2, 1),
+        ],
+        order
+    );
+}
+
+#[test]
+fn test_borders_first() {
+    let problem = include_bytes!("../../test_cases/03.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+
+    let (recovered, root_pos, log) = solve(
+        fragments.clone(),
+        grid,
+        ResolveHints::default(),
+        FillContext::new(
+            Metric::Euclidean,
+            super::DoubleSideScoring::default(),
+            &super::ROTS,
+        ),
+    )
+    .unwrap();
+    let root = recovered[root_pos].as_ref().unwrap();
+    assert_eq!(grid.pos(0, 0), root.pos);
+    assert_eq!(Rot::R0, root.rot);
+    assert_eq!(fragments.len(), log.provenances.len());
+
+    let mut placed: Vec<_> = recovered.iter().map(|f| f.as_ref().unwrap().pos).collect();
+    placed.sort();
+    let mut all: Vec<_> = fragments.iter().map(|f| f.pos).collect();
+    all.sort();
+    assert_eq!(all, placed);
+
+    // やめるよう求められていれば, どの角からも埋めずにやめる
+    let cancel = crate::cancel::CancellationToken::new();
+    cancel.cancel();
+    let cancelled = solve(
+        fragments,
+        grid,
+        ResolveHints::default(),
+        FillContext {
+            abandon: super::Abandon::on_cancel(Some(&cancel)),
+            ..FillContext::new(
+                Metric::Euclidean,
+                super::DoubleSideScoring::default(),
+                &super::ROTS,
+            )
+        },
+    );
+    assert!(matches!(cancelled, Err(Error::Cancelled)));
+}
+
+#[test]
+fn test_recover_smooth_image() {
+    // 継ぎ目がなめらかにつながる画像なら, 回転させた断片も元の位置と向きに戻せる
+    let problem = crate::scramble::smooth_problem(5, 3);
+    let grid = problem.grid();
+    let mut fragments = Fragment::new_all(&problem).unwrap();
+    fragments[3].rotate(Rot::R90);
+    fragments[7].rotate(Rot::R180);
+    fragments[14].rotate(Rot::R270);
+
+    let (recovered, root_pos, _) = solve(
+        fragments,
+        grid,
+        ResolveHints::default(),
+        FillContext::new(
+            Metric::Euclidean,
+            super::DoubleSideScoring::default(),
+            &super::ROTS,
+        ),
+    )
+    .unwrap();
+    assert_eq!(grid.pos(0, 0), root_pos);
+    for (pos, cell) in recovered.iter_with_pos() {
+        let fragment = cell.as_ref().unwrap();
+        assert_eq!(pos, fragment.pos);
+        assert_eq!(Rot::R0, fragment.rot);
+    }
+}
@@ -1,27 +1,50 @@
+use std::ops::Index;
+
+use rayon::prelude::*;
+
 use crate::{
-    basis::{Color, Dir},
+    basis::{Color, Dir, Rot},
+    error::Error,
     fragment::Fragment,
-    grid::{Pos, VecOnGrid},
+    grid::{on_grid::OnGrid, Grid, Pos, VecOnGrid},
 };
 
-use super::{average_distance, find_with, gui::EdgePos, DiffEntry, FindAndRemove, ResolveHints};
+use super::{
+    average_distance, find_with, soft_penalty, BlockSuggestion, Candidates, DiffEntry, EdgePos,
+    FillContext, FragmentPool, LockedPairs, MatchLog, Provenance, ResolveHints,
+};
 
-fn get_edge_pixels(grid: &VecOnGrid<Option<Fragment>>, pos: Pos, dir: Dir) -> Option<&Vec<Color>> {
+fn get_edge_pixels<V>(grid: &V, pos: Pos, dir: Dir) -> Option<&Vec<Color>>
+where
+    V: OnGrid + Index<Pos, Output = Option<Fragment>>,
+{
     Some(&grid[pos].as_ref()?.edges.edge(dir).pixels)
 }
 
-fn find_by_double_side<'a, I, B>(
-    fragments: &'a [Fragment],
-    reference_iter: I,
-    (blocklist, blocklist_ref_index): (B, usize),
-) -> DiffEntry
+/// 2 つの参照する辺 `references` にそれぞれ合う辺を持つ断片を探す. 2 つの辺の一致度は別々に測ってから `context.scoring` でまとめる.
+///
+/// 1 つ目の参照する辺は `ref_dir` を向いており, 置いたときの回転が `context.rots` に無い向きの断片は候補にしない.
+fn find_by_double_side<'a, I, B, S>(
+    fragments: &'a FragmentPool,
+    (references, ref_dir): ([I; 2], Dir),
+    (blocklist, soft_blocklist, blocklist_ref_index): (B, S, usize),
+    context: FillContext<'_>,
+) -> Option<Candidates>
 where
     I: Iterator<Item = &'a Color> + Clone + 'a,
     B: Iterator<Item = &'a EdgePos> + Clone + 'a,
+    S: Iterator<Item = &'a (EdgePos, f64)> + Clone + 'a,
 {
+    let FillContext {
+        metric,
+        scoring,
+        rots,
+        ..
+    } = context;
     find_with(fragments, move |fragment| {
-        let reference_iter = reference_iter.clone();
+        let references = references.clone();
         let blocklist = blocklist.clone();
+        let soft_blocklist = soft_blocklist.clone();
 
         IntoIterator::into_iter([
             [Dir::North, Dir::East],
@@ -33,37 +56,70 @@ where
             !blocklist
                 .clone()
                 .any(|x| x.pos == fragment.pos && x.dir == a[blocklist_ref_index])
+                && rots.contains(&(fragment.rot + ref_dir.calc_rot(a[0])))
         })
-        .map(move |[dir_a, dir_b]| (fragment.edges.edge(dir_a), fragment.edges.edge(dir_b)))
-        .map(move |(edge_a, edge_b)| DiffEntry {
-            pos: fragment.pos,
-            dir: edge_a.dir,
-            score: average_distance(
-                reference_iter.clone(),
-                edge_a.pixels.iter().chain(edge_b.pixels.iter()),
-            ),
+        .map(move |dirs| {
+            let [score_a, score_b] = [0, 1].map(|i| {
+                average_distance(
+                    metric,
+                    references[i].clone(),
+                    fragment.edges.edge(dirs[i]).pixels.iter(),
+                )
+            });
+            DiffEntry {
+                pos: fragment.pos,
+                dir: fragment.edges.edge(dirs[0]).dir,
+                score: scoring.combine(score_a, score_b)
+                    * soft_penalty(
+                        soft_blocklist.clone(),
+                        fragment.pos,
+                        dirs[blocklist_ref_index],
+                    ),
+            }
         })
     })
 }
 
-fn fill_by_double_side_inner(
-    fragments: &mut Vec<Fragment>,
-    fragment_grid: &mut VecOnGrid<Option<Fragment>>,
-    hints: &ResolveHints,
+/// `pos` のマスを 2 つの隣の断片の辺から埋め, 置いた根拠とブロックリストの候補を `log` に残す.
+///
+/// ブロックリストなどで置ける断片が無ければ `Error::NoCandidate` を返す.
+fn fill_by_double_side_inner<V>(
+    fragments: &mut FragmentPool,
+    fragment_grid: &mut V,
+    hints: &mut ResolveHints,
+    context: FillContext<'_>,
+    log: &mut MatchLog,
     pos: Pos,
-    (ref1_pos, ref1_dir): (Pos, Dir),
-    (ref2_pos, ref2_dir): (Pos, Dir),
-) {
-    let reference_iter = get_edge_pixels(fragment_grid, ref1_pos, ref1_dir)
-        .unwrap()
-        .iter()
-        .rev()
-        .chain(
-            get_edge_pixels(fragment_grid, ref2_pos, ref2_dir)
-                .unwrap()
-                .iter()
-                .rev(),
-        );
+    [(ref1_pos, ref1_dir), (ref2_pos, ref2_dir)]: [(Pos, Dir); 2],
+) -> crate::Result<()>
+where
+    V: OnGrid + Index<Pos, Output = Option<Fragment>>,
+{
+    let locked = take_locked_fragment(
+        fragments,
+        fragment_grid,
+        hints,
+        &mut log.unapplied_locks,
+        &[(ref1_pos, ref1_dir), (ref2_pos, ref2_dir)],
+    );
+    if let Some((fragment, reference)) = locked {
+        log.provenances
+            .insert(fragment.pos, Provenance::Locked { reference });
+        log.placed.push(fragment.pos);
+        fragment_grid[pos] = Some(fragment);
+        return Ok(());
+    }
+    let references = [
+        EdgePos::new(fragment_grid[ref1_pos].as_ref().unwrap().pos, ref1_dir),
+        EdgePos::new(fragment_grid[ref2_pos].as_ref().unwrap().pos, ref2_dir),
+    ];
+
+    let reference_edges = [(ref1_pos, ref1_dir), (ref2_pos, ref2_dir)].map(|(pos, dir)| {
+        get_edge_pixels(fragment_grid, pos, dir)
+            .unwrap()
+            .iter()
+            .rev()
+    });
 
     let (blocklist_pos, index) = match (ref1_dir, ref2_dir) {
         (Dir::North | Dir::South, _) => (ref1_pos, 0),
@@ -72,85 +128,610 @@ fn fill_by_double_side_inner(
     };
 
     let blocklist_pos = fragment_grid[blocklist_pos].as_ref().unwrap().pos;
-    let blocklist = hints.take_blacklist(blocklist_pos);
+    let blocklist = (
+        hints.blocklist_of(blocklist_pos),
+        hints.soft_blocklist_of(blocklist_pos),
+        index,
+    );
 
-    let min = find_by_double_side(fragments, reference_iter, (blocklist, index));
+    let candidates =
+        find_by_double_side(fragments, (reference_edges, ref1_dir), blocklist, context)
+            .ok_or(Error::NoCandidate(blocklist_pos))?;
+    // ブロックリストには dir_a か dir_b のうち index が指す方の辺が入る
+    let edge_of = |d: &DiffEntry| EdgePos::new(d.pos, d.dir.rotate(Rot::from_num(index as u8)));
+    log.suggestions.extend(BlockSuggestion::from_candidates(
+        blocklist_pos,
+        &candidates,
+        edge_of,
+    ));
 
-    let mut fragment = fragments.find_and_remove(|x| x.pos == min.pos).unwrap();
+    let min = candidates.best;
+    let mut fragment = fragments.take(min.pos).unwrap();
     fragment.rotate(ref1_dir.calc_rot(min.dir));
 
+    log.provenances.insert(
+        fragment.pos,
+        Provenance::DoubleSide {
+            references,
+            score: min.score,
+        },
+    );
+    log.placed.push(fragment.pos);
     fragment_grid[pos] = Some(fragment);
+    Ok(())
 }
 
-/// 2辺から最も合う断片を探して fragment_grid に入れる
-pub(super) fn fill_by_double_side(
+/// 隣の断片の辺から続くロックされた組があれば, その先頭の断片を回転させて, 組が続いていた辺と一緒に取り出す.
+///
+/// 残りの断片は取り出した断片の同じ向きの辺から続く組として戻し, その先のマスを埋めるときに使う.
+/// 組の断片がもう使われていれば, その組の辺を `unapplied` に加える.
+pub(super) fn take_locked_fragment<V>(
+    fragments: &mut FragmentPool,
+    fragment_grid: &V,
+    hints: &mut ResolveHints,
+    unapplied: &mut Vec<EdgePos>,
+    refs: &[(Pos, Dir)],
+) -> Option<(Fragment, EdgePos)>
+where
+    V: OnGrid + Index<Pos, Output = Option<Fragment>>,
+{
+    for &(ref_pos, ref_dir) in refs {
+        let edgepos = EdgePos::new(fragment_grid[ref_pos].as_ref().unwrap().pos, ref_dir);
+        let mut pairs = match hints.consume_locked_pairs(edgepos) {
+            Some(v) if !v.tail.is_empty() => v,
+            _ => continue,
+        };
+
+        let (pos, rot) = pairs.tail.remove(0);
+        let mut fragment = match fragments.take(pos) {
+            Some(v) => v,
+            None => {
+                tracing::warn!(
+                    ?edgepos,
+                    "double_side: couldn't apply locked_pair because fragment in pair is already taken"
+                );
+                unapplied.push(edgepos);
+                continue;
+            }
+        };
+        tracing::debug!(
+            ?edgepos,
+            tail_len = pairs.tail.len(),
+            "applying locked_pairs"
+        );
+
+        fragment.rotate(rot);
+        if !pairs.tail.is_empty() {
+            hints.push_locked_pair(EdgePos::new(pos, ref_dir), pairs);
+        }
+        return Some((fragment, edgepos));
+    }
+    None
+}
+
+/// `view` 上で `root_pos` より右下の領域のマスを, 埋める順に 1 列ずつ返す.
+///
+/// `by_rows` なら 1 列ではなく 1 行ずつ並べる. どちらの順でも, 左と上のマスはそのマスより先に並ぶ.
+fn fill_lines(grid: Grid, root_pos: Pos, by_rows: bool) -> Vec<Vec<Pos>> {
+    let xs = root_pos.x() + 1..grid.width();
+    let ys = root_pos.y() + 1..grid.height();
+    if by_rows {
+        ys.map(|y| xs.clone().map(|x| grid.pos(x, y)).collect())
+            .collect()
+    } else {
+        xs.map(|x| ys.clone().map(|y| grid.pos(x, y)).collect())
+            .collect()
+    }
+}
+
+/// `view` 上で `root_pos` より右下の領域を, 左と上の断片の辺から埋める. 前回の配置を使い回して埋まっているマスは飛ばす.
+///
+/// `view` は `quadrant` の領域が右下に来るよう回転した系で, 埋める順は `fill_lines` に従う.
+/// `context.abandon` でやめるよう求められたら, 1 列埋めるごとに確かめてやめる.
+fn fill_quadrant<V>(
+    mut view: V,
     root_pos: Pos,
+    quadrant: Quadrant,
+    context: FillContext<'_>,
     hints: &mut ResolveHints,
-    fragments: &mut Vec<Fragment>,
-    fragment_grid: &mut VecOnGrid<Option<Fragment>>,
-) {
-    let grid = fragment_grid.grid;
-
-    // ------------
-    //    2    x  1
-    //         x
-    // xxxxxxxxrxxx
-    //    3    x  4
-    // ------------
-
-    // 1
-    for x in root_pos.x() + 1..grid.width() {
-        for y in (0..root_pos.y()).rev() {
+    log: &mut MatchLog,
+    fragments: &mut FragmentPool,
+) -> crate::Result<()>
+where
+    V: OnGrid + Index<Pos, Output = Option<Fragment>>,
+{
+    let grid = view.grid();
+    let root_pos = view.view_pos(root_pos);
+    let (east, south) = quadrant.view_dirs();
+
+    for line in fill_lines(grid, root_pos, quadrant.by_rows()) {
+        if context.abandon.is_requested() {
+            return Err(context.abandon.error());
+        }
+        for pos in line {
+            if view[pos].is_some() {
+                continue;
+            }
+            let (x, y) = (pos.x(), pos.y());
             fill_by_double_side_inner(
                 fragments,
-                fragment_grid,
+                &mut view,
                 hints,
-                grid.pos(x, y),
-                (grid.pos(x, y + 1), Dir::North),
-                (grid.pos(x - 1, y), Dir::East),
-            );
+                context,
+                log,
+                pos,
+                [(grid.pos(x - 1, y), east), (grid.pos(x, y - 1), south)],
+            )?;
         }
     }
+    Ok(())
+}
 
-    // 2
-    for x in (0..root_pos.x()).rev() {
-        for y in (0..root_pos.y()).rev() {
-            fill_by_double_side_inner(
-                fragments,
-                fragment_grid,
-                hints,
-                grid.pos(x, y),
-                (grid.pos(x + 1, y), Dir::West),
-                (grid.pos(x, y + 1), Dir::North),
-            );
+/// root から見た 4 つの領域.
+///
+/// ```text
+/// ------------
+///    2    x  1
+///         x
+/// xxxxxxxxrxxx
+///    3    x  4
+/// ------------
+/// ```
+#[derive(Debug, Clone, Copy)]
+enum Quadrant {
+    First,
+    Second,
+    Third,
+    Fourth,
+}
+
+const QUADRANTS: [Quadrant; 4] = [
+    Quadrant::First,
+    Quadrant::Second,
+    Quadrant::Third,
+    Quadrant::Fourth,
+];
+
+/// 1 つの領域を埋めた盤面と, その間に見つけたブロックリストの候補と断片を置いた根拠
+struct QuadrantFill {
+    fragment_grid: VecOnGrid<Option<Fragment>>,
+    log: MatchLog,
+    /// 埋め始めたときのヒントにあって, この領域で使ったロックされた組の辺
+    consumed_locks: Vec<EdgePos>,
+    /// この領域でつなげた組の残りとして加えたロックされた組
+    pushed_locks: Vec<(EdgePos, LockedPairs)>,
+}
+
+impl Quadrant {
+    /// 回転した系で行ごとに埋めるかどうか.
+    ///
+    /// どの領域も元の系で x を外側にして埋めるので, 横倒しに回す 1 と 3 は回転した系では行ごとになる.
+    fn by_rows(self) -> bool {
+        matches!(self, Quadrant::First | Quadrant::Third)
+    }
+
+    /// 回転した系の右向きと下向きが, 元の系でどの向きに当たるか.
+    fn view_dirs(self) -> (Dir, Dir) {
+        match self {
+            Quadrant::First => (Dir::North, Dir::East),
+            Quadrant::Second => (Dir::West, Dir::North),
+            Quadrant::Third => (Dir::South, Dir::West),
+            Quadrant::Fourth => (Dir::East, Dir::South),
         }
     }
 
-    // 3
-    for x in (0..root_pos.x()).rev() {
-        for y in root_pos.y() + 1..grid.height() {
-            fill_by_double_side_inner(
-                fragments,
-                fragment_grid,
-                hints,
-                grid.pos(x, y),
-                (grid.pos(x, y - 1), Dir::South),
-                (grid.pos(x + 1, y), Dir::West),
-            );
+    /// 盤面と断片とヒントを複製して, この領域だけを埋める.
+    ///
+    /// 4 の領域を基準として, 1, 2, 3 は系全体を回転させて同じように埋める.
+    fn fill(
+        self,
+        root_pos: Pos,
+        base_hints: &ResolveHints,
+        context: FillContext<'_>,
+        fragments: &FragmentPool,
+        fragment_grid: &VecOnGrid<Option<Fragment>>,
+    ) -> crate::Result<QuadrantFill> {
+        let mut hints = base_hints.clone();
+        let mut fragments = fragments.clone();
+        let mut fragment_grid = fragment_grid.clone();
+        let mut log = MatchLog::default();
+
+        let grid = fragment_grid.by_ref();
+        match self {
+            Quadrant::First => fill_quadrant(
+                grid.rotate_to_right(),
+                root_pos,
+                self,
+                context,
+                &mut hints,
+                &mut log,
+                &mut fragments,
+            ),
+            Quadrant::Second => fill_quadrant(
+                grid.rotate_to_right().rotate_to_right(),
+                root_pos,
+                self,
+                context,
+                &mut hints,
+                &mut log,
+                &mut fragments,
+            ),
+            Quadrant::Third => fill_quadrant(
+                grid.rotate_to_left(),
+                root_pos,
+                self,
+                context,
+                &mut hints,
+                &mut log,
+                &mut fragments,
+            ),
+            Quadrant::Fourth => fill_quadrant(
+                grid,
+                root_pos,
+                self,
+                context,
+                &mut hints,
+                &mut log,
+                &mut fragments,
+            ),
+        }?;
+
+        let consumed_locks = base_hints
+            .locked_pairs()
+            .map(|(&edge, _)| edge)
+            .filter(|&edge| hints.locked_pairs_of(edge).is_none())
+            .collect();
+        let pushed_locks = hints
+            .locked_pairs()
+            .filter(|(&edge, _)| base_hints.locked_pairs_of(edge).is_none())
+            .map(|(&edge, pairs)| (edge, pairs.clone()))
+            .collect();
+        Ok(QuadrantFill {
+            fragment_grid,
+            log,
+            consumed_locks,
+            pushed_locks,
+        })
+    }
+}
+
+impl QuadrantFill {
+    /// この領域に置いた断片か使ったロックされた組のうち, `fragments` か `hints` に残っていないものがあるか.
+    fn conflicts_with(
+        &self,
+        fragments: &FragmentPool,
+        fragment_grid: &VecOnGrid<Option<Fragment>>,
+        hints: &ResolveHints,
+    ) -> bool {
+        self.fragment_grid
+            .iter_with_pos()
+            .filter(|(pos, _)| fragment_grid[*pos].is_none())
+            .filter_map(|(_, cell)| cell.as_ref())
+            .any(|placed| !fragments.contains(placed.pos))
+            || self
+                .consumed_locks
+                .iter()
+                .any(|&edge| hints.locked_pairs_of(edge).is_none())
+    }
+
+    /// この領域に置いた断片を `fragment_grid` に移し, `fragments` から取り除く. 使ったロックされた組は `hints` から取り除く.
+    fn apply(
+        self,
+        fragments: &mut FragmentPool,
+        fragment_grid: &mut VecOnGrid<Option<Fragment>>,
+        hints: &mut ResolveHints,
+        log: &mut MatchLog,
+    ) {
+        for edge in self.consumed_locks {
+            hints.consume_locked_pairs(edge);
+        }
+        for (edge, pairs) in self.pushed_locks {
+            hints.push_locked_pair(edge, pairs);
         }
+        for (pos, cell) in self.fragment_grid.into_iter_with_pos() {
+            if fragment_grid[pos].is_some() {
+                continue;
+            }
+            if let Some(placed) = cell {
+                fragments.take(placed.pos);
+                fragment_grid[pos] = Some(placed);
+            }
+        }
+        log.extend(self.log);
     }
+}
 
-    // 4
-    for x in root_pos.x() + 1..grid.width() {
-        for y in root_pos.y() + 1..grid.height() {
-            fill_by_double_side_inner(
-                fragments,
-                fragment_grid,
-                hints,
-                grid.pos(x, y),
-                (grid.pos(x - 1, y), Dir::East),
-                (grid.pos(x, y - 1), Dir::South),
+/// 2辺から最も合う断片を探して fragment_grid に入れる
+///
+/// 4 つの領域は重ならないので, 残りの断片とヒントを複製してそれぞれ並列に埋める. 領域の中のマスは前のマスを参照するので並列にしない.
+/// 1 から順に結果を採用し, 先に採用した領域と断片かロックされた組を取り合っていたらその領域だけ残りの断片とヒントで埋め直すので,
+/// 順に埋めたときと同じ配置になり, 各組も一度しかつなげない. 使ったロックされた組は `hints` から取り除く.
+pub(super) fn fill_by_double_side(
+    root_pos: Pos,
+    hints: &mut ResolveHints,
+    context: FillContext<'_>,
+    log: &mut MatchLog,
+    fragments: &mut FragmentPool,
+    fragment_grid: &mut VecOnGrid<Option<Fragment>>,
+) -> crate::Result<()> {
+    let fills: Vec<_> = QUADRANTS
+        .par_iter()
+        .map(|quadrant| quadrant.fill(root_pos, hints, context, fragments, fragment_grid))
+        .collect();
+
+    for (quadrant, fill) in QUADRANTS.iter().zip(fills) {
+        // 埋められなかった領域も, 残りの断片で埋め直せばそのとき順に埋めたのと同じ結果になる
+        let fill = match fill {
+            Ok(fill) if !fill.conflicts_with(fragments, fragment_grid, hints) => fill,
+            Err(e @ (Error::Abandoned | Error::Cancelled)) => return Err(e),
+            _ => {
+                tracing::debug!(
+                    ?quadrant,
+                    "double_side: refilling the quadrant with the remaining fragments"
+                );
+                quadrant.fill(root_pos, hints, context, fragments, fragment_grid)?
+            }
+        };
+        fill.apply(fragments, fragment_grid, hints, log);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+use super::{DoubleSideScoring, Metric};
+
+#[test]
+fn test_locked_pair_in_quadrant() {
+    use crate::basis::Rot;
+
+    let problem = include_bytes!("../../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+
+    let (recovered, root_pos, _) = super::solve(
+        fragments.clone(),
+        grid,
+        ResolveHints::default(),
+        (Metric::Euclidean, DoubleSideScoring::default()),
+        &super::ROTS,
+    )
+    .unwrap();
+
+    // 2x2 では root の対角のマスだけが 2 辺から埋められる
+    let target = grid.pos(1 - root_pos.x(), 1 - root_pos.y());
+    let neighbor = grid.pos(target.x(), root_pos.y());
+    let dir = if target.y() > neighbor.y() {
+        Dir::South
+    } else {
+        Dir::North
+    };
+    let neighbor_fragment = recovered[neighbor].as_ref().unwrap().pos;
+    let target_fragment = recovered[target].as_ref().unwrap();
+    let rot = target_fragment.rot + Rot::R90;
+
+    let mut hints = ResolveHints::default();
+    hints.push_locked_pair(
+        EdgePos::new(neighbor_fragment, dir),
+        LockedPairs::new(vec![(target_fragment.pos, rot)]),
+    );
+    let (locked, _, _) = super::solve(
+        fragments,
+        grid,
+        hints,
+        (Metric::Euclidean, DoubleSideScoring::default()),
+        &super::ROTS,
+    )
+    .unwrap();
+
+    let placed = locked[target].as_ref().unwrap();
+    assert_eq!(target_fragment.pos, placed.pos);
+    assert_eq!(rot, placed.rot);
+}
+
+#[test]
+fn test_parallel_fill() {
+    let problem = include_bytes!("../../test_cases/03.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let arrangement = super::resolve_headless(Fragment::new_all(&problem).unwrap(), grid).unwrap();
+
+    // root の行と列だけが埋まった, shaker の後のような盤面を作る
+    let root_pos = grid.pos(3, 1);
+    let mut initial = VecOnGrid::<Option<Fragment>>::with_default(grid);
+    for (pos, fragment) in arrangement.recovered().iter_with_pos() {
+        if pos.x() == root_pos.x() || pos.y() == root_pos.y() {
+            initial[pos] = Some(fragment.clone());
+        }
+    }
+    let pool: FragmentPool = Fragment::new_all(&problem)
+        .unwrap()
+        .into_iter()
+        .filter(|f| !initial.iter().flatten().any(|x| x.pos == f.pos))
+        .collect();
+    let hints = ResolveHints::default();
+    let context = FillContext::new(
+        Metric::Euclidean,
+        DoubleSideScoring::default(),
+        &super::ROTS,
+    );
+
+    let (mut fragments, mut parallel, mut parallel_log) =
+        (pool.clone(), initial.clone(), MatchLog::default());
+    fill_by_double_side(
+        root_pos,
+        &mut hints.clone(),
+        context,
+        &mut parallel_log,
+        &mut fragments,
+        &mut parallel,
+    )
+    .unwrap();
+    assert!(fragments.is_empty());
+
+    let (mut fragments, mut sequential, mut sequential_log) = (pool, initial, MatchLog::default());
+    let mut hints = hints;
+    for quadrant in QUADRANTS {
+        quadrant
+            .fill(root_pos, &hints, context, &fragments, &sequential)
+            .unwrap()
+            .apply(
+                &mut fragments,
+                &mut sequential,
+                &mut hints,
+                &mut sequential_log,
             );
+    }
+
+    let placement = |grid: &VecOnGrid<Option<Fragment>>| {
+        grid.iter()
+            .map(|f| f.as_ref().map(|f| (f.pos, f.rot)))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(placement(&sequential), placement(&parallel));
+    assert_eq!(sequential_log.provenances, parallel_log.provenances);
+}
+
+#[test]
+fn test_double_side_scoring() {
+    // 片方の辺が完全に合っていても, もう片方がまったく合わなければ選ばない
+    let (masked, balanced) = ((0.0, 10.0), (6.0, 6.0));
+    let old = DoubleSideScoring::WeightedSum(0.5);
+    assert!(old.combine(masked.0, masked.1) < old.combine(balanced.0, balanced.1));
+    let max = DoubleSideScoring::Max;
+    assert!(max.combine(balanced.0, balanced.1) < max.combine(masked.0, masked.1));
+
+    // 同梱の問題は, 2 つの辺を繋げて平均していたときと同じ配置に復元できる
+    for problem in [
+        &include_bytes!("../../test_cases/01_q.ppm")[..],
+        &include_bytes!("../../test_cases/03.ppm")[..],
+    ] {
+        let problem = crate::image::read_problem(problem).unwrap();
+        let grid = problem.grid();
+        let placement = |scoring| {
+            let (recovered, root_pos, _) = super::solve(
+                Fragment::new_all(&problem).unwrap(),
+                grid,
+                ResolveHints::default(),
+                (Metric::Euclidean, scoring),
+                &super::ROTS,
+            )
+            .unwrap();
+            let placement: Vec<_> = recovered
+                .iter()
+                .map(|f| f.as_ref().map(|f| (f.pos, f.rot)))
+                .collect();
+            (root_pos, placement)
+        };
+        let expected = placement(old);
+        for scoring in [DoubleSideScoring::Max, DoubleSideScoring::WeightedSum(0.75)] {
+            assert_eq!(expected, placement(scoring), "{:?}", scoring);
         }
     }
 }
+
+#[test]
+fn test_fill_order() {
+    let grid = Grid::new(6, 5);
+    let root_pos = grid.pos(2, 2);
+    let mut cells = VecOnGrid::with_init(grid, ());
+
+    // 元の系でどのマスをどの順に埋めるか
+    let order = |view: &dyn OnGrid<Output = ()>, quadrant: Quadrant| -> Vec<Pos> {
+        let lines = fill_lines(view.grid(), view.view_pos(root_pos), quadrant.by_rows());
+        lines
+            .into_iter()
+            .flatten()
+            .map(|pos| view.source_pos(pos))
+            .collect()
+    };
+    let expected = |xs: Vec<u8>, ys: Vec<u8>| -> Vec<Pos> {
+        xs.into_iter()
+            .flat_map(|x| ys.iter().map(move |&y| grid.pos(x, y)))
+            .collect()
+    };
+
+    assert_eq!(
+        expected(vec![3, 4, 5], vec![1, 0]),
+        order(&cells.by_ref().rotate_to_right(), Quadrant::First)
+    );
+    assert_eq!(
+        expected(vec![1, 0], vec![1, 0]),
+        order(
+            &cells.by_ref().rotate_to_right().rotate_to_right(),
+            Quadrant::Second
+        )
+    );
+    assert_eq!(
+        expected(vec![1, 0], vec![3, 4]),
+        order(&cells.by_ref().rotate_to_left(), Quadrant::Third)
+    );
+    assert_eq!(
+        expected(vec![3, 4, 5], vec![3, 4]),
+        order(&cells.by_ref(), Quadrant::Fourth)
+    );
+}
+
+#[test]
+fn test_locked_pair_applied_once() {
+    use crate::basis::Rot;
+
+    let problem = include_bytes!("../../test_cases/03.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+    let context = FillContext::new(
+        Metric::Euclidean,
+        DoubleSideScoring::default(),
+        &super::ROTS,
+    );
+
+    let mut hints = ResolveHints::default();
+    let (mut fragment_grid, root_pos, mut log, mut fragments) =
+        super::fill_cross(fragments, grid, &mut hints, context).unwrap();
+    assert!(root_pos.x() + 1 < grid.width() && root_pos.y() + 1 < grid.height());
+
+    // 何もロックしなければ root の右下に置かれる断片を, 回転させてロックする
+    let anchor = grid.pos(root_pos.x() + 1, root_pos.y());
+    let target = grid.pos(root_pos.x() + 1, root_pos.y() + 1);
+    let unlocked = {
+        let mut fragments = fragments.clone();
+        let mut fragment_grid = fragment_grid.clone();
+        fill_by_double_side(
+            root_pos,
+            &mut hints.clone(),
+            context,
+            &mut MatchLog::default(),
+            &mut fragments,
+            &mut fragment_grid,
+        )
+        .unwrap();
+        fragment_grid[target].clone().unwrap()
+    };
+    let (locked, rot) = (unlocked.pos, unlocked.rot + Rot::R90);
+    let edge = EdgePos::new(fragment_grid[anchor].as_ref().unwrap().pos, Dir::South);
+    hints.push_locked_pair(edge, LockedPairs::new(vec![(locked, rot)]));
+
+    fill_by_double_side(
+        root_pos,
+        &mut hints,
+        context,
+        &mut log,
+        &mut fragments,
+        &mut fragment_grid,
+    )
+    .unwrap();
+
+    let placed = fragment_grid[target].as_ref().unwrap();
+    assert_eq!((locked, rot), (placed.pos, placed.rot));
+    assert_eq!(
+        1,
+        fragment_grid
+            .iter()
+            .filter(|cell| matches!(cell, Some(f) if f.pos == locked))
+            .count()
+    );
+    assert!(log.unapplied_locks.is_empty());
+    // 使った組は呼び出し元のヒントからも消え, 後の段階で二度つなげない
+    assert!(hints.locked_pairs_of(edge).is_none());
+}
@@ -0,0 +1,329 @@
+//! 平坦な領域などから切り出した, 見分けのつかない断片を見つける.
+//!
+//! そのような断片は 1 辺や 2 辺だけではどれを置いても同じように合ってしまうので, 他のマスをすべて埋めてから,
+//! 周りの辺がすべてわかった状態で置き直す.
+
+use std::collections::HashMap;
+
+use super::{
+    average_distance, soft_penalty, EdgePos, FillContext, MatchLog, Metric, Provenance,
+    ResolveHints,
+};
+use crate::{
+    basis::{Dir, Rot},
+    fragment::Fragment,
+    grid::{Grid, Pos, VecOnGrid},
+};
+
+/// 断片を縮小した画像の一辺のブロック数
+const BLOCKS: usize = 4;
+/// 縮小した画像の色の各成分をこの幅で量子化してから比べる
+const QUANTUM: u8 = 16;
+
+/// 断片を `BLOCKS` x `BLOCKS` に縮小して色を量子化したもの.
+///
+/// 4 通りの回転のうち辞書順で最小のものを使うので, 回転させた断片とも一致する.
+fn fingerprint(fragment: &Fragment) -> Vec<[u8; 3]> {
+    let mut fragment = fragment.clone();
+    let side = fragment.side_length();
+    let pixels = fragment.pixels();
+
+    let span = |i: usize| i * side / BLOCKS..((i + 1) * side / BLOCKS).max(i * side / BLOCKS + 1);
+    let mut blocks = vec![];
+    for by in 0..BLOCKS {
+        for bx in 0..BLOCKS {
+            let (mut sum, mut count) = ([0u32; 3], 0);
+            for y in span(by) {
+                for x in span(bx) {
+                    let c = pixels[x + y * side];
+                    sum[0] += c.r as u32;
+                    sum[1] += c.g as u32;
+                    sum[2] += c.b as u32;
+                    count += 1;
+                }
+            }
+            blocks.push(sum.map(|s| (s / count) as u8 / QUANTUM));
+        }
+    }
+
+    let rotate = |blocks: &[[u8; 3]]| -> Vec<[u8; 3]> {
+        (0..BLOCKS * BLOCKS)
+            .map(|i| blocks[(BLOCKS - 1 - i % BLOCKS) * BLOCKS + i / BLOCKS])
+            .collect()
+    };
+    let mut min = blocks.clone();
+    for _ in 0..3 {
+        blocks = rotate(&blocks);
+        min = min.min(blocks.clone());
+    }
+    min
+}
+
+/// 縮小した画像が一致する断片の組を, 2 つ以上の断片を含むものだけ返す.
+pub(super) fn find_duplicates(fragments: &[Fragment]) -> Vec<Vec<Pos>> {
+    let mut clusters = HashMap::<_, Vec<Pos>>::new();
+    for fragment in fragments {
+        clusters
+            .entry(fingerprint(fragment))
+            .or_default()
+            .push(fragment.pos);
+    }
+    let mut clusters: Vec<_> = clusters
+        .into_values()
+        .filter(|cluster| 2 <= cluster.len())
+        .map(|mut cluster| {
+            cluster.sort();
+            cluster
+        })
+        .collect();
+    clusters.sort();
+    clusters
+}
+
+/// `pos` の `dir` 向きの隣のマス. 盤面の外なら `None` を返す.
+pub(super) fn neighbor(grid: Grid, pos: Pos, dir: Dir) -> Option<Pos> {
+    let (x, y) = (pos.x(), pos.y());
+    match dir {
+        Dir::North if 0 < y => Some(grid.pos(x, y - 1)),
+        Dir::East if x + 1 < grid.width() => Some(grid.pos(x + 1, y)),
+        Dir::South if y + 1 < grid.height() => Some(grid.pos(x, y + 1)),
+        Dir::West if 0 < x => Some(grid.pos(x - 1, y)),
+        _ => None,
+    }
+}
+
+/// `candidate` を `rot` だけ回転させて `pos` に置いたときの, 埋まっている隣の断片の数とそれらの辺との一致度の平均.
+///
+/// ブロックリストに入った辺と接するなら `None` を返す.
+pub(super) fn score_at(
+    fragment_grid: &VecOnGrid<Option<Fragment>>,
+    hints: &ResolveHints,
+    metric: Metric,
+    pos: Pos,
+    (candidate, rot): (&Fragment, Rot),
+) -> Option<(u8, f64)> {
+    // 回転させた後に dir を向く辺の, 今の向き
+    let current_dir = |dir: Dir| dir.rotate(rot.inverse());
+    // ブロックリストは回転させる前の断片の辺の向きで持っている
+    let original_dir = |dir: Dir| dir.rotate((rot + candidate.rot).inverse());
+
+    let (mut neighbors, mut sum) = (0, 0.0);
+    for dir in [Dir::North, Dir::East, Dir::South, Dir::West] {
+        let reference =
+            match neighbor(fragment_grid.grid, pos, dir).and_then(|p| fragment_grid[p].as_ref()) {
+                Some(reference) => reference,
+                None => continue,
+            };
+        let blocked = hints.is_blocked(
+            reference.pos,
+            EdgePos::new(candidate.pos, original_dir(dir)),
+        );
+        if blocked {
+            return None;
+        }
+        neighbors += 1;
+        sum += average_distance(
+            metric,
+            reference.edges.edge(dir.opposite()).pixels.iter(),
+            candidate.edges.edge(current_dir(dir)).pixels.iter().rev(),
+        ) * soft_penalty(
+            hints.soft_blocklist_of(reference.pos),
+            candidate.pos,
+            original_dir(dir),
+        );
+    }
+    Some((neighbors, sum / neighbors.max(1) as f64))
+}
+
+/// `clusters` の断片を置いたマスを空け, 埋まっている隣の断片が多く, その辺と最も合うマスと断片の組から順に置き直す.
+///
+/// root の断片とロックされた組で置いた断片は動かさず, 置き直す断片は回転が `context.rots` にある向きにだけ置く.
+/// `context.abandon` でやめるよう求められたら, 1 つ置き直すごとに確かめてやめる. そのとき盤面は空いたマスが残ったままになる.
+pub(super) fn refill_duplicates(
+    clusters: &[Vec<Pos>],
+    fragment_grid: &mut VecOnGrid<Option<Fragment>>,
+    hints: &ResolveHints,
+    context: FillContext<'_>,
+    log: &mut MatchLog,
+) -> crate::Result<()> {
+    let FillContext {
+        metric,
+        rots,
+        abandon,
+        ..
+    } = context;
+    for cluster in clusters {
+        let mut cells = vec![];
+        let mut fragments = vec![];
+        for pos in fragment_grid.grid.all_pos() {
+            let movable = match &fragment_grid[pos] {
+                Some(fragment) => {
+                    cluster.contains(&fragment.pos)
+                        && !matches!(
+                            log.provenances.get(&fragment.pos),
+                            Some(Provenance::Root | Provenance::Locked { .. })
+                        )
+                }
+                None => false,
+            };
+            if movable {
+                cells.push(pos);
+                fragments.push(fragment_grid[pos].take().unwrap());
+            }
+        }
+        tracing::debug!(?cluster, cells = cells.len(), "refilling duplicates");
+
+        while !cells.is_empty() {
+            if abandon.is_requested() {
+                return Err(abandon.error());
+            }
+            let mut best: Option<(usize, usize, Rot, u8, f64)> = None;
+            for (i, &pos) in cells.iter().enumerate() {
+                for (j, fragment) in fragments.iter().enumerate() {
+                    for &rot in &super::ROTS {
+                        if !rots.contains(&(fragment.rot + rot)) {
+                            continue;
+                        }
+                        let (neighbors, score) =
+                            match score_at(fragment_grid, hints, metric, pos, (fragment, rot)) {
+                                Some(v) => v,
+                                None => continue,
+                            };
+                        let better = match best {
+                            Some((_, _, _, n, s)) => n < neighbors || (n == neighbors && score < s),
+                            None => true,
+                        };
+                        if better {
+                            best = Some((i, j, rot, neighbors, score));
+                        }
+                    }
+                }
+            }
+
+            // すべての向きがブロックされていれば, 残りを元の順に戻す
+            let (i, j, rot, neighbors, score) = match best {
+                Some(v) => v,
+                None => {
+                    tracing::warn!(?cluster, "couldn't refill duplicates because of blocklist");
+                    for (pos, fragment) in cells.drain(..).zip(fragments.drain(..)) {
+                        fragment_grid[pos] = Some(fragment);
+                    }
+                    break;
+                }
+            };
+            let pos = cells.remove(i);
+            let mut fragment = fragments.remove(j);
+            fragment.rotate(rot);
+            log.provenances
+                .insert(fragment.pos, Provenance::Deferred { neighbors, score });
+            fragment_grid[pos] = Some(fragment);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn flat_problem() -> crate::basis::Problem {
+    use crate::basis::{Color, Image, Problem};
+
+    // 3x2 の盤面のうち, 左の列だけに模様があり, 残りの 4 つはわずかにむらのある灰色の断片
+    let (side, width, height) = (8, 24, 16);
+    let mut pixels = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(if x < side {
+                Color {
+                    r: (x * 30) as u8,
+                    g: (y * 15) as u8,
+                    b: 200,
+                }
+            } else {
+                let v = 100 + ((x * 7 + y * 3) % 4) as u8;
+                Color { r: v, g: v, b: v }
+            });
+        }
+    }
+    Problem {
+        select_limit: 2,
+        select_cost: 1,
+        swap_cost: 1,
+        dims: crate::grid::Dims {
+            width: 3,
+            height: 2,
+        },
+        image: Image {
+            width: width as u16,
+            height: height as u16,
+            pixels,
+        },
+    }
+}
+
+#[test]
+fn test_find_duplicates() {
+    let problem = flat_problem();
+    let grid = problem.grid();
+    let mut fragments = Fragment::new_all(&problem).unwrap();
+    // 回転させた断片も見分けがつかない
+    fragments[2].rotate(Rot::R90);
+
+    assert_eq!(
+        vec![vec![
+            grid.pos(1, 0),
+            grid.pos(1, 1),
+            grid.pos(2, 0),
+            grid.pos(2, 1)
+        ]],
+        find_duplicates(&fragments)
+    );
+}
+
+#[test]
+fn test_refill_duplicates() {
+    let problem = flat_problem();
+    let grid = problem.grid();
+    let (recovered, _, log) = super::solve(
+        Fragment::new_all(&problem).unwrap(),
+        grid,
+        ResolveHints::default(),
+        (Metric::Euclidean, super::DoubleSideScoring::default()),
+        &super::ROTS,
+    )
+    .unwrap();
+
+    let mut placed: Vec<_> = recovered.iter().map(|f| f.as_ref().unwrap().pos).collect();
+    placed.sort();
+    let mut all: Vec<_> = grid.all_pos().collect();
+    all.sort();
+    assert_eq!(all, placed);
+
+    for pos in [(1, 0), (1, 1), (2, 0), (2, 1)] {
+        let pos = grid.pos(pos.0, pos.1);
+        assert!(matches!(
+            log.provenances[&pos],
+            Provenance::Deferred { neighbors, .. } if 1 <= neighbors
+        ));
+    }
+    // やめるよう求められていれば, 置き直す前にやめる
+    let cancel = crate::cancel::CancellationToken::new();
+    cancel.cancel();
+    let context = FillContext {
+        abandon: super::Abandon::on_cancel(Some(&cancel)),
+        ..FillContext::new(
+            Metric::Euclidean,
+            super::DoubleSideScoring::default(),
+            &super::ROTS,
+        )
+    };
+    let mut cancelled = recovered.clone();
+    let mut cancelled_log = log.clone();
+    let clusters = find_duplicates(&Fragment::new_all(&problem).unwrap());
+    let result = refill_duplicates(
+        &clusters,
+        &mut cancelled,
+        &ResolveHints::default(),
+        context,
+        &mut cancelled_log,
+    );
+    assert!(matches!(result, Err(crate::Error::Cancelled)));
+}
@@ -0,0 +1,143 @@
+//! 断片の辺どうしの一致度をすべての組について求め, 外部で分析できるように書き出す.
+
+use std::{fmt::Write as _, io};
+
+use rayon::prelude::*;
+
+use super::{average_distance, Metric};
+use crate::{basis::Dir, fragment::Fragment, grid::Pos};
+
+const DIRS: [Dir; 4] = [Dir::North, Dir::East, Dir::South, Dir::West];
+
+/// `EdgeScores` は断片のすべての辺の組について, pixel_match と同じ方法で測った色距離の平均を表す.
+///
+/// 辺は与えた断片の順に, 各断片について北, 東, 南, 西の順に並ぶ. 同じ断片の辺どうしは NaN にする.
+#[derive(Debug, Clone)]
+pub struct EdgeScores {
+    edges: Vec<(Pos, Dir)>,
+    scores: Vec<f64>,
+}
+
+impl EdgeScores {
+    pub fn new(fragments: &[Fragment], metric: Metric) -> Self {
+        let edges: Vec<_> = fragments
+            .iter()
+            .flat_map(|f| DIRS.iter().map(move |&dir| (f, dir)))
+            .collect();
+        let scores = edges
+            .par_iter()
+            .flat_map_iter(|&(a, a_dir)| {
+                edges.iter().map(move |&(b, b_dir)| {
+                    if a.pos == b.pos {
+                        return f64::NAN;
+                    }
+                    average_distance(
+                        metric,
+                        a.edges.edge(a_dir).pixels.iter(),
+                        b.edges.edge(b_dir).pixels.iter().rev(),
+                    )
+                })
+            })
+            .collect();
+        Self {
+            edges: edges.into_iter().map(|(f, dir)| (f.pos, dir)).collect(),
+            scores,
+        }
+    }
+
+    /// 辺の数. 行列は `len` x `len` になる.
+    pub fn len(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+
+    /// 行列の各行と各列に対応する辺.
+    pub fn edges(&self) -> &[(Pos, Dir)] {
+        &self.edges
+    }
+
+    pub fn score(&self, row: usize, col: usize) -> f64 {
+        self.scores[row * self.len() + col]
+    }
+
+    /// 1 行目と 1 列目に `00N` のような辺の名前を付けた CSV にする. NaN は空欄にする.
+    pub fn to_csv(&self) -> String {
+        let labels: Vec<_> = self.edges.iter().map(|&e| edge_label(e)).collect();
+        let mut result = format!("edge,{}\n", labels.join(","));
+        for (row, label) in labels.iter().enumerate() {
+            result.push_str(label);
+            for col in 0..self.len() {
+                let score = self.score(row, col);
+                result.push(',');
+                if !score.is_nan() {
+                    let _ = write!(result, "{}", score);
+                }
+            }
+            result.push('\n');
+        }
+        result
+    }
+
+    /// NumPy の `.npy` 形式 (version 1.0) の `float64` の 2 次元配列として書き出す.
+    pub fn write_npy(&self, mut out: impl io::Write) -> io::Result<()> {
+        let dict = format!(
+            "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}), }}",
+            self.len(),
+            self.len()
+        );
+        // magic (6) + version (2) + header length (2) + header が 64 の倍数になるよう空白で埋める
+        let padding = 63 - (10 + dict.len()) % 64;
+        let header = format!("{}{}\n", dict, " ".repeat(padding));
+
+        out.write_all(b"\x93NUMPY\x01\x00")?;
+        out.write_all(&(header.len() as u16).to_le_bytes())?;
+        out.write_all(header.as_bytes())?;
+        for score in &self.scores {
+            out.write_all(&score.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn edge_label((pos, dir): (Pos, Dir)) -> String {
+    let dir = match dir {
+        Dir::North => 'N',
+        Dir::East => 'E',
+        Dir::South => 'S',
+        Dir::West => 'W',
+    };
+    format!("{:X}{:X}{}", pos.x(), pos.y(), dir)
+}
+
+#[test]
+fn test_edge_scores() {
+    let problem = include_bytes!("../../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let scores = EdgeScores::new(&Fragment::new_all(&problem).unwrap(), Metric::Euclidean);
+    assert_eq!(16, scores.len());
+    for row in 0..scores.len() {
+        for col in 0..scores.len() {
+            let same_fragment = row / 4 == col / 4;
+            assert_eq!(same_fragment, scores.score(row, col).is_nan());
+            if !same_fragment {
+                assert_eq!(scores.score(row, col), scores.score(col, row));
+            }
+        }
+    }
+
+    let csv = scores.to_csv();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(17, lines.len());
+    assert!(lines[0].starts_with("edge,00N,00E,00S,00W,"));
+    assert!(lines[1].starts_with("00N,,,,,"));
+
+    let mut npy = vec![];
+    scores.write_npy(&mut npy).unwrap();
+    let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+    assert_eq!(0, (10 + header_len) % 64);
+    assert_eq!(10 + header_len + 16 * 16 * 8, npy.len());
+    assert_eq!(b'\n', npy[9 + header_len]);
+}
@@ -0,0 +1,498 @@
+//! 遺伝的アルゴリズムで断片の配置を探す. 貪欲法とヒントでも復元できない, 特に難しい問題のためのもの.
+//!
+//! shaker で並べた配置を種にして個体を作り, よく合う継ぎ目の続く長方形の範囲を親から受け継ぐ交叉と,
+//! 周りと合っていない断片を入れ替える突然変異を繰り返す. 個体の良さは継ぎ目の一致度の合計で測る.
+
+use std::{collections::HashMap, time::Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{
+    average_distance, soft_penalty, Abandon, EdgePos, FillContext, MatchLog, Metric, Provenance,
+    ResolveHints, SolveOptions, Solved,
+};
+use crate::{
+    basis::{Dir, Rot},
+    fragment::Fragment,
+    grid::{Grid, Pos, VecOnGrid},
+};
+
+const DIRS: [Dir; 4] = [Dir::North, Dir::East, Dir::South, Dir::West];
+
+/// ブロックリストに入った組の継ぎ目の一致度. どんな継ぎ目よりも悪くする
+const BLOCKED: f64 = 1e6;
+/// 交叉で受け継ぐ長方形の範囲を選ぶときに比べる候補の数
+const BLOCK_CANDIDATES: usize = 8;
+/// 次の世代にそのまま残す, 最も良い個体の数
+const ELITES: usize = 2;
+/// 親を選ぶトーナメントの大きさ
+const TOURNAMENT: usize = 3;
+
+/// `GeneticParam` は遺伝的アルゴリズムの個体数と世代数を表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeneticParam {
+    pub population: usize,
+    pub generations: usize,
+}
+
+impl Default for GeneticParam {
+    fn default() -> Self {
+        Self {
+            population: 50,
+            generations: 200,
+        }
+    }
+}
+
+/// `SeamTable` はすべての断片の辺の組の継ぎ目の一致度を前もって求めたもの.
+struct SeamTable {
+    grid: Grid,
+    num_fragments: usize,
+    table: Vec<f64>,
+}
+
+impl SeamTable {
+    fn new(fragments: &[Fragment], grid: Grid, hints: &ResolveHints, metric: Metric) -> Self {
+        let num_edges = fragments.len() * 4;
+        let mut table = vec![0.0; num_edges * num_edges];
+        for (i, a) in fragments.iter().enumerate() {
+            for (j, b) in fragments.iter().enumerate() {
+                if i == j {
+                    continue;
+                }
+                for a_dir in DIRS {
+                    for b_dir in DIRS {
+                        let blocked = hints.is_blocked(a.pos, EdgePos::new(b.pos, b_dir))
+                            || hints.is_blocked(b.pos, EdgePos::new(a.pos, a_dir));
+                        let score = if blocked {
+                            BLOCKED
+                        } else {
+                            average_distance(
+                                metric,
+                                a.edges.edge(a_dir).pixels.iter(),
+                                b.edges.edge(b_dir).pixels.iter().rev(),
+                            ) * soft_penalty(hints.soft_blocklist_of(a.pos), b.pos, b_dir)
+                                * soft_penalty(hints.soft_blocklist_of(b.pos), a.pos, a_dir)
+                        };
+                        table[(i * 4 + a_dir.as_num() as usize) * num_edges
+                            + j * 4
+                            + b_dir.as_num() as usize] = score;
+                    }
+                }
+            }
+        }
+        Self {
+            grid,
+            num_fragments: fragments.len(),
+            table,
+        }
+    }
+
+    /// 断片 `a` の `dir` 向きの隣に断片 `b` を置いたときの継ぎ目の一致度.
+    fn seam(&self, (a, a_rot): Gene, (b, b_rot): Gene, dir: Dir) -> f64 {
+        let a_edge = a * 4 + dir.rotate(a_rot.inverse()).as_num() as usize;
+        let b_edge = b * 4 + dir.opposite().rotate(b_rot.inverse()).as_num() as usize;
+        self.table[a_edge * self.num_fragments * 4 + b_edge]
+    }
+
+    fn index(&self, pos: Pos) -> usize {
+        pos.y() as usize * self.grid.width() as usize + pos.x() as usize
+    }
+
+    /// `cells` の `index` のマスと, その隣のマスとの継ぎ目の一致度の合計.
+    fn cell_energy(&self, cells: &[Gene], index: usize) -> f64 {
+        let width = self.grid.width() as usize;
+        let pos = self.grid.pos((index % width) as u8, (index / width) as u8);
+        DIRS.iter()
+            .filter_map(|&dir| {
+                let other = super::duplicates::neighbor(self.grid, pos, dir)?;
+                Some(self.seam(cells[index], cells[self.index(other)], dir))
+            })
+            .sum()
+    }
+
+    fn energy(&self, cells: &[Gene]) -> f64 {
+        let width = self.grid.width() as usize;
+        let mut energy = 0.0;
+        for (index, &gene) in cells.iter().enumerate() {
+            if index % width + 1 < width {
+                energy += self.seam(gene, cells[index + 1], Dir::East);
+            }
+            if index + width < cells.len() {
+                energy += self.seam(gene, cells[index + width], Dir::South);
+            }
+        }
+        energy
+    }
+}
+
+/// マスに置く断片の番号と, その断片を回転させる角度
+type Gene = (usize, Rot);
+
+/// `Individual` は盤面の左上から行ごとに並べた, 各マスに置く断片.
+#[derive(Debug, Clone)]
+struct Individual {
+    cells: Vec<Gene>,
+    energy: f64,
+}
+
+impl Individual {
+    fn new(table: &SeamTable, cells: Vec<Gene>) -> Self {
+        let energy = table.energy(&cells);
+        Self { cells, energy }
+    }
+}
+
+/// `Evolution` は個体を作り替えるのに必要なものをまとめたもの.
+struct Evolution<'a> {
+    table: &'a SeamTable,
+    /// root の断片やロックされた組の両端の断片が無い, 動かしてよいマスの番号
+    movable: &'a [usize],
+    /// 断片の回転として試すもの
+    rots: &'a [Rot],
+    rng: StdRng,
+    /// やめるよう求められたら, 個体を作るのや世代を進めるのをやめてそれまでで最も良い個体を返す
+    abandon: Abandon<'a>,
+    /// これを過ぎたら世代を進めるのをやめてそれまでで最も良い個体を返す
+    deadline: Option<Instant>,
+}
+
+impl Evolution<'_> {
+    fn random_movable(&mut self) -> usize {
+        self.movable[self.rng.gen_range(0..self.movable.len())]
+    }
+
+    /// `index` のマスの断片を, `rots` のうち隣の断片と最もよく合う向きに回転させる.
+    fn best_rotation(&self, cells: &mut [Gene], index: usize) {
+        let fragment = cells[index].0;
+        let best = self
+            .rots
+            .iter()
+            .map(|&rot| {
+                cells[index] = (fragment, rot);
+                (rot, self.table.cell_energy(cells, index))
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .unwrap()
+            .0;
+        cells[index] = (fragment, best);
+    }
+
+    /// 動かしてよいマスから無作為に選んだ中で最も周りと合っていない断片を, 別のマスの断片と入れ替える.
+    fn mutate(&mut self, cells: &mut [Gene]) {
+        let picked: Vec<_> = (0..TOURNAMENT).map(|_| self.random_movable()).collect();
+        let worst = picked
+            .into_iter()
+            .max_by(|&a, &b| {
+                let (a, b) = (
+                    self.table.cell_energy(cells, a),
+                    self.table.cell_energy(cells, b),
+                );
+                a.partial_cmp(&b).unwrap()
+            })
+            .unwrap();
+        let other = self.random_movable();
+        cells.swap(worst, other);
+        self.best_rotation(cells, worst);
+        self.best_rotation(cells, other);
+    }
+
+    /// `b` の中で継ぎ目がよく合う長方形の範囲を `a` に移す. 移したことで重なった断片は, `a` のその範囲にあった断片で置き換える.
+    fn crossover(&mut self, a: &Individual, b: &Individual) -> Vec<Gene> {
+        let grid = self.table.grid;
+        let (width, height) = (grid.width() as usize, grid.height() as usize);
+        let mut best: Option<(Vec<usize>, f64)> = None;
+        for _ in 0..BLOCK_CANDIDATES {
+            let (w, h) = (
+                self.rng.gen_range(1..=width.div_ceil(2)),
+                self.rng.gen_range(1..=height.div_ceil(2)),
+            );
+            let (x, y) = (
+                self.rng.gen_range(0..=width - w),
+                self.rng.gen_range(0..=height - h),
+            );
+            let block: Vec<_> = (y..y + h)
+                .flat_map(|y| (x..x + w).map(move |x| y * width + x))
+                .collect();
+            let seams = (w - 1) * h + w * (h - 1);
+            let score = if seams == 0 {
+                f64::MAX
+            } else {
+                let internal: f64 = block
+                    .iter()
+                    .map(|&i| {
+                        let mut sum = 0.0;
+                        if block.contains(&(i + 1)) && (i + 1) % width != 0 {
+                            sum += self.table.seam(b.cells[i], b.cells[i + 1], Dir::East);
+                        }
+                        if block.contains(&(i + width)) {
+                            sum += self.table.seam(b.cells[i], b.cells[i + width], Dir::South);
+                        }
+                        sum
+                    })
+                    .sum();
+                internal / seams as f64
+            };
+            if best.as_ref().is_none_or(|(_, s)| score < *s) {
+                best = Some((block, score));
+            }
+        }
+        let (block, _) = best.unwrap();
+
+        let mut child = a.cells.clone();
+        let from_b: Vec<_> = block.iter().map(|&i| b.cells[i].0).collect();
+        // a の範囲にあって b の範囲に無い断片は, 範囲の外で重なった断片の代わりに置く
+        let mut lost = block
+            .iter()
+            .map(|&i| a.cells[i])
+            .filter(|(fragment, _)| !from_b.contains(fragment));
+        for &i in &block {
+            child[i] = b.cells[i];
+        }
+        for (i, gene) in child.iter_mut().enumerate() {
+            if !block.contains(&i) && from_b.contains(&gene.0) {
+                *gene = lost.next().unwrap();
+            }
+        }
+        child
+    }
+
+    fn select<'p>(&mut self, population: &'p [Individual]) -> &'p Individual {
+        (0..TOURNAMENT)
+            .map(|_| &population[self.rng.gen_range(0..population.len())])
+            .min_by(|a, b| a.energy.partial_cmp(&b.energy).unwrap())
+            .unwrap()
+    }
+
+    /// `seed` とそれを少しずつ変えた個体から始めて, `param` の世代数だけ進化させた最も良い個体を返す.
+    fn evolve(&mut self, seed: Individual, param: GeneticParam) -> Individual {
+        if self.movable.len() < 2 {
+            return seed;
+        }
+        let mut population = vec![seed.clone()];
+        for i in 1..param.population.max(ELITES + 1) {
+            if self.abandon.is_requested() {
+                break;
+            }
+            let mut cells = seed.cells.clone();
+            for _ in 0..1 + i % 5 {
+                self.mutate(&mut cells);
+            }
+            population.push(Individual::new(self.table, cells));
+        }
+
+        for generation in 0..param.generations {
+            if self.abandon.is_requested() {
+                tracing::info!(generation, "stopped evolving on request");
+                break;
+            }
+            if matches!(self.deadline, Some(deadline) if deadline <= Instant::now()) {
+                tracing::info!(generation, "stopped evolving at the deadline");
+                break;
+            }
+            population.sort_by(|a, b| a.energy.partial_cmp(&b.energy).unwrap());
+            tracing::trace!(generation, best = population[0].energy, "evolving");
+            let mut next = population[..ELITES].to_vec();
+            while next.len() < population.len() {
+                let (a, b) = (self.select(&population), self.select(&population));
+                let mut child = self.crossover(a, b);
+                if self.rng.gen_bool(0.5) {
+                    self.mutate(&mut child);
+                }
+                next.push(Individual::new(self.table, child));
+            }
+            population = next;
+        }
+        population
+            .into_iter()
+            .min_by(|a, b| a.energy.partial_cmp(&b.energy).unwrap())
+            .unwrap()
+    }
+}
+
+/// shaker で並べた配置を種にして, 遺伝的アルゴリズムで継ぎ目の一致度の合計が小さい配置を探す.
+///
+/// root の断片とロックされた組の両端の断片は動かさない. 種から動かした断片の根拠は `Provenance::Refined` にする.
+/// `abandon` でやめるよう求められたら, 種を並べる途中なら `Abandon::error` を返し, 進化の途中ならそこまでの結果を返す.
+/// `deadline` を過ぎたときも, 進化の途中ならそこまでの結果を返す.
+#[tracing::instrument(name = "pixel_match_genetic", skip_all)]
+pub(super) fn solve(
+    fragments: Vec<Fragment>,
+    grid: Grid,
+    hints: ResolveHints,
+    options: SolveOptions,
+    abandon: Abandon<'_>,
+    deadline: Option<Instant>,
+) -> crate::Result<Solved> {
+    let context = FillContext {
+        abandon,
+        ..FillContext::new(options.metric, options.double_side, options.rots())
+    };
+    let (seed_grid, _, mut log) =
+        super::solve_reusing(fragments.clone(), grid, hints.clone(), context, &mut None)?;
+    // 継ぎ目の表を作るのは重いので, 種を並べる途中でやめたときは作らない
+    let table = SeamTable::new(&fragments, grid, &hints, options.metric);
+
+    let index_of: HashMap<_, _> = fragments
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (f.pos, i))
+        .collect();
+    let seed: Vec<Gene> = grid
+        .all_pos()
+        .map(|pos| {
+            let fragment = seed_grid[pos].as_ref().unwrap();
+            (index_of[&fragment.pos], fragment.rot)
+        })
+        .collect();
+    let pinned = log.pinned_fragments(&hints);
+    let movable: Vec<_> = grid
+        .all_pos()
+        .filter(|&pos| !pinned.contains(&seed_grid[pos].as_ref().unwrap().pos))
+        .map(|pos| table.index(pos))
+        .collect();
+
+    let seed = Individual::new(&table, seed);
+    let seed_energy = seed.energy;
+    let best = Evolution {
+        table: &table,
+        movable: &movable,
+        rots: options.rots(),
+        rng: StdRng::seed_from_u64(0),
+        abandon,
+        deadline,
+    }
+    .evolve(seed.clone(), options.genetic);
+    tracing::info!(
+        seed_energy,
+        best_energy = best.energy,
+        "evolved the arrangement"
+    );
+
+    mark_refined(&fragments, &seed, &best, &mut log);
+    let mut fragment_grid = VecOnGrid::<Option<Fragment>>::with_default(grid);
+    let mut root_pos = grid.pos(0, 0);
+    for (pos, &(index, rot)) in grid.all_pos().zip(&best.cells) {
+        let mut fragment = fragments[index].clone();
+        fragment.rotate(rot);
+        if fragment.pos == grid.pos(0, 0) {
+            root_pos = pos;
+        }
+        fragment_grid[pos] = Some(fragment);
+    }
+    Ok((fragment_grid, root_pos, log))
+}
+
+/// 種から位置か向きの変わった断片の根拠を `Provenance::Refined` にする.
+fn mark_refined(fragments: &[Fragment], seed: &Individual, best: &Individual, log: &mut MatchLog) {
+    for (&before, &after) in seed.cells.iter().zip(&best.cells) {
+        if before != after {
+            log.provenances
+                .insert(fragments[after.0].pos, Provenance::Refined);
+        }
+    }
+}
+
+#[test]
+fn test_crossover_keeps_permutation() {
+    let problem = crate::scramble::smooth_problem(4, 3);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+    let table = SeamTable::new(
+        &fragments,
+        grid,
+        &ResolveHints::default(),
+        Metric::Euclidean,
+    );
+    let movable: Vec<_> = (1..fragments.len()).collect();
+    let mut evolution = Evolution {
+        table: &table,
+        movable: &movable,
+        rots: &super::ROTS,
+        rng: StdRng::seed_from_u64(3),
+        abandon: Abandon::default(),
+        deadline: None,
+    };
+
+    let identity: Vec<_> = (0..fragments.len()).map(|i| (i, Rot::R0)).collect();
+    let a = Individual::new(&table, identity.clone());
+    for _ in 0..20 {
+        let mut cells = identity.clone();
+        for _ in 0..4 {
+            evolution.mutate(&mut cells);
+        }
+        let b = Individual::new(&table, cells);
+        let child = evolution.crossover(&a, &b);
+
+        let mut used: Vec<_> = child.iter().map(|&(i, _)| i).collect();
+        used.sort_unstable();
+        assert_eq!((0..fragments.len()).collect::<Vec<_>>(), used);
+        assert_eq!((0, Rot::R0), child[0], "the root must not move");
+    }
+}
+
+#[test]
+fn test_evolve() {
+    let problem = crate::scramble::smooth_problem(4, 3);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+    let table = SeamTable::new(
+        &fragments,
+        grid,
+        &ResolveHints::default(),
+        Metric::Euclidean,
+    );
+    let movable: Vec<_> = (1..fragments.len()).collect();
+
+    // 正しい配置から断片を入れ替えて回転させた種からでも, 正しい配置に戻せる
+    let mut seed: Vec<_> = (0..fragments.len()).map(|i| (i, Rot::R0)).collect();
+    seed.swap(2, 9);
+    seed.swap(5, 11);
+    seed[7].1 = Rot::R180;
+    let seed = Individual::new(&table, seed);
+    let best = Evolution {
+        table: &table,
+        movable: &movable,
+        rots: &super::ROTS,
+        rng: StdRng::seed_from_u64(0),
+        abandon: Abandon::default(),
+        deadline: None,
+    }
+    .evolve(
+        seed.clone(),
+        GeneticParam {
+            population: 30,
+            generations: 50,
+        },
+    );
+    assert!(best.energy < seed.energy);
+    let expected: Vec<_> = (0..fragments.len()).map(|i| (i, Rot::R0)).collect();
+    assert_eq!(expected, best.cells);
+}
+
+#[test]
+fn test_solve() {
+    let problem = crate::scramble::smooth_problem(4, 3);
+    let grid = problem.grid();
+    let (recovered, root_pos, log) = solve(
+        Fragment::new_all(&problem).unwrap(),
+        grid,
+        ResolveHints::default(),
+        SolveOptions {
+            genetic: GeneticParam {
+                population: 10,
+                generations: 10,
+            },
+            ..SolveOptions::default()
+        },
+        Abandon::default(),
+        None,
+    )
+    .unwrap();
+    assert_eq!(grid.pos(0, 0), root_pos);
+    for (pos, cell) in recovered.iter_with_pos() {
+        let fragment = cell.as_ref().unwrap();
+        assert_eq!((pos, Rot::R0), (fragment.pos, fragment.rot));
+    }
+    assert_eq!(Some(&Provenance::Root), log.provenances.get(&root_pos));
+}
@@ -1,13 +1,16 @@
 use std::{
     borrow::Cow,
+    collections::HashMap,
     ops::{Deref, DerefMut},
-    sync::mpsc::{Receiver, Sender},
-    time::Duration,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
 };
 
 use sdl2::{
-    event::{Event, WindowEvent},
-    keyboard::Keycode,
+    event::Event,
     pixels::Color as SdlColor,
     rect::Rect,
     render::{Canvas, Texture, TextureCreator},
@@ -19,71 +22,93 @@ use sdl2::{
 use crate::{
     basis::{Dir, Rot},
     fragment::Fragment,
-    grid::{Grid, Pos as GridPos, VecOnGrid},
-    pixel_match::gui::image_preview::RecoveredImagePreview,
+    grid::{Grid, Overflow, Pos as GridPos, VecOnGrid},
+    pixel_match::gui::image_preview::{ImageComposer, RecoveredImagePreview},
+    pixel_match::gui::key_bindings::{action_of, Action, BINDINGS},
 };
 
-use super::{LockedPairs, ResolveHints};
+use super::{
+    BlockSuggestion, EdgePos, GuiOptions, HintKey, LockedPairs, Provenance, ResolveHints,
+    WindowSize,
+};
 
 mod arrow_texture;
 mod image_preview;
+mod key_bindings;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub(super) struct EdgePos {
-    pub(super) pos: GridPos,
-    pub(super) dir: Dir,
-}
-
-impl EdgePos {
-    #[inline]
-    pub(super) fn new(pos: GridPos, dir: Dir) -> Self {
-        Self { pos, dir }
-    }
-}
-
-const WINDOW_WIDTH: u32 = 800;
-const WINDOW_HEIGHT: u32 = 800;
+/// 再計算の結果を待っている間に受信を確かめる間隔 (ミリ秒)
+const RESPONSE_POLL_INTERVAL: u32 = 16;
+/// 何も起きていないときにイベントを待つ時間 (ミリ秒). 描画先の大きさの変化はこの間隔で確かめる.
+const IDLE_TIMEOUT: u32 = 500;
 
 pub(super) enum GuiRequest {
-    Recalculate(ResolveHints),
+    /// 要求の番号とヒント. 番号は送るたびに 1 ずつ増える.
+    Recalculate(u64, ResolveHints),
     Quit,
 }
 
+/// GUI への応答. どの番号の要求に応えたものかを持つ.
 pub(super) enum GuiResponse {
-    Recalculated(RecalculateArtifact),
+    Recalculated(u64, RecalculateArtifact),
+    /// 復元に失敗した. その理由を持つ.
+    Failed(u64, String),
+}
+
+impl GuiResponse {
+    fn request_id(&self) -> u64 {
+        match self {
+            GuiResponse::Recalculated(id, _) | GuiResponse::Failed(id, _) => *id,
+        }
+    }
+}
+
+/// 計算を始める前に溜まっていた要求を `first` とまとめる.
+///
+/// 再計算は最新のヒントでだけ行えばよいので, 最後の要求だけを返す. 途中に Quit があればそれを返す.
+pub(super) fn coalesce_requests(first: GuiRequest, rx: &Receiver<GuiRequest>) -> GuiRequest {
+    rx.try_iter().fold(first, |request, newer| match request {
+        GuiRequest::Quit => request,
+        GuiRequest::Recalculate(..) => newer,
+    })
 }
 
 pub(super) struct RecalculateArtifact {
     pub(super) recovered_image: VecOnGrid<Option<Fragment>>,
     pub(super) root_pos: GridPos,
+    /// 取り違えている可能性があり, ブロックリストに入れることを勧める継ぎ目
+    pub(super) suggestions: Vec<BlockSuggestion>,
+    /// 断片の問題画像での位置ごとの, その断片を置いた根拠
+    pub(super) provenances: HashMap<GridPos, Provenance>,
+    /// 復元できるようにするために捨てたヒント. 捨てた順に並ぶ.
+    pub(super) discarded_hints: Vec<HintKey>,
 }
 
 pub(super) struct GuiContext {
     pub(super) tx: Sender<GuiRequest>,
     pub(super) rx: Receiver<GuiResponse>,
+    /// 前回の GUI で与えたヒント. これを引き継いで編集を始める.
+    pub(super) initial_hints: ResolveHints,
+    /// ソフトなブロックリストに入れた組の一致度に掛ける倍率
+    pub(super) soft_penalty: f64,
+    pub(super) options: GuiOptions,
+    /// 断片を回転させずに並べているか. そうなら断片の向きを示す矢印を描かない.
+    pub(super) fixed_rotation: bool,
+    /// 最後に送った要求の番号. 復元中の古い要求はこれを見て途中でやめる.
+    pub(super) latest_request: Arc<AtomicU64>,
 }
 
-pub(super) fn begin(ctx: GuiContext) {
+/// GUI を開き, 閉じたときのウィンドウの大きさを返す.
+pub(super) fn begin(ctx: GuiContext) -> WindowSize {
     let sdl = sdl2::init().expect("failed to initialize sdl");
     let video = sdl.video().expect("failed to initialize video subsystem");
     let ttf = sdl2::ttf::init().expect("failed to initialize ttf subsystem");
 
-    let ttf_bytes = include_bytes!("../../mplus-1m-medium.ttf");
-
-    let font_ttf = RWops::from_bytes(ttf_bytes).expect("failed to create rwops");
-    let big_font = ttf
-        .load_font_from_rwops(font_ttf, 30)
-        .expect("failed to load font");
-
-    let font_ttf = RWops::from_bytes(ttf_bytes).expect("failed to create rwops");
-    let small_font = ttf
-        .load_font_from_rwops(font_ttf, 12)
-        .expect("failed to load font");
-
+    let GuiOptions { window_size, scale } = ctx.options;
     let mut canvas = video
-        .window("procon2021_comp", WINDOW_WIDTH, WINDOW_HEIGHT)
+        .window("procon2021_comp", window_size.width, window_size.height)
         .position_centered()
         .resizable()
+        .allow_highdpi()
         .opengl()
         .build()
         .unwrap()
@@ -91,15 +116,38 @@ pub(super) fn begin(ctx: GuiContext) {
         .build()
         .unwrap();
 
+    // HiDPI の画面では描画先の画素数がウィンドウの大きさより多いので, その分だけ文字も大きくする
+    let output_size = canvas.output_size().unwrap();
+    let dpi_scale = output_size.0 as f64 / canvas.window().size().0.max(1) as f64;
+    let font_size = |size: f64| (size * scale * dpi_scale).round().max(1.0) as u16;
+
+    let ttf_bytes = include_bytes!("../../mplus-1m-medium.ttf");
+
+    let font_ttf = RWops::from_bytes(ttf_bytes).expect("failed to create rwops");
+    let big_font = ttf
+        .load_font_from_rwops(font_ttf, font_size(30.0))
+        .expect("failed to load font");
+
+    let font_ttf = RWops::from_bytes(ttf_bytes).expect("failed to create rwops");
+    let small_font = ttf
+        .load_font_from_rwops(font_ttf, font_size(12.0))
+        .expect("failed to load font");
+
     let texture_creator = canvas.texture_creator();
 
+    let hints = ctx.initial_hints.clone();
     let mut state = GuiState {
         running: true,
-        window_size: (WINDOW_WIDTH, WINDOW_HEIGHT),
+        window_size: output_size,
         ctx,
-        hints: ResolveHints::default(),
+        hints,
         hints_edit_history: vec![],
         hints_updated: false,
+        show_problem_image: false,
+        show_help: false,
+        failure: None,
+        request_id: 0,
+        awaiting_response: true,
     };
 
     let mut renderer = Renderer {
@@ -111,9 +159,27 @@ pub(super) fn begin(ctx: GuiContext) {
     };
 
     let mut preview: Option<RecoveredImagePreview> = None;
+    let mut image_composer = ImageComposer::default();
+
+    let mut event_pump = sdl.event_pump().unwrap();
+    // 最初の 1 回は必ず描く
+    let mut redraw = true;
 
     loop {
-        for event in sdl.event_pump().unwrap().poll_iter() {
+        // 再計算の結果は SDL のイベントにならないので, 待っている間だけは短い間隔で受信を確かめる
+        let timeout = if state.awaiting_response {
+            RESPONSE_POLL_INTERVAL
+        } else {
+            IDLE_TIMEOUT
+        };
+        let first_event = event_pump.wait_event_timeout(timeout);
+        let events: Vec<_> = first_event
+            .into_iter()
+            .chain(event_pump.poll_iter())
+            .collect();
+
+        for event in events {
+            redraw = true;
             state.process_sdl_event(&event);
 
             if let Some(ref mut preview) = preview {
@@ -127,56 +193,101 @@ pub(super) fn begin(ctx: GuiContext) {
 
         if state.hints_updated {
             preview = None;
+            redraw = true;
             state.send_recalculate_request();
         }
 
-        renderer.set_draw_color(SdlColor::BLACK);
-        renderer.clear();
-
-        if let Some(ref preview) = preview {
-            preview.render(&mut renderer, &state);
-        } else {
-            WaitingMessage.render(&mut renderer);
+        if state.awaiting_response {
+            // 古い要求への応答は捨て, 最後に送った要求への応答だけを表示する
+            let request_id = state.request_id;
+            let response = state
+                .ctx
+                .rx
+                .try_iter()
+                .find(|response| response.request_id() == request_id);
+            match response {
+                Some(GuiResponse::Recalculated(_, a)) => {
+                    state.awaiting_response = false;
+                    state.failure = None;
+                    preview = Some(RecoveredImagePreview::new(
+                        &mut renderer,
+                        &mut image_composer,
+                        a,
+                        !state.ctx.fixed_rotation,
+                    ));
+                    redraw = true;
+                }
 
-            // TODO: process other GuiResponse
-            #[allow(clippy::single_match)]
-            match state.ctx.rx.try_recv() {
-                Ok(GuiResponse::Recalculated(a)) => {
-                    preview = Some(RecoveredImagePreview::new(&mut renderer, a));
+                Some(GuiResponse::Failed(_, reason)) => {
+                    state.awaiting_response = false;
+                    state.failure = Some(reason);
+                    redraw = true;
                 }
 
-                Err(_) => {}
+                None => {}
             }
         }
 
-        renderer.present();
+        // ウィンドウの大きさが変わったり, 倍率の違う画面に移ったりしても描画先の大きさに合わせる
+        let output_size = renderer.output_size().unwrap();
+        if state.window_size != output_size {
+            state.window_size = output_size;
+            redraw = true;
+        }
+
+        if !redraw {
+            continue;
+        }
+        redraw = false;
+
+        renderer.set_draw_color(SdlColor::BLACK);
+        renderer.clear();
+
+        match (&preview, &state.failure) {
+            (Some(preview), _) => preview.render(&mut renderer, &state),
+            (None, Some(reason)) => FailureMessage { reason }.render(&mut renderer),
+            (None, None) => WaitingMessage.render(&mut renderer),
+        }
 
-        // 60fps
-        std::thread::sleep(Duration::from_secs_f64(1.0 / 60.0));
+        if state.show_help {
+            HelpOverlay.render(&mut renderer);
+        }
+
+        renderer.present();
     }
 
     state.ctx.tx.send(GuiRequest::Quit).unwrap();
+
+    let (width, height) = renderer.window().size();
+    WindowSize { width, height }
 }
 
 struct GuiState {
     running: bool,
+    /// 描画先の大きさ. HiDPI の画面ではウィンドウの大きさより大きい.
     window_size: (u32, u32),
 
     hints: ResolveHints,
-    hints_edit_history: Vec<HintsEditKind>,
+    hints_edit_history: Vec<HintKey>,
     hints_updated: bool,
+    /// 復元画像の横に問題画像を並べるか
+    show_problem_image: bool,
+    /// キー操作の一覧を重ねて表示するか
+    show_help: bool,
+    /// 直前の再計算が失敗した理由. 失敗している間はプレビューの代わりにこれを表示する.
+    failure: Option<String>,
+    /// 最後に送った再計算の要求の番号. 最初の復元は 0 番.
+    request_id: u64,
+    /// 最後に送った要求への応答をまだ受け取っていないか. 受け取った後は次の要求を送るまで受信を確かめない.
+    awaiting_response: bool,
 
     ctx: GuiContext,
 }
 
-enum HintsEditKind {
-    Blocklist(GridPos, EdgePos),
-    LockedPairs(EdgePos),
-}
-
 #[derive(Debug)]
 enum Hint {
     Blocklist(GridPos, EdgePos),
+    SoftBlocklist(GridPos, EdgePos),
     ConfirmedPair(EdgePos, Vec<(GridPos, Rot)>),
 }
 
@@ -184,31 +295,27 @@ impl GuiState {
     fn push_hint(&mut self, hint: Hint) {
         match hint {
             Hint::Blocklist(p, e) => {
-                self.hints_edit_history.push(HintsEditKind::Blocklist(p, e));
+                self.hints_edit_history.push(HintKey::Blocklist(p, e));
                 self.hints.push_blocklist(p, e);
             }
 
+            Hint::SoftBlocklist(p, e) => {
+                self.hints_edit_history.push(HintKey::SoftBlocklist(p, e));
+                self.hints.push_soft_blocklist(p, e, self.ctx.soft_penalty);
+            }
+
             Hint::ConfirmedPair(e, t) => {
                 // ここでは再計算をしない (ロックをしただけでは結果画像は変化しないため)
-                self.hints_edit_history.push(HintsEditKind::LockedPairs(e));
+                self.hints_edit_history.push(HintKey::LockedPairs(e));
                 self.hints.push_locked_pair(e, LockedPairs::new(t));
             }
         }
     }
 
     fn pop_hints(&mut self) {
-        match self.hints_edit_history.pop() {
-            Some(HintsEditKind::Blocklist(p, e)) => {
-                self.hints_updated = true;
-                self.hints.remove_blocklist(p, e);
-            }
-
-            Some(HintsEditKind::LockedPairs(e)) => {
-                self.hints_updated = true;
-                self.hints.remove_locked_pair(e);
-            }
-
-            _ => {}
+        if let Some(key) = self.hints_edit_history.pop() {
+            self.hints_updated = true;
+            self.hints.remove(key);
         }
     }
 
@@ -221,10 +328,10 @@ impl GuiState {
             .hints_edit_history
             .iter()
             .rev()
-            .find(|x| matches!(x, HintsEditKind::LockedPairs(_)));
+            .find(|x| matches!(x, HintKey::LockedPairs(_)));
 
-        if let Some(&HintsEditKind::LockedPairs(p)) = last_locked_pair {
-            self.hints.lock_pair_as_end(p);
+        if let Some(&HintKey::LockedPairs(p)) = last_locked_pair {
+            self.hints.stop_locked_pairs_after_apply(p);
             self.hints_updated = true;
         }
     }
@@ -233,34 +340,56 @@ impl GuiState {
         use Event::*;
 
         match event {
-            &Window {
-                win_event: WindowEvent::Resized(w, h),
-                ..
-            } => {
-                self.window_size = (w as u32, h as u32);
-            }
-
-            Quit { .. }
-            | KeyDown {
-                keycode: Some(Keycode::Escape | Keycode::Q),
-                ..
-            } => {
+            Quit { .. } => {
                 self.running = false;
             }
 
+            &KeyDown {
+                keycode: Some(k), ..
+            } => match action_of(k) {
+                Some(Action::Quit) => self.running = false,
+                Some(Action::ToggleHelp) => self.show_help = !self.show_help,
+                // 失敗している間はプレビューが無いので, やり直しと取り消しはここで受け付ける
+                Some(Action::Recalculate) if self.failure.is_some() => self.force_update(),
+                Some(Action::Undo) if self.failure.is_some() => self.pop_hints(),
+                _ => {}
+            },
+
             _ => {}
         }
     }
 
     fn send_recalculate_request(&mut self) {
         self.hints_updated = false;
+        self.request_id += 1;
+        self.awaiting_response = true;
+        self.ctx
+            .latest_request
+            .store(self.request_id, Ordering::Relaxed);
         self.ctx
             .tx
-            .send(GuiRequest::Recalculate(self.hints.clone()))
+            .send(GuiRequest::Recalculate(self.request_id, self.hints.clone()))
             .unwrap();
     }
 }
 
+struct HelpOverlay;
+
+impl HelpOverlay {
+    fn render(&self, canvas: &mut Renderer<'_>) {
+        let line_height = canvas.line_height();
+
+        for (i, binding) in BINDINGS.iter().enumerate() {
+            canvas.render_text(
+                format!("{:<14}{}", binding.label, binding.description),
+                (8, 8 + line_height * i as i32),
+                SdlColor::WHITE,
+                false,
+            );
+        }
+    }
+}
+
 struct WaitingMessage;
 
 impl WaitingMessage {
@@ -269,6 +398,33 @@ impl WaitingMessage {
     }
 }
 
+struct FailureMessage<'a> {
+    reason: &'a str,
+}
+
+impl FailureMessage<'_> {
+    fn render(&self, canvas: &mut Renderer<'_>) {
+        canvas.render_text("Failed to recover the image", (0, 0), SdlColor::RED, true);
+
+        let line_height = canvas.line_height();
+        let top = 48;
+        canvas.render_text(
+            "press R to retry, or U to undo the last hint and retry",
+            (0, top),
+            SdlColor::WHITE,
+            false,
+        );
+        for (i, line) in self.reason.lines().enumerate() {
+            canvas.render_text(
+                line.to_owned(),
+                (0, top + line_height * (i as i32 + 2)),
+                SdlColor::WHITE,
+                false,
+            );
+        }
+    }
+}
+
 struct TextEntry<'a> {
     text: String,
     color: SdlColor,
@@ -308,6 +464,11 @@ bitflags::bitflags! {
 }
 
 impl Renderer<'_> {
+    /// 小さい文字を複数行並べるときの行の間隔. 文字の拡大率に合わせて変わる.
+    fn line_height(&self) -> i32 {
+        self.small_font.recommended_line_spacing()
+    }
+
     fn render_text<'a>(
         &'a mut self,
         text: impl Into<Cow<'a, str>>,
@@ -431,13 +592,11 @@ impl Pos {
         grid.pos(self.0, self.1)
     }
 
-    fn move_to(self, dir: Dir) -> Self {
-        match dir {
-            Dir::North => Pos(self.x(), self.y() - 1),
-            Dir::South => Pos(self.x(), self.y() + 1),
-            Dir::West => Pos(self.x() - 1, self.y()),
-            Dir::East => Pos(self.x() + 1, self.y()),
-        }
+    /// `dir` の方向に 1 つ動かした位置. `grid` の外に出るなら `None` を返す.
+    fn move_to(self, dir: Dir, grid: Grid) -> Option<Self> {
+        let (dx, dy) = dir.to_movement().delta();
+        grid.offset(self.into_grid_pos(grid), dx, dy, Overflow::Checked)
+            .map(Self::from)
     }
 
     fn aligned_axis(self, other: Self) -> Option<Axis> {
@@ -450,3 +609,40 @@ impl Pos {
         })
     }
 }
+
+#[test]
+fn test_move_to() {
+    let grid = Grid::new(3, 2);
+    assert_eq!(Some(Pos(1, 1)), Pos(1, 0).move_to(Dir::South, grid));
+    assert_eq!(Some(Pos(0, 0)), Pos(1, 0).move_to(Dir::West, grid));
+    // 盤面の外には動かず, 反対側にも回り込まない
+    assert_eq!(None, Pos(1, 0).move_to(Dir::North, grid));
+    assert_eq!(None, Pos(0, 1).move_to(Dir::West, grid));
+    assert_eq!(None, Pos(2, 1).move_to(Dir::East, grid));
+    assert_eq!(None, Pos(2, 1).move_to(Dir::South, grid));
+}
+
+#[test]
+fn test_coalesce_requests() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let id_of = |request: GuiRequest| match request {
+        GuiRequest::Recalculate(id, _) => Some(id),
+        GuiRequest::Quit => None,
+    };
+
+    // 溜まっていた要求のうち最後のヒントだけで再計算する
+    for id in 2..=4 {
+        tx.send(GuiRequest::Recalculate(id, ResolveHints::default()))
+            .unwrap();
+    }
+    let first = GuiRequest::Recalculate(1, ResolveHints::default());
+    assert_eq!(Some(4), id_of(coalesce_requests(first, &rx)));
+    assert!(rx.try_recv().is_err());
+
+    // 途中に Quit があれば, その後の要求があっても終了する
+    tx.send(GuiRequest::Quit).unwrap();
+    tx.send(GuiRequest::Recalculate(6, ResolveHints::default()))
+        .unwrap();
+    let first = GuiRequest::Recalculate(5, ResolveHints::default());
+    assert_eq!(None, id_of(coalesce_requests(first, &rx)));
+}
@@ -1,26 +1,39 @@
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::ops::RangeInclusive;
 
 use sdl2::{
-    event::Event, keyboard::Keycode, pixels::Color as SdlColor, pixels::PixelFormatEnum,
-    rect::Rect, render::Texture, surface::Surface,
+    event::Event,
+    keyboard::{Keycode, Mod},
+    pixels::Color as SdlColor,
+    pixels::PixelFormatEnum,
+    rect::Rect,
+    render::Texture,
+    surface::Surface,
 };
 
 use crate::{
-    basis::{Dir, Rot},
+    basis::{Color, Dir, Image, Rot},
     fragment::Fragment,
-    grid::VecOnGrid,
-    pixel_match::gui::{EdgePos, Hint},
+    grid::{Grid, Overflow, Pos as GridPos, VecOnGrid},
+    pixel_match::{gui::Hint, EdgePos},
+    render,
 };
 
 use super::{
-    arrow_texture::arrow_texture, Axis, GuiState, Pos, RecalculateArtifact, Renderer, Sides,
+    arrow_texture::arrow_texture,
+    key_bindings::{action_of, Action},
+    Axis, GuiState, Pos, Provenance, RecalculateArtifact, Renderer, Sides,
 };
 
 pub(super) struct RecoveredImagePreview<'tc> {
     image: RecalculateArtifact,
     recovered_image_texture: Texture<'tc>,
+    /// 問題画像. 断片を回転させずに元の位置に並べ直して作る.
+    problem_image_texture: Texture<'tc>,
     arrow_texture: Texture<'tc>,
+    /// デバッグ表示で断片の向きを矢印で示すか. 断片を回転させないときはどれも上を向くので描かない.
+    show_rotations: bool,
 
     pub(super) selecting_at: Pos,
     dragging_from: Option<Pos>,
@@ -28,10 +41,27 @@ pub(super) struct RecoveredImagePreview<'tc> {
 }
 
 impl<'tc> RecoveredImagePreview<'tc> {
-    pub(super) fn new(renderer: &mut Renderer<'tc>, mut image: RecalculateArtifact) -> Self {
+    pub(super) fn new(
+        renderer: &mut Renderer<'tc>,
+        composer: &mut ImageComposer,
+        mut image: RecalculateArtifact,
+        show_rotations: bool,
+    ) -> Self {
+        let mut problem_image = problem_image_of(&image.recovered_image);
+
         Self {
-            recovered_image_texture: create_image_texture(renderer, &mut image.recovered_image),
+            recovered_image_texture: create_image_texture(
+                renderer,
+                composer,
+                &mut image.recovered_image,
+            ),
+            problem_image_texture: create_image_texture(
+                renderer,
+                &mut ImageComposer::default(),
+                &mut problem_image,
+            ),
             arrow_texture: arrow_texture(renderer.texture_creator),
+            show_rotations,
 
             selecting_at: image.root_pos.into(),
             dragging_from: None,
@@ -45,20 +75,42 @@ impl<'tc> RecoveredImagePreview<'tc> {
         use Event::*;
         let grid = self.image.recovered_image.grid;
 
-        match event {
+        let (k, keymod, pressed) = match event {
             KeyDown {
-                keycode: Some(k @ (Keycode::Up | Keycode::Down | Keycode::Left | Keycode::Right)),
+                keycode: Some(k),
+                keymod,
+                ..
+            } => (k, keymod, true),
+            KeyUp {
+                keycode: Some(k),
+                keymod,
                 ..
-            } => {
-                let mut updated = self.selecting_at;
-
-                match k {
-                    Keycode::Up => updated.1 = updated.1.saturating_sub(1),
-                    Keycode::Left => updated.0 = updated.0.saturating_sub(1),
-                    Keycode::Down if self.selecting_at.1 < grid.height() - 1 => updated.1 += 1,
-                    Keycode::Right if self.selecting_at.0 < grid.width() - 1 => updated.0 += 1,
+            } => (k, keymod, false),
+            _ => return,
+        };
+
+        let action = match action_of(k) {
+            Some(v) => v,
+            None => return,
+        };
+
+        match (action, pressed) {
+            (Action::MoveSelection, true) => {
+                let (dx, dy) = match k {
+                    Keycode::Up => (0, -1),
+                    Keycode::Left => (-1, 0),
+                    Keycode::Down => (0, 1),
+                    Keycode::Right => (1, 0),
                     _ => return,
-                }
+                };
+                let updated = grid
+                    .offset(
+                        self.selecting_at.into_grid_pos(grid),
+                        dx,
+                        dy,
+                        Overflow::Saturate,
+                    )
+                    .map_or(self.selecting_at, Pos::from);
 
                 let root = self.image.root_pos.into();
 
@@ -69,53 +121,45 @@ impl<'tc> RecoveredImagePreview<'tc> {
                 self.selecting_at = updated;
             }
 
-            KeyDown {
-                keycode: Some(Keycode::LShift),
-                ..
-            } => {
-                self.show_fragment_debug = true;
+            (Action::ShowFragmentDebug, pressed) => {
+                self.show_fragment_debug = pressed;
             }
 
-            KeyUp {
-                keycode: Some(Keycode::LShift),
-                ..
-            } => {
-                self.show_fragment_debug = false;
+            (Action::ToggleProblemImage, true) => {
+                global_state.show_problem_image = !global_state.show_problem_image;
             }
 
-            KeyDown {
-                keycode: Some(Keycode::U),
-                ..
-            } => {
+            (Action::Undo, true) => {
                 global_state.pop_hints();
             }
 
-            KeyDown {
-                keycode: Some(Keycode::R),
-                ..
-            } => {
+            (Action::Recalculate, true) => {
                 global_state.force_update();
             }
 
-            KeyDown {
-                keycode: Some(Keycode::LCtrl),
-                ..
-            } => {
+            (Action::LockPairs, true) => {
                 self.dragging_from = Some(self.selecting_at);
             }
 
-            KeyDown {
-                keycode: Some(Keycode::F),
-                ..
-            } => {
-                println!("gui: set locked_pair continue field to false");
+            (Action::ApplySuggestion, true) => {
+                let suggestion = self
+                    .image
+                    .suggestions
+                    .iter()
+                    .find(|s| !global_state.hints.is_blocked(s.pos, s.against));
+
+                if let Some(s) = suggestion {
+                    tracing::info!(pos = ?s.pos, against = ?s.against, "gui: applied suggested blocklist");
+                    global_state.push_hint(Hint::Blocklist(s.pos, s.against));
+                }
+            }
+
+            (Action::StopAfterLockedPairs, true) => {
+                tracing::info!("gui: set locked_pair continue field to false");
                 global_state.stop_continue_last_hint();
             }
 
-            KeyUp {
-                keycode: Some(Keycode::LCtrl),
-                ..
-            } => {
+            (Action::LockPairs, false) => {
                 let grid = self.image.recovered_image.grid;
                 let root_pos: Pos = self.image.root_pos.into();
                 let selecting_at: Pos = self.selecting_at;
@@ -153,7 +197,7 @@ impl<'tc> RecoveredImagePreview<'tc> {
                         && table[1] != table[2]
                         && table[1] == dragging_axis_of(root_pos)
                     {
-                        println!("rootを跨げません");
+                        tracing::warn!("rootを跨げません");
                         return;
                     }
                 }
@@ -178,7 +222,13 @@ impl<'tc> RecoveredImagePreview<'tc> {
                 .collect::<Vec<_>>();
 
                 let reference_side = Self::calc_reference_side(root_pos, near_to_root);
-                let reference_pos = near_to_root.move_to(reference_side);
+                let reference_pos = match near_to_root.move_to(reference_side, grid) {
+                    Some(pos) => pos,
+                    None => {
+                        tracing::warn!("gui: no fragment next to the dragged line");
+                        return;
+                    }
+                };
 
                 let reference_image_pos = self.image.recovered_image
                     [reference_pos.into_grid_pos(grid)]
@@ -194,21 +244,24 @@ impl<'tc> RecoveredImagePreview<'tc> {
                 global_state.push_hint(Hint::ConfirmedPair(edgepos, list));
             }
 
-            KeyDown {
-                keycode: Some(Keycode::Space),
-                ..
-            } => {
+            (Action::Block, true) => {
                 let root = self.image.root_pos.into();
                 let selecting = self.selecting_at;
                 let grid = self.image.recovered_image.grid;
 
                 if selecting == root {
-                    println!("gui: cannot apply blocklist on exact root pos");
+                    tracing::warn!("gui: cannot apply blocklist on exact root pos");
                     return;
                 }
 
                 let reference_side = Self::calc_reference_side(root, selecting);
-                let reference_pos = selecting.move_to(reference_side);
+                let reference_pos = match selecting.move_to(reference_side, grid) {
+                    Some(pos) => pos,
+                    None => {
+                        tracing::warn!("gui: no fragment next to the selection");
+                        return;
+                    }
+                };
 
                 let selecting = grid.pos(selecting.0, selecting.1);
                 let selecting_fragment = self.image.recovered_image[selecting].as_ref().unwrap();
@@ -223,8 +276,14 @@ impl<'tc> RecoveredImagePreview<'tc> {
                 .as_ref()
                 .unwrap();
 
-                global_state.push_hint(Hint::Blocklist(reference_fragment.pos, entry));
-                println!("gui: blocklist updated silently")
+                // Alt を押しながらなら除外せず, 一致度に倍率を掛けるだけにする
+                if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) {
+                    global_state.push_hint(Hint::SoftBlocklist(reference_fragment.pos, entry));
+                    tracing::info!("gui: soft blocklist updated silently")
+                } else {
+                    global_state.push_hint(Hint::Blocklist(reference_fragment.pos, entry));
+                    tracing::info!("gui: blocklist updated silently")
+                }
             }
 
             _ => {}
@@ -238,17 +297,7 @@ impl<'tc> RecoveredImagePreview<'tc> {
     /// W   E  -- R90 --> S   N このとき答えは West
     ///   S                 E
     fn calc_intersects_dir(fragment_rot: Rot, reference: Dir) -> Dir {
-        let mut table = [Dir::North, Dir::East, Dir::South, Dir::West];
-        table.rotate_right(fragment_rot.as_num() as usize);
-
-        let index = match reference {
-            Dir::North => 0,
-            Dir::East => 1,
-            Dir::South => 2,
-            Dir::West => 3,
-        };
-
-        table[index]
+        reference.rotate(fragment_rot.inverse())
     }
 
     /// Pos にある fragment の reference となる fragment の方向を返す
@@ -284,10 +333,17 @@ impl<'tc> RecoveredImagePreview<'tc> {
     }
 
     pub(super) fn render(&self, renderer: &mut Renderer<'_>, global_state: &GuiState) {
+        // 問題画像を並べるときは, 左半分に復元画像を, 右半分に問題画像を描く
+        let area = if global_state.show_problem_image {
+            (global_state.window_size.0 / 2, global_state.window_size.1)
+        } else {
+            global_state.window_size
+        };
+
         let image_size = {
             let query = self.recovered_image_texture.query();
 
-            let src = global_state.window_size;
+            let src = area;
             let dst = (query.width as u32, query.height as u32);
 
             let candidate_a = (
@@ -320,6 +376,124 @@ impl<'tc> RecoveredImagePreview<'tc> {
         if self.show_fragment_debug {
             self.render_fragment_debug(renderer, image_size);
         }
+
+        if global_state.show_problem_image {
+            self.render_problem_image(renderer, (area.0 as i32, 0), image_size);
+        }
+
+        self.render_suggestions(renderer, global_state);
+        self.render_discarded_hints(renderer);
+    }
+
+    /// 復元できるようにするために捨てたヒントを左上に警告として表示する.
+    fn render_discarded_hints(&self, renderer: &mut Renderer<'_>) {
+        let discarded = &self.image.discarded_hints;
+        if discarded.is_empty() {
+            return;
+        }
+
+        let line_height = renderer.line_height();
+        renderer.render_text(
+            "discarded hints to recover the image:",
+            (0, 0),
+            SdlColor::YELLOW,
+            false,
+        );
+        for (i, hint) in discarded.iter().enumerate() {
+            renderer.render_text(
+                hint.to_string(),
+                (0, line_height * (i as i32 + 1)),
+                SdlColor::YELLOW,
+                false,
+            );
+        }
+    }
+
+    /// ブロックリストの候補を左下に一覧表示する. B キーでまだ入れていない先頭の候補を採用する.
+    fn render_suggestions(&self, renderer: &mut Renderer<'_>, global_state: &GuiState) {
+        let suggestions = &self.image.suggestions;
+        if suggestions.is_empty() {
+            return;
+        }
+
+        let line_height = renderer.line_height();
+        let top = global_state.window_size.1 as i32 - line_height * (suggestions.len() as i32 + 1);
+        renderer.render_text(
+            "suggested blocklist (press B to apply):",
+            (0, top),
+            SdlColor::WHITE,
+            false,
+        );
+
+        for (i, s) in suggestions.iter().enumerate() {
+            let applied = global_state.hints.is_blocked(s.pos, s.against);
+            let color = if applied {
+                SdlColor::GRAY
+            } else {
+                SdlColor::CYAN
+            };
+
+            renderer.render_text(
+                format!(
+                    "{} | {} (runner-up {}, x{:.3})",
+                    pos_label(s.pos),
+                    edge_label(s.against),
+                    edge_label(s.runner_up),
+                    s.ratio()
+                ),
+                (0, top + line_height * (i as i32 + 1)),
+                color,
+                false,
+            );
+        }
+    }
+
+    /// 問題画像をマスの枠と一緒に描き, 復元画像で選択中の断片と root の断片が元々あった位置を囲む.
+    fn render_problem_image(
+        &self,
+        renderer: &mut Renderer<'_>,
+        (left, top): (i32, i32),
+        image_size: (u32, u32),
+    ) {
+        let grid = self.image.recovered_image.grid;
+
+        renderer
+            .copy(
+                &self.problem_image_texture,
+                None,
+                Some(Rect::new(left, top, image_size.0, image_size.1)),
+            )
+            .unwrap();
+
+        let cell_side_length = image_size.0 as f64 / grid.width() as f64;
+        let cell_size = (cell_side_length as i32, cell_side_length as i32);
+        let scale_by_side = |p: u8| (cell_side_length * p as f64) as i32;
+        let offset_of = |p: GridPos| (left + scale_by_side(p.x()), top + scale_by_side(p.y()));
+
+        renderer.set_draw_color(SdlColor::GRAY);
+        for pos in grid.all_pos() {
+            renderer.draw_partial_rect(offset_of(pos), cell_size, Sides::all());
+        }
+
+        let original_pos_at = |pos: Pos| {
+            self.image.recovered_image[pos.into_grid_pos(grid)]
+                .as_ref()
+                .unwrap()
+                .pos
+        };
+
+        renderer.set_draw_color(SdlColor::BLUE);
+        renderer.draw_partial_rect(
+            offset_of(original_pos_at(self.image.root_pos.into())),
+            cell_size,
+            Sides::all(),
+        );
+        renderer.set_draw_color(SdlColor::GREEN);
+        renderer.draw_partial_rect(
+            offset_of(original_pos_at(self.selecting_at)),
+            cell_size,
+            Sides::all(),
+        );
     }
 
     fn render_selection_and_root(&self, renderer: &mut Renderer<'_>, image_size: (u32, u32)) {
@@ -402,7 +576,7 @@ impl<'tc> RecoveredImagePreview<'tc> {
                 .into()
         };
 
-        for (edgepos, entry) in &global_state.hints.locked_pairs {
+        for (edgepos, entry) in global_state.hints.locked_pairs() {
             let list = &entry.tail;
 
             let growing_dir = match (
@@ -419,7 +593,10 @@ impl<'tc> RecoveredImagePreview<'tc> {
             let offset = match growing_dir {
                 Dir::North | Dir::West => offset_of(pos_on_gui_grid(list.last().unwrap().0.into())),
                 d @ (Dir::South | Dir::East) => {
-                    offset_of(pos_on_gui_grid(edgepos.pos.into()).move_to(d))
+                    match pos_on_gui_grid(edgepos.pos.into()).move_to(d, grid) {
+                        Some(pos) => offset_of(pos),
+                        None => continue,
+                    }
                 }
             };
 
@@ -447,13 +624,26 @@ impl<'tc> RecoveredImagePreview<'tc> {
         for (pos, fragment) in self.image.recovered_image.iter_with_pos() {
             let fragment = fragment.as_ref().unwrap();
 
+            let offset = offset_of(pos.x(), pos.y());
             renderer.render_text(
                 format!("{}, {}", fragment.pos.x(), fragment.pos.y()),
-                offset_of(pos.x(), pos.y()),
+                offset,
                 SdlColor::GREEN,
                 false,
             );
+            // どの辺との一致度で置かれたかを座標の下に書く
+            if let Some(provenance) = self.image.provenances.get(&fragment.pos) {
+                renderer.render_text(
+                    provenance_label(provenance),
+                    (offset.0, offset.1 + renderer.line_height()),
+                    SdlColor::GREEN,
+                    false,
+                );
+            }
 
+            if !self.show_rotations {
+                continue;
+            }
             // assuming arrow is always square.
             let arrow_side_length = 20;
             let arrow_pos = offset_of(pos.x() + 1, pos.y() + 1);
@@ -493,54 +683,118 @@ fn diff_u8(a: u8, b: u8) -> u8 {
     }
 }
 
+/// 復元画像の断片を回転させずに問題画像での位置に並べ直し, 問題画像と同じものを作る.
+fn problem_image_of(recovered_image: &VecOnGrid<Option<Fragment>>) -> VecOnGrid<Option<Fragment>> {
+    let mut problem_image = VecOnGrid::with_default(recovered_image.grid);
+    for mut fragment in recovered_image.iter().flatten().cloned() {
+        fragment.rotate(fragment.rot.inverse());
+        let pos = fragment.pos;
+        problem_image[pos] = Some(fragment);
+    }
+    problem_image
+}
+
 fn create_image_texture<'tc>(
     renderer: &mut Renderer<'tc>,
+    composer: &mut ImageComposer,
     fragment_grid: &mut VecOnGrid<Option<Fragment>>,
 ) -> Texture<'tc> {
-    let grid = fragment_grid.grid;
-    let side_length = fragment_grid[grid.pos(0, 0)]
-        .as_ref()
+    composer.compose(fragment_grid);
+    let image = &composer.data;
+
+    let mut surface = Surface::new(
+        image.width as u32,
+        image.height as u32,
+        PixelFormatEnum::RGB24,
+    )
+    .unwrap();
+    let data: Vec<_> = image.pixels.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+    surface.with_lock_mut(|x| x.copy_from_slice(&data));
+
+    renderer
+        .texture_creator
+        .create_texture_from_surface(surface)
         .unwrap()
-        .side_length();
-
-    let width = (side_length * grid.width() as usize) as u32;
-    let height = (side_length * grid.height() as usize) as u32;
-
-    const BYTES_PER_PIXEL: usize = 3;
-    let mut data = Vec::with_capacity(
-        side_length
-            * side_length
-            * grid.width() as usize
-            * grid.height() as usize
-            * BYTES_PER_PIXEL,
-    );
+}
 
-    for y in 0..grid.height() {
-        for py in 0..side_length {
-            for x in 0..grid.width() {
-                let grid_pos = grid.pos(x, y);
+/// `ImageComposer` は復元画像の画素列を, 前回から断片が変わったマスだけ描き直して作る.
+///
+/// 回転させた断片の画素は (問題画像での位置, 回転) ごとに覚えておき, 再計算のたびに回転し直さないようにする.
+pub(super) struct ImageComposer {
+    blocks: HashMap<(GridPos, Rot), Vec<Color>>,
+    grid: Option<Grid>,
+    side_length: usize,
+    /// 各マスに今描かれている断片
+    cells: Vec<Option<(GridPos, Rot)>>,
+    data: Image,
+}
 
-                if let Some(x) = &mut fragment_grid[grid_pos] {
-                    data.extend(
-                        x.pixels()[(py * side_length) as usize..((py + 1) * side_length) as usize]
-                            .iter()
-                            .flat_map(|x| [x.r, x.g, x.b])
-                            .map(|x| ((x as f32) * 0.8) as u8),
-                    );
-                } else {
-                    data.extend(std::iter::repeat(0).take(side_length * 3));
-                }
-            }
+impl Default for ImageComposer {
+    fn default() -> Self {
+        Self {
+            blocks: HashMap::new(),
+            grid: None,
+            side_length: 0,
+            cells: vec![],
+            data: render::blank_image(Grid::new(0, 0), 0),
         }
     }
+}
 
-    let mut surface = Surface::new(width, height, PixelFormatEnum::RGB24).unwrap();
-    surface.with_lock_mut(|x| x.copy_from_slice(&data));
+impl ImageComposer {
+    fn compose(&mut self, fragment_grid: &mut VecOnGrid<Option<Fragment>>) {
+        let grid = fragment_grid.grid;
+        let side_length = fragment_grid[grid.pos(0, 0)]
+            .as_ref()
+            .unwrap()
+            .side_length();
+
+        // 別の問題なら覚えたものをすべて捨てる
+        if self.grid != Some(grid) || self.side_length != side_length {
+            let cells = grid.width() as usize * grid.height() as usize;
+            *self = Self {
+                blocks: HashMap::new(),
+                grid: Some(grid),
+                side_length,
+                cells: vec![None; cells],
+                data: render::blank_image(grid, side_length),
+            };
+        }
 
-    renderer
-        .texture_creator
-        .create_texture_from_surface(surface)
-        .unwrap()
+        let mut changed = 0;
+
+        for (index, (pos, cell)) in fragment_grid.iter_mut_with_pos().enumerate() {
+            let key = cell.as_ref().map(|f| (f.pos, f.rot));
+            if self.cells[index] == key {
+                continue;
+            }
+            self.cells[index] = key;
+            changed += 1;
+
+            let block = cell.as_mut().map(|fragment| {
+                &*self
+                    .blocks
+                    .entry((fragment.pos, fragment.rot))
+                    .or_insert_with(|| {
+                        fragment
+                            .pixels()
+                            .iter()
+                            .map(|&Color { r, g, b }| {
+                                let dim = |x: u8| ((x as f32) * 0.8) as u8;
+                                Color {
+                                    r: dim(r),
+                                    g: dim(g),
+                                    b: dim(b),
+                                }
+                            })
+                            .collect()
+                    })
+            });
+            render::draw_cell(&mut self.data, grid, pos, block.map(Vec::as_slice));
+        }
+
+        tracing::debug!(changed, "gui: recomposed the recovered image");
+    }
 }
 
 struct BidirectionalInclusiveRange {
@@ -586,3 +840,137 @@ fn test_calc_intersects_dir() {
         Dir::West
     );
 }
+
+fn pos_label(pos: GridPos) -> String {
+    format!("{:X}{:X}", pos.x(), pos.y())
+}
+
+fn edge_label(edge: EdgePos) -> String {
+    let dir = match edge.dir {
+        Dir::North => 'N',
+        Dir::East => 'E',
+        Dir::South => 'S',
+        Dir::West => 'W',
+    };
+    format!("{}{}", pos_label(edge.pos), dir)
+}
+
+fn provenance_label(provenance: &Provenance) -> String {
+    match *provenance {
+        Provenance::Root => "root".to_owned(),
+        Provenance::Locked { reference } => format!("lock {}", edge_label(reference)),
+        Provenance::SingleSide { reference, score } => {
+            format!("{} {:.2}", edge_label(reference), score)
+        }
+        Provenance::DoubleSide {
+            references: [a, b],
+            score,
+        } => format!("{}+{} {:.2}", edge_label(a), edge_label(b), score),
+        Provenance::Deferred { neighbors, score } => format!("dup/{} {:.2}", neighbors, score),
+        Provenance::Surrounded { neighbors, score } => format!("nb/{} {:.2}", neighbors, score),
+        Provenance::Refined => "refine".to_owned(),
+    }
+}
+
+#[test]
+fn test_provenance_label() {
+    let grid = Grid::new(3, 3);
+    let (a, b) = (
+        EdgePos::new(grid.pos(1, 0), Dir::East),
+        EdgePos::new(grid.pos(0, 2), Dir::South),
+    );
+    assert_eq!("root", provenance_label(&Provenance::Root));
+    assert_eq!("refine", provenance_label(&Provenance::Refined));
+    assert_eq!(
+        "lock 10E",
+        provenance_label(&Provenance::Locked { reference: a })
+    );
+    assert_eq!(
+        "10E 1.50",
+        provenance_label(&Provenance::SingleSide {
+            reference: a,
+            score: 1.5
+        })
+    );
+    assert_eq!(
+        "10E+02S 12.50",
+        provenance_label(&Provenance::DoubleSide {
+            references: [a, b],
+            score: 12.5
+        })
+    );
+    assert_eq!(
+        "dup/4 0.75",
+        provenance_label(&Provenance::Deferred {
+            neighbors: 4,
+            score: 0.75
+        })
+    );
+    assert_eq!(
+        "nb/3 2.00",
+        provenance_label(&Provenance::Surrounded {
+            neighbors: 3,
+            score: 2.0
+        })
+    );
+}
+
+#[test]
+fn test_image_composer() {
+    let problem = include_bytes!("../../../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem)
+        .unwrap()
+        .into_iter()
+        .map(Some)
+        .collect();
+    let mut fragment_grid = VecOnGrid::from_vec(grid, fragments).unwrap();
+
+    let composed = |fragment_grid: &mut VecOnGrid<Option<Fragment>>| {
+        let mut composer = ImageComposer::default();
+        composer.compose(fragment_grid);
+        composer.data.pixels
+    };
+
+    let mut composer = ImageComposer::default();
+    composer.compose(&mut fragment_grid);
+    assert!(composed(&mut fragment_grid) == composer.data.pixels);
+
+    // 入れ替えたマスと回転させたマス, 空にしたマスだけ描き直しても最初から作ったものと同じになる
+    let (a, b) = (grid.pos(0, 0), grid.pos(1, 1));
+    let fragment_a = fragment_grid[a].take();
+    fragment_grid[a] = std::mem::replace(&mut fragment_grid[b], fragment_a);
+    fragment_grid[b].as_mut().unwrap().rotate(Rot::R90);
+    fragment_grid[grid.pos(1, 0)] = None;
+    composer.compose(&mut fragment_grid);
+    assert!(composed(&mut fragment_grid) == composer.data.pixels);
+    // 回転させた断片の分だけ増える
+    assert_eq!(5, composer.blocks.len());
+}
+
+#[test]
+fn test_problem_image_of() {
+    let problem = include_bytes!("../../../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+    let arrangement = crate::pixel_match::resolve_headless(fragments.clone(), grid).unwrap();
+    let recovered = arrangement.into_recovered().into_iter().map(Some).collect();
+    let recovered = VecOnGrid::from_vec(grid, recovered).unwrap();
+
+    let mut problem_image = problem_image_of(&recovered);
+    let mut expected =
+        VecOnGrid::from_vec(grid, fragments.into_iter().map(Some).collect()).unwrap();
+    for (pos, fragment) in problem_image.iter_with_pos() {
+        let fragment = fragment.as_ref().unwrap();
+        assert_eq!(pos, fragment.pos);
+        assert_eq!(Rot::R0, fragment.rot);
+    }
+
+    let mut composer = ImageComposer::default();
+    composer.compose(&mut problem_image);
+    let mut expected_composer = ImageComposer::default();
+    expected_composer.compose(&mut expected);
+    assert!(expected_composer.data.pixels == composer.data.pixels);
+}
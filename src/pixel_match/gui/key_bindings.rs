@@ -0,0 +1,114 @@
+//! GUI のキー操作の一覧. イベントの処理とヘルプの表示の両方がこの表を使う.
+
+use sdl2::keyboard::Keycode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Action {
+    MoveSelection,
+    Block,
+    LockPairs,
+    StopAfterLockedPairs,
+    ApplySuggestion,
+    Undo,
+    Recalculate,
+    ShowFragmentDebug,
+    ToggleProblemImage,
+    ToggleHelp,
+    Quit,
+}
+
+pub(super) struct Binding {
+    pub(super) keys: &'static [Keycode],
+    /// ヘルプに表示するキーの名前
+    pub(super) label: &'static str,
+    pub(super) action: Action,
+    pub(super) description: &'static str,
+}
+
+pub(super) const BINDINGS: &[Binding] = &[
+    Binding {
+        keys: &[Keycode::Up, Keycode::Down, Keycode::Left, Keycode::Right],
+        label: "Arrows",
+        action: Action::MoveSelection,
+        description: "Move the selection",
+    },
+    Binding {
+        keys: &[Keycode::Space],
+        label: "Space",
+        action: Action::Block,
+        description: "Block the selected fragment against its reference (Alt: soft block)",
+    },
+    Binding {
+        keys: &[Keycode::LCtrl],
+        label: "LCtrl (hold)",
+        action: Action::LockPairs,
+        description: "Drag away from the root and release to lock the fragments",
+    },
+    Binding {
+        keys: &[Keycode::F],
+        label: "F",
+        action: Action::StopAfterLockedPairs,
+        description: "Stop extending after the last locked fragments",
+    },
+    Binding {
+        keys: &[Keycode::B],
+        label: "B",
+        action: Action::ApplySuggestion,
+        description: "Apply the first suggested blocklist entry",
+    },
+    Binding {
+        keys: &[Keycode::U],
+        label: "U",
+        action: Action::Undo,
+        description: "Undo the last hint",
+    },
+    Binding {
+        keys: &[Keycode::R],
+        label: "R",
+        action: Action::Recalculate,
+        description: "Recalculate with the current hints",
+    },
+    Binding {
+        keys: &[Keycode::LShift],
+        label: "LShift (hold)",
+        action: Action::ShowFragmentDebug,
+        description: "Show positions and rotations of the fragments",
+    },
+    Binding {
+        keys: &[Keycode::O],
+        label: "O",
+        action: Action::ToggleProblemImage,
+        description: "Show the problem image beside the recovered image",
+    },
+    Binding {
+        keys: &[Keycode::Question, Keycode::Slash],
+        label: "?",
+        action: Action::ToggleHelp,
+        description: "Show this help",
+    },
+    Binding {
+        keys: &[Keycode::Escape, Keycode::Q],
+        label: "Esc / Q",
+        action: Action::Quit,
+        description: "Finish with the current recovered image",
+    },
+];
+
+pub(super) fn action_of(keycode: Keycode) -> Option<Action> {
+    BINDINGS
+        .iter()
+        .find(|b| b.keys.contains(&keycode))
+        .map(|b| b.action)
+}
+
+#[test]
+fn test_bindings() {
+    for (i, a) in BINDINGS.iter().enumerate() {
+        for b in &BINDINGS[i + 1..] {
+            assert_ne!(a.action, b.action);
+            assert!(a.keys.iter().all(|k| !b.keys.contains(k)), "{}", a.label);
+        }
+    }
+    assert_eq!(Some(Action::Block), action_of(Keycode::Space));
+    assert_eq!(None, action_of(Keycode::Z));
+}
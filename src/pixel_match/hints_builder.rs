@@ -0,0 +1,204 @@
+//! 外部のスクリプトなどから `ResolveHints` を組み立てるためのビルダー.
+//!
+//! GUI やヒントファイルを通さずに作ったヒントは座標の誤りなどに気付きにくいので, `build` でまとめて確かめる.
+
+use super::{EdgePos, LockedPairs, ResolveHints};
+use crate::{
+    basis::{Dir, Rot},
+    error::Error,
+    grid::{Grid, Pos},
+};
+
+/// `ResolveHintsBuilder` はヒントを加えた順に覚えておき, `build` で確かめてから `ResolveHints` にする.
+///
+/// ```
+/// use procon2021_comp::{basis::{Dir, Rot}, grid::Grid, pixel_match::{EdgePos, ResolveHints}};
+///
+/// let grid = Grid::new(3, 2);
+/// let hints = ResolveHints::builder(grid)
+///     .block(grid.pos(0, 0), EdgePos::new(grid.pos(1, 0), Dir::West))
+///     .lock(EdgePos::new(grid.pos(0, 0), Dir::South), vec![(grid.pos(2, 1), Rot::R90)], true)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ResolveHintsBuilder {
+    grid: Grid,
+    hints: ResolveHints,
+}
+
+impl ResolveHints {
+    /// `grid` の盤面の断片についてのヒントを組み立てる.
+    pub fn builder(grid: Grid) -> ResolveHintsBuilder {
+        ResolveHintsBuilder {
+            grid,
+            hints: ResolveHints::default(),
+        }
+    }
+}
+
+impl ResolveHintsBuilder {
+    /// `pos` の断片の隣に, 断片の辺 `against` を置かないようにする.
+    pub fn block(mut self, pos: Pos, against: EdgePos) -> Self {
+        self.hints.push_blocklist(pos, against);
+        self
+    }
+
+    /// `pos` の断片と断片の辺 `against` の組の一致度に `penalty` を掛け, 選ばれにくくする.
+    pub fn soft_block(mut self, pos: Pos, against: EdgePos, penalty: f64) -> Self {
+        self.hints.push_soft_blocklist(pos, against, penalty);
+        self
+    }
+
+    /// 断片の辺 `edge` の向きに, `tail` の断片をその回転で順につなげる.
+    ///
+    /// `continues` が `false` なら, つなげた後はその先を普通に探す.
+    pub fn lock(mut self, edge: EdgePos, tail: Vec<(Pos, Rot)>, continues: bool) -> Self {
+        let mut pairs = LockedPairs::new(tail);
+        if !continues {
+            pairs.stop_after_apply();
+        }
+        self.hints.push_locked_pair(edge, pairs);
+        self
+    }
+
+    /// 座標が盤面の中にあり, 倍率が正で, ロックされた組がブロックリストと矛盾しないか確かめる.
+    pub fn build(self) -> Result<ResolveHints, Error> {
+        let grid = self.grid;
+        let hints = self.hints;
+        let check_pos = |pos: Pos| {
+            if grid.is_pos_valid(pos) {
+                Ok(())
+            } else {
+                Err(Error::OutOfGrid(pos))
+            }
+        };
+
+        for (pos, edge) in hints.blocklist() {
+            check_pos(pos)?;
+            check_pos(edge.pos)?;
+        }
+        for (pos, edge, penalty) in hints.soft_blocklist() {
+            check_pos(pos)?;
+            check_pos(edge.pos)?;
+            if !(0.0 < penalty && penalty.is_finite()) {
+                return Err(Error::InvalidHint(format!(
+                    "the penalty against {:?} must be positive, but found {}",
+                    edge, penalty
+                )));
+            }
+        }
+        for (&edge, pairs) in hints.locked_pairs() {
+            check_pos(edge.pos)?;
+            let mut previous = edge.pos;
+            for &(pos, rot) in &pairs.tail {
+                check_pos(pos)?;
+                if pos == edge.pos || pairs.tail.iter().filter(|&&(p, _)| p == pos).count() != 1 {
+                    return Err(Error::InvalidHint(format!(
+                        "the locked pairs from {:?} use the fragment at {:?} twice",
+                        edge, pos
+                    )));
+                }
+                // つなげた断片の, 前の断片を向く辺の回転させる前の向き
+                let facing = facing_dir(edge.dir, rot);
+                if hints.is_blocked(previous, EdgePos::new(pos, facing)) {
+                    return Err(Error::ContradictoryHint {
+                        locked: previous,
+                        blocked: pos,
+                    });
+                }
+                previous = pos;
+            }
+        }
+        Ok(hints)
+    }
+}
+
+/// `dir` の向きにつなげる断片を `rot` だけ回転させたとき, 前の断片を向く辺の回転させる前の向き.
+fn facing_dir(dir: Dir, rot: Rot) -> Dir {
+    dir.opposite().rotate(rot.inverse())
+}
+
+#[test]
+fn test_build() {
+    let grid = Grid::new(3, 2);
+    let hints = ResolveHints::builder(grid)
+        .block(grid.pos(0, 0), EdgePos::new(grid.pos(1, 0), Dir::West))
+        .soft_block(
+            grid.pos(0, 0),
+            EdgePos::new(grid.pos(2, 0), Dir::North),
+            2.0,
+        )
+        .lock(
+            EdgePos::new(grid.pos(0, 0), Dir::South),
+            vec![(grid.pos(2, 1), Rot::R90)],
+            false,
+        )
+        .build()
+        .unwrap();
+    assert_eq!(
+        vec![&EdgePos::new(grid.pos(1, 0), Dir::West)],
+        hints.blocklist_of(grid.pos(0, 0)).collect::<Vec<_>>()
+    );
+    assert_eq!(3, hints.order.len());
+    let pairs = hints
+        .locked_pairs_of(EdgePos::new(grid.pos(0, 0), Dir::South))
+        .unwrap();
+    assert!(!pairs.continue_after_apply);
+
+    let out_of_grid = Grid::new(4, 2).pos(3, 0);
+    assert!(matches!(
+        ResolveHints::builder(grid)
+            .block(grid.pos(0, 0), EdgePos::new(out_of_grid, Dir::West))
+            .build(),
+        Err(Error::OutOfGrid(pos)) if pos == out_of_grid
+    ));
+    assert!(matches!(
+        ResolveHints::builder(grid)
+            .soft_block(grid.pos(0, 0), EdgePos::new(grid.pos(1, 0), Dir::West), 0.0)
+            .build(),
+        Err(Error::InvalidHint(_))
+    ));
+    assert!(matches!(
+        ResolveHints::builder(grid)
+            .lock(
+                EdgePos::new(grid.pos(0, 0), Dir::East),
+                vec![(grid.pos(1, 0), Rot::R0), (grid.pos(1, 0), Rot::R0)],
+                true
+            )
+            .build(),
+        Err(Error::InvalidHint(_))
+    ));
+}
+
+#[test]
+fn test_build_contradiction() {
+    let grid = Grid::new(3, 2);
+    // (1, 0) を 90 度回転させて東につなげると, 元の南の辺が (0, 0) を向く
+    let lock = |builder: ResolveHintsBuilder| {
+        builder.lock(
+            EdgePos::new(grid.pos(0, 0), Dir::East),
+            vec![(grid.pos(1, 0), Rot::R90), (grid.pos(2, 0), Rot::R0)],
+            true,
+        )
+    };
+    assert!(matches!(
+        lock(ResolveHints::builder(grid))
+            .block(grid.pos(0, 0), EdgePos::new(grid.pos(1, 0), Dir::South))
+            .build(),
+        Err(Error::ContradictoryHint { locked, blocked })
+            if locked == grid.pos(0, 0) && blocked == grid.pos(1, 0)
+    ));
+    assert!(matches!(
+        lock(ResolveHints::builder(grid))
+            .block(grid.pos(1, 0), EdgePos::new(grid.pos(2, 0), Dir::West))
+            .build(),
+        Err(Error::ContradictoryHint { locked, blocked })
+            if locked == grid.pos(1, 0) && blocked == grid.pos(2, 0)
+    ));
+    // 別の辺をブロックしていれば矛盾しない
+    assert!(lock(ResolveHints::builder(grid))
+        .block(grid.pos(0, 0), EdgePos::new(grid.pos(1, 0), Dir::North))
+        .build()
+        .is_ok());
+}
@@ -0,0 +1,221 @@
+//! `ResolveHints` をテキストファイルに保存するための形式.
+//!
+//! 1 行に 1 つのヒントを書く. 座標は回答と同じく 2 桁の 16 進数で表す.
+//!
+//! ```text
+//! block <断片の座標> <辺の断片の座標> <辺の向き N/E/S/W>
+//! soft <断片の座標> <辺の断片の座標> <辺の向き> <一致度に掛ける倍率>
+//! lock <辺の断片の座標> <辺の向き> <続けるなら 1> [<断片の座標> <回転 0-3>]...
+//! ```
+
+use std::fmt::Write as _;
+
+use anyhow::{bail, ensure, Context as _, Result};
+
+use super::{EdgePos, HintKey, LockedPairs, ResolveHints};
+use crate::{
+    basis::{Dir, Rot},
+    grid::{Grid, Pos},
+};
+
+pub(super) fn write_hints(hints: &ResolveHints) -> String {
+    let mut result = String::new();
+    let mut blocklist: Vec<_> = hints.blocklist().collect();
+    blocklist.sort_by_key(|&(pos, edge)| (pos, edge.pos, dir_char(edge.dir)));
+    for (pos, edge) in blocklist {
+        let _ = writeln!(
+            result,
+            "block {} {} {}",
+            pos_str(pos),
+            pos_str(edge.pos),
+            dir_char(edge.dir)
+        );
+    }
+
+    let mut soft_blocklist: Vec<_> = hints.soft_blocklist().collect();
+    soft_blocklist.sort_by_key(|&(pos, edge, _)| (pos, edge.pos, dir_char(edge.dir)));
+    for (pos, edge, penalty) in soft_blocklist {
+        let _ = writeln!(
+            result,
+            "soft {} {} {} {}",
+            pos_str(pos),
+            pos_str(edge.pos),
+            dir_char(edge.dir),
+            penalty
+        );
+    }
+
+    let mut locked: Vec<_> = hints.locked_pairs().collect();
+    locked.sort_by_key(|(edge, _)| (edge.pos, dir_char(edge.dir)));
+    for (edge, pairs) in locked {
+        let _ = write!(
+            result,
+            "lock {} {} {}",
+            pos_str(edge.pos),
+            dir_char(edge.dir),
+            pairs.continue_after_apply as u8
+        );
+        for &(pos, rot) in &pairs.tail {
+            let _ = write!(result, " {} {}", pos_str(pos), rot.as_num());
+        }
+        result.push('\n');
+    }
+    result
+}
+
+pub(super) fn read_hints(grid: Grid, text: &str) -> Result<ResolveHints> {
+    let mut hints = ResolveHints::default();
+    for (i, line) in text.lines().enumerate() {
+        let tokens: Vec<_> = line.split_ascii_whitespace().collect();
+        let parsed = match tokens.as_slice() {
+            [] => Ok(()),
+            ["block", pos, edge_pos, dir] => (|| {
+                let edge = EdgePos::new(parse_pos(grid, edge_pos)?, parse_dir(dir)?);
+                hints.push_blocklist(parse_pos(grid, pos)?, edge);
+                Ok(())
+            })(),
+            ["soft", pos, edge_pos, dir, penalty] => (|| {
+                let edge = EdgePos::new(parse_pos(grid, edge_pos)?, parse_dir(dir)?);
+                let penalty = match penalty.parse::<f64>() {
+                    Ok(v) if 0.0 < v && v.is_finite() => v,
+                    _ => bail!("expected a positive penalty, but found {}", penalty),
+                };
+                hints.push_soft_blocklist(parse_pos(grid, pos)?, edge, penalty);
+                Ok(())
+            })(),
+            ["lock", edge_pos, dir, continues, tail @ ..] => (|| {
+                let edge = EdgePos::new(parse_pos(grid, edge_pos)?, parse_dir(dir)?);
+                ensure!(tail.len() % 2 == 0, "each fragment needs its rotation");
+                let tail = tail
+                    .chunks(2)
+                    .map(|pair| Ok((parse_pos(grid, pair[0])?, parse_rot(pair[1])?)))
+                    .collect::<Result<_>>()?;
+                let mut pairs = LockedPairs::new(tail);
+                match *continues {
+                    "1" => {}
+                    "0" => pairs.stop_after_apply(),
+                    _ => bail!("expected 0 or 1, but found {}", continues),
+                }
+                hints.push_locked_pair(edge, pairs);
+                Ok(())
+            })(),
+            _ => Err(anyhow::anyhow!("unknown hint")),
+        };
+        parsed.with_context(|| format!("failed to parse line {}: '{}'", i + 1, line))?;
+    }
+    Ok(hints)
+}
+
+/// ヒントファイルの行と同じ書き方で表す. ロックされた組の続く断片は省く.
+impl std::fmt::Display for HintKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            HintKey::Blocklist(pos, edge) => write!(
+                f,
+                "block {} {} {}",
+                pos_str(pos),
+                pos_str(edge.pos),
+                dir_char(edge.dir)
+            ),
+            HintKey::SoftBlocklist(pos, edge) => write!(
+                f,
+                "soft {} {} {}",
+                pos_str(pos),
+                pos_str(edge.pos),
+                dir_char(edge.dir)
+            ),
+            HintKey::LockedPairs(edge) => {
+                write!(f, "lock {} {}", pos_str(edge.pos), dir_char(edge.dir))
+            }
+        }
+    }
+}
+
+fn pos_str(pos: Pos) -> String {
+    format!("{:X}{:X}", pos.x(), pos.y())
+}
+
+fn dir_char(dir: Dir) -> char {
+    match dir {
+        Dir::North => 'N',
+        Dir::East => 'E',
+        Dir::South => 'S',
+        Dir::West => 'W',
+    }
+}
+
+fn parse_pos(grid: Grid, token: &str) -> Result<Pos> {
+    let digits: Vec<_> = token.chars().filter_map(|c| c.to_digit(16)).collect();
+    ensure!(
+        token.len() == 2 && digits.len() == 2,
+        "expected 2 hex digits, but found {}",
+        token
+    );
+    let (x, y) = (digits[0] as u8, digits[1] as u8);
+    ensure!(
+        x < grid.width() && y < grid.height(),
+        "{} is out of the grid",
+        token
+    );
+    Ok(grid.pos(x, y))
+}
+
+fn parse_dir(token: &str) -> Result<Dir> {
+    Ok(match token {
+        "N" => Dir::North,
+        "E" => Dir::East,
+        "S" => Dir::South,
+        "W" => Dir::West,
+        _ => bail!("expected one of N/E/S/W, but found {}", token),
+    })
+}
+
+fn parse_rot(token: &str) -> Result<Rot> {
+    match token.parse() {
+        Ok(rot @ 0..=3) => Ok(Rot::from_num(rot)),
+        _ => bail!("expected a rotation 0-3, but found {}", token),
+    }
+}
+
+#[test]
+fn test_hints_file() {
+    let grid = Grid::new(3, 3);
+    let text =
+        "block 10 21 E\nblock 10 02 N\nsoft 11 12 W 1.5\nlock 00 S 0 01 1 02 3\nlock 22 W 1\n";
+    let hints = read_hints(grid, text).unwrap();
+    assert_eq!(2, hints.blocklist_of(grid.pos(1, 0)).count());
+    assert_eq!(
+        vec![&(EdgePos::new(grid.pos(1, 2), Dir::West), 1.5)],
+        hints.soft_blocklist_of(grid.pos(1, 1)).collect::<Vec<_>>()
+    );
+    let locked = hints
+        .locked_pairs_of(EdgePos::new(grid.pos(0, 0), Dir::South))
+        .unwrap();
+    assert_eq!(
+        vec![(grid.pos(0, 1), Rot::R90), (grid.pos(0, 2), Rot::R270)],
+        locked.tail
+    );
+    assert!(!locked.continue_after_apply);
+
+    let written = write_hints(&hints);
+    assert_eq!(
+        "block 10 02 N\nblock 10 21 E\nsoft 11 12 W 1.5\nlock 00 S 0 01 1 02 3\nlock 22 W 1\n",
+        written
+    );
+
+    // 読み込んだ順に新しいものから捨てられる
+    let mut hints = hints;
+    let newest = hints.drop_newest().unwrap();
+    assert_eq!("lock 22 W", newest.to_string());
+    assert_eq!(1, hints.locked_pairs().count());
+    assert_eq!("lock 00 S", hints.drop_newest().unwrap().to_string());
+    assert_eq!("soft 11 12 W", hints.drop_newest().unwrap().to_string());
+    assert_eq!(0, hints.soft_blocklist_of(grid.pos(1, 1)).count());
+    assert_eq!("block 10 02 N", hints.drop_newest().unwrap().to_string());
+    assert_eq!(1, hints.blocklist_of(grid.pos(1, 0)).count());
+
+    assert!(read_hints(grid, "block 10 31 E").is_err());
+    assert!(read_hints(grid, "lock 00 S 1 01").is_err());
+    assert!(read_hints(grid, "soft 11 12 W 0").is_err());
+    assert!(read_hints(grid, "hoge").is_err());
+}
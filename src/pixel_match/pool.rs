@@ -0,0 +1,108 @@
+//! 復元中にまだ置いていない断片を持つ.
+
+use std::collections::HashMap;
+
+use super::FindAndRemove;
+use crate::{fragment::Fragment, grid::Pos};
+
+/// `FragmentPool` はまだ置いていない断片を持ち, 問題画像での位置から断片を取り出す.
+///
+/// 同じ一致度の候補からどの断片を選ぶかは探す順番で決まるので, 取り出した断片の場所は詰めずに空けておく.
+#[derive(Debug, Clone, Default)]
+pub(super) struct FragmentPool {
+    slots: Vec<Option<Fragment>>,
+    index: HashMap<Pos, usize>,
+}
+
+impl FragmentPool {
+    pub(super) fn new(fragments: Vec<Fragment>) -> Self {
+        let index = fragments
+            .iter()
+            .enumerate()
+            .map(|(i, fragment)| (fragment.pos, i))
+            .collect();
+        Self {
+            slots: fragments.into_iter().map(Some).collect(),
+            index,
+        }
+    }
+
+    /// 問題画像で `pos` にあった断片を取り出す. もう取り出していれば `None` を返す.
+    pub(super) fn take(&mut self, pos: Pos) -> Option<Fragment> {
+        let i = self.index.remove(&pos)?;
+        self.slots[i].take()
+    }
+
+    pub(super) fn contains(&self, pos: Pos) -> bool {
+        self.index.contains_key(&pos)
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// 残っている断片を, 最初に渡された順に返す.
+    pub(super) fn iter(&self) -> impl Iterator<Item = &Fragment> + Clone {
+        self.slots.iter().flatten()
+    }
+}
+
+impl FromIterator<Fragment> for FragmentPool {
+    fn from_iter<T: IntoIterator<Item = Fragment>>(iter: T) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl<'a> IntoIterator for &'a FragmentPool {
+    type Item = &'a Fragment;
+    type IntoIter = std::iter::Flatten<std::slice::Iter<'a, Option<Fragment>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.slots.iter().flatten()
+    }
+}
+
+impl FindAndRemove<Fragment> for FragmentPool {
+    fn find_and_remove(&mut self, mut pred: impl FnMut(&Fragment) -> bool) -> Option<Fragment> {
+        let pos = self.iter().find(|&fragment| pred(fragment))?.pos;
+        self.take(pos)
+    }
+}
+
+#[test]
+fn test_fragment_pool() {
+    let problem = crate::scramble::smooth_problem(3, 2);
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem).unwrap();
+    let mut pool = FragmentPool::new(fragments.clone());
+    assert_eq!(6, pool.len());
+
+    assert_eq!(
+        Some(grid.pos(1, 0)),
+        pool.take(grid.pos(1, 0)).map(|f| f.pos)
+    );
+    assert!(pool.take(grid.pos(1, 0)).is_none());
+    assert!(!pool.contains(grid.pos(1, 0)));
+    assert_eq!(
+        Some(grid.pos(2, 1)),
+        pool.find_and_remove(|f| f.pos.y() == 1 && f.pos.x() == 2)
+            .map(|f| f.pos)
+    );
+
+    // 取り出した後も残りの断片の順番は変わらない
+    let rest: Vec<_> = fragments
+        .iter()
+        .map(|f| f.pos)
+        .filter(|&pos| pos != grid.pos(1, 0) && pos != grid.pos(2, 1))
+        .collect();
+    assert_eq!(rest, pool.iter().map(|f| f.pos).collect::<Vec<_>>());
+    assert_eq!(4, pool.len());
+    while let Some(fragment) = pool.find_and_remove(|_| true) {
+        assert!(!pool.contains(fragment.pos));
+    }
+    assert!(pool.is_empty());
+}
@@ -0,0 +1,132 @@
+//! 復元画像の継ぎ目の一致度の統計から, 取り違えていそうな継ぎ目を見つける.
+
+use super::{average_distance, Metric};
+use crate::{
+    basis::Dir,
+    fragment::Fragment,
+    grid::{Pos, VecOnGrid},
+};
+
+/// `Seam` は復元画像で隣り合う 2 つの断片の継ぎ目を表す.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Seam {
+    /// 左か上の断片の, 復元画像での位置
+    pub a: Pos,
+    /// 右か下の断片の, 復元画像での位置
+    pub b: Pos,
+    pub score: f64,
+}
+
+/// `SeamReport` は復元画像のすべての継ぎ目の一致度と, その平均と標準偏差を表す.
+#[derive(Debug, Clone)]
+pub struct SeamReport {
+    seams: Vec<Seam>,
+    mean: f64,
+    std_dev: f64,
+}
+
+impl SeamReport {
+    pub fn new(recovered: &VecOnGrid<Fragment>, metric: Metric) -> Self {
+        let grid = recovered.grid;
+        let seam = |a: Pos, b: Pos, dir: Dir| Seam {
+            a,
+            b,
+            score: average_distance(
+                metric,
+                recovered[a].edges.edge(dir).pixels.iter(),
+                recovered[b].edges.edge(dir.opposite()).pixels.iter().rev(),
+            ),
+        };
+
+        let mut seams = vec![];
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                let a = grid.pos(x, y);
+                if x + 1 < grid.width() {
+                    seams.push(seam(a, grid.pos(x + 1, y), Dir::East));
+                }
+                if y + 1 < grid.height() {
+                    seams.push(seam(a, grid.pos(x, y + 1), Dir::South));
+                }
+            }
+        }
+
+        let len = seams.len().max(1) as f64;
+        let mean = seams.iter().map(|s| s.score).sum::<f64>() / len;
+        let variance = seams.iter().map(|s| (s.score - mean).powi(2)).sum::<f64>() / len;
+
+        Self {
+            seams,
+            mean,
+            std_dev: variance.sqrt(),
+        }
+    }
+
+    pub fn seams(&self) -> &[Seam] {
+        &self.seams
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.std_dev
+    }
+
+    /// 一致度が平均より標準偏差の `k` 倍を超えて悪い継ぎ目.
+    pub fn suspicious(&self, k: f64) -> impl Iterator<Item = &Seam> + '_ {
+        let threshold = self.mean + k * self.std_dev;
+        self.seams.iter().filter(move |s| threshold < s.score)
+    }
+
+    /// 怪しい継ぎ目があり, 目で確かめた方がよいか.
+    pub fn is_suspicious(&self, k: f64) -> bool {
+        self.suspicious(k).next().is_some()
+    }
+
+    /// 怪しい継ぎ目をログに出し, 1 つでもあったかを返す.
+    pub fn warn_suspicious(&self, k: f64) -> bool {
+        let mut found = false;
+        for seam in self.suspicious(k) {
+            tracing::warn!(
+                a = ?seam.a,
+                b = ?seam.b,
+                score = seam.score,
+                mean = self.mean,
+                std_dev = self.std_dev,
+                "suspicious seam in the recovered image"
+            );
+            found = true;
+        }
+        found
+    }
+}
+
+#[test]
+fn test_seam_report() {
+    let problem = include_bytes!("../../test_cases/03.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let arrangement = super::resolve_headless(Fragment::new_all(&problem).unwrap(), grid).unwrap();
+
+    let report = SeamReport::new(arrangement.recovered(), Metric::Euclidean);
+    let (w, h) = (grid.width() as usize, grid.height() as usize);
+    assert_eq!((w - 1) * h + w * (h - 1), report.seams().len());
+    assert!(!report.is_suspicious(f64::INFINITY));
+
+    // 離れた 2 つの断片を入れ替えると, その周りの継ぎ目だけが怪しくなる
+    let (a, b) = (
+        grid.pos(1, 1),
+        grid.pos(grid.width() - 2, grid.height() - 2),
+    );
+    let mut swapped = arrangement.into_recovered();
+    let fragment_a = swapped[a].clone();
+    swapped[a] = std::mem::replace(&mut swapped[b], fragment_a);
+
+    let report = SeamReport::new(&swapped, Metric::Euclidean);
+    assert!(report.is_suspicious(1.0));
+    assert!(report
+        .suspicious(1.0)
+        .all(|s| [a, b].contains(&s.a) || [a, b].contains(&s.b)));
+}
@@ -1,20 +1,28 @@
-use super::{average_distance, find_with, gui::EdgePos, DiffEntry, FindAndRemove, ResolveHints};
+use super::{
+    average_distance, find_with, soft_penalty, BlockSuggestion, Candidates, DiffEntry, EdgePos,
+    FillContext, FragmentPool, MatchLog, Metric, Provenance, ResolveHints,
+};
 use crate::{
-    basis::Dir,
+    basis::{Dir, Rot},
+    error::Error,
     fragment::{Edge, Fragment},
+    grid::Pos,
 };
 use std::cell::RefCell;
 
-fn find_by_single_side<'a, B>(
-    fragments: &[Fragment],
+fn find_by_single_side<'a, B, S>(
+    fragments: &FragmentPool,
     reference_edge: &Edge,
-    blocklist: B,
-) -> DiffEntry
+    (blocklist, soft_blocklist): (B, S),
+    (metric, rots): (Metric, &'static [Rot]),
+) -> Option<Candidates>
 where
     B: Iterator<Item = &'a EdgePos> + Clone + 'a,
+    S: Iterator<Item = &'a (EdgePos, f64)> + Clone + 'a,
 {
     find_with(fragments, move |fragment| {
         let blocklist = blocklist.clone();
+        let soft_blocklist = soft_blocklist.clone();
         fragment
             .edges
             .iter()
@@ -22,20 +30,28 @@ where
                 !blocklist
                     .clone()
                     .any(|b| b.pos == fragment.pos && b.dir == e.dir)
+                    && rots.contains(&(fragment.rot + reference_edge.dir.calc_rot(e.dir)))
             })
             .map(move |edge| DiffEntry {
                 pos: fragment.pos,
                 dir: edge.dir,
-                score: average_distance(reference_edge.pixels.iter(), edge.pixels.iter().rev()),
+                score: average_distance(
+                    metric,
+                    reference_edge.pixels.iter(),
+                    edge.pixels.iter().rev(),
+                ) * soft_penalty(soft_blocklist.clone(), fragment.pos, edge.dir),
             })
     })
 }
 
 struct Context<'a> {
     hints: RefCell<&'a mut ResolveHints>,
+    metric: Metric,
+    rots: &'static [Rot],
     num_fragment: u8,
-    fragments: RefCell<&'a mut Vec<Fragment>>,
+    fragments: RefCell<&'a mut FragmentPool>,
     root_ref: &'a Fragment,
+    log: RefCell<&'a mut MatchLog>,
 }
 
 struct Finder<'a> {
@@ -47,35 +63,42 @@ struct Finder<'a> {
 }
 
 impl<'a> Finder<'a> {
+    /// 次の断片をつなげる, 列の端にある断片の問題画像での位置.
+    fn reference_pos(&self) -> Pos {
+        self.list.borrow().last().unwrap_or(self.ctx.root_ref).pos
+    }
+
     fn apply_locked_pairs(&mut self) {
-        let fragment_pos = self.list.borrow().last().unwrap_or(self.ctx.root_ref).pos;
-        let edgepos = EdgePos::new(fragment_pos, self.dir);
+        let edgepos = EdgePos::new(self.reference_pos(), self.dir);
 
-        if let Some(pairs) = self.ctx.hints.borrow_mut().take_locked_pairs(edgepos) {
+        if let Some(pairs) = self.ctx.hints.borrow_mut().consume_locked_pairs(edgepos) {
             let tail_len = pairs.tail.len();
+            tracing::debug!(?edgepos, tail_len, "applying locked_pairs");
 
             if self.list.borrow().len() + self.oppisite_list.borrow().len() + tail_len + 1
                 > self.ctx.num_fragment as usize
             {
-                println!("shaker_fill: couldn't apply locked_pairs because of size overrun");
+                tracing::warn!("shaker_fill: couldn't apply locked_pairs because of size overrun");
+                self.ctx.log.borrow_mut().unapplied_locks.push(edgepos);
                 return;
             }
 
             for (pos, rot) in pairs.tail {
-                let mut fragment = match self
-                    .ctx
-                    .fragments
-                    .borrow_mut()
-                    .find_and_remove(|x| x.pos == pos)
-                {
+                let mut fragment = match self.ctx.fragments.borrow_mut().take(pos) {
                     Some(v) => v,
                     None => {
-                        println!("shaker_fill: partially applied locked_pair because fragment in pair is already taken. edgepos: {:?}", edgepos);
+                        tracing::warn!(?edgepos, "shaker_fill: partially applied locked_pair because fragment in pair is already taken");
+                        self.ctx.log.borrow_mut().unapplied_locks.push(edgepos);
                         return;
                     }
                 };
 
                 fragment.rotate(rot);
+                self.ctx
+                    .log
+                    .borrow_mut()
+                    .provenances
+                    .insert(pos, Provenance::Locked { reference: edgepos });
                 self.list.borrow_mut().push(fragment);
             }
 
@@ -85,29 +108,48 @@ impl<'a> Finder<'a> {
         }
     }
 
-    fn find_match(&self) -> DiffEntry {
+    /// 列の端につなげる断片の候補を探す. 置ける断片が無ければ `None` を返す.
+    fn find_match(&self) -> Option<Candidates> {
         let list_ref = self.list.borrow();
         let fragment_ref = list_ref.last().unwrap_or(self.ctx.root_ref);
+        let hints = self.ctx.hints.borrow();
         let mut result = find_by_single_side(
             *self.ctx.fragments.borrow(),
             fragment_ref.edges.edge(self.dir),
-            self.ctx.hints.borrow().take_blacklist(fragment_ref.pos),
+            (
+                hints.blocklist_of(fragment_ref.pos),
+                hints.soft_blocklist_of(fragment_ref.pos),
+            ),
+            (self.ctx.metric, self.ctx.rots),
         );
 
-        if self.stop {
-            result.score = f64::MAX;
+        if let (true, Some(result)) = (self.stop, result.as_mut()) {
+            result.best.score = f64::MAX;
         }
 
         result
     }
 
-    fn apply(&mut self, d: DiffEntry) {
-        let mut fragment = self
-            .ctx
-            .fragments
-            .borrow_mut()
-            .find_and_remove(|x| x.pos == d.pos)
-            .unwrap();
+    fn apply(&mut self, candidates: Candidates) {
+        let reference_pos = self.reference_pos();
+        let mut log = self.ctx.log.borrow_mut();
+        if let Some(suggestion) =
+            BlockSuggestion::from_candidates(reference_pos, &candidates, |d| {
+                EdgePos::new(d.pos, d.dir)
+            })
+        {
+            log.suggestions.push(suggestion);
+        }
+
+        let d = candidates.best;
+        log.provenances.insert(
+            d.pos,
+            Provenance::SingleSide {
+                reference: EdgePos::new(reference_pos, self.dir),
+                score: d.score,
+            },
+        );
+        let mut fragment = self.ctx.fragments.borrow_mut().take(d.pos).unwrap();
 
         fragment.rotate(self.dir.calc_rot(d.dir));
         self.list.borrow_mut().push(fragment);
@@ -115,20 +157,34 @@ impl<'a> Finder<'a> {
 }
 
 /// root_ref から left_dir と left_dir.opposite() 方向に探索して、スコアが良い順に採用する。
+///
+/// どちらの方向にも置ける断片が無くなったら, `Error::NoCandidate` を返す.
+/// `context.abandon` でやめるよう求められたら, 1 つ置くごとに確かめてやめる.
 pub(super) fn shaker_fill(
     num_fragment: u8,
-    fragments: &mut Vec<Fragment>,
+    fragments: &mut FragmentPool,
     left_dir: Dir,
     root_ref: &Fragment,
     hints: &mut ResolveHints,
-) -> (Vec<Fragment>, Vec<Fragment>) {
+    context: FillContext<'_>,
+    log: &mut MatchLog,
+) -> crate::Result<(Vec<Fragment>, Vec<Fragment>)> {
+    let FillContext {
+        metric,
+        rots,
+        abandon,
+        ..
+    } = context;
     let (left, right) = (RefCell::new(vec![]), RefCell::new(vec![]));
 
     let ctx = Context {
         hints: RefCell::new(hints),
+        metric,
+        rots,
         num_fragment,
         fragments: RefCell::new(fragments),
         root_ref,
+        log: RefCell::new(log),
     };
 
     let mut left_finder = Finder {
@@ -148,6 +204,9 @@ pub(super) fn shaker_fill(
     };
 
     while right.borrow().len() + left.borrow().len() + (1/* for root */) != num_fragment as usize {
+        if abandon.is_requested() {
+            return Err(abandon.error());
+        }
         right_finder.apply_locked_pairs();
         left_finder.apply_locked_pairs();
 
@@ -155,15 +214,19 @@ pub(super) fn shaker_fill(
             break;
         }
 
-        let right_score = right_finder.find_match();
-        let left_score = left_finder.find_match();
-
-        if right_score.score < left_score.score {
-            right_finder.apply(right_score);
-        } else {
-            left_finder.apply(left_score);
+        match (right_finder.find_match(), left_finder.find_match()) {
+            (Some(right_score), Some(left_score)) => {
+                if right_score.best.score < left_score.best.score {
+                    right_finder.apply(right_score);
+                } else {
+                    left_finder.apply(left_score);
+                }
+            }
+            (Some(right_score), None) => right_finder.apply(right_score),
+            (None, Some(left_score)) => left_finder.apply(left_score),
+            (None, None) => return Err(Error::NoCandidate(right_finder.reference_pos())),
         }
     }
 
-    (left.into_inner(), right.into_inner())
+    Ok((left.into_inner(), right.into_inner()))
 }
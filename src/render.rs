@@ -0,0 +1,127 @@
+//! 断片を盤面の通りに並べた画像を作る. GUI の表示と画像ファイルへの書き出しで同じものを使う.
+
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+#[cfg(feature = "fs")]
+use anyhow::{Context as _, Result};
+
+use crate::{
+    basis::{Color, Image},
+    fragment::Fragment,
+    grid::{Grid, Pos, VecOnGrid},
+};
+
+/// 各マスの断片を回転させて並べた画像を作る. 断片の無いマスは黒く塗る.
+pub fn compose(fragments: &VecOnGrid<Option<Fragment>>) -> Image {
+    let grid = fragments.grid;
+    let side_length = fragments
+        .iter()
+        .flatten()
+        .next()
+        .map_or(0, Fragment::side_length);
+    let mut image = blank_image(grid, side_length);
+    for (pos, cell) in fragments.iter_with_pos() {
+        let mut fragment = cell.clone();
+        draw_cell(
+            &mut image,
+            grid,
+            pos,
+            fragment.as_mut().map(|fragment| fragment.pixels()),
+        );
+    }
+    image
+}
+
+/// 一辺 `side_length` の断片を `grid` の通りに並べられる, 黒く塗った画像を作る.
+pub fn blank_image(grid: Grid, side_length: usize) -> Image {
+    let width = side_length * grid.width() as usize;
+    let height = side_length * grid.height() as usize;
+    Image {
+        width: width as u16,
+        height: height as u16,
+        pixels: vec![Color { r: 0, g: 0, b: 0 }; width * height],
+    }
+}
+
+/// `image` の `pos` のマスを, 断片の画素 `pixels` で塗る. `pixels` が無ければ黒く塗る.
+pub fn draw_cell(image: &mut Image, grid: Grid, pos: Pos, pixels: Option<&[Color]>) {
+    let side_length = image.width as usize / grid.width() as usize;
+    let origin =
+        pos.y() as usize * side_length * image.width as usize + pos.x() as usize * side_length;
+    for py in 0..side_length {
+        let dst = &mut image.pixels[origin + py * image.width as usize..][..side_length];
+        match pixels {
+            Some(pixels) => dst.copy_from_slice(&pixels[py * side_length..][..side_length]),
+            None => dst.fill(Color { r: 0, g: 0, b: 0 }),
+        }
+    }
+}
+
+/// `image` を PNG 形式で `path` に書き出す.
+#[cfg(feature = "fs")]
+pub fn save_png(image: &Image, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let bytes = image.pixels.iter().flat_map(|c| [c.r, c.g, c.b]).collect();
+    ::image::RgbImage::from_raw(image.width as u32, image.height as u32, bytes)
+        .context("the pixels do not match the image size")?
+        .save_with_format(path, ::image::ImageFormat::Png)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[test]
+fn test_compose() {
+    use crate::basis::Rot;
+
+    let problem = include_bytes!("../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(&problem)
+        .unwrap()
+        .into_iter()
+        .map(Some)
+        .collect();
+    let mut fragments = VecOnGrid::from_vec(grid, fragments).unwrap();
+
+    // 問題画像の位置に回転させずに並べれば問題画像と同じになる
+    let image = compose(&fragments);
+    assert_eq!(
+        (problem.image.width, problem.image.height),
+        (image.width, image.height)
+    );
+    assert!(problem.image.pixels == image.pixels);
+
+    let (a, b) = (grid.pos(0, 0), grid.pos(1, 1));
+    let mut rotated = fragments[a].take().unwrap();
+    rotated.rotate(Rot::R180);
+    fragments[b] = Some(rotated.clone());
+    let image = compose(&fragments);
+    let side_length = rotated.side_length();
+    let at = |pos: Pos, x: usize, y: usize| {
+        let x = pos.x() as usize * side_length + x;
+        let y = pos.y() as usize * side_length + y;
+        image.pixels[x + y * image.width as usize]
+    };
+    assert!(Color { r: 0, g: 0, b: 0 } == at(a, 0, 0));
+    assert!(
+        rotated.pixels()[side_length + 1] == at(b, 1, 1),
+        "the fragment must be drawn rotated"
+    );
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_save_png() {
+    let grid = Grid::new(2, 1);
+    let mut image = blank_image(grid, 2);
+    let red = Color { r: 255, g: 0, b: 0 };
+    draw_cell(&mut image, grid, grid.pos(1, 0), Some(&[red; 4]));
+
+    let path = std::env::temp_dir().join(format!("render_test_{}.png", std::process::id()));
+    save_png(&image, &path).unwrap();
+    let loaded = ::image::open(&path).unwrap().into_rgb8();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!((4, 2), loaded.dimensions());
+    assert_eq!([0, 0, 0], loaded.get_pixel(1, 1).0);
+    assert_eq!([255, 0, 0], loaded.get_pixel(2, 0).0);
+}
@@ -0,0 +1,151 @@
+//! 回答の候補ごとに, 選択と交換の回数やコスト, 推定した得点を人が読める形にまとめる.
+//!
+//! ```text
+//! selections: 2/8, swaps: 5, cost: 2 * 10 + 5 * 3 = 35
+//! estimated: 0 position and 1 rotation mismatches
+//!   #1 select 10, 3 swaps: LDR
+//!   #2 select 21, 2 swaps: UU
+//! ```
+
+use std::fmt;
+
+use crate::{
+    basis::{Operation, Problem, Rot},
+    grid::{Pos, VecOnGrid},
+    simulator::{self, Score},
+};
+
+/// `Report` は回答の候補 1 つを, 復元した配置が正しいとして採点した結果と操作の内訳.
+#[derive(Debug, Clone)]
+pub struct Report<'a> {
+    score: Score,
+    select_limit: u8,
+    select_cost: u16,
+    swap_cost: u16,
+    ops: &'a [Operation],
+}
+
+impl<'a> Report<'a> {
+    /// 問題画像の位置の順に並べた回転 `rots` と操作列 `ops` を, 正しい配置 `truth` と比べて採点する.
+    pub fn new(
+        problem: &Problem,
+        truth: &VecOnGrid<(Pos, Rot)>,
+        rots: &[Rot],
+        ops: &'a [Operation],
+    ) -> Self {
+        let rots = VecOnGrid::from_vec(truth.grid, rots.to_vec())
+            .expect("rotations must be given for all fragments");
+        Self {
+            score: simulator::score(problem, truth, &rots, ops),
+            select_limit: problem.select_limit,
+            select_cost: problem.select_cost,
+            swap_cost: problem.swap_cost,
+            ops,
+        }
+    }
+
+    /// 推定した得点. 復元した配置が正しければ, サーバーでも同じ得点になる.
+    pub fn score(&self) -> Score {
+        self.score
+    }
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Score {
+            cost,
+            select_count,
+            swap_count,
+            pos_mismatch_count,
+            rot_mismatch_count,
+        } = self.score;
+        write!(
+            f,
+            "selections: {}/{}, swaps: {}, cost: {} * {} + {} * {} = {}",
+            select_count,
+            self.select_limit,
+            swap_count,
+            select_count,
+            self.select_cost,
+            swap_count,
+            self.swap_cost,
+            cost
+        )?;
+        if select_count > self.select_limit as usize {
+            write!(f, " (over the limit)")?;
+        }
+        if self.score.is_perfect() {
+            write!(f, "\nestimated: perfect")?;
+        } else {
+            write!(
+                f,
+                "\nestimated: {} position and {} rotation mismatches",
+                pos_mismatch_count, rot_mismatch_count
+            )?;
+        }
+        for (i, op) in self.ops.iter().enumerate() {
+            let movements: String = op.movements.iter().map(|m| m.as_char()).collect();
+            write!(
+                f,
+                "\n  #{} select {:X}{:X}, {} swaps: {}",
+                i + 1,
+                op.select.x(),
+                op.select.y(),
+                op.movements.len(),
+                movements
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_report() {
+    use crate::{
+        basis::{Color, Image, Movement},
+        grid::Dims,
+    };
+
+    let dims = Dims {
+        width: 2,
+        height: 2,
+    };
+    let problem = Problem {
+        select_limit: 2,
+        select_cost: 10,
+        swap_cost: 3,
+        dims,
+        image: Image {
+            width: 2,
+            height: 2,
+            pixels: vec![Color { r: 0, g: 0, b: 0 }; 4],
+        },
+    };
+    let grid = problem.grid();
+    // (0, 0) と (1, 0) が入れ替わっていて, (1, 1) は 90 度回っている問題
+    let mut truth = VecOnGrid::with_init(grid, (grid.pos(0, 0), Rot::R0));
+    for pos in grid.all_pos() {
+        truth[pos] = (pos, Rot::R0);
+    }
+    truth[grid.pos(0, 0)].0 = grid.pos(1, 0);
+    truth[grid.pos(1, 0)].0 = grid.pos(0, 0);
+    truth[grid.pos(1, 1)].1 = Rot::R90;
+
+    let ops = [Operation {
+        select: grid.pos(0, 0),
+        movements: vec![Movement::Right],
+    }];
+    let rots = [Rot::R0; 4];
+    let report = Report::new(&problem, &truth, &rots, &ops);
+    assert_eq!(13, report.score().cost);
+    assert_eq!(
+        "selections: 1/2, swaps: 1, cost: 1 * 10 + 1 * 3 = 13\n\
+         estimated: 0 position and 1 rotation mismatches\n  \
+         #1 select 00, 1 swaps: R",
+        report.to_string()
+    );
+
+    let rots = [Rot::R0, Rot::R0, Rot::R0, Rot::R90];
+    let report = Report::new(&problem, &truth, &rots, &ops);
+    assert!(report.to_string().contains("estimated: perfect"));
+}
@@ -0,0 +1,150 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    basis::{Color, Image, Problem, Rot},
+    grid::{Dims, Pos, VecOnGrid},
+};
+
+/// `Scrambled` はばらばらにした問題と, その正解を表す.
+#[derive(Debug)]
+pub struct Scrambled {
+    pub problem: Problem,
+    /// 問題画像の各位置にある断片が, 原画像でどの位置にどれだけ回転して置かれるべきか.
+    pub truth: VecOnGrid<(Pos, Rot)>,
+}
+
+/// 完成した画像を持つ `original` の断片の位置と向きを `seed` から決まる乱数で入れ替える.
+///
+/// pixel_match は問題画像の左上の断片の向きを基準にするので, その断片だけは回転させない.
+pub fn scramble(original: &Problem, seed: u64) -> Scrambled {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let grid = original.grid();
+    let side = (original.image.width / original.dims.width as u16) as usize;
+
+    let mut sources: Vec<_> = grid.all_pos().collect();
+    sources.shuffle(&mut rng);
+
+    let mut truth = VecOnGrid::with_init(grid, (grid.pos(0, 0), Rot::R0));
+    let mut pixels = original.image.pixels.clone();
+    for (dest, source) in grid.all_pos().zip(sources) {
+        let rot = if dest == grid.pos(0, 0) {
+            Rot::R0
+        } else {
+            Rot::from_num(rng.gen_range(0..4))
+        };
+        let mut block = read_block(&original.image, source, side);
+        for _ in 0..rot.as_num() {
+            block = rotate_clockwise(&block, side);
+        }
+        write_block(
+            &mut pixels,
+            original.image.width as usize,
+            dest,
+            side,
+            &block,
+        );
+        // 元に戻すには逆向きに回転させる
+        truth[dest] = (source, rot.inverse());
+    }
+
+    Scrambled {
+        problem: Problem {
+            select_limit: original.select_limit,
+            select_cost: original.select_cost,
+            swap_cost: original.swap_cost,
+            dims: original.dims,
+            image: Image {
+                width: original.image.width,
+                height: original.image.height,
+                pixels,
+            },
+        },
+        truth,
+    }
+}
+
+/// 継ぎ目がなめらかにつながる, 一辺 8 ピクセルの断片が横に `width` 個, 縦に `height` 個並んだ完成した画像.
+///
+/// 断片を正しく並べたときだけ隣の断片と色がよく合うので, テストやベンチマークで `scramble` する元の画像に使う.
+pub fn smooth_problem(width: u8, height: u8) -> Problem {
+    let dims = Dims { width, height };
+    let side = 8;
+    let (width, height) = (side * dims.width as usize, side * dims.height as usize);
+    let mut pixels = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            let (x, y) = (x as f64, y as f64);
+            pixels.push(Color {
+                r: (128.0 + 100.0 * (x / 7.0).sin()) as u8,
+                g: (128.0 + 100.0 * (y / 5.0 + x / 11.0).cos()) as u8,
+                b: (x * 2.0 + y * 3.0) as u8,
+            });
+        }
+    }
+    Problem {
+        select_limit: 2,
+        select_cost: 1,
+        swap_cost: 1,
+        dims,
+        image: Image {
+            width: width as u16,
+            height: height as u16,
+            pixels,
+        },
+    }
+}
+
+fn read_block(image: &Image, pos: Pos, side: usize) -> Vec<Color> {
+    let width = image.width as usize;
+    let (left, top) = (pos.x() as usize * side, pos.y() as usize * side);
+    (0..side)
+        .flat_map(|y| {
+            let start = (top + y) * width + left;
+            image.pixels[start..start + side].iter().copied()
+        })
+        .collect()
+}
+
+fn write_block(pixels: &mut [Color], width: usize, pos: Pos, side: usize, block: &[Color]) {
+    let (left, top) = (pos.x() as usize * side, pos.y() as usize * side);
+    for (y, row) in block.chunks(side).enumerate() {
+        let start = (top + y) * width + left;
+        pixels[start..start + side].copy_from_slice(row);
+    }
+}
+
+fn rotate_clockwise(block: &[Color], side: usize) -> Vec<Color> {
+    let mut result = block.to_vec();
+    for i in 0..side {
+        for j in 0..side {
+            result[j * side + side - 1 - i] = block[i * side + j];
+        }
+    }
+    result
+}
+
+#[test]
+fn test_scramble() {
+    use crate::fragment::Fragment;
+
+    let original = include_bytes!("../test_cases/01_q.ppm");
+    let original = crate::image::read_problem(original.as_ref()).unwrap();
+    let scrambled = scramble(&original, 42);
+    assert!(scramble(&original, 42).problem.image.pixels == scrambled.problem.image.pixels);
+
+    let mut originals = Fragment::new_all(&original).unwrap();
+    let mut fragments = Fragment::new_all(&scrambled.problem).unwrap();
+    // 正解どおりに回転させれば, 元の位置の断片と同じ画像になる
+    for fragment in &mut fragments {
+        let (source, rot) = scrambled.truth[fragment.pos];
+        fragment.rotate(rot);
+        let source = originals
+            .iter_mut()
+            .find(|f| f.pos == source)
+            .unwrap()
+            .pixels()
+            .to_vec();
+        assert!(fragment.pixels() == source.as_slice());
+    }
+    assert_eq!(Rot::R0, scrambled.truth[original.grid().pos(0, 0)].1);
+}
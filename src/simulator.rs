@@ -0,0 +1,330 @@
+use anyhow::{ensure, Context as _, Result};
+
+use crate::{
+    basis::{Image, Operation, Problem, Rot},
+    fragment::Fragment,
+    grid::{Grid, Pos, VecOnGrid},
+    kaitou,
+    move_resolve::{self, edges_nodes::Nodes, ResolveParam},
+    pixel_match::{Metric, SeamReport},
+    render,
+};
+
+/// `Score` は回答を採点した結果を表す.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Score {
+    pub cost: u64,
+    pub select_count: usize,
+    pub swap_count: usize,
+    pub pos_mismatch_count: usize,
+    pub rot_mismatch_count: usize,
+}
+
+impl Score {
+    pub fn is_perfect(&self) -> bool {
+        self.pos_mismatch_count == 0 && self.rot_mismatch_count == 0
+    }
+}
+
+/// 復元した画像から, 問題画像の各位置にある断片が原画像でどこにどれだけ回転して置かれるかを求める.
+pub fn truth_from_recovered(recovered: &VecOnGrid<Fragment>) -> VecOnGrid<(Pos, Rot)> {
+    let grid = recovered.grid;
+    let mut truth = VecOnGrid::with_init(grid, (grid.pos(0, 0), Rot::R0));
+    for (pos, fragment) in recovered.iter_with_pos() {
+        truth[fragment.pos] = (pos, fragment.rot);
+    }
+    truth
+}
+
+/// 正解の回答 `answer` から, 問題画像の各位置にある断片が原画像でどこにどれだけ回転して置かれるかを求める.
+///
+/// `answer` は断片をすべて正しい位置と向きに並べ直すものでなければならない.
+pub fn truth_from_answer(grid: Grid, answer: &str) -> Result<VecOnGrid<(Pos, Rot)>> {
+    let (rots, ops) = kaitou::parse(grid, answer)?;
+    let rots = VecOnGrid::from_vec(grid, rots).expect("kaitou::parse checks the length");
+    let mut truth = VecOnGrid::with_init(grid, (grid.pos(0, 0), Rot::R0));
+    for (pos, &fragment) in replay(grid, &ops).iter_with_pos() {
+        truth[fragment] = (pos, rots[fragment]);
+    }
+    Ok(truth)
+}
+
+/// 断片を正解 `truth` の通りに並べ直す回答を `move_resolve` で求める. 最初に見つかった手順を使うので, コストは最小とは限らない.
+pub fn truth_answer(problem: &Problem, truth: &VecOnGrid<(Pos, Rot)>) -> Result<String> {
+    let grid = problem.grid();
+    let movements: Vec<_> = truth
+        .iter_with_pos()
+        .filter(|&(pos, &(correct, _))| pos != correct)
+        .map(|(pos, &(correct, _))| (correct, pos))
+        .collect();
+    let rots: Vec<_> = truth.iter().map(|&(_, rot)| rot).collect();
+    let param = ResolveParam {
+        select_limit: problem.select_limit,
+        swap_cost: problem.swap_cost,
+        select_cost: problem.select_cost,
+    };
+    let (ops, _) = move_resolve::resolve(grid, &movements, param)?
+        .next()
+        .context("move_resolve found no answer")?;
+    Ok(kaitou::ans(&ops, &rots))
+}
+
+/// 回答文字列 `answer` を `problem` に適用し, 公式と同じ方法でコストと不一致数を計算する.
+///
+/// `truth` は問題画像の各位置にある断片が, 原画像でどの位置にどれだけ回転して置かれるべきかを表す.
+pub fn simulate(problem: &Problem, truth: &VecOnGrid<(Pos, Rot)>, answer: &str) -> Result<Score> {
+    let grid = problem.grid();
+    ensure!(
+        truth.grid == grid,
+        "the truth grid {:?} does not match to the problem {:?}",
+        truth.grid,
+        grid
+    );
+    let (rots, ops) = kaitou::parse(grid, answer)?;
+    ensure!(
+        ops.len() <= problem.select_limit as usize,
+        "select count {} exceeds the limit {}",
+        ops.len(),
+        problem.select_limit
+    );
+    let rots = VecOnGrid::from_vec(grid, rots).expect("kaitou::parse checks the length");
+    Ok(score(problem, truth, &rots, &ops))
+}
+
+/// 回転情報 `rots` と操作列 `ops` を適用した結果を採点する.
+pub fn score(
+    problem: &Problem,
+    truth: &VecOnGrid<(Pos, Rot)>,
+    rots: &VecOnGrid<Rot>,
+    ops: &[Operation],
+) -> Score {
+    let grid = truth.grid;
+    let field = replay(grid, ops);
+
+    let swap_count = ops.iter().map(|op| op.movements.len()).sum();
+    let select_count = ops.len();
+    let cost = cost(problem.select_cost, problem.swap_cost, ops);
+
+    let pos_mismatch_count = field
+        .iter_with_pos()
+        .filter(|&(pos, &fragment)| truth[fragment].0 != pos)
+        .count();
+    let rot_mismatch_count = grid
+        .all_pos()
+        .filter(|&fragment| truth[fragment].1 != rots[fragment])
+        .count();
+
+    Score {
+        cost,
+        select_count,
+        swap_count,
+        pos_mismatch_count,
+        rot_mismatch_count,
+    }
+}
+
+/// `SeamQuality` は回答の通りに断片を並べた画像で, 隣り合う断片の継ぎ目の色がどれだけ食い違うかを表す.
+///
+/// 正しい配置が分からない本番の問題でも, 位置や回転を取り違えていそうかの目安になる. 小さいほど良い.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeamQuality {
+    /// すべての継ぎ目の食い違いの和
+    pub total: f64,
+    /// 継ぎ目 1 つあたりの食い違いの平均
+    pub mean: f64,
+    /// 最も食い違う継ぎ目の食い違い
+    pub worst: f64,
+}
+
+/// 回答文字列 `answer` を `problem` に適用して並べた画像の継ぎ目を, `metric` の色距離で測る. 正しい配置は要らない.
+pub fn simulate_seams(problem: &Problem, answer: &str, metric: Metric) -> Result<SeamQuality> {
+    let grid = problem.grid();
+    let (rots, ops) = kaitou::parse(grid, answer)?;
+    let rots = VecOnGrid::from_vec(grid, rots).expect("kaitou::parse checks the length");
+    seam_quality(problem, &rots, &ops, metric)
+}
+
+/// 回転情報 `rots` と操作列 `ops` の通りに並べた画像の継ぎ目を, `metric` の色距離で測る.
+pub fn seam_quality(
+    problem: &Problem,
+    rots: &VecOnGrid<Rot>,
+    ops: &[Operation],
+    metric: Metric,
+) -> Result<SeamQuality> {
+    let claimed = claimed_arrangement(problem, rots, ops)?;
+    let report = SeamReport::new(&claimed, metric);
+    let scores = report.seams().iter().map(|seam| seam.score);
+    Ok(SeamQuality {
+        total: scores.clone().sum(),
+        mean: report.mean(),
+        worst: scores.fold(0.0, f64::max),
+    })
+}
+
+/// 回答文字列 `answer` を `problem` の問題画像に適用し, 断片を並べ直した画像を作る.
+///
+/// 提出する操作で本当に原画像が復元されるかを, 人が目で確かめるのに使う.
+pub fn render_answer(problem: &Problem, answer: &str) -> Result<Image> {
+    let grid = problem.grid();
+    let (rots, ops) = kaitou::parse(grid, answer)?;
+    let rots = VecOnGrid::from_vec(grid, rots).expect("kaitou::parse checks the length");
+    let claimed = claimed_arrangement(problem, &rots, &ops)?;
+    let cells = claimed.into_iter().map(Some).collect();
+    Ok(render::compose(
+        &VecOnGrid::from_vec(grid, cells).expect("a fragment for each position"),
+    ))
+}
+
+/// 問題画像の断片を `rots` だけ回転させ, `ops` を適用した位置に並べる.
+pub fn claimed_arrangement(
+    problem: &Problem,
+    rots: &VecOnGrid<Rot>,
+    ops: &[Operation],
+) -> Result<VecOnGrid<Fragment>> {
+    let grid = problem.grid();
+    ensure!(
+        rots.grid == grid,
+        "the rotations for {:?} do not match to the problem {:?}",
+        rots.grid,
+        grid
+    );
+    let mut fragments = Fragment::new_all(problem)?;
+    for fragment in &mut fragments {
+        fragment.rotate(rots[fragment.pos]);
+    }
+    let fragments = VecOnGrid::from_vec(grid, fragments).expect("a fragment for each position");
+    let field = replay(grid, ops);
+    let claimed = grid
+        .all_pos()
+        .map(|pos| fragments[field[pos]].clone())
+        .collect();
+    Ok(VecOnGrid::from_vec(grid, claimed).expect("a fragment for each position"))
+}
+
+/// 操作列 `ops` のコストを計算する.
+pub fn cost(select_cost: u16, swap_cost: u16, ops: &[Operation]) -> u64 {
+    let swap_count: usize = ops.iter().map(|op| op.movements.len()).sum();
+    select_cost as u64 * ops.len() as u64 + swap_cost as u64 * swap_count as u64
+}
+
+/// 問題画像の状態から `ops` を適用し, 各位置に問題画像のどの位置の断片が来るかを求める.
+pub fn replay(grid: Grid, ops: &[Operation]) -> VecOnGrid<Pos> {
+    let mut nodes = Nodes::new(grid, &[]);
+    nodes.apply(ops);
+    nodes.nodes
+}
+
+#[cfg(test)]
+fn test_case_01() -> (Problem, VecOnGrid<(Pos, Rot)>) {
+    let problem = include_bytes!("../test_cases/01_q.ppm");
+    let problem = crate::image::read_problem(problem.as_ref()).unwrap();
+    let grid = problem.grid();
+    // 右上と右下の断片が入れ替わっている
+    let mut truth = VecOnGrid::with_init(grid, (grid.pos(0, 0), Rot::R0));
+    for (pos, cell) in truth.iter_mut_with_pos() {
+        cell.0 = pos;
+    }
+    truth[grid.pos(1, 0)].0 = grid.pos(1, 1);
+    truth[grid.pos(1, 1)].0 = grid.pos(1, 0);
+    (problem, truth)
+}
+
+#[test]
+fn simulate_test_case() {
+    let (problem, truth) = test_case_01();
+    let score = simulate(&problem, &truth, include_str!("../test_cases/01_a.txt")).unwrap();
+    assert_eq!(
+        Score {
+            cost: 4,
+            select_count: 1,
+            swap_count: 1,
+            pos_mismatch_count: 0,
+            rot_mismatch_count: 0,
+        },
+        score
+    );
+    assert!(score.is_perfect());
+}
+
+#[test]
+fn truth_answer_round_trip() {
+    let original = include_bytes!("../test_cases/01_q.ppm");
+    let original = crate::image::read_problem(original.as_ref()).unwrap();
+    let scrambled = crate::scramble::scramble(&original, 42);
+
+    // 正解の回答を適用すれば不一致は無く, 回答から正解を求め直せる
+    let answer = truth_answer(&scrambled.problem, &scrambled.truth).unwrap();
+    let score = simulate(&scrambled.problem, &scrambled.truth, &answer).unwrap();
+    assert!(score.is_perfect());
+    assert_eq!(
+        scrambled.truth,
+        truth_from_answer(original.grid(), &answer).unwrap()
+    );
+
+    let (problem, truth) = test_case_01();
+    assert_eq!(
+        truth,
+        truth_from_answer(problem.grid(), include_str!("../test_cases/01_a.txt")).unwrap()
+    );
+}
+
+#[test]
+fn simulate_mismatches() {
+    let (problem, truth) = test_case_01();
+
+    let score = simulate(&problem, &truth, "0120\r\n1\r\n00\r\n1\r\nR\r\n").unwrap();
+    assert_eq!(4, score.cost);
+    assert_eq!(3, score.pos_mismatch_count);
+    assert_eq!(2, score.rot_mismatch_count);
+
+    // 選択回数の上限を超えている
+    assert!(simulate(
+        &problem,
+        &truth,
+        "0000\r\n2\r\n10\r\n1\r\nD\r\n10\r\n0\r\n\r\n"
+    )
+    .is_err());
+}
+
+#[test]
+fn simulate_seam_quality() {
+    let (problem, _) = test_case_01();
+    let perfect = simulate_seams(
+        &problem,
+        include_str!("../test_cases/01_a.txt"),
+        Metric::Euclidean,
+    )
+    .unwrap();
+    let (w, h) = (2, 2);
+    let seams = ((w - 1) * h + w * (h - 1)) as f64;
+    assert!((perfect.total - perfect.mean * seams).abs() < 1e-9);
+    assert!(perfect.mean <= perfect.worst && perfect.worst <= perfect.total);
+
+    // 位置と回転を取り違えた回答は継ぎ目が食い違う
+    let wrong =
+        simulate_seams(&problem, "0120\r\n1\r\n00\r\n1\r\nR\r\n", Metric::Euclidean).unwrap();
+    assert!(perfect.total < wrong.total, "{:?} {:?}", perfect, wrong);
+}
+
+#[test]
+fn render_answer_test_case() {
+    let (problem, _) = test_case_01();
+    let grid = problem.grid();
+
+    // 何もしない回答なら問題画像のまま
+    let image = render_answer(&problem, "0000\r\n0\r\n").unwrap();
+    assert!(problem.image.pixels == image.pixels);
+
+    // 右上と右下の断片を入れ替えた画像になる
+    let image = render_answer(&problem, include_str!("../test_cases/01_a.txt")).unwrap();
+    let fragments = Fragment::new_all(&problem)
+        .unwrap()
+        .into_iter()
+        .map(Some)
+        .collect();
+    let mut fragments = VecOnGrid::from_vec(grid, fragments).unwrap();
+    fragments.swap(grid.pos(1, 0), grid.pos(1, 1));
+    let expected = render::compose(&fragments);
+    assert!(expected.pixels == image.pixels);
+    assert!(problem.image.pixels != image.pixels);
+}
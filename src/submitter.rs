@@ -0,0 +1,441 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context as _, Result};
+use tokio::{
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    best_submission::{BestSubmission, Standing},
+    client::{Client, ClientError, SubmitResult},
+};
+
+/// `Schedule` は提出の間隔の下限と, 競技中に提出できる残りの回数を表す.
+///
+/// サーバは短い間隔の提出を制限するので, それを超えないように提出する.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    interval: Duration,
+    /// 残りの提出回数. `None` なら制限しない
+    remaining: Option<usize>,
+}
+
+impl Schedule {
+    /// 提出の間隔を `interval` 以上空け, 回数は制限しない.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            remaining: None,
+        }
+    }
+
+    /// 残りの提出回数を `quota` にする.
+    pub fn with_quota(self, quota: usize) -> Self {
+        Self {
+            remaining: Some(quota),
+            ..self
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn remaining(&self) -> Option<usize> {
+        self.remaining
+    }
+
+    /// もう提出できないか.
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    fn consume(&mut self) {
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.saturating_sub(1);
+        }
+    }
+}
+
+/// `Submitter` は回答の提出を別スレッドで行い, 探索を止めずに済むようにする.
+///
+/// 提出の間隔は `schedule` の間隔以上空け, 残りの提出回数を使い切ったらそれ以降は提出しない.
+/// 待っている間に届いた回答からは予想されるコストが最も小さいものだけを提出し,
+/// 一度提出したものと同じ回答や, 予想されるコストでは `best` の記録を良くできない回答は提出しない.
+/// サーバの返答はすべてログファイルに追記し, 提出した回答と返答そのものは `archive_dir` に 1 回ずつ残す.
+/// 採点待ちの返答では不一致数が分からないので, `best` の記録は更新しない.
+#[derive(Debug)]
+pub struct Submitter {
+    sender: Option<mpsc::UnboundedSender<(String, u64)>>,
+    handle: Option<thread::JoinHandle<(BestSubmission, Schedule)>>,
+}
+
+impl Submitter {
+    pub fn spawn(
+        client: Client,
+        schedule: Schedule,
+        log_path: impl AsRef<Path>,
+        archive_dir: impl AsRef<Path>,
+        best: BestSubmission,
+    ) -> Result<Self> {
+        Self::spawn_with(
+            move |answer| client.submit(answer),
+            schedule,
+            log_path,
+            archive_dir,
+            best,
+        )
+    }
+
+    fn spawn_with(
+        submit: impl Fn(&str) -> Result<SubmitResult, ClientError> + Send + Sync + 'static,
+        schedule: Schedule,
+        log_path: impl AsRef<Path>,
+        archive_dir: impl AsRef<Path>,
+        best: BestSubmission,
+    ) -> Result<Self> {
+        let log_path = log_path.as_ref();
+        let archive_dir = archive_dir.as_ref().to_owned();
+        fs::create_dir_all(&archive_dir)
+            .with_context(|| format!("failed to create {}", archive_dir.display()))?;
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_path)
+            .with_context(|| format!("failed to open {}", log_path.display()))?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .context("failed to build tokio runtime")?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let handle = thread::spawn(move || {
+            runtime.block_on(run(
+                Arc::new(submit),
+                receiver,
+                schedule,
+                log,
+                &archive_dir,
+                best,
+            ))
+        });
+        Ok(Self {
+            sender: Some(sender),
+            handle: Some(handle),
+        })
+    }
+
+    /// 予想されるコストが `predicted_cost` の回答を提出待ちに加える. すぐに戻る.
+    pub fn submit(&self, answer: String, predicted_cost: u64) {
+        if let Some(sender) = &self.sender {
+            // 受信側はすべて送り終えるまで終了しない
+            let _ = sender.send((answer, predicted_cost));
+        }
+    }
+
+    /// 提出待ちの回答をすべて処理し終えるまで待ち, 更新された記録と残りの提出回数を返す.
+    pub fn finish(mut self) -> Option<(BestSubmission, Schedule)> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Option<(BestSubmission, Schedule)> {
+        self.sender.take();
+        match self.handle.take()?.join() {
+            Ok(result) => Some(result),
+            Err(_) => {
+                tracing::error!("submitter thread panicked");
+                None
+            }
+        }
+    }
+}
+
+impl Drop for Submitter {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+async fn run<F>(
+    submit: Arc<F>,
+    mut receiver: mpsc::UnboundedReceiver<(String, u64)>,
+    mut schedule: Schedule,
+    mut log: File,
+    archive_dir: &Path,
+    mut best: BestSubmission,
+) -> (BestSubmission, Schedule)
+where
+    F: Fn(&str) -> Result<SubmitResult, ClientError> + Send + Sync + 'static,
+{
+    let mut submitted = HashSet::new();
+    let mut last_submitted: Option<Instant> = None;
+    // 提出する回答は復元した配置どおりに並べ替えるので, 不一致はないと予想する
+    let should_submit =
+        |submitted: &HashSet<String>, best: &BestSubmission, (answer, cost): &(String, u64)| {
+            !submitted.contains(answer)
+                && best.could_improve(Standing {
+                    mismatch_count: 0,
+                    cost: *cost,
+                })
+        };
+    while let Some(mut candidate) = receiver.recv().await {
+        if !should_submit(&submitted, &best, &candidate) {
+            continue;
+        }
+        if schedule.is_exhausted() {
+            tracing::debug!(cost = candidate.1, "skipped an answer. no submissions left");
+            continue;
+        }
+        if let Some(last) = last_submitted {
+            sleep_until(last + schedule.interval).await;
+        }
+        // 同じコストなら新しい方を提出する
+        while let Ok(newer) = receiver.try_recv() {
+            if should_submit(&submitted, &best, &newer) && newer.1 <= candidate.1 {
+                candidate = newer;
+            }
+        }
+        let (answer, cost) = candidate;
+        schedule.consume();
+
+        last_submitted = Some(Instant::now());
+        let submit = Arc::clone(&submit);
+        let to_submit = answer.clone();
+        let result = tokio::task::spawn_blocking(move || submit(&to_submit))
+            .await
+            .expect("submit must not panic");
+        match &result {
+            Ok(res) => tracing::info!(?res, cost, "submitted"),
+            Err(e) => tracing::warn!(%e, cost, "failed to submit"),
+        }
+
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let written = match &result {
+            Ok(res) => writeln!(log, "{}\tOK\t{:?}", epoch, res),
+            Err(e) => writeln!(log, "{}\tERR\t{}", epoch, e),
+        };
+        if let Err(e) = written {
+            tracing::warn!(%e, "failed to write submission log");
+        }
+        if let Err(e) = archive(archive_dir, &answer, &result) {
+            tracing::warn!(%e, "failed to archive the submission");
+        }
+        if let Ok(res) = &result {
+            match res.status.mismatch_count() {
+                Some(mismatch_count) => {
+                    let standing = Standing {
+                        mismatch_count,
+                        cost,
+                    };
+                    if let Err(e) = best.record(standing) {
+                        tracing::warn!(?e, "failed to record the best submission");
+                    }
+                }
+                None => tracing::info!(cost, "the submission is not judged yet"),
+            }
+        }
+        submitted.insert(answer);
+    }
+    (best, schedule)
+}
+
+/// 提出した回答とサーバの返答をそのまま `dir` に書く. 返答を解釈できなかったときも後から調べられるようにする.
+fn archive(
+    dir: &Path,
+    answer: &str,
+    result: &Result<SubmitResult, ClientError>,
+) -> std::io::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let response = match result {
+        Ok(res) => res.body.clone(),
+        Err(ClientError::Status { status, body }) => format!("{}\n{}", status, body),
+        Err(ClientError::InvalidResponse { body, .. }) => body.clone(),
+        Err(e) => e.to_string(),
+    };
+    let status = if result.is_ok() { "OK" } else { "ERR" };
+    let mut file = create_unique(dir, &now.as_millis().to_string())?;
+    write!(
+        file,
+        "submitted_at: {}.{:03}\n--- request\n{}\n--- response ({})\n{}\n",
+        now.as_secs(),
+        now.subsec_millis(),
+        answer.trim_end(),
+        status,
+        response.trim_end()
+    )
+}
+
+/// `dir` に `{stem}.txt` を新しく作る. 同じ名前のファイルがあれば `{stem}-1.txt`, `{stem}-2.txt`, ... と番号を付けて, 前のファイルを上書きしない.
+fn create_unique(dir: &Path, stem: &str) -> std::io::Result<File> {
+    for suffix in 0.. {
+        let name = match suffix {
+            0 => format!("{}.txt", stem),
+            _ => format!("{}-{}.txt", stem, suffix),
+        };
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(dir.join(name))
+        {
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            result => return result,
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn test_create_unique() {
+    let dir = std::env::temp_dir().join(format!("create_unique_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // 同じミリ秒に提出しても, 先に残した回答を上書きしない
+    for answer in ["a", "b", "c"] {
+        write!(create_unique(&dir, "1000").unwrap(), "{}", answer).unwrap();
+    }
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!("a", read("1000.txt"));
+    assert_eq!("b", read("1000-1.txt"));
+    assert_eq!("c", read("1000-2.txt"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_submitter() {
+    use std::sync::Mutex;
+
+    let log_path = std::env::temp_dir().join(format!("submitter_test_{}.log", std::process::id()));
+    let best_path = log_path.with_extension("best");
+    let archive_dir = log_path.with_extension("d");
+    let _ = std::fs::remove_file(&best_path);
+    let _ = std::fs::remove_dir_all(&archive_dir);
+    let calls = Arc::new(Mutex::new(vec![]));
+    let submitter = {
+        let calls = Arc::clone(&calls);
+        Submitter::spawn_with(
+            move |answer| {
+                use crate::client::SubmitStatus;
+
+                calls.lock().unwrap().push(answer.to_owned());
+                // 最初の回答はまだ採点されていない
+                let (status, body) = if answer == "a" {
+                    (SubmitStatus::Queued, "QUEUED")
+                } else {
+                    (
+                        SubmitStatus::Accepted {
+                            pos_mismatch_count: 0,
+                            rot_mismatch_count: 0,
+                        },
+                        "ACCEPTED 0 0",
+                    )
+                };
+                Ok(SubmitResult {
+                    status,
+                    request_id: None,
+                    body: body.to_owned(),
+                })
+            },
+            Schedule::new(Duration::from_millis(10)),
+            &log_path,
+            &archive_dir,
+            BestSubmission::load(&best_path).unwrap(),
+        )
+        .unwrap()
+    };
+
+    submitter.submit("a".to_owned(), 10);
+    thread::sleep(Duration::from_millis(50));
+    submitter.submit("a".to_owned(), 10);
+    submitter.submit("b".to_owned(), 5);
+    thread::sleep(Duration::from_millis(50));
+    submitter.submit("c".to_owned(), 7);
+    let (best, _) = submitter.finish().unwrap();
+
+    assert_eq!(
+        Some(Standing {
+            mismatch_count: 0,
+            cost: 5
+        }),
+        best.best()
+    );
+    assert_eq!(vec!["a".to_owned(), "b".to_owned()], *calls.lock().unwrap());
+    let log = std::fs::read_to_string(&log_path).unwrap();
+    assert_eq!(2, log.lines().count());
+    let archived: Vec<_> = std::fs::read_dir(&archive_dir)
+        .unwrap()
+        .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    assert_eq!(2, archived.len());
+    assert!(archived
+        .iter()
+        .any(|a| a.contains("--- request\na\n--- response (OK)\nQUEUED\n")));
+    assert!(archived
+        .iter()
+        .any(|a| a.contains("--- request\nb\n--- response (OK)\nACCEPTED 0 0\n")));
+    std::fs::remove_dir_all(archive_dir).unwrap();
+    std::fs::remove_file(log_path).unwrap();
+    std::fs::remove_file(best_path).unwrap();
+}
+
+#[test]
+fn test_submitter_schedule() {
+    use std::sync::Mutex;
+
+    let log_path =
+        std::env::temp_dir().join(format!("submitter_schedule_{}.log", std::process::id()));
+    let best_path = log_path.with_extension("best");
+    let archive_dir = log_path.with_extension("d");
+    let _ = std::fs::remove_file(&best_path);
+    let calls = Arc::new(Mutex::new(vec![]));
+    let submitter = {
+        let calls = Arc::clone(&calls);
+        Submitter::spawn_with(
+            move |answer| {
+                calls.lock().unwrap().push(answer.to_owned());
+                Ok(SubmitResult {
+                    status: crate::client::SubmitStatus::Accepted {
+                        pos_mismatch_count: 0,
+                        rot_mismatch_count: 0,
+                    },
+                    request_id: None,
+                    body: "ACCEPTED 0 0".to_owned(),
+                })
+            },
+            Schedule::new(Duration::from_millis(200)).with_quota(2),
+            &log_path,
+            &archive_dir,
+            BestSubmission::load(&best_path).unwrap(),
+        )
+        .unwrap()
+    };
+
+    submitter.submit("a".to_owned(), 10);
+    thread::sleep(Duration::from_millis(50));
+    // 次に提出できるまでに届いた回答からは, 最もコストの小さいものを提出する
+    submitter.submit("b".to_owned(), 8);
+    submitter.submit("c".to_owned(), 6);
+    submitter.submit("d".to_owned(), 7);
+    thread::sleep(Duration::from_millis(300));
+    // 提出回数を使い切ったら, より良い回答でも提出しない
+    submitter.submit("e".to_owned(), 1);
+    let (best, schedule) = submitter.finish().unwrap();
+
+    assert_eq!(vec!["a".to_owned(), "c".to_owned()], *calls.lock().unwrap());
+    assert_eq!(Some(6), best.best().map(|s| s.cost));
+    assert!(schedule.is_exhausted());
+    std::fs::remove_dir_all(archive_dir).unwrap();
+    std::fs::remove_file(log_path).unwrap();
+    std::fs::remove_file(best_path).unwrap();
+}
@@ -0,0 +1,65 @@
+//! 問題ファイルの読み込みから回答の採点まで, 本番と同じ順に通して動かす.
+//!
+//! 時間がかかるので普段は実行しない. `cargo test --test pipeline -- --ignored` で実行する.
+
+use procon2021_comp::{
+    basis::{Problem, Rot},
+    fragment::Fragment,
+    grid::{Pos, VecOnGrid},
+    image, kaitou,
+    move_resolve::{self, ResolveParam},
+    pixel_match::Resolver,
+    scramble,
+    simulator::{self, Score},
+};
+
+/// `problem` を GUI なしで復元して回答を作り, 正解 `truth` と比べて採点する.
+fn solve(problem: &Problem, truth: &VecOnGrid<(Pos, Rot)>) -> (Score, String) {
+    let grid = problem.grid();
+    let fragments = Fragment::new_all(problem).unwrap();
+
+    let arrangement = Resolver::new(fragments, grid)
+        .resolve_without_gui()
+        .unwrap();
+    let movements = arrangement.movements();
+    let param = ResolveParam {
+        select_limit: problem.select_limit,
+        swap_cost: problem.swap_cost,
+        select_cost: problem.select_cost,
+    };
+    let (ops, _) = move_resolve::resolve(grid, &movements, param)
+        .unwrap()
+        .next()
+        .expect("move_resolve found no answer");
+
+    let answer = kaitou::ans(&ops, &arrangement.rotations());
+    let score = simulator::simulate(problem, truth, &answer).unwrap();
+    (score, answer)
+}
+
+#[test]
+#[ignore]
+fn pipeline_01() {
+    let problem = image::read_problem(&include_bytes!("../test_cases/01_q.ppm")[..]).unwrap();
+    let truth =
+        simulator::truth_from_answer(problem.grid(), include_str!("../test_cases/01_a.txt"))
+            .unwrap();
+    let (score, answer) = solve(&problem, &truth);
+    assert!(score.is_perfect(), "{:?}\n{}", score, answer);
+}
+
+#[test]
+#[ignore]
+fn pipeline_03() {
+    // 03 の正解は無いので, 同じ大きさと選択回数の完成した画像をばらばらにして正解ごと作る
+    let header = image::read_problem(&include_bytes!("../test_cases/03.ppm")[..]).unwrap();
+    let original = Problem {
+        select_limit: header.select_limit,
+        select_cost: header.select_cost,
+        swap_cost: header.swap_cost,
+        ..scramble::smooth_problem(header.dims.width, header.dims.height)
+    };
+    let scrambled = scramble::scramble(&original, 3);
+    let (score, answer) = solve(&scrambled.problem, &scrambled.truth);
+    assert!(score.is_perfect(), "{:?}\n{}", score, answer);
+}